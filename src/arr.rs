@@ -108,6 +108,14 @@ impl<T: Clone> Clone for Arr<T> {
   }
 }
 
+impl<T: PartialEq> PartialEq for Arr<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.len() == other.len() && self.iter().zip(other.iter()).all(|(x, y)| x == y)
+  }
+}
+
+impl<T: Eq> Eq for Arr<T> {}
+
 impl<T> Default for Arr<T> {
   #[inline(always)]
   fn default() -> Self {
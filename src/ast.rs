@@ -6,8 +6,11 @@ pub enum Item<'a> {
   Fun(Fun<'a>),
 }
 
+// NB: a name in the source is given with the byte offset where it starts.
+
 pub struct Fun<'a> {
   pub name: Symbol,
+  pub pos: u32,
   pub args: &'a [Binding],
   pub body: &'a [Stmt<'a>],
 }
@@ -16,6 +19,7 @@ pub struct Fun<'a> {
 
 pub struct Binding {
   pub name: Option<Symbol>,
+  pub pos: u32,
 }
 
 // TODO: consider, e.g.,
@@ -41,7 +45,7 @@ pub enum Expr<'a> {
   PreOp(&'a (Symbol, Op1)),
   Ternary(&'a (Expr<'a>, Expr<'a>, Expr<'a>)),
   Undefined,
-  Variable(Symbol),
+  Variable(Symbol, u32),
 }
 
 pub enum Stmt<'a> {
@@ -53,6 +57,150 @@ pub enum Stmt<'a> {
   Set(Symbol, Expr<'a>),
   SetField(Expr<'a>, Symbol, Expr<'a>),
   SetIndex(Expr<'a>, Expr<'a>, Expr<'a>),
-  Var(Symbol, Expr<'a>),
+  Var(Symbol, u32, Expr<'a>),
   While(Expr<'a>, &'a [Stmt<'a>]),
 }
+
+// NB: the AST is printed as S-expressions, one statement per line.
+
+impl<'a> std::fmt::Display for Item<'a> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let Item::Fun(fun) = self;
+    write!(f, "(fun {} (", fun.name)?;
+    bindings(f, fun.args)?;
+    write!(f, ")")?;
+    block(f, fun.body, 1)?;
+    return write!(f, ")");
+  }
+}
+
+fn bindings(f: &mut std::fmt::Formatter<'_>, xs: &[Binding]) -> std::fmt::Result {
+  for (i, x) in xs.iter().enumerate() {
+    if i != 0 { write!(f, " ")?; }
+    match x.name {
+      None => write!(f, "_")?,
+      Some(s) => write!(f, "{}", s)?,
+    }
+  }
+  return Ok(());
+}
+
+fn block(f: &mut std::fmt::Formatter<'_>, xs: &[Stmt<'_>], depth: usize) -> std::fmt::Result {
+  for x in xs.iter() {
+    write!(f, "\n{:1$}", "", 2 * depth)?;
+    stmt(f, x, depth)?;
+  }
+  return Ok(());
+}
+
+fn exprs(f: &mut std::fmt::Formatter<'_>, xs: &[Expr<'_>], depth: usize) -> std::fmt::Result {
+  for x in xs.iter() {
+    write!(f, " ")?;
+    expr(f, x, depth)?;
+  }
+  return Ok(());
+}
+
+fn stmt(f: &mut std::fmt::Formatter<'_>, x: &Stmt<'_>, depth: usize) -> std::fmt::Result {
+  match *x {
+    Stmt::ExprList(xs) => {
+      write!(f, "(do")?;
+      exprs(f, xs, depth)?;
+    }
+    Stmt::Break(xs) => {
+      write!(f, "(break")?;
+      exprs(f, xs, depth)?;
+    }
+    Stmt::Continue => {
+      write!(f, "(continue")?;
+    }
+    Stmt::Let(xs, ys) => {
+      write!(f, "(let (")?;
+      bindings(f, xs)?;
+      write!(f, ")")?;
+      exprs(f, ys, depth)?;
+    }
+    Stmt::Return(xs) => {
+      write!(f, "(return")?;
+      exprs(f, xs, depth)?;
+    }
+    Stmt::Set(s, ref y) => {
+      write!(f, "(set {} ", s)?;
+      expr(f, y, depth)?;
+    }
+    Stmt::SetField(ref x, s, ref y) => {
+      write!(f, "(set (. ")?;
+      expr(f, x, depth)?;
+      write!(f, " {}) ", s)?;
+      expr(f, y, depth)?;
+    }
+    Stmt::SetIndex(ref x, ref i, ref y) => {
+      write!(f, "(set ([] ")?;
+      expr(f, x, depth)?;
+      write!(f, " ")?;
+      expr(f, i, depth)?;
+      write!(f, ") ")?;
+      expr(f, y, depth)?;
+    }
+    Stmt::Var(s, _, ref y) => {
+      write!(f, "(var {} ", s)?;
+      expr(f, y, depth)?;
+    }
+    Stmt::While(ref p, xs) => {
+      write!(f, "(while ")?;
+      expr(f, p, depth)?;
+      block(f, xs, depth + 1)?;
+    }
+  }
+  return write!(f, ")");
+}
+
+fn expr(f: &mut std::fmt::Formatter<'_>, x: &Expr<'_>, depth: usize) -> std::fmt::Result {
+  // Writes `(head x y ...`, without the closing parenthesis.
+  let list = |f: &mut std::fmt::Formatter<'_>, head: &str, xs: &[&Expr<'_>]| -> std::fmt::Result {
+    write!(f, "({}", head)?;
+    for x in xs.iter() {
+      write!(f, " ")?;
+      expr(f, x, depth)?;
+    }
+    return Ok(());
+  };
+
+  match *x {
+    Expr::And((x, y)) => list(f, "&&", &[x, y])?,
+    Expr::Bool(p) => return write!(f, "{}", p),
+    Expr::Call((g, xs)) => {
+      list(f, "call", &[g])?;
+      exprs(f, xs, depth)?;
+    }
+    Expr::Field((x, s)) => {
+      list(f, ".", &[x])?;
+      write!(f, " {}", s)?;
+    }
+    Expr::If((p, xs)) => {
+      list(f, "if", &[p])?;
+      block(f, xs, depth + 1)?;
+    }
+    Expr::IfElse((p, xs, ys)) => {
+      list(f, "if", &[p])?;
+      block(f, xs, depth + 1)?;
+      write!(f, "\n{:1$}else", "", 2 * depth)?;
+      block(f, ys, depth + 1)?;
+    }
+    Expr::Index((x, y)) => list(f, "[]", &[x, y])?,
+    Expr::Int(n) => return write!(f, "{}", n),
+    Expr::Loop(xs) => {
+      write!(f, "(loop")?;
+      block(f, xs, depth + 1)?;
+    }
+    Expr::Op1((op, x)) => list(f, op.as_str(), &[x])?,
+    Expr::Op2((op, x, y)) => list(f, op.as_str(), &[x, y])?,
+    Expr::Or((x, y)) => list(f, "||", &[x, y])?,
+    Expr::PostOp((s, op)) => write!(f, "(post{} {}", op, s)?,
+    Expr::PreOp((s, op)) => write!(f, "(pre{} {}", op, s)?,
+    Expr::Ternary((p, x, y)) => list(f, "?:", &[p, x, y])?,
+    Expr::Undefined => return write!(f, "undefined"),
+    Expr::Variable(s, _) => return write!(f, "{}", s),
+  }
+  return write!(f, ")");
+}
@@ -0,0 +1,619 @@
+//! control-flow analyses
+//!
+//! the bytecode of a function -> basic blocks, dominators, loops, and liveness
+//!
+//! The analyses work on untyped and typed bytecode alike, through the `Inst`
+//! trait. A basic block is a `Label` and the code up to its terminator, which
+//! the verifier checks. A block ends in one of the following ways:
+//!
+//! - a `Goto`, with one successor;
+//!
+//! - a `Cond` and its two `Goto`s, with a successor for each branch;
+//!
+//! - a `Call` and the `Goto` to its continuation, which is a successor of the
+//!   block, since control comes back there once the callee returns;
+//!
+//! - a `Ret`, a `TailCall`, or a static error, with no successors.
+//!
+//! Blocks are numbered in order of code, so the entry block is block `0`.
+//! Values are program points, like in the bytecode.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::irp;
+use crate::iru;
+
+/// How an instruction affects control flow.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flow {
+  Label,
+  Goto(u32),
+  Cond,
+  Call,
+  /// Leaves the function.
+  Exit,
+  /// Falls through to the next instruction.
+  Next,
+}
+
+/// What the analyses need to know about an instruction.
+
+pub trait Inst: Copy + std::fmt::Display {
+  fn flow(self) -> Flow;
+
+  /// Whether the instruction defines a value.
+  fn is_value(self) -> bool;
+
+  /// Applies `f` to every value that the instruction uses, including locals.
+  fn for_each_use(self, f: impl FnMut(u32));
+}
+
+impl Inst for iru::Inst {
+  fn flow(self) -> Flow {
+    match self {
+      Self::Label(_) => Flow::Label,
+      Self::Goto(a) => Flow::Goto(a),
+      Self::Cond(_) => Flow::Cond,
+      Self::Call(_) => Flow::Call,
+      Self::GotoStaticError | Self::Ret | Self::TailCall(_) => Flow::Exit,
+      _ => Flow::Next,
+    }
+  }
+
+  fn is_value(self) -> bool {
+    match self {
+      | Self::Get(..)
+      | Self::Const(..)
+      | Self::ConstBool(..)
+      | Self::ConstInt(..)
+      | Self::Field(..)
+      | Self::Index(..)
+      | Self::Op1(..)
+      | Self::Op2(..)
+      | Self::Local(..)
+      | Self::GetLocal(..) =>
+        true,
+      _ =>
+        false,
+    }
+  }
+
+  fn for_each_use(self, f: impl FnMut(u32)) {
+    let mut f = f;
+    match self {
+      | Self::GotoStaticError
+      | Self::Label(..)
+      | Self::Get(..)
+      | Self::Goto(..)
+      | Self::Ret
+      | Self::Const(..)
+      | Self::ConstBool(..)
+      | Self::ConstInt(..) => {
+      }
+      | Self::Put(_, x)
+      | Self::Cond(x)
+      | Self::Call(x)
+      | Self::TailCall(x)
+      | Self::Field(x, _)
+      | Self::Op1(_, x)
+      | Self::Local(x)
+      | Self::GetLocal(x) => {
+        f(x);
+      }
+      | Self::Index(x, y)
+      | Self::Op2(_, x, y)
+      | Self::SetField(x, _, y)
+      | Self::SetLocal(x, y) => {
+        f(x);
+        f(y);
+      }
+      Self::SetIndex(x, y, z) => {
+        f(x);
+        f(y);
+        f(z);
+      }
+    }
+  }
+}
+
+impl Inst for irp::Inst {
+  fn flow(self) -> Flow {
+    match self {
+      Self::Label(_) => Flow::Label,
+      Self::Goto(a) => Flow::Goto(a),
+      Self::Cond(_) => Flow::Cond,
+      Self::Call(_) => Flow::Call,
+      Self::GotoStaticError | Self::Ret | Self::TailCall(_) => Flow::Exit,
+      _ => Flow::Next,
+    }
+  }
+
+  fn is_value(self) -> bool {
+    match self {
+      | Self::Get(..)
+      | Self::Const(..)
+      | Self::ConstFun(..)
+      | Self::ConstBool(..)
+      | Self::ConstInt(..)
+      | Self::Index(..)
+      | Self::PrimOp1(..)
+      | Self::PrimOp2(..)
+      | Self::Local(..)
+      | Self::GetLocal(..) =>
+        true,
+      _ =>
+        false,
+    }
+  }
+
+  fn for_each_use(self, f: impl FnMut(u32)) {
+    let mut f = f;
+    // NB: the only points that are not values are the targets of `Goto`s.
+    if let Self::Goto(_) = self { return; }
+    let _ = self.map_points(|x| { f(x); x });
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EdgeKind {
+  Jump,
+  /// The branch of a `Cond` that is taken when its condition is false.
+  False,
+  True,
+  /// From a block that ends with a `Call` to the continuation of the call.
+  Return,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Edge {
+  pub block: u32,
+  pub kind: EdgeKind,
+}
+
+#[derive(Debug)]
+pub struct Block {
+  /// The program point of the `Label` of the block.
+  pub pos: u32,
+  pub len: u32,
+  pub succs: Arr<Edge>,
+  pub preds: Arr<u32>,
+}
+
+pub struct Cfg {
+  /// The program point where the function starts.
+  pub pos: u32,
+  pub blocks: Arr<Block>,
+  // The block of each program point of the function.
+  block_at: Arr<u32>,
+}
+
+impl Cfg {
+  /// Builds the basic blocks of the function whose code is `code[pos .. pos +
+  /// len]`, which must be well-formed.
+
+  pub fn new<I: Inst>(code: &Arr<I>, pos: u32, len: u32) -> Self {
+    let mut block_at = Arr::new(len, |_| 0);
+    let mut starts = Buf::new();
+
+    for i in pos .. pos + len {
+      if let Flow::Label = code[i].flow() { starts.push(i); }
+      block_at[i - pos] = starts.len() - 1;
+    }
+
+    let n = starts.len();
+    let mut succs = Arr::new(n, |_| Buf::new());
+    let mut preds = Arr::new(n, |_| Buf::new());
+
+    for b in 0 .. n {
+      let start = starts[b];
+      let stop = if b + 1 < n { starts[b + 1] } else { pos + len };
+      let mut kind = EdgeKind::Jump;
+
+      for i in start .. stop {
+        match code[i].flow() {
+          Flow::Goto(a) => {
+            let c = block_at[a - pos];
+            succs[b].push(Edge { block: c, kind });
+            preds[c].push(b);
+            if kind == EdgeKind::False { kind = EdgeKind::True; }
+          }
+          Flow::Cond => {
+            kind = EdgeKind::False;
+          }
+          Flow::Call => {
+            kind = EdgeKind::Return;
+          }
+          Flow::Label | Flow::Exit | Flow::Next => {
+          }
+        }
+      }
+    }
+
+    let blocks =
+      Arr::new(n, |b| {
+        let stop = if b + 1 < n { starts[b + 1] } else { pos + len };
+        Block {
+          pos: starts[b],
+          len: stop - starts[b],
+          succs: Arr::from(succs[b].drain()),
+          preds: Arr::from(preds[b].drain()),
+        }
+      });
+
+    return Self { pos, blocks, block_at };
+  }
+
+  pub fn len(&self) -> u32 {
+    return self.blocks.len();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.blocks.len() == 0;
+  }
+
+  /// The block that contains the program point `i`.
+
+  pub fn block_at(&self, i: u32) -> u32 {
+    return self.block_at[i - self.pos];
+  }
+
+  /// The blocks that are reachable from the entry, in reverse postorder, so
+  /// that a block comes before its successors, except along back edges.
+
+  pub fn reverse_postorder(&self) -> Arr<u32> {
+    let n = self.blocks.len();
+    let mut order = Buf::new();
+    let mut seen = Arr::new(n, |_| false);
+    let mut stack = Buf::new();
+
+    if n != 0 {
+      seen[0] = true;
+      stack.push((0, 0));
+    }
+
+    // NB: each entry of the stack is a block and the index of the next of its
+    // successors to visit.
+
+    while ! stack.is_empty() {
+      let (b, k) = *stack.top();
+      let succs = &self.blocks[b].succs;
+      if k < succs.len() {
+        stack.top_mut().1 = k + 1;
+        let c = succs[k].block;
+        if ! seen[c] {
+          seen[c] = true;
+          stack.push((c, 0));
+        }
+      } else {
+        let _ = stack.pop();
+        order.push(b);
+      }
+    }
+
+    let m = order.len();
+    return Arr::new(m, |i| order[m - 1 - i]);
+  }
+}
+
+/// The dominator tree of a function.
+
+pub struct Dominators {
+  idom: Arr<Option<u32>>,
+  // The index of each block in reverse postorder.
+  order: Arr<u32>,
+}
+
+impl Dominators {
+  // NB: the algorithm is that of Cooper, Harvey, and Kennedy, "A Simple, Fast
+  // Dominance Algorithm".
+
+  pub fn new(cfg: &Cfg) -> Self {
+    let n = cfg.len();
+    let rpo = cfg.reverse_postorder();
+    let mut order = Arr::new(n, |_| u32::MAX);
+    let mut idom = Arr::new(n, |_| None);
+
+    for (i, &b) in rpo.iter().enumerate() {
+      order[b] = i as u32;
+    }
+
+    if n != 0 { idom[0] = Some(0); }
+
+    let mut changed = true;
+
+    while changed {
+      changed = false;
+      for &b in rpo.iter().skip(1) {
+        let mut new = None;
+        for &p in cfg.blocks[b].preds.iter() {
+          if idom[p].is_none() { continue; }
+          new =
+            match new {
+              None => Some(p),
+              Some(q) => Some(intersect(&idom, &order, p, q)),
+            };
+        }
+        if new != idom[b] {
+          idom[b] = new;
+          changed = true;
+        }
+      }
+    }
+
+    return Self { idom, order };
+  }
+
+  /// The immediate dominator of block `b`, which for the entry is itself, or
+  /// `None` if `b` is unreachable.
+
+  pub fn idom(&self, b: u32) -> Option<u32> {
+    return self.idom[b];
+  }
+
+  pub fn is_reachable(&self, b: u32) -> bool {
+    return self.idom[b].is_some();
+  }
+
+  /// Whether block `a` dominates block `b`, which every block does itself.
+
+  pub fn dominates(&self, a: u32, b: u32) -> bool {
+    if ! self.is_reachable(a) || ! self.is_reachable(b) { return false; }
+    let mut b = b;
+    while self.order[b] > self.order[a] {
+      b = self.idom[b].unwrap();
+    }
+    return a == b;
+  }
+}
+
+fn intersect(idom: &Arr<Option<u32>>, order: &Arr<u32>, a: u32, b: u32) -> u32 {
+  let mut a = a;
+  let mut b = b;
+  while a != b {
+    while order[a] > order[b] { a = idom[a].unwrap(); }
+    while order[b] > order[a] { b = idom[b].unwrap(); }
+  }
+  return a;
+}
+
+#[derive(Debug)]
+pub struct Loop {
+  pub header: u32,
+  /// The blocks of the loop, including its header and those of nested loops,
+  /// in order of code.
+  pub blocks: Arr<u32>,
+  /// The innermost loop that contains this one.
+  pub parent: Option<u32>,
+}
+
+/// The natural loops of a function and how they nest.
+
+pub struct Loops {
+  /// The loops, in order of their headers.
+  pub loops: Arr<Loop>,
+  // The innermost loop of each block.
+  innermost: Arr<Option<u32>>,
+}
+
+impl Loops {
+  /// Finds a loop for each block that is the target of a back edge, that is,
+  /// of an edge from a block that it dominates. The loop has every block that
+  /// reaches such an edge without going through the header.
+
+  pub fn new(cfg: &Cfg, dom: &Dominators) -> Self {
+    let n = cfg.len();
+    let mut loops = Buf::new();
+
+    for h in 0 .. n {
+      let mut body = Arr::new(n, |_| false);
+      let mut stack = Buf::new();
+
+      for &p in cfg.blocks[h].preds.iter() {
+        if dom.dominates(h, p) && ! body[p] {
+          body[p] = true;
+          stack.push(p);
+        }
+      }
+
+      if stack.is_empty() { continue; }
+
+      body[h] = true;
+
+      while ! stack.is_empty() {
+        let b = stack.pop();
+        if b == h { continue; }
+        for &p in cfg.blocks[b].preds.iter() {
+          if dom.is_reachable(p) && ! body[p] {
+            body[p] = true;
+            stack.push(p);
+          }
+        }
+      }
+
+      let blocks: Buf<u32> = (0 .. n).filter(|&b| body[b]).collect();
+      loops.push(Loop { header: h, blocks: Arr::from(blocks.iter().copied()), parent: None });
+    }
+
+    let mut loops = Arr::from(loops.drain());
+    let mut innermost = Arr::new(n, |_| None);
+
+    // NB: of two loops with different headers, either one contains the other,
+    // or they are disjoint, so the innermost loop of a block is the smallest
+    // that contains it.
+
+    for b in 0 .. n {
+      let mut best: Option<u32> = None;
+      for (i, l) in loops.iter().enumerate() {
+        if l.blocks.iter().any(|&c| c == b) && best.is_none_or(|j| l.blocks.len() < loops[j].blocks.len()) {
+          best = Some(i as u32);
+        }
+      }
+      innermost[b] = best;
+    }
+
+    for i in 0 .. loops.len() {
+      let h = loops[i].header;
+      let mut best: Option<u32> = None;
+      for (j, l) in loops.iter().enumerate() {
+        let j = j as u32;
+        if j != i && l.blocks.iter().any(|&c| c == h) && best.is_none_or(|k| l.blocks.len() < loops[k].blocks.len()) {
+          best = Some(j);
+        }
+      }
+      loops[i].parent = best;
+    }
+
+    return Self { loops, innermost };
+  }
+
+  /// The innermost loop that contains block `b`, if any.
+
+  pub fn innermost(&self, b: u32) -> Option<u32> {
+    return self.innermost[b];
+  }
+
+  /// The number of loops that contain block `b`.
+
+  pub fn depth(&self, b: u32) -> u32 {
+    let mut n = 0;
+    let mut l = self.innermost[b];
+    while let Some(i) = l {
+      n += 1;
+      l = self.loops[i].parent;
+    }
+    return n;
+  }
+}
+
+// A set of the program points of a function.
+
+#[derive(Clone, Eq, PartialEq)]
+struct Set {
+  words: Arr<u64>,
+}
+
+impl Set {
+  fn new(n: u32) -> Self {
+    return Self { words: Arr::new(n.div_ceil(64), |_| 0) };
+  }
+
+  fn contains(&self, i: u32) -> bool {
+    return self.words[i / 64] & 1 << (i % 64) != 0;
+  }
+
+  fn insert(&mut self, i: u32) {
+    self.words[i / 64] |= 1 << (i % 64);
+  }
+
+  fn remove(&mut self, i: u32) {
+    self.words[i / 64] &= ! (1 << (i % 64));
+  }
+
+  fn union(&mut self, other: &Set) {
+    for i in 0 .. self.words.len() {
+      self.words[i] |= other.words[i];
+    }
+  }
+
+  fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+    return (0 .. self.words.len() * 64).filter(|&i| self.contains(i));
+  }
+}
+
+/// The values that are live at the start and at the end of each block, that
+/// is, that are used later without being defined again first.
+
+pub struct Liveness {
+  pos: u32,
+  live_in: Arr<Set>,
+  live_out: Arr<Set>,
+}
+
+impl Liveness {
+  pub fn new<I: Inst>(code: &Arr<I>, cfg: &Cfg) -> Self {
+    let n = cfg.len();
+    let len = cfg.blocks.iter().map(|b| b.len).sum();
+    let pos = cfg.pos;
+    let mut live_in = Arr::new(n, |_| Set::new(len));
+    let mut live_out = Arr::new(n, |_| Set::new(len));
+    let rpo = cfg.reverse_postorder();
+    let mut changed = true;
+
+    // NB: liveness flows backwards, so the blocks are visited in postorder.
+
+    while changed {
+      changed = false;
+      for k in (0 .. rpo.len()).rev() {
+        let b = rpo[k];
+        let block = &cfg.blocks[b];
+        let mut out = Set::new(len);
+        for e in block.succs.iter() {
+          out.union(&live_in[e.block]);
+        }
+        let mut live = out.clone();
+        for i in (block.pos .. block.pos + block.len).rev() {
+          let inst = code[i];
+          if inst.is_value() { live.remove(i - pos); }
+          inst.for_each_use(|x| live.insert(x - pos));
+        }
+        if live != live_in[b] {
+          live_in[b] = live;
+          changed = true;
+        }
+        live_out[b] = out;
+      }
+    }
+
+    return Self { pos, live_in, live_out };
+  }
+
+  pub fn live_in(&self, b: u32) -> impl Iterator<Item = u32> + '_ {
+    return self.live_in[b].iter().map(|i| i + self.pos);
+  }
+
+  pub fn live_out(&self, b: u32) -> impl Iterator<Item = u32> + '_ {
+    return self.live_out[b].iter().map(|i| i + self.pos);
+  }
+
+  pub fn is_live_out(&self, b: u32, x: u32) -> bool {
+    return self.live_out[b].contains(x - self.pos);
+  }
+}
+
+/// Writes the blocks of a function as a Graphviz graph, with the code, the
+/// live-in values, and the loop depth of each block.
+
+pub fn dot<I: Inst>(out: &mut impl std::fmt::Write, name: &str, code: &Arr<I>, cfg: &Cfg) -> std::fmt::Result {
+  let dom = Dominators::new(cfg);
+  let loops = Loops::new(cfg, &dom);
+  let live = Liveness::new(code, cfg);
+
+  writeln!(out, "digraph \"{}\" {{", name)?;
+  writeln!(out, "  node [shape=box, fontname=monospace];")?;
+
+  for (b, block) in cfg.blocks.iter().enumerate() {
+    let b = b as u32;
+    write!(out, "  b{} [label=\"b{} depth {} live", b, b, loops.depth(b))?;
+    for x in live.live_in(b) { write!(out, " %{}", x)?; }
+    write!(out, "\\l")?;
+    for i in block.pos .. block.pos + block.len {
+      write!(out, "%{} {}\\l", i, code[i])?;
+    }
+    writeln!(out, "\"];")?;
+  }
+
+  for (b, block) in cfg.blocks.iter().enumerate() {
+    for e in block.succs.iter() {
+      match e.kind {
+        EdgeKind::Jump => writeln!(out, "  b{} -> b{};", b, e.block)?,
+        EdgeKind::False => writeln!(out, "  b{} -> b{} [label=\"false\"];", b, e.block)?,
+        EdgeKind::True => writeln!(out, "  b{} -> b{} [label=\"true\"];", b, e.block)?,
+        EdgeKind::Return => writeln!(out, "  b{} -> b{} [style=dashed];", b, e.block)?,
+      }
+    }
+  }
+
+  writeln!(out, "}}")?;
+  return Ok(());
+}
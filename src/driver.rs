@@ -0,0 +1,227 @@
+//! command-line driver
+//!
+//! source text -> dumps, type schemes, diagnostics, and results
+//!
+//! Each command of the `lilac` binary is a function here that writes what the
+//! command prints to `out`. A command fails with the first diagnostic in the
+//! source, after it has written its output, so that a dump is still useful for
+//! a program with errors.
+//!
+//! An invalid token or a syntax error is located by its byte offset, and a
+//! static error by its function and program point, which `iru` shows.
+
+use crate::arr::Arr;
+use crate::cfg;
+use crate::eval_iru;
+use crate::eval_iru::Value;
+use crate::format;
+use crate::irp;
+use crate::iru;
+use crate::lexer::Lexer;
+use crate::make_irp;
+use crate::make_iru;
+use crate::opt;
+use crate::parse;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::typecheck;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The lexer could not read a token at the given byte offset.
+  InvalidToken(usize),
+  SyntaxError(parse::Error),
+  /// The source is not in the layout that `format` prints.
+  NotFormatted,
+  /// A static error was detected in the given function, at the given program
+  /// point.
+  StaticError(Symbol, u32),
+  TypeError(make_irp::Error),
+  RuntimeError(eval_iru::Error),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::InvalidToken(i) => write!(f, "invalid token at byte {}", i),
+      Self::SyntaxError(e) => write!(f, "{} at byte {}", e, e.pos()),
+      Self::NotFormatted => write!(f, "not formatted"),
+      Self::StaticError(s, i) => write!(f, "static error in {} at %{}", s, i),
+      Self::TypeError(e) => write!(f, "{}", e),
+      Self::RuntimeError(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+/// Lists the tokens of `source` with their byte offsets.
+
+pub fn lex(source: &str, out: &mut String) -> Result<(), Error> {
+  let mut lexer = Lexer::new(source.as_bytes());
+  let mut error = None;
+
+  while lexer.token() != Token::Eof {
+    let span = String::from_utf8_lossy(lexer.token_span());
+    writeln!(out, "{} {:?} {:?}", lexer.token_start(), lexer.token(), span).unwrap();
+    if lexer.token() == Token::Error && error.is_none() {
+      error = Some(Error::InvalidToken(lexer.token_start()));
+    }
+    lexer.next();
+  }
+
+  return error.map_or(Ok(()), Err);
+}
+
+/// Prints the syntax tree of `source`.
+
+pub fn parse(source: &str, out: &mut String) -> Result<(), Error> {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(source.as_bytes(), store.arena());
+
+  for item in items.iter() {
+    writeln!(out, "{}", item).unwrap();
+  }
+
+  return check_static(source, &errors, &make_iru::compile(&items));
+}
+
+/// Prints `source` in canonical layout.
+
+pub fn format(source: &str, out: &mut String) -> Result<(), Error> {
+  out.push_str(&format::format(source).map_err(Error::SyntaxError)?);
+  return Ok(());
+}
+
+/// Checks that `source` is already in canonical layout, printing nothing.
+
+pub fn format_check(source: &str) -> Result<(), Error> {
+  if format::format(source).map_err(Error::SyntaxError)? != source {
+    return Err(Error::NotFormatted);
+  }
+  return Ok(());
+}
+
+/// Prints the untyped bytecode of `source`.
+
+pub fn iru(source: &str, out: &mut String) -> Result<(), Error> {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(source.as_bytes(), store.arena());
+  let module = make_iru::compile(&items);
+
+  write!(out, "{}", module).unwrap();
+  return check_static(source, &errors, &module);
+}
+
+/// Prints the control-flow graph of every function of `source` in Graphviz
+/// format.
+
+pub fn cfg(source: &str, out: &mut String) -> Result<(), Error> {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(source.as_bytes(), store.arena());
+  let module = make_iru::compile(&items);
+
+  for f in module.decl.iter() {
+    let g = cfg::Cfg::new(&module.code, f.pos, f.len);
+    cfg::dot(out, &f.name.to_string(), &module.code, &g).unwrap();
+  }
+
+  return check_static(source, &errors, &module);
+}
+
+/// Prints the type scheme of every function of `source`, and checks that the
+/// whole program is well-typed.
+
+pub fn check(source: &str, out: &mut String) -> Result<(), Error> {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(source.as_bytes(), store.arena());
+  let module = make_iru::compile(&items);
+  let (environment, mut solver) = typecheck::typecheck(&module);
+  let type_errors = make_irp::check(&module, &environment, &mut solver);
+
+  // NB: a function whose type could not be generalized is not in the
+  // environment. A function with only a static error is still well-typed.
+
+  for (f, e) in module.decl.iter().zip(type_errors.iter()) {
+    match (environment.get(f.name), e) {
+      (Some(t), None | Some(make_irp::Error::StaticError(..))) =>
+        writeln!(out, "fun {} : {}", f.name, t.display(solver.types())).unwrap(),
+      _ =>
+        writeln!(out, "fun {} : <type error>", f.name).unwrap(),
+    }
+  }
+
+  check_static(source, &errors, &module)?;
+
+  if let Some(&e) = type_errors.iter().flatten().next() {
+    return Err(Error::TypeError(e));
+  }
+
+  return Ok(());
+}
+
+/// Prints the typed bytecode of `source` after the optimization `passes`.
+
+pub fn opt(source: &str, passes: &[opt::Pass], out: &mut String) -> Result<(), Error> {
+  let (_, typed) = compile(source)?;
+
+  write!(out, "{}", opt::optimize(typed, passes)).unwrap();
+  return Ok(());
+}
+
+/// Checks `source` like `check`, then interprets the function `name` and
+/// prints its results.
+
+pub fn run(source: &str, name: &str, args: impl IntoIterator<Item = Value>, out: &mut String) -> Result<(), Error> {
+  let (module, _) = compile(source)?;
+  let results = eval_iru::run(&module, Symbol::from_str(name), args).map_err(Error::RuntimeError)?;
+
+  for (i, x) in results.iter().enumerate() {
+    if i != 0 { out.push_str(", "); }
+    write!(out, "{}", x).unwrap();
+  }
+
+  out.push('\n');
+  return Ok(());
+}
+
+/// Compiles `source` to untyped bytecode, which the interpreter runs, and
+/// checks it by compiling it to typed bytecode too.
+
+pub fn compile(source: &str) -> Result<(iru::Module, irp::Module), Error> {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(source.as_bytes(), store.arena());
+  let module = make_iru::compile(&items);
+  let (environment, solver) = typecheck::typecheck(&module);
+
+  check_static(source, &errors, &module)?;
+  let typed = make_irp::compile(&module, &environment, solver).map_err(Error::TypeError)?;
+  return Ok((module, typed));
+}
+
+// Finds the first invalid token, or else the first syntax error, or else the
+// first static error.
+
+fn check_static(source: &str, errors: &Arr<parse::Error>, module: &iru::Module) -> Result<(), Error> {
+  let mut lexer = Lexer::new(source.as_bytes());
+
+  while lexer.token() != Token::Eof {
+    if lexer.token() == Token::Error {
+      return Err(Error::InvalidToken(lexer.token_start()));
+    }
+    lexer.next();
+  }
+
+  if errors.len() != 0 {
+    return Err(Error::SyntaxError(errors[0]));
+  }
+
+  for f in module.decl.iter() {
+    for i in f.pos .. f.pos + f.len {
+      if let iru::Inst::GotoStaticError = module.code[i] {
+        return Err(Error::StaticError(f.name, i));
+      }
+    }
+  }
+
+  return Ok(());
+}
@@ -0,0 +1,521 @@
+//! C backend
+//!
+//! monomorphic typed bytecode -> C source
+//!
+//! The module becomes a single C translation unit. Every function has the same
+//! C signature, taking its arguments from and leaving its results in the
+//! global register file `lilac_regs`. A function returns either nothing, or
+//! the function that it tail calls, and `lilac_call` runs this trampoline to
+//! completion. Tail calls therefore run in constant stack space without
+//! relying on `musttail`, which not every C compiler supports.
+//!
+//! Within a function, every value is a C local variable named after its program
+//! point, every `Label` is a C label, and every block argument is a C local
+//! variable that is assigned before jumping to the label.
+//!
+//! An array is a pointer to a struct holding its length followed by its
+//! elements, with one struct type per element type.
+//!
+//! Unlike the x86-64 backend, this one has no `array` builtin, so only the
+//! host makes arrays, and a module that calls `array` is rejected with
+//! `Error::UnboundVariable`. There are no stack maps either, since nothing
+//! is ever collected.
+//!
+//! Arithmetic follows `PrimOp2::eval`, and is computed without undefined
+//! behavior. Its traps and out of bounds indexing print the same message as
+//! the interpreters, and exit with status 1.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::eval_iru::Builtin;
+use crate::eval_iru::Value;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::symbol::Symbol;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The argument at the given position does not match the parameter type.
+  ArgumentMismatch(u32),
+  UnboundVariable(Symbol),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::ArgumentMismatch(i) => write!(f, "argument mismatch at position {}", i),
+      Self::UnboundVariable(s) => write!(f, "unbound variable {}", s),
+    }
+  }
+}
+
+static PRELUDE: &str = r#"#include <inttypes.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef struct lilac_cont lilac_cont;
+typedef lilac_cont (*lilac_fun)(void);
+struct lilac_cont { lilac_fun fun; };
+
+typedef union { bool b; int64_t i; void *p; lilac_fun f; } lilac_reg;
+
+static void lilac_call(lilac_fun f) {
+  lilac_cont c = { f };
+  while (c.fun) c = c.fun();
+}
+
+static _Noreturn void lilac_trap(const char *msg, uint32_t pc) {
+  fflush(stdout);
+  fprintf(stderr, "error: %s at %%%" PRIu32 "\n", msg, pc);
+  exit(1);
+}
+
+static inline int64_t lilac_check_index(int64_t i, int64_t len, uint32_t pc) {
+  if (! (0 <= i && i < len)) lilac_trap("index out of bounds", pc);
+  return i;
+}
+
+static inline int64_t lilac_dec_i64(int64_t x, uint32_t pc) { (void) pc; return (int64_t) ((uint64_t) x - 1); }
+static inline int64_t lilac_inc_i64(int64_t x, uint32_t pc) { (void) pc; return (int64_t) ((uint64_t) x + 1); }
+static inline int64_t lilac_neg_i64(int64_t x, uint32_t pc) { (void) pc; return (int64_t) (0 - (uint64_t) x); }
+static inline bool lilac_not_bool(bool x, uint32_t pc) { (void) pc; return ! x; }
+
+static inline int64_t lilac_sat_i64(bool negative) { return negative ? INT64_MIN : INT64_MAX; }
+
+static inline int64_t lilac_add_i64(int64_t x, int64_t y, uint32_t pc) { int64_t z; (void) pc; (void) __builtin_add_overflow(x, y, &z); return z; }
+static inline int64_t lilac_sub_i64(int64_t x, int64_t y, uint32_t pc) { int64_t z; (void) pc; (void) __builtin_sub_overflow(x, y, &z); return z; }
+static inline int64_t lilac_mul_i64(int64_t x, int64_t y, uint32_t pc) { int64_t z; (void) pc; (void) __builtin_mul_overflow(x, y, &z); return z; }
+static inline int64_t lilac_addsat_i64(int64_t x, int64_t y, uint32_t pc) { int64_t z; (void) pc; return __builtin_add_overflow(x, y, &z) ? lilac_sat_i64(x < 0) : z; }
+static inline int64_t lilac_subsat_i64(int64_t x, int64_t y, uint32_t pc) { int64_t z; (void) pc; return __builtin_sub_overflow(x, y, &z) ? lilac_sat_i64(x < 0) : z; }
+static inline int64_t lilac_mulsat_i64(int64_t x, int64_t y, uint32_t pc) { int64_t z; (void) pc; return __builtin_mul_overflow(x, y, &z) ? lilac_sat_i64((x < 0) != (y < 0)) : z; }
+static inline int64_t lilac_bitand_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x & y; }
+static inline int64_t lilac_bitor_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x | y; }
+static inline int64_t lilac_bitxor_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x ^ y; }
+static inline bool lilac_cmpeq_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x == y; }
+static inline bool lilac_cmpge_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x >= y; }
+static inline bool lilac_cmpgt_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x > y; }
+static inline bool lilac_cmple_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x <= y; }
+static inline bool lilac_cmplt_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x < y; }
+static inline bool lilac_cmpne_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x != y; }
+
+static inline int64_t lilac_div_i64(int64_t x, int64_t y, uint32_t pc) {
+  if (y == 0) lilac_trap("division by zero", pc);
+  if (y == -1 && x == INT64_MIN) lilac_trap("integer overflow", pc);
+  return x / y;
+}
+
+static inline int64_t lilac_divwrap_i64(int64_t x, int64_t y, uint32_t pc) {
+  if (y == 0) lilac_trap("division by zero", pc);
+  if (y == -1) return (int64_t) (0 - (uint64_t) x);
+  return x / y;
+}
+
+static inline int64_t lilac_divsat_i64(int64_t x, int64_t y, uint32_t pc) {
+  if (y == 0) lilac_trap("division by zero", pc);
+  if (y == -1 && x == INT64_MIN) return INT64_MAX;
+  return x / y;
+}
+
+static inline int64_t lilac_rem_i64(int64_t x, int64_t y, uint32_t pc) {
+  if (y == 0) lilac_trap("division by zero", pc);
+  if (y == -1) return 0;
+  return x % y;
+}
+
+static inline int64_t lilac_shl_i64(int64_t x, int64_t y, uint32_t pc) {
+  if ((uint64_t) y > 63) lilac_trap("shift out of range", pc);
+  return (int64_t) ((uint64_t) x << y);
+}
+
+static inline int64_t lilac_shr_i64(int64_t x, int64_t y, uint32_t pc) {
+  if ((uint64_t) y > 63) lilac_trap("shift out of range", pc);
+  return x >> y;
+}
+
+static inline int64_t lilac_shlsat_i64(int64_t x, int64_t y, uint32_t pc) {
+  if (y < 0) lilac_trap("shift out of range", pc);
+  int s = y > 63 ? 63 : (int) y;
+  int64_t z = (int64_t) ((uint64_t) x << s);
+  return z >> s == x ? z : lilac_sat_i64(x < 0);
+}
+
+static inline int64_t lilac_shlwrap_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return (int64_t) ((uint64_t) x << (y & 63)); }
+static inline int64_t lilac_shrwrap_i64(int64_t x, int64_t y, uint32_t pc) { (void) pc; return x >> (y & 63); }
+
+static lilac_cont lilac_len(void);
+"#;
+
+// NB: writing to a `String` cannot fail.
+
+macro_rules! emit {
+  ($out:expr, $($arg:tt)*) => {
+    $out.write_fmt(format_args!($($arg)*)).unwrap()
+  };
+}
+
+// The C type of a value of type `t`.
+
+fn ctype(types: &TypeStore, t: TypeId) -> String {
+  match types[t] {
+    Type::Bool => "bool".to_string(),
+    Type::I64 => "int64_t".to_string(),
+    Type::Array(_) => format!("arr{} *", t.0),
+    Type::Fun(..) => "lilac_fun".to_string(),
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => unreachable!(),
+  }
+}
+
+// The member of `lilac_reg` that holds a value of type `t`.
+
+fn field(types: &TypeStore, t: TypeId) -> &'static str {
+  match types[t] {
+    Type::Bool => "b",
+    Type::I64 => "i",
+    Type::Array(_) => "p",
+    Type::Fun(..) => "f",
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => unreachable!(),
+  }
+}
+
+fn decl_types(out: &mut String, module: &Module) {
+  // NB: a type is always interned after its components, so emitting the
+  // array types in order of their ids defines every element type first.
+
+  let types = &module.types;
+  let mut used = Buf::new();
+  for _ in 0 .. types.len() { used.push(false); }
+
+  for t in module.value_types().iter().flatten() {
+    mark(types, *t, &mut used);
+  }
+
+  for f in module.decl.iter() {
+    mark(types, f.scheme.1, &mut used);
+  }
+
+  for i in 0 .. types.len() {
+    if used[i] && let Type::Array(a) = types[TypeId(i)] {
+      emit!(out, "typedef struct {{ int64_t len; {} elts[]; }} arr{};\n", ctype(types, a), i);
+    }
+  }
+}
+
+fn decl_funs(out: &mut String, module: &Module) {
+  let n = module.decl.iter().map(|f| arity(&module.types, f.scheme.1)).max().unwrap_or(0);
+
+  emit!(out, "\nstatic lilac_reg lilac_regs[{}];\n\n", n.max(1));
+  emit!(out, "static lilac_cont lilac_len(void) {{\n");
+  emit!(out, "  lilac_regs[0].i = * (int64_t *) lilac_regs[0].p;\n");
+  emit!(out, "  return (lilac_cont) {{ 0 }};\n");
+  emit!(out, "}}\n\n");
+
+  for (k, f) in module.decl.iter().enumerate() {
+    emit!(out, "static lilac_cont f{}(void); // {}\n", k, f.name);
+  }
+}
+
+fn decl_fun(out: &mut String, module: &Module, k: u32, value_types: &Arr<Option<TypeId>>) -> Result<(), Error> {
+  let code = &module.code;
+  let types = &module.types;
+  let f = &module.decl[k];
+  let ty = |x: u32| value_types[x].unwrap();
+
+  // The type of every block argument that is read, by label.
+
+  let mut args: Buf<(u32, u32, TypeId)> = Buf::new();
+  let mut label = f.pos;
+
+  for i in f.pos .. f.pos + f.len {
+    match code[i] {
+      Inst::Label(_) => { label = i; }
+      Inst::Get(j, t) => { args.push((label, j, t)); }
+      _ => {}
+    }
+  }
+
+  emit!(out, "\n// {}\nstatic lilac_cont f{}(void) {{\n", f.name, k);
+
+  for &(a, j, t) in args.iter() {
+    if a == f.pos {
+      emit!(out, "  {} a{}_{} = lilac_regs[{}].{};\n", ctype(types, t), a, j, j, field(types, t));
+    } else {
+      emit!(out, "  {} a{}_{};\n", ctype(types, t), a, j);
+    }
+  }
+
+  for i in f.pos .. f.pos + f.len {
+    if let Some(t) = value_types[i] {
+      emit!(out, "  {} v{};\n", ctype(types, t), i);
+    }
+  }
+
+  // Moves the pending outgoing values into the register file.
+
+  let store = |out: &mut String, puts: &mut Buf<u32>| {
+    for (j, &x) in puts.iter().enumerate() {
+      emit!(out, "  lilac_regs[{}].{} = v{};\n", j, field(types, ty(x)), x);
+    }
+    puts.clear();
+  };
+
+  let mut puts: Buf<u32> = Buf::new();
+  let mut label = f.pos;
+  let mut i = f.pos;
+
+  while i < f.pos + f.len {
+    match code[i] {
+      Inst::GotoStaticError => {
+        unreachable!()
+      }
+      Inst::Label(_) => {
+        label = i;
+        if i != f.pos { emit!(out, " L{}:;\n", i); }
+      }
+      Inst::Get(j, _) => {
+        emit!(out, "  v{} = a{}_{};\n", i, label, j);
+      }
+      Inst::Put(_, x) => {
+        puts.push(x);
+      }
+      Inst::Goto(a) => {
+        for (j, &x) in puts.iter().enumerate() {
+          let j = j as u32;
+          if args.iter().any(|&(b, k, _)| a == b && j == k) {
+            emit!(out, "  a{}_{} = v{};\n", a, j, x);
+          }
+        }
+        puts.clear();
+        emit!(out, "  goto L{};\n", a);
+      }
+      Inst::Cond(x) => {
+        debug_assert!(puts.is_empty());
+        let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+        let Inst::Goto(b) = code[i + 2] else { unreachable!() };
+        emit!(out, "  if (v{}) goto L{}; else goto L{};\n", x, b, a);
+        i += 2;
+      }
+      Inst::Ret => {
+        store(out, &mut puts);
+        emit!(out, "  return (lilac_cont) {{ 0 }};\n");
+      }
+      Inst::Call(g) => {
+        // NB: the call is followed by a `Goto` to its continuation.
+        store(out, &mut puts);
+        emit!(out, "  lilac_call(v{});\n", g);
+        let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+        for &(b, j, t) in args.iter() {
+          if a == b { emit!(out, "  a{}_{} = lilac_regs[{}].{};\n", a, j, j, field(types, t)); }
+        }
+        emit!(out, "  goto L{};\n", a);
+        i += 1;
+      }
+      Inst::TailCall(g) => {
+        store(out, &mut puts);
+        emit!(out, "  return (lilac_cont) {{ v{} }};\n", g);
+      }
+      Inst::Const(s, _) => {
+        let Some(Builtin::Len) = Builtin::from_symbol(s) else {
+          return Err(Error::UnboundVariable(s));
+        };
+        emit!(out, "  v{} = lilac_len;\n", i);
+      }
+      Inst::ConstFun(g) => {
+        emit!(out, "  v{} = f{};\n", i, g);
+      }
+      Inst::ConstBool(p) => {
+        emit!(out, "  v{} = {};\n", i, p);
+      }
+      Inst::ConstInt(n) => {
+        if n == i64::MIN {
+          emit!(out, "  v{} = INT64_MIN;\n", i);
+        } else {
+          emit!(out, "  v{} = INT64_C({});\n", i, n);
+        }
+      }
+      Inst::Index(x, y) => {
+        emit!(out, "  v{} = v{}->elts[lilac_check_index(v{}, v{}->len, {})];\n", i, x, y, x, i);
+      }
+      Inst::PrimOp1(op, x) => {
+        emit!(out, "  v{} = lilac_{}(v{}, {});\n", i, op.as_str().replace('.', "_"), x, i);
+      }
+      Inst::PrimOp2(op, x, y) => {
+        emit!(out, "  v{} = lilac_{}(v{}, v{}, {});\n", i, op.as_str().replace('.', "_"), x, y, i);
+      }
+      Inst::Local(x) | Inst::GetLocal(x) => {
+        emit!(out, "  v{} = v{};\n", i, x);
+      }
+      Inst::SetIndex(x, y, z) => {
+        emit!(out, "  v{}->elts[lilac_check_index(v{}, v{}->len, {})] = v{};\n", x, y, x, i, z);
+      }
+      Inst::SetLocal(v, x) => {
+        emit!(out, "  v{} = v{};\n", v, x);
+      }
+    }
+
+    i += 1;
+  }
+
+  emit!(out, "}}\n");
+  return Ok(());
+}
+
+// Emits statements that build the host value `x` of type `t` into the C
+// variable `name`.
+
+fn build(out: &mut String, types: &TypeStore, name: &str, x: &Value, t: TypeId, tmp: &mut u32) -> Option<()> {
+  match (x, types[t]) {
+    (Value::Bool(p), Type::Bool) => {
+      emit!(out, "  {} = {};\n", name, p);
+    }
+    (Value::Int(n), Type::I64) => {
+      emit!(out, "  {} = (int64_t) UINT64_C({});\n", name, *n as u64);
+    }
+    (Value::Array(a), Type::Array(u)) => {
+      let a = a.borrow();
+      emit!(out, "  {} = malloc(sizeof(arr{}) + {} * sizeof({}));\n", name, t.0, a.len(), ctype(types, u));
+      emit!(out, "  {}->len = {};\n", name, a.len());
+      for (j, y) in a.iter().enumerate() {
+        let z = format!("t{}", *tmp);
+        *tmp += 1;
+        emit!(out, "  {} {};\n", ctype(types, u), z);
+        build(out, types, &z, y, u, tmp)?;
+        emit!(out, "  {}->elts[{}] = {};\n", name, j, z);
+      }
+    }
+    _ => {
+      return None;
+    }
+  }
+  return Some(());
+}
+
+// Emits statements that print the C expression `x` of type `t` in the same
+// format as the `Display` of interpreter values.
+
+fn print(out: &mut String, types: &TypeStore, x: &str, t: TypeId, depth: u32) {
+  match types[t] {
+    Type::Bool => {
+      emit!(out, "  fputs({} ? \"true\" : \"false\", stdout);\n", x);
+    }
+    Type::I64 => {
+      emit!(out, "  printf(\"%\" PRId64, {});\n", x);
+    }
+    Type::Fun(..) => {
+      emit!(out, "  fputs(\"<fun>\", stdout);\n");
+    }
+    Type::Array(u) => {
+      emit!(out, "  fputs(\"[\", stdout);\n");
+      emit!(out, "  for (int64_t i{} = 0; i{} < {}->len; i{} ++) {{\n", depth, depth, x, depth);
+      emit!(out, "  if (i{} != 0) fputs(\", \", stdout);\n", depth);
+      print(out, types, &format!("{}->elts[i{}]", x, depth), u, depth + 1);
+      emit!(out, "  }}\n");
+      emit!(out, "  fputs(\"]\", stdout);\n");
+    }
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => {
+      unreachable!()
+    }
+  }
+}
+
+fn mark(types: &TypeStore, t: TypeId, used: &mut Buf<bool>) {
+  if used[t.0] { return; }
+  used[t.0] = true;
+  match types[t] {
+    Type::Array(a) => {
+      mark(types, a, used);
+    }
+    Type::Fun(a, b) => {
+      mark(types, a, used);
+      mark(types, b, used);
+    }
+    Type::Tuple(..) => {
+      for a in types.tuple_elts(t) {
+        mark(types, a, used);
+      }
+    }
+    Type::Bool | Type::I64 | Type::TupleElt(..) | Type::Var(_) => {}
+  }
+}
+
+// The largest number of arguments or results of the function type `t`.
+
+fn arity(types: &TypeStore, t: TypeId) -> u32 {
+  let Type::Fun(a, b) = types[t] else { unreachable!() };
+  return (types.tuple_elts(a).len() as u32).max(types.tuple_elts(b).len() as u32);
+}
+
+/// Translates the monomorphized `module` into a C translation unit.
+
+pub fn emit(module: &Module) -> Result<String, Error> {
+  let mut out = String::new();
+  let value_types = module.value_types();
+
+  out.push_str(PRELUDE);
+  out.push('\n');
+  decl_types(&mut out, module);
+  decl_funs(&mut out, module);
+
+  for k in 0 .. module.decl.len() {
+    decl_fun(&mut out, module, k, &value_types)?;
+  }
+
+  return Ok(out);
+}
+
+/// Emits a C `main` function, to be appended to the output of `emit`, that
+/// calls the first function named `name` with the given arguments and prints
+/// its results.
+
+pub fn emit_main(module: &Module, name: Symbol, args: &[Value]) -> Result<String, Error> {
+  let Some(k) = module.decl.iter().position(|f| f.name == name) else {
+    return Err(Error::UnboundVariable(name));
+  };
+
+  let types = &module.types;
+  let Type::Fun(a, b) = types[module.decl[k as u32].scheme.1] else { unreachable!() };
+  let n = types.tuple_elts(a).len();
+
+  if n != args.len() {
+    return Err(Error::ArgumentMismatch(n.min(args.len()) as u32));
+  }
+
+  let mut out = String::new();
+  let mut tmp = 0;
+
+  out.push_str("\nint main(void) {\n");
+
+  for (j, (t, x)) in types.tuple_elts(a).zip(args).enumerate() {
+    let z = format!("x{}", j);
+    emit!(out, "  {} {};\n", ctype(types, t), z);
+    let Some(()) = build(&mut out, types, &z, x, t, &mut tmp) else {
+      return Err(Error::ArgumentMismatch(j as u32));
+    };
+    emit!(out, "  lilac_regs[{}].{} = {};\n", j, field(types, t), z);
+  }
+
+  emit!(out, "  lilac_call(f{});\n", k);
+
+  for (j, t) in types.tuple_elts(b).enumerate() {
+    if j != 0 {
+      emit!(out, "  fputs(\", \", stdout);\n");
+    }
+    let r =
+      match types[t] {
+        Type::Array(_) => format!("(({}) lilac_regs[{}].p)", ctype(types, t), j),
+        _ => format!("lilac_regs[{}].{}", j, field(types, t)),
+      };
+    print(&mut out, types, &r, t, 0);
+  }
+
+  emit!(out, "  fputs(\"\\n\", stdout);\n");
+  emit!(out, "  return 0;\n");
+  emit!(out, "}}\n");
+
+  return Ok(out);
+}
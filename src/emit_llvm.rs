@@ -0,0 +1,731 @@
+//! LLVM backend
+//!
+//! monomorphic typed bytecode -> LLVM assembly
+//!
+//! Every `Label` becomes a basic block, and every `Get` becomes a phi node
+//! whose incoming values are the `Put`s before the jumps to the block. A
+//! function with several results returns them as a struct, and a tail call is
+//! a `musttail` call. All functions use the `tailcc` calling convention,
+//! which lets a `musttail` call pass different arguments than its caller.
+//!
+//! Values have the same representation as in the x86-64 backend: an array is
+//! a pointer to its header word followed by its elements, an element of an
+//! `Array[bool]` is a byte, and every other element is a 64-bit word. The host
+//! provides `lilac_trap(kind, pc)`, which is called when division by zero
+//! (kind 0), an out of bounds index (kind 1), an overflowing division (kind 3),
+//! or a shift out of range (kind 4) happens at `pc`, and enters lilac code
+//! through `lilac_invoke_f<k>(args, results)`, which is defined for the first
+//! function with each name.
+//!
+//! The output uses typed pointers, which are understood by LLVM 14 and later
+//! versions up to 16.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::emit_x64;
+use crate::eval_iru::Builtin;
+use crate::eval_iru::Value;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::layout;
+use crate::prim::PrimOp1;
+use crate::prim::PrimOp2;
+use crate::symbol::Symbol;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The argument at the given position does not match the parameter type.
+  ArgumentMismatch(u32),
+  UnboundVariable(Symbol),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::ArgumentMismatch(i) => write!(f, "argument mismatch at position {}", i),
+      Self::UnboundVariable(s) => write!(f, "unbound variable {}", s),
+    }
+  }
+}
+
+// NB: writing to a `String` cannot fail.
+
+macro_rules! emit {
+  ($out:expr, $($arg:tt)*) => {
+    $out.write_fmt(format_args!($($arg)*)).unwrap()
+  };
+}
+
+static PRELUDE: &str = r#"declare void @lilac_trap(i64, i64) noreturn nounwind
+
+define internal tailcc i64 @lilac_len(i64* %a) {
+  %h = load i64, i64* %a
+  %n = and i64 %h, 4294967295
+  ret i64 %n
+}
+
+define internal i64 @lilac_div_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %zero = icmp eq i64 %y, 0
+  br i1 %zero, label %trap, label %nonzero
+trap:
+  call void @lilac_trap(i64 0, i64 %pc)
+  unreachable
+nonzero:
+  %min = icmp eq i64 %x, -9223372036854775808
+  %minus_one = icmp eq i64 %y, -1
+  %overflow = and i1 %min, %minus_one
+  br i1 %overflow, label %trap_overflow, label %div
+trap_overflow:
+  call void @lilac_trap(i64 3, i64 %pc)
+  unreachable
+div:
+  %q = sdiv i64 %x, %y
+  ret i64 %q
+}
+
+define internal i64 @lilac_divsat_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %zero = icmp eq i64 %y, 0
+  br i1 %zero, label %trap, label %nonzero
+trap:
+  call void @lilac_trap(i64 0, i64 %pc)
+  unreachable
+nonzero:
+  %minus_one = icmp eq i64 %y, -1
+  br i1 %minus_one, label %neg, label %div
+neg:
+  %min = icmp eq i64 %x, -9223372036854775808
+  %z = sub i64 0, %x
+  %r = select i1 %min, i64 9223372036854775807, i64 %z
+  ret i64 %r
+div:
+  %q = sdiv i64 %x, %y
+  ret i64 %q
+}
+
+define internal i64 @lilac_divwrap_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %zero = icmp eq i64 %y, 0
+  br i1 %zero, label %trap, label %nonzero
+trap:
+  call void @lilac_trap(i64 0, i64 %pc)
+  unreachable
+nonzero:
+  %minus_one = icmp eq i64 %y, -1
+  br i1 %minus_one, label %neg, label %div
+neg:
+  %z = sub i64 0, %x
+  ret i64 %z
+div:
+  %q = sdiv i64 %x, %y
+  ret i64 %q
+}
+
+define internal i64 @lilac_rem_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %zero = icmp eq i64 %y, 0
+  br i1 %zero, label %trap, label %nonzero
+trap:
+  call void @lilac_trap(i64 0, i64 %pc)
+  unreachable
+nonzero:
+  %minus_one = icmp eq i64 %y, -1
+  br i1 %minus_one, label %zero_rem, label %rem
+zero_rem:
+  ret i64 0
+rem:
+  %r = srem i64 %x, %y
+  ret i64 %r
+}
+
+declare i64 @llvm.sadd.sat.i64(i64, i64)
+declare i64 @llvm.ssub.sat.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+
+define internal i64 @lilac_mulsat_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %p = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %x, i64 %y)
+  %z = extractvalue { i64, i1 } %p, 0
+  %overflow = extractvalue { i64, i1 } %p, 1
+  %sign = xor i64 %x, %y
+  %neg = icmp slt i64 %sign, 0
+  %sat = select i1 %neg, i64 -9223372036854775808, i64 9223372036854775807
+  %r = select i1 %overflow, i64 %sat, i64 %z
+  ret i64 %r
+}
+
+define internal i64 @lilac_shl_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %ok = icmp ult i64 %y, 64
+  br i1 %ok, label %shift, label %trap
+trap:
+  call void @lilac_trap(i64 4, i64 %pc)
+  unreachable
+shift:
+  %z = shl i64 %x, %y
+  ret i64 %z
+}
+
+define internal i64 @lilac_shr_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %ok = icmp ult i64 %y, 64
+  br i1 %ok, label %shift, label %trap
+trap:
+  call void @lilac_trap(i64 4, i64 %pc)
+  unreachable
+shift:
+  %z = ashr i64 %x, %y
+  ret i64 %z
+}
+
+define internal i64 @lilac_shlsat_i64(i64 %x, i64 %y, i64 %pc) alwaysinline {
+  %negative = icmp slt i64 %y, 0
+  br i1 %negative, label %trap, label %shift
+trap:
+  call void @lilac_trap(i64 4, i64 %pc)
+  unreachable
+shift:
+  %large = icmp sgt i64 %y, 63
+  %s = select i1 %large, i64 63, i64 %y
+  %z = shl i64 %x, %s
+  %back = ashr i64 %z, %s
+  %exact = icmp eq i64 %back, %x
+  %neg = icmp slt i64 %x, 0
+  %sat = select i1 %neg, i64 -9223372036854775808, i64 9223372036854775807
+  %r = select i1 %exact, i64 %z, i64 %sat
+  ret i64 %r
+}
+"#;
+
+// The LLVM type of a value of type `t`.
+
+fn lltype(types: &TypeStore, t: TypeId) -> String {
+  match types[t] {
+    Type::Bool => "i1".to_string(),
+    Type::I64 => "i64".to_string(),
+    Type::Array(_) => "i64*".to_string(),
+    Type::Fun(a, b) => {
+      let params: Buf<String> = types.tuple_elts(a).map(|u| lltype(types, u)).collect();
+      format!("{} ({})*", ret_type(types, b), join(&params))
+    }
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => unreachable!(),
+  }
+}
+
+// The LLVM return type for the results `b`.
+
+fn ret_type(types: &TypeStore, b: TypeId) -> String {
+  let results: Buf<String> = types.tuple_elts(b).map(|u| lltype(types, u)).collect();
+  match results.len() {
+    0 => "void".to_string(),
+    1 => results[0].clone(),
+    _ => format!("{{ {} }}", join(&results)),
+  }
+}
+
+fn join(xs: &Buf<String>) -> String {
+  let mut s = String::new();
+  for (i, x) in xs.iter().enumerate() {
+    if i != 0 { s.push_str(", "); }
+    s.push_str(x);
+  }
+  return s;
+}
+
+// The LLVM operand for the value at `x`. Constants are used directly, so that
+// calls to known functions are direct calls.
+
+fn operand(module: &Module, x: u32) -> String {
+  match module.code[x] {
+    Inst::Const(..) => "@lilac_len".to_string(),
+    Inst::ConstFun(k) => format!("@f{}", k),
+    Inst::ConstBool(p) => format!("{}", p),
+    Inst::ConstInt(n) => format!("{}", n),
+    _ => format!("%v{}", x),
+  }
+}
+
+// Emits instructions that convert the 64-bit word `x` to a value of type `t`,
+// and returns the resulting operand.
+
+fn from_word(out: &mut String, types: &TypeStore, name: &str, x: &str, t: TypeId) -> String {
+  match types[t] {
+    Type::I64 => {
+      return x.to_string();
+    }
+    Type::Bool => {
+      emit!(out, "  {} = trunc i64 {} to i1\n", name, x);
+    }
+    _ => {
+      emit!(out, "  {} = inttoptr i64 {} to {}\n", name, x, lltype(types, t));
+    }
+  }
+  return name.to_string();
+}
+
+// Emits instructions that convert the operand `x` of type `t` to a 64-bit
+// word, and returns the resulting operand.
+
+fn to_word(out: &mut String, types: &TypeStore, name: &str, x: &str, t: TypeId) -> String {
+  match types[t] {
+    Type::I64 => {
+      return x.to_string();
+    }
+    Type::Bool => {
+      emit!(out, "  {} = zext i1 {} to i64\n", name, x);
+    }
+    _ => {
+      emit!(out, "  {} = ptrtoint {} {} to i64\n", name, lltype(types, t), x);
+    }
+  }
+  return name.to_string();
+}
+
+fn decl_fun(out: &mut String, module: &Module, k: u32, value_types: &Arr<Option<TypeId>>) -> Result<(), Error> {
+  let code = &module.code;
+  let types = &module.types;
+  let f = &module.decl[k];
+  let ty = |x: u32| value_types[x].unwrap();
+  let Type::Fun(a, b) = types[f.scheme.1] else { unreachable!() };
+
+  // The result operands of the call at `i`.
+
+  let results = |i: u32| -> Buf<String> {
+    let Type::Fun(_, b) = types[ty(match code[i] { Inst::Call(g) => g, _ => unreachable!() })] else { unreachable!() };
+    let n = types.tuple_elts(b).len();
+    if n == 1 { return [format!("%c{}", i)].into_iter().collect(); }
+    return (0 .. n).map(|j| format!("%c{}.{}", i, j)).collect();
+  };
+
+  // The labels that are reachable from the entry. Other labels are not
+  // emitted, because a phi node cannot be empty.
+
+  let mut reachable = Arr::new(f.len, |_| false);
+  let mut stack = Buf::new();
+  reachable[0] = true;
+  stack.push(f.pos);
+
+  while ! stack.is_empty() {
+    let mut i = stack.pop() + 1;
+    while i < f.pos + f.len {
+      match code[i] {
+        Inst::Goto(a) => {
+          if ! reachable[a - f.pos] {
+            reachable[a - f.pos] = true;
+            stack.push(a);
+          }
+        }
+        Inst::Label(_) | Inst::Ret | Inst::TailCall(_) => {
+          break;
+        }
+        _ => {}
+      }
+      i += 1;
+    }
+  }
+
+  // The incoming block and values of every jump to each label. A bounds check
+  // starts a new block.
+
+  let mut incoming: Arr<Buf<(String, Buf<String>)>> = Arr::new(f.len, |_| Buf::new());
+  let mut block = String::from("entry");
+  let mut puts: Buf<String> = Buf::new();
+  let mut live = true;
+
+  incoming[0].push((block.clone(), types.tuple_elts(a).enumerate().map(|(j, _)| format!("%a{}", j)).collect()));
+
+  for i in f.pos .. f.pos + f.len {
+    match code[i] {
+      Inst::Label(_) => {
+        live = reachable[i - f.pos];
+        block = format!("L{}", i);
+      }
+      Inst::Index(..) | Inst::SetIndex(..) => {
+        block = format!("I{}", i);
+      }
+      Inst::Put(_, x) => {
+        puts.push(operand(module, x));
+      }
+      Inst::Goto(a) if live => {
+        let values =
+          match code[i - 1] {
+            Inst::Call(_) => results(i - 1),
+            _ => puts.drain().collect(),
+          };
+        incoming[a - f.pos].push((block.clone(), values));
+      }
+      Inst::Ret | Inst::Call(_) | Inst::TailCall(_) | Inst::Goto(_) => {
+        puts.clear();
+      }
+      _ => {}
+    }
+  }
+
+  let params: Buf<String> =
+    types.tuple_elts(a).enumerate().map(|(j, t)| format!("{} %a{}", lltype(types, t), j)).collect();
+
+  emit!(out, "\n; {}\n", f.name);
+  emit!(out, "define internal tailcc {} @f{}({}) {{\n", ret_type(types, b), k, join(&params));
+  emit!(out, "entry:\n");
+
+  for i in f.pos .. f.pos + f.len {
+    if let Inst::Local(_) = code[i] {
+      emit!(out, "  %v{} = alloca {}\n", i, lltype(types, ty(i)));
+    }
+  }
+
+  emit!(out, "  br label %L{}\n", f.pos);
+
+  let mut puts: Buf<u32> = Buf::new();
+  let mut label = f.pos;
+  let mut live = true;
+  let mut i = f.pos;
+
+  while i < f.pos + f.len {
+    if let Inst::Label(_) = code[i] {
+      live = reachable[i - f.pos];
+      label = i;
+    }
+
+    if ! live {
+      i += 1;
+      continue;
+    }
+
+    match code[i] {
+      Inst::GotoStaticError => {
+        unreachable!()
+      }
+      Inst::Label(_) => {
+        emit!(out, "L{}:\n", i);
+      }
+      Inst::Get(j, t) => {
+        emit!(out, "  %v{} = phi {}", i, lltype(types, t));
+        for (n, (block, values)) in incoming[label - f.pos].iter().enumerate() {
+          emit!(out, "{} [ {}, %{} ]", if n == 0 { "" } else { "," }, values[j], block);
+        }
+        emit!(out, "\n");
+      }
+      Inst::Put(_, x) => {
+        puts.push(x);
+      }
+      Inst::Goto(a) => {
+        puts.clear();
+        emit!(out, "  br label %L{}\n", a);
+      }
+      Inst::Cond(x) => {
+        let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+        let Inst::Goto(b) = code[i + 2] else { unreachable!() };
+        emit!(out, "  br i1 {}, label %L{}, label %L{}\n", operand(module, x), b, a);
+        i += 2;
+      }
+      Inst::Ret => {
+        let results: Buf<u32> = puts.drain().collect();
+        match results.len() {
+          0 => {
+            emit!(out, "  ret void\n");
+          }
+          1 => {
+            emit!(out, "  ret {} {}\n", lltype(types, ty(results[0])), operand(module, results[0]));
+          }
+          n => {
+            let s = ret_type(types, b);
+            let mut r = "undef".to_string();
+            for j in 0 .. n {
+              let x = results[j];
+              emit!(out, "  %r{}.{} = insertvalue {} {}, {} {}, {}\n", i, j, s, r, lltype(types, ty(x)), operand(module, x), j);
+              r = format!("%r{}.{}", i, j);
+            }
+            emit!(out, "  ret {} {}\n", s, r);
+          }
+        }
+      }
+      Inst::Call(g) | Inst::TailCall(g) => {
+        let args: Buf<String> =
+          puts.drain().map(|x| format!("{} {}", lltype(types, ty(x)), operand(module, x))).collect();
+        let Type::Fun(_, c) = types[ty(g)] else { unreachable!() };
+        let s = ret_type(types, c);
+        let call = format!("tailcc {} {}({})", s, operand(module, g), join(&args));
+        let n = types.tuple_elts(c).len();
+
+        if let Inst::TailCall(_) = code[i] {
+          if n == 0 {
+            emit!(out, "  musttail call {}\n", call);
+            emit!(out, "  ret void\n");
+          } else {
+            emit!(out, "  %c{} = musttail call {}\n", i, call);
+            emit!(out, "  ret {} %c{}\n", s, i);
+          }
+        } else {
+          // NB: the call is followed by a `Goto` to its continuation, whose
+          // phi nodes read the results.
+          if n == 0 {
+            emit!(out, "  call {}\n", call);
+          } else {
+            emit!(out, "  %c{} = call {}\n", i, call);
+          }
+          if n >= 2 {
+            for j in 0 .. n {
+              emit!(out, "  %c{}.{} = extractvalue {} %c{}, {}\n", i, j, s, i, j);
+            }
+          }
+        }
+      }
+      Inst::Const(s, _) => {
+        let Some(Builtin::Len) = Builtin::from_symbol(s) else {
+          return Err(Error::UnboundVariable(s));
+        };
+      }
+      Inst::ConstFun(_) | Inst::ConstBool(_) | Inst::ConstInt(_) => {
+      }
+      Inst::Index(x, y) => {
+        let bytes = layout::repr(types, ty(i)).size == 1;
+        let p = check_index(out, module, x, y, i, bytes);
+        if bytes {
+          emit!(out, "  %v{}.b = load i8, i8* {}\n", i, p);
+          emit!(out, "  %v{} = trunc i8 %v{}.b to i1\n", i, i);
+        } else if let Type::I64 = types[ty(i)] {
+          emit!(out, "  %v{} = load i64, i64* {}\n", i, p);
+        } else {
+          emit!(out, "  %v{}.w = load i64, i64* {}\n", i, p);
+          let _ = from_word(out, types, &format!("%v{}", i), &format!("%v{}.w", i), ty(i));
+        }
+      }
+      Inst::PrimOp1(op, x) => {
+        let x = operand(module, x);
+        match op {
+          PrimOp1::DecI64 => emit!(out, "  %v{} = sub i64 {}, 1\n", i, x),
+          PrimOp1::IncI64 => emit!(out, "  %v{} = add i64 {}, 1\n", i, x),
+          PrimOp1::NegI64 => emit!(out, "  %v{} = sub i64 0, {}\n", i, x),
+          PrimOp1::NotBool => emit!(out, "  %v{} = xor i1 {}, true\n", i, x),
+        }
+      }
+      Inst::PrimOp2(op, x, y) => {
+        let x = operand(module, x);
+        let y = operand(module, y);
+        let s =
+          match op {
+            PrimOp2::AddI64 => "add i64",
+            PrimOp2::BitAndI64 => "and i64",
+            PrimOp2::BitOrI64 => "or i64",
+            PrimOp2::BitXorI64 => "xor i64",
+            PrimOp2::CmpEqI64 => "icmp eq i64",
+            PrimOp2::CmpGeI64 => "icmp sge i64",
+            PrimOp2::CmpGtI64 => "icmp sgt i64",
+            PrimOp2::CmpLeI64 => "icmp sle i64",
+            PrimOp2::CmpLtI64 => "icmp slt i64",
+            PrimOp2::CmpNeI64 => "icmp ne i64",
+            PrimOp2::MulI64 => "mul i64",
+            PrimOp2::SubI64 => "sub i64",
+            PrimOp2::AddSatI64 | PrimOp2::SubSatI64 => {
+              let s = if op == PrimOp2::AddSatI64 { "sadd" } else { "ssub" };
+              emit!(out, "  %v{} = call i64 @llvm.{}.sat.i64(i64 {}, i64 {})\n", i, s, x, y);
+              i += 1;
+              continue;
+            }
+            PrimOp2::ShlWrapI64 | PrimOp2::ShrWrapI64 => {
+              // NB: LLVM shifts by 64 or more are poison, so the amount is
+              // masked like in the interpreters.
+              let s = if op == PrimOp2::ShlWrapI64 { "shl" } else { "ashr" };
+              emit!(out, "  %v{}.n = and i64 {}, 63\n", i, y);
+              emit!(out, "  %v{} = {} i64 {}, %v{}.n\n", i, s, x, i);
+              i += 1;
+              continue;
+            }
+            | PrimOp2::DivI64
+            | PrimOp2::DivSatI64
+            | PrimOp2::DivWrapI64
+            | PrimOp2::MulSatI64
+            | PrimOp2::RemI64
+            | PrimOp2::ShlI64
+            | PrimOp2::ShlSatI64
+            | PrimOp2::ShrI64 => {
+              let s = op.as_str().replace('.', "_");
+              emit!(out, "  %v{} = call i64 @lilac_{}(i64 {}, i64 {}, i64 {})\n", i, s, x, y, i);
+              i += 1;
+              continue;
+            }
+          };
+        emit!(out, "  %v{} = {} {}, {}\n", i, s, x, y);
+      }
+      Inst::Local(x) => {
+        emit!(out, "  store {} {}, {}* %v{}\n", lltype(types, ty(i)), operand(module, x), lltype(types, ty(i)), i);
+      }
+      Inst::GetLocal(x) => {
+        let t = lltype(types, ty(i));
+        emit!(out, "  %v{} = load {}, {}* %v{}\n", i, t, t, x);
+      }
+      Inst::SetIndex(x, y, z) => {
+        let bytes = layout::repr(types, ty(z)).size == 1;
+        let p = check_index(out, module, x, y, i, bytes);
+        if bytes {
+          emit!(out, "  %v{}.b = zext i1 {} to i8\n", i, operand(module, z));
+          emit!(out, "  store i8 %v{}.b, i8* {}\n", i, p);
+        } else {
+          let w = to_word(out, types, &format!("%v{}.w", i), &operand(module, z), ty(z));
+          emit!(out, "  store i64 {}, i64* {}\n", w, p);
+        }
+      }
+      Inst::SetLocal(v, x) => {
+        let t = lltype(types, ty(v));
+        emit!(out, "  store {} {}, {}* %v{}\n", t, operand(module, x), t, v);
+      }
+    }
+
+    i += 1;
+  }
+
+  emit!(out, "}}\n");
+  return Ok(());
+}
+
+// Emits the bounds check for indexing the array `x` by `y` at `i`, which
+// branches to a trap or continues in a new block, and returns the address of
+// the element, which is an `i8*` if the elements are bytes.
+
+fn check_index(out: &mut String, module: &Module, x: u32, y: u32, i: u32, bytes: bool) -> String {
+  let x = operand(module, x);
+  let y = operand(module, y);
+  emit!(out, "  %v{}.hdr = load i64, i64* {}\n", i, x);
+  emit!(out, "  %v{}.len = and i64 %v{}.hdr, 4294967295\n", i, i);
+  emit!(out, "  %v{}.ok = icmp ult i64 {}, %v{}.len\n", i, y, i);
+  emit!(out, "  br i1 %v{}.ok, label %I{}, label %T{}\n", i, i, i);
+  emit!(out, "T{}:\n", i);
+  emit!(out, "  call void @lilac_trap(i64 1, i64 {})\n", i);
+  emit!(out, "  unreachable\n");
+  emit!(out, "I{}:\n", i);
+  if bytes {
+    emit!(out, "  %v{}.e = getelementptr i64, i64* {}, i64 1\n", i, x);
+    emit!(out, "  %v{}.e8 = bitcast i64* %v{}.e to i8*\n", i, i);
+    emit!(out, "  %v{}.p = getelementptr i8, i8* %v{}.e8, i64 {}\n", i, i, y);
+    return format!("%v{}.p", i);
+  }
+  emit!(out, "  %v{}.j = add i64 {}, 1\n", i, y);
+  emit!(out, "  %v{}.p = getelementptr i64, i64* {}, i64 %v{}.j\n", i, x, i);
+  return format!("%v{}.p", i);
+}
+
+// Emits the entry point `lilac_invoke_f<k>(args, results)` that the host
+// calls with arrays of 64-bit words.
+
+fn decl_invoke(out: &mut String, module: &Module, k: u32) {
+  let types = &module.types;
+  let Type::Fun(a, b) = types[module.decl[k].scheme.1] else { unreachable!() };
+
+  emit!(out, "\ndefine void @lilac_invoke_f{}(i64* %args, i64* %results) {{\n", k);
+
+  let mut args = Buf::new();
+
+  for (j, t) in types.tuple_elts(a).enumerate() {
+    emit!(out, "  %p{} = getelementptr i64, i64* %args, i64 {}\n", j, j);
+    emit!(out, "  %w{} = load i64, i64* %p{}\n", j, j);
+    let x = from_word(out, types, &format!("%x{}", j), &format!("%w{}", j), t);
+    args.push(format!("{} {}", lltype(types, t), x));
+  }
+
+  let s = ret_type(types, b);
+  let n = types.tuple_elts(b).len();
+  let call = format!("tailcc {} @f{}({})", s, k, join(&args));
+
+  if n == 0 {
+    emit!(out, "  call {}\n", call);
+  } else {
+    emit!(out, "  %r = call {}\n", call);
+  }
+
+  for (j, t) in types.tuple_elts(b).enumerate() {
+    let y =
+      if n == 1 {
+        "%r".to_string()
+      } else {
+        emit!(out, "  %y{} = extractvalue {} %r, {}\n", j, s, j);
+        format!("%y{}", j)
+      };
+    let w = to_word(out, types, &format!("%z{}", j), &y, t);
+    emit!(out, "  %q{} = getelementptr i64, i64* %results, i64 {}\n", j, j);
+    emit!(out, "  store i64 {}, i64* %q{}\n", w, j);
+  }
+
+  emit!(out, "  ret void\n");
+  emit!(out, "}}\n");
+}
+
+/// Translates the monomorphized `module` into an LLVM module in text format.
+
+pub fn emit(module: &Module) -> Result<String, Error> {
+  let mut out = String::new();
+  let value_types = module.value_types();
+
+  out.push_str(PRELUDE);
+
+  for k in 0 .. module.decl.len() {
+    decl_fun(&mut out, module, k, &value_types)?;
+  }
+
+  for k in 0 .. module.decl.len() {
+    let name = module.decl[k].name;
+    if module.decl.iter().position(|g| g.name == name) == Some(k as usize) {
+      decl_invoke(&mut out, module, k);
+    }
+  }
+
+  return Ok(out);
+}
+
+/// Emits a C host program, to be linked with the output of `emit`, that calls
+/// the first function named `name` with the given arguments and prints its
+/// results.
+
+pub fn emit_main(module: &Module, name: Symbol, args: &[Value]) -> Result<String, Error> {
+  let Some(k) = module.decl.iter().position(|f| f.name == name) else {
+    return Err(Error::UnboundVariable(name));
+  };
+
+  let types = &module.types;
+  let Type::Fun(a, b) = types[module.decl[k as u32].scheme.1] else { unreachable!() };
+  let n = types.tuple_elts(a).len();
+  let m = types.tuple_elts(b).len();
+
+  if n != args.len() {
+    return Err(Error::ArgumentMismatch(n.min(args.len()) as u32));
+  }
+
+  let mut out = String::new();
+  let mut tmp = 0;
+
+  emit!(out, "#include <inttypes.h>\n");
+  emit!(out, "#include <stdint.h>\n");
+  emit!(out, "#include <stdio.h>\n");
+  emit!(out, "#include <stdlib.h>\n\n");
+  emit!(out, "void lilac_invoke_f{}(int64_t *args, int64_t *results);\n\n", k);
+  emit!(out, "void lilac_trap(int64_t kind, int64_t pc) {{\n");
+  emit!(out, "  fflush(stdout);\n");
+  emit!(out, "  static const char *what[] = {{\n");
+  emit!(out, "    \"division by zero\", \"index out of bounds\", \"negative length\", \"integer overflow\", \"shift out of range\",\n");
+  emit!(out, "  }};\n");
+  emit!(out, "  fprintf(stderr, \"error: %s at %%%\" PRId64 \"\\n\", what[kind], pc);\n");
+  emit!(out, "  exit(1);\n");
+  emit!(out, "}}\n\n");
+  emit!(out, "int main(void) {{\n");
+  emit!(out, "  int64_t args[{}] = {{ 0 }};\n", n.max(1));
+  emit!(out, "  int64_t results[{}];\n", m.max(1));
+
+  for (j, (t, x)) in types.tuple_elts(a).zip(args).enumerate() {
+    let Some(()) = emit_x64::build(&mut out, types, &format!("args[{}]", j), x, t, &mut tmp) else {
+      return Err(Error::ArgumentMismatch(j as u32));
+    };
+  }
+
+  emit!(out, "  lilac_invoke_f{}(args, results);\n", k);
+
+  for (j, t) in types.tuple_elts(b).enumerate() {
+    if j != 0 {
+      emit!(out, "  fputs(\", \", stdout);\n");
+    }
+    emit_x64::print(&mut out, types, &format!("results[{}]", j), t, 0);
+  }
+
+  emit!(out, "  fputs(\"\\n\", stdout);\n");
+  emit!(out, "  return 0;\n");
+  emit!(out, "}}\n");
+
+  return Ok(out);
+}
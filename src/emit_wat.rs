@@ -0,0 +1,926 @@
+//! WebAssembly backend
+//!
+//! monomorphic typed bytecode -> WebAssembly text format
+//!
+//! An `i64` is an `i64`, and a `bool` is an `i32` that is `0` or `1`. A
+//! function is an `i32` index into the function table, and an array is an
+//! `i32` address in linear memory, holding its 8-byte length followed by its
+//! elements, with the size per element that `layout::repr` gives: one byte for
+//! a `bool`, and eight bytes otherwise.
+//!
+//! Every value is a local. A block argument is the local of the `Get` that
+//! reads it, and the `Put`s that jump to the block push their values and then
+//! set those locals, which gives parallel assignment. Multiple results are
+//! returned as multiple values. A tail call is a `return_call` or a
+//! `return_call_indirect`, from the tail call extension, which the host must
+//! support.
+//!
+//! Control flow is structured with the algorithm from "Beyond Relooper"
+//! (Ramsey, 2022). Every node is placed in its immediate dominator. A node
+//! with several forward predecessors follows a `block` that branches to it
+//! can leave, and a loop header is wrapped in a `loop`. This works for any
+//! reducible control flow graph.
+//!
+//! The host provides `lilac.trap(kind, pc)`, which is called when division by
+//! zero (kind 0), an out of bounds index (kind 1), an overflowing division
+//! (kind 3), or a shift out of range (kind 4) happens at `pc`. The shifts that
+//! wrap are plain WebAssembly shifts, which take the amount modulo 64.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::eval_iru::Builtin;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::layout;
+use crate::prim::PrimOp1;
+use crate::prim::PrimOp2;
+use crate::symbol::Symbol;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  UnboundVariable(Symbol),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::UnboundVariable(s) => write!(f, "unbound variable {}", s),
+    }
+  }
+}
+
+// NB: writing to a `String` cannot fail.
+
+macro_rules! emit {
+  ($out:expr, $($arg:tt)*) => {
+    $out.write_fmt(format_args!($($arg)*)).unwrap()
+  };
+}
+
+macro_rules! line {
+  ($ctx:expr, $($arg:tt)*) => {{
+    let s = format!($($arg)*);
+    $ctx.line(&s)
+  }};
+}
+
+static PRELUDE: &str = r#"  (import "lilac" "trap" (func $lilac_trap (param i32 i32)))
+
+  (memory (export "memory") 1)
+
+  (func $lilac_len (param $a i32) (result i64)
+    local.get $a
+    i64.load)
+
+  (func $lilac_index (param $a i32) (param $i i64) (param $size i32) (param $pc i32) (result i32)
+    local.get $i
+    local.get $a
+    i64.load
+    i64.ge_u
+    if
+      i32.const 1
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $a
+    i32.const 8
+    i32.add
+    local.get $i
+    i32.wrap_i64
+    local.get $size
+    i32.mul
+    i32.add)
+
+  (func $lilac_div_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    local.get $y
+    i64.eqz
+    if
+      i32.const 0
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $x
+    i64.const -9223372036854775808
+    i64.eq
+    local.get $y
+    i64.const -1
+    i64.eq
+    i32.and
+    if
+      i32.const 3
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $x
+    local.get $y
+    i64.div_s)
+
+  (func $lilac_divsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    local.get $y
+    i64.eqz
+    if
+      i32.const 0
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $y
+    i64.const -1
+    i64.eq
+    if
+      i64.const 9223372036854775807
+      i64.const 0
+      local.get $x
+      i64.sub
+      local.get $x
+      i64.const -9223372036854775808
+      i64.eq
+      select
+      return
+    end
+    local.get $x
+    local.get $y
+    i64.div_s)
+
+  (func $lilac_divwrap_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    local.get $y
+    i64.eqz
+    if
+      i32.const 0
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $y
+    i64.const -1
+    i64.eq
+    if
+      i64.const 0
+      local.get $x
+      i64.sub
+      return
+    end
+    local.get $x
+    local.get $y
+    i64.div_s)
+
+  (func $lilac_rem_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    local.get $y
+    i64.eqz
+    if
+      i32.const 0
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $x
+    local.get $y
+    i64.rem_s)
+
+  (func $lilac_addsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    (local $z i64)
+    local.get $x
+    local.get $y
+    i64.add
+    local.set $z
+    local.get $x
+    i64.const 63
+    i64.shr_s
+    i64.const 9223372036854775807
+    i64.xor
+    local.get $z
+    local.get $x
+    local.get $z
+    i64.xor
+    local.get $y
+    local.get $z
+    i64.xor
+    i64.and
+    i64.const 0
+    i64.lt_s
+    select)
+
+  (func $lilac_subsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    (local $z i64)
+    local.get $x
+    local.get $y
+    i64.sub
+    local.set $z
+    local.get $x
+    i64.const 63
+    i64.shr_s
+    i64.const 9223372036854775807
+    i64.xor
+    local.get $z
+    local.get $x
+    local.get $y
+    i64.xor
+    local.get $x
+    local.get $z
+    i64.xor
+    i64.and
+    i64.const 0
+    i64.lt_s
+    select)
+
+  (func $lilac_mulsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    (local $z i64)
+    local.get $x
+    local.get $y
+    i64.mul
+    local.set $z
+    local.get $x
+    i64.const 1
+    i64.add
+    i64.const 1
+    i64.le_u
+    if
+      local.get $x
+      i64.const -1
+      i64.eq
+      local.get $y
+      i64.const -9223372036854775808
+      i64.eq
+      i32.and
+      if
+        i64.const 9223372036854775807
+        return
+      end
+      local.get $z
+      return
+    end
+    local.get $x
+    local.get $y
+    i64.xor
+    i64.const 63
+    i64.shr_s
+    i64.const 9223372036854775807
+    i64.xor
+    local.get $z
+    local.get $z
+    local.get $x
+    i64.div_s
+    local.get $y
+    i64.ne
+    select)
+
+  (func $lilac_shl_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    local.get $y
+    i64.const 64
+    i64.ge_u
+    if
+      i32.const 4
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $x
+    local.get $y
+    i64.shl)
+
+  (func $lilac_shr_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    local.get $y
+    i64.const 64
+    i64.ge_u
+    if
+      i32.const 4
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $x
+    local.get $y
+    i64.shr_s)
+
+  (func $lilac_shlsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+    (local $z i64)
+    local.get $y
+    i64.const 0
+    i64.lt_s
+    if
+      i32.const 4
+      local.get $pc
+      call $lilac_trap
+      unreachable
+    end
+    local.get $y
+    i64.const 63
+    local.get $y
+    i64.const 63
+    i64.lt_s
+    select
+    local.set $y
+    local.get $x
+    local.get $y
+    i64.shl
+    local.set $z
+    local.get $z
+    local.get $x
+    i64.const 63
+    i64.shr_s
+    i64.const 9223372036854775807
+    i64.xor
+    local.get $z
+    local.get $y
+    i64.shr_s
+    local.get $x
+    i64.eq
+    select)
+"#;
+
+fn valtype(types: &TypeStore, t: TypeId) -> &'static str {
+  match types[t] {
+    Type::I64 => "i64",
+    Type::Bool | Type::Array(_) | Type::Fun(..) => "i32",
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => unreachable!(),
+  }
+}
+
+fn functype(types: &TypeStore, t: TypeId) -> String {
+  let Type::Fun(a, b) = types[t] else { unreachable!() };
+  let mut s = String::from("(func");
+  if types.tuple_elts(a).len() != 0 {
+    s.push_str(" (param");
+    for u in types.tuple_elts(a) { s.push(' '); s.push_str(valtype(types, u)); }
+    s.push(')');
+  }
+  if types.tuple_elts(b).len() != 0 {
+    s.push_str(" (result");
+    for u in types.tuple_elts(b) { s.push(' '); s.push_str(valtype(types, u)); }
+    s.push(')');
+  }
+  s.push(')');
+  return s;
+}
+
+// A function that is statically known to be the value of a program point.
+
+#[derive(Clone, Copy)]
+enum Callee {
+  Fun(u32),
+  Len,
+}
+
+// The terminator of a basic block, and the `Put`s before it.
+
+#[derive(Clone, Copy)]
+enum Exit {
+  Goto(u32),
+  Cond(u32, /* true */ u32, /* false */ u32),
+  Ret,
+  Call(u32, u32),
+  TailCall(u32),
+}
+
+struct Node {
+  pos: u32,
+  exit: Exit,
+  puts: (u32, u32),
+}
+
+impl Exit {
+  fn succs(self) -> Buf<u32> {
+    let mut out = Buf::new();
+    match self {
+      Exit::Goto(a) | Exit::Call(_, a) => { out.push(a); }
+      Exit::Cond(_, a, b) => { out.push(a); out.push(b); }
+      Exit::Ret | Exit::TailCall(_) => {}
+    }
+    return out;
+  }
+}
+
+struct Ctx<'a> {
+  module: &'a Module,
+  value_types: &'a Arr<Option<TypeId>>,
+  callees: Arr<Option<Callee>>,
+  pos: u32,
+  // The basic blocks in reverse postorder, and the index of the block at each
+  // label.
+  nodes: Buf<Node>,
+  index: Arr<u32>,
+  idom: Buf<u32>,
+  is_loop: Buf<bool>,
+  is_merge: Buf<bool>,
+  out: String,
+  depth: u32,
+}
+
+impl<'a> Ctx<'a> {
+  fn line(&mut self, s: &str) {
+    for _ in 0 .. self.depth { self.out.push_str("  "); }
+    self.out.push_str(s);
+    self.out.push('\n');
+  }
+
+  fn ty(&self, x: u32) -> TypeId {
+    return self.value_types[x].unwrap();
+  }
+
+  fn node(&self, a: u32) -> u32 {
+    return self.index[a - self.pos];
+  }
+
+  fn get(&mut self, x: u32) {
+    line!(self, "local.get $v{}", x);
+  }
+
+  // Pushes the `Put` values of the node.
+
+  fn puts(&mut self, n: u32) {
+    let (a, b) = self.nodes[n].puts;
+    for i in a .. b {
+      let Inst::Put(_, x) = self.module.code[i] else { unreachable!() };
+      self.get(x);
+    }
+  }
+
+  // Pops `n` values that are passed to the block at `a` into its arguments.
+
+  fn set_args(&mut self, a: u32, n: u32) {
+    let code = &self.module.code;
+    let mut gets = Buf::new();
+    let mut i = a + 1;
+    while let Inst::Get(j, _) = code[i] {
+      gets.push((j, i));
+      i += 1;
+    }
+    for j in (0 .. n).rev() {
+      match gets.iter().find(|&&(k, _)| j == k) {
+        None => line!(self, "drop"),
+        Some(&(_, x)) => line!(self, "local.set $v{}", x),
+      }
+    }
+  }
+
+  fn call(&mut self, f: u32, tail: bool) {
+    let prefix = if tail { "return_" } else { "" };
+    match self.callees[f - self.pos] {
+      Some(Callee::Fun(k)) => {
+        line!(self, "{}call $f{}", prefix, k);
+      }
+      Some(Callee::Len) => {
+        line!(self, "{}call $lilac_len", prefix);
+      }
+      None => {
+        self.get(f);
+        line!(self, "{}call_indirect (type $t{})", prefix, self.ty(f).0);
+      }
+    }
+  }
+
+  fn do_tree(&mut self, n: u32) {
+    // NB: the merge nodes that `n` dominates are placed after it, in reverse
+    // postorder, so that a branch to one of them leaves a `block`.
+
+    let mut merges: Buf<u32> = Buf::new();
+    for k in (n + 1 .. self.nodes.len()).rev() {
+      if self.idom[k] == n && self.is_merge[k] { merges.push(k); }
+    }
+
+    if self.is_loop[n] {
+      line!(self, "loop $L{}", self.nodes[n].pos);
+      self.depth += 1;
+      self.node_within(n, &merges, 0);
+      self.depth -= 1;
+      line!(self, "end");
+    } else {
+      self.node_within(n, &merges, 0);
+    }
+  }
+
+  fn node_within(&mut self, n: u32, merges: &Buf<u32>, k: u32) {
+    if k < merges.len() {
+      let m = merges[k];
+      let a = self.nodes[m].pos;
+      line!(self, "block $B{}", a);
+      self.depth += 1;
+      self.node_within(n, merges, k + 1);
+      self.depth -= 1;
+      line!(self, "end");
+      self.do_tree(m);
+      return;
+    }
+
+    let pos = self.nodes[n].pos;
+    let mut i = pos + 1;
+    while let Inst::Get(..) = self.module.code[i] { i += 1; }
+    let (end, _) = self.nodes[n].puts;
+
+    for i in i .. end {
+      self.inst(i);
+    }
+
+    match self.nodes[n].exit {
+      Exit::Goto(a) => {
+        self.puts(n);
+        let (x, y) = self.nodes[n].puts;
+        self.set_args(a, y - x);
+        self.branch(n, a);
+      }
+      Exit::Cond(x, a, b) => {
+        self.get(x);
+        line!(self, "if");
+        self.depth += 1;
+        self.branch(n, a);
+        self.depth -= 1;
+        line!(self, "else");
+        self.depth += 1;
+        self.branch(n, b);
+        self.depth -= 1;
+        line!(self, "end");
+      }
+      Exit::Ret => {
+        self.puts(n);
+        line!(self, "return");
+      }
+      Exit::Call(f, a) => {
+        self.puts(n);
+        self.call(f, false);
+        let Type::Fun(_, b) = self.module.types[self.ty(f)] else { unreachable!() };
+        let m = self.module.types.tuple_elts(b).len() as u32;
+        self.set_args(a, m);
+        self.branch(n, a);
+      }
+      Exit::TailCall(f) => {
+        self.puts(n);
+        self.call(f, true);
+      }
+    }
+  }
+
+  fn branch(&mut self, n: u32, a: u32) {
+    let m = self.node(a);
+    if m <= n {
+      line!(self, "br $L{}", a);
+    } else if self.is_merge[m] {
+      line!(self, "br $B{}", a);
+    } else {
+      self.do_tree(m);
+    }
+  }
+
+  fn inst(&mut self, i: u32) {
+    match self.module.code[i] {
+      | Inst::GotoStaticError
+      | Inst::Label(..)
+      | Inst::Put(..)
+      | Inst::Goto(..)
+      | Inst::Cond(..)
+      | Inst::Ret
+      | Inst::Call(..)
+      | Inst::TailCall(..) => {
+        unreachable!()
+      }
+      Inst::Get(..) => {
+        return;
+      }
+      Inst::Const(..) => {
+        line!(self, "i32.const {}", self.module.decl.len());
+      }
+      Inst::ConstFun(k) => {
+        line!(self, "i32.const {}", k);
+      }
+      Inst::ConstBool(p) => {
+        line!(self, "i32.const {}", p as u32);
+      }
+      Inst::ConstInt(n) => {
+        line!(self, "i64.const {}", n);
+      }
+      Inst::Index(x, y) => {
+        self.get(x);
+        self.get(y);
+        let size = layout::repr(&self.module.types, self.ty(i)).size;
+        line!(self, "i32.const {}", size);
+        line!(self, "i32.const {}", i);
+        line!(self, "call $lilac_index");
+        if size == 1 {
+          line!(self, "i32.load8_u");
+        } else {
+          line!(self, "{}.load", valtype(&self.module.types, self.ty(i)));
+        }
+      }
+      Inst::PrimOp1(op, x) => {
+        match op {
+          PrimOp1::DecI64 | PrimOp1::IncI64 => {
+            self.get(x);
+            line!(self, "i64.const 1");
+            line!(self, "{}", if op == PrimOp1::DecI64 { "i64.sub" } else { "i64.add" });
+          }
+          PrimOp1::NegI64 => {
+            line!(self, "i64.const 0");
+            self.get(x);
+            line!(self, "i64.sub");
+          }
+          PrimOp1::NotBool => {
+            self.get(x);
+            line!(self, "i32.eqz");
+          }
+        }
+      }
+      Inst::PrimOp2(op, x, y) => {
+        self.get(x);
+        self.get(y);
+        let s =
+          match op {
+            PrimOp2::AddI64 => "i64.add",
+            PrimOp2::BitAndI64 => "i64.and",
+            PrimOp2::BitOrI64 => "i64.or",
+            PrimOp2::BitXorI64 => "i64.xor",
+            PrimOp2::CmpEqI64 => "i64.eq",
+            PrimOp2::CmpGeI64 => "i64.ge_s",
+            PrimOp2::CmpGtI64 => "i64.gt_s",
+            PrimOp2::CmpLeI64 => "i64.le_s",
+            PrimOp2::CmpLtI64 => "i64.lt_s",
+            PrimOp2::CmpNeI64 => "i64.ne",
+            PrimOp2::MulI64 => "i64.mul",
+            PrimOp2::ShlWrapI64 => "i64.shl",
+            PrimOp2::ShrWrapI64 => "i64.shr_s",
+            PrimOp2::SubI64 => "i64.sub",
+            | PrimOp2::AddSatI64
+            | PrimOp2::DivI64
+            | PrimOp2::DivSatI64
+            | PrimOp2::DivWrapI64
+            | PrimOp2::MulSatI64
+            | PrimOp2::RemI64
+            | PrimOp2::ShlI64
+            | PrimOp2::ShlSatI64
+            | PrimOp2::ShrI64
+            | PrimOp2::SubSatI64 => {
+              line!(self, "i32.const {}", i);
+              line!(self, "call $lilac_{}", op.as_str().replace('.', "_"));
+              line!(self, "local.set $v{}", i);
+              return;
+            }
+          };
+        line!(self, "{}", s);
+      }
+      Inst::Local(x) | Inst::GetLocal(x) => {
+        self.get(x);
+      }
+      Inst::SetIndex(x, y, z) => {
+        self.get(x);
+        self.get(y);
+        let size = layout::repr(&self.module.types, self.ty(z)).size;
+        line!(self, "i32.const {}", size);
+        line!(self, "i32.const {}", i);
+        line!(self, "call $lilac_index");
+        self.get(z);
+        if size == 1 {
+          line!(self, "i32.store8");
+        } else {
+          line!(self, "{}.store", valtype(&self.module.types, self.ty(z)));
+        }
+        return;
+      }
+      Inst::SetLocal(v, x) => {
+        self.get(x);
+        line!(self, "local.set $v{}", v);
+        return;
+      }
+    }
+    line!(self, "local.set $v{}", i);
+  }
+}
+
+// Splits the function into basic blocks in reverse postorder, and computes the
+// dominator tree.
+
+fn analyze(ctx: &mut Ctx<'_>, len: u32) {
+  let code = &ctx.module.code;
+  let pos = ctx.pos;
+
+  // The exit of the block at each label.
+
+  let mut exits: Arr<Option<(Exit, (u32, u32))>> = Arr::new(len, |_| None);
+  let mut label = pos;
+  let mut puts = pos;
+
+  for i in pos .. pos + len {
+    let exit =
+      match code[i] {
+        Inst::Label(_) => { label = i; None }
+        Inst::Put(..) => { None }
+        Inst::Goto(a) => Some(Exit::Goto(a)),
+        Inst::Cond(x) => {
+          let Inst::Goto(b) = code[i + 1] else { unreachable!() };
+          let Inst::Goto(a) = code[i + 2] else { unreachable!() };
+          Some(Exit::Cond(x, a, b))
+        }
+        Inst::Ret => Some(Exit::Ret),
+        Inst::Call(f) => {
+          let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+          Some(Exit::Call(f, a))
+        }
+        Inst::TailCall(f) => Some(Exit::TailCall(f)),
+        _ => { puts = i + 1; None }
+      };
+    if let Inst::Label(_) | Inst::Get(..) = code[i] { puts = i + 1; }
+    if let Some(exit) = exit && exits[label - pos].is_none() {
+      exits[label - pos] = Some((exit, (puts, i)));
+    }
+  }
+
+  // Depth-first search for the postorder.
+
+  let mut order = Buf::new();
+  let mut seen = Arr::new(len, |_| false);
+  let mut stack = Buf::new();
+  stack.push((pos, 0));
+  seen[0] = true;
+
+  while ! stack.is_empty() {
+    let (a, k) = *stack.top();
+    let succs = exits[a - pos].unwrap().0.succs();
+    if k < succs.len() {
+      stack.top_mut().1 += 1;
+      let b = succs[k];
+      if ! seen[b - pos] {
+        seen[b - pos] = true;
+        stack.push((b, 0));
+      }
+    } else {
+      order.push(a);
+      let _ = stack.pop();
+    }
+  }
+
+  let n = order.len();
+
+  for k in 0 .. n {
+    let a = order[n - 1 - k];
+    ctx.index[a - pos] = k;
+    let (exit, puts) = exits[a - pos].unwrap();
+    ctx.nodes.push(Node { pos: a, exit, puts });
+  }
+
+  let mut preds: Arr<Buf<u32>> = Arr::new(n, |_| Buf::new());
+
+  for k in 0 .. n {
+    for &b in ctx.nodes[k].exit.succs().iter() {
+      preds[ctx.index[b - pos]].push(k);
+    }
+  }
+
+  // Cooper, Harvey, and Kennedy, "A Simple, Fast Dominance Algorithm".
+
+  for _ in 0 .. n { ctx.idom.push(u32::MAX); }
+  ctx.idom[0] = 0;
+
+  let mut changed = true;
+
+  while changed {
+    changed = false;
+    for k in 1 .. n {
+      let mut d = u32::MAX;
+      for &p in preds[k].iter() {
+        if ctx.idom[p] == u32::MAX { continue; }
+        if d == u32::MAX { d = p; continue; }
+        let mut x = p;
+        while x != d {
+          while x > d { x = ctx.idom[x]; }
+          while d > x { d = ctx.idom[d]; }
+        }
+      }
+      if ctx.idom[k] != d {
+        ctx.idom[k] = d;
+        changed = true;
+      }
+    }
+  }
+
+  for k in 0 .. n {
+    ctx.is_loop.push(preds[k].iter().any(|&p| p >= k));
+    ctx.is_merge.push(preds[k].iter().filter(|&&p| p < k).count() >= 2);
+  }
+}
+
+fn decl_fun(out: &mut String, module: &Module, k: u32, value_types: &Arr<Option<TypeId>>, exported: bool) {
+  let types = &module.types;
+  let f = &module.decl[k];
+
+  let mut callees = Arr::new(f.len, |_| None);
+
+  for i in f.pos .. f.pos + f.len {
+    callees[i - f.pos] =
+      match module.code[i] {
+        Inst::ConstFun(g) => Some(Callee::Fun(g)),
+        Inst::Const(..) => Some(Callee::Len),
+        _ => None,
+      };
+  }
+
+  let mut ctx =
+    Ctx {
+      module,
+      value_types,
+      callees,
+      pos: f.pos,
+      nodes: Buf::new(),
+      index: Arr::new(f.len, |_| u32::MAX),
+      idom: Buf::new(),
+      is_loop: Buf::new(),
+      is_merge: Buf::new(),
+      out: String::new(),
+      depth: 2,
+    };
+
+  analyze(&mut ctx, f.len);
+
+  let Type::Fun(a, b) = types[f.scheme.1] else { unreachable!() };
+
+  emit!(out, "\n  ;; {}\n", f.name);
+  emit!(out, "  (func $f{}", k);
+  if exported {
+    emit!(out, " (export \"{}\")", f.name);
+  }
+  emit!(out, " (type $t{})", f.scheme.1.0);
+  for (j, t) in types.tuple_elts(a).enumerate() {
+    emit!(out, " (param $p{} {})", j, valtype(types, t));
+  }
+  if types.tuple_elts(b).len() != 0 {
+    emit!(out, " (result");
+    for t in types.tuple_elts(b) {
+      emit!(out, " {}", valtype(types, t));
+    }
+    emit!(out, ")");
+  }
+  emit!(out, "\n");
+
+  for i in f.pos .. f.pos + f.len {
+    if let Some(t) = value_types[i] {
+      emit!(out, "    (local $v{} {})\n", i, valtype(types, t));
+    }
+  }
+
+  for j in 0 .. types.tuple_elts(a).len() as u32 {
+    line!(ctx, "local.get $p{}", j);
+  }
+  ctx.set_args(f.pos, types.tuple_elts(a).len() as u32);
+  ctx.do_tree(0);
+
+  // NB: every path has already returned, but the validator does not know
+  // that control cannot fall off the end.
+
+  line!(ctx, "unreachable)");
+
+  out.push_str(&ctx.out);
+}
+
+/// Translates the monomorphized `module` into a WebAssembly module in text
+/// format. The first function with each name is exported under that name.
+
+pub fn emit(module: &Module) -> Result<String, Error> {
+  let types = &module.types;
+  let value_types = module.value_types();
+  let mut out = String::new();
+
+  for i in module.code.iter() {
+    if let Inst::Const(s, _) = *i && Builtin::from_symbol(s) != Some(Builtin::Len) {
+      return Err(Error::UnboundVariable(s));
+    }
+  }
+
+  emit!(out, "(module\n");
+
+  // NB: `call_indirect` needs a type for every function type in the module.
+
+  let mut fun_types = Buf::new();
+
+  for t in value_types.iter().flatten().copied().chain(module.decl.iter().map(|f| f.scheme.1)) {
+    if let Type::Fun(..) = types[t] && ! fun_types.iter().any(|&u| u == t) {
+      fun_types.push(t);
+    }
+  }
+
+  for &t in fun_types.iter() {
+    emit!(out, "  (type $t{} {})\n", t.0, functype(types, t));
+  }
+
+  emit!(out, "\n");
+  out.push_str(PRELUDE);
+
+  emit!(out, "\n  (table {} funcref)\n", module.decl.len() + 1);
+  emit!(out, "  (elem (i32.const 0) func");
+  for k in 0 .. module.decl.len() {
+    emit!(out, " $f{}", k);
+  }
+  emit!(out, " $lilac_len)\n");
+
+  for k in 0 .. module.decl.len() {
+    let name = module.decl[k].name;
+    let exported = module.decl.iter().position(|g| g.name == name) == Some(k as usize);
+    decl_fun(&mut out, module, k, &value_types, exported);
+  }
+
+  emit!(out, ")\n");
+
+  return Ok(out);
+}
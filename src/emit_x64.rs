@@ -0,0 +1,1131 @@
+//! x86-64 backend
+//!
+//! monomorphic typed bytecode -> GNU assembler source
+//!
+//! Every value is a 64-bit word: a `bool` is `0` or `1`, a function is the
+//! address of its code, and an array is a pointer to its header word followed
+//! by its elements, which are laid out as `layout::repr` says: an element of an
+//! `Array[bool]` is a byte.
+//!
+//! Registers are assigned by linear scan over the program points of each
+//! function. A block argument lives wherever the `Get` that reads it was
+//! allocated, and the `Put`s that jump to the block move their values there.
+//! Values that are live across a call are kept in the stack frame, because
+//! every register is caller-saved.
+//!
+//! The calling convention passes up to `ARG_REGS.len()` arguments in
+//! registers, and returns the same number of results in the same registers,
+//! so a tail call is a jump after the frame is torn down.
+//!
+//! The header word of an array holds its length in the low half and its
+//! number of references in the high half, like in the interpreters' heap. The
+//! header of an array of bytes has its top bit set instead.
+//!
+//! Every value that is live across a call is in the stack frame, so the stack
+//! map of each call site lists the frame offsets that hold references. The
+//! slots of a frame that can hold references are zeroed in the prologue and
+//! listed at every call site of the function, so that a slot whose value is
+//! dead still holds `0` or a valid reference.
+//!
+//! The host provides `lilac_trap(kind, pc)`, which is called when division by
+//! zero (kind 0), an out of bounds index (kind 1), a negative length (kind 2),
+//! an overflowing division (kind 3), a shift out of range (kind 4), or running
+//! out of memory (kind 5) happens at `pc`, and enters lilac code through
+//! `lilac_invoke(f, args, results)`.
+//! The host also provides `lilac_new_array(n, x, refs, rbp, ret, pc)`, which
+//! is passed the frame pointer and return address of the lilac caller so that
+//! it can walk the stack with the maps in `lilac_stack_maps`.
+//! The runtime in `RUNTIME` implements both.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::eval_iru::Builtin;
+use crate::eval_iru::Value;
+use crate::heap;
+use crate::irp::Inst;
+use crate::layout;
+use crate::layout::Class;
+use crate::irp::Module;
+use crate::prim::PrimOp1;
+use crate::prim::PrimOp2;
+use crate::symbol::Symbol;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The argument at the given position does not match the parameter type.
+  ArgumentMismatch(u32),
+  UnboundVariable(Symbol),
+  /// A call or return at the given program point has more values than fit in
+  /// registers.
+  Unsupported(u32),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::ArgumentMismatch(i) => write!(f, "argument mismatch at position {}", i),
+      Self::UnboundVariable(s) => write!(f, "unbound variable {}", s),
+      Self::Unsupported(i) => write!(f, "too many values at %{}", i),
+    }
+  }
+}
+
+// NB: the header of an array of bytes has this bit set, and no references.
+
+const BYTES: u64 = 1 << 63;
+
+// NB: writing to a `String` cannot fail.
+
+macro_rules! emit {
+  ($out:expr, $($arg:tt)*) => {
+    $out.write_fmt(format_args!($($arg)*)).unwrap()
+  };
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct Reg(u8);
+
+const RAX: Reg = Reg(0);
+const RBX: Reg = Reg(3);
+const RSI: Reg = Reg(4);
+const RDI: Reg = Reg(5);
+const R8: Reg = Reg(6);
+const R9: Reg = Reg(7);
+const R10: Reg = Reg(8);
+const R11: Reg = Reg(9);
+const R12: Reg = Reg(10);
+const R13: Reg = Reg(11);
+const R14: Reg = Reg(12);
+const R15: Reg = Reg(13);
+
+static REG_NAMES: [&str; 14] = [
+  "%rax", "%rcx", "%rdx", "%rbx", "%rsi", "%rdi", "%r8",
+  "%r9", "%r10", "%r11", "%r12", "%r13", "%r14", "%r15",
+];
+
+// NB: %rax, %rcx, and %rdx are never allocated, because they are needed as
+// scratch registers for division and shifts.
+
+static POOL: [Reg; 11] = [RBX, RSI, RDI, R8, R9, R10, R11, R12, R13, R14, R15];
+
+static ARG_REGS: [Reg; 8] = [RDI, RSI, R8, R9, R10, R11, R12, R13];
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Loc {
+  Reg(Reg),
+  Stack(u32),
+}
+
+impl Loc {
+  fn is_mem(self) -> bool {
+    return matches!(self, Loc::Stack(_));
+  }
+}
+
+impl std::fmt::Display for Loc {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Loc::Reg(r) => write!(f, "{}", REG_NAMES[r.0 as usize]),
+      Loc::Stack(i) => write!(f, "-{}(%rbp)", 8 * (i + 1)),
+    }
+  }
+}
+
+struct Interval {
+  value: u32,
+  start: u32,
+  end: u32,
+}
+
+// The location of every value of a function, indexed relative to its start,
+// and the number of stack slots.
+
+struct Alloc {
+  locs: Arr<Option<Loc>>,
+  slots: u32,
+}
+
+fn allocate(module: &Module, pos: u32, len: u32, value_types: &Arr<Option<TypeId>>) -> Alloc {
+  let code = &module.code;
+  let r = |x: u32| x - pos;
+
+  let mut start = Arr::new(len, |i| i + pos);
+  let mut end = Arr::new(len, |i| i + pos);
+  let mut site = Arr::new(len, |i| i + pos);
+  let mut calls = Buf::new();
+  let mut back_edges = Buf::new();
+  let mut puts = Buf::new();
+
+  let use_at = |end: &mut Arr<u32>, x: u32, i: u32| {
+    end[r(x)] = end[r(x)].max(i);
+  };
+
+  for i in pos .. pos + len {
+    match code[i] {
+      Inst::Put(_, x) => {
+        puts.push(x);
+      }
+      Inst::Goto(a) => {
+        for &x in puts.iter() { use_at(&mut end, x, i); }
+        puts.clear();
+        site[r(a)] = site[r(a)].min(i);
+        if a <= i { back_edges.push((a, i)); }
+      }
+      Inst::Ret => {
+        for &x in puts.iter() { use_at(&mut end, x, i); }
+        puts.clear();
+      }
+      Inst::Call(f) | Inst::TailCall(f) => {
+        for &x in puts.iter() { use_at(&mut end, x, i); }
+        puts.clear();
+        use_at(&mut end, f, i);
+        calls.push(i);
+      }
+      Inst::Cond(x) | Inst::Local(x) | Inst::GetLocal(x) => {
+        use_at(&mut end, x, i);
+      }
+      Inst::Index(x, y) | Inst::PrimOp2(_, x, y) | Inst::SetLocal(x, y) => {
+        use_at(&mut end, x, i);
+        use_at(&mut end, y, i);
+      }
+      Inst::PrimOp1(_, x) => {
+        use_at(&mut end, x, i);
+      }
+      Inst::SetIndex(x, y, z) => {
+        use_at(&mut end, x, i);
+        use_at(&mut end, y, i);
+        use_at(&mut end, z, i);
+      }
+      _ => {}
+    }
+  }
+
+  // A block argument must be reserved from the first jump to its block.
+
+  let mut label = pos;
+
+  for i in pos .. pos + len {
+    match code[i] {
+      Inst::Label(_) => { label = i; }
+      Inst::Get(..) => { start[r(i)] = site[r(label)]; }
+      _ => {}
+    }
+  }
+
+  let mut intervals = Buf::new();
+
+  for i in pos .. pos + len {
+    if value_types[i].is_some() {
+      intervals.push(Interval { value: i, start: start[r(i)], end: end[r(i)] });
+    }
+  }
+
+  // A value that is live at the head of a loop must stay live until the jump
+  // back to the head.
+
+  let mut changed = true;
+
+  while changed {
+    changed = false;
+    for &(a, i) in back_edges.iter() {
+      for k in 0 .. intervals.len() {
+        let v = &mut intervals[k];
+        if v.start < a && a <= v.end && v.end < i {
+          v.end = i;
+          changed = true;
+        }
+      }
+    }
+  }
+
+  let mut locs = Arr::new(len, |_| None);
+  let mut slots = 0;
+  let mut spill = |locs: &mut Arr<Option<Loc>>, x: u32| {
+    locs[r(x)] = Some(Loc::Stack(slots));
+    slots += 1;
+  };
+
+  // NB: a block argument starts before its `Get`, so the intervals are not
+  // already in order.
+
+  let mut order: Box<[u32]> = (0 .. intervals.len()).collect();
+  order.sort_by_key(|&k| intervals[k].start);
+
+  let mut active: Buf<u32> = Buf::new();
+  let mut free: Buf<Reg> = POOL.iter().rev().copied().collect();
+
+  for &k in order.iter() {
+    let v = &intervals[k];
+
+    if calls.iter().any(|&c| v.start < c && c < v.end) {
+      spill(&mut locs, v.value);
+      continue;
+    }
+
+    let mut j = 0;
+    while j < active.len() {
+      let w = &intervals[active[j]];
+      if w.end <= v.start {
+        let Some(Loc::Reg(x)) = locs[r(w.value)] else { unreachable!() };
+        free.push(x);
+        active[j] = active[active.len() - 1];
+        let _ = active.pop();
+      } else {
+        j += 1;
+      }
+    }
+
+    if ! free.is_empty() {
+      locs[r(v.value)] = Some(Loc::Reg(free.pop()));
+      active.push(k);
+      continue;
+    }
+
+    // Spill whichever interval ends last.
+
+    let (j, &w) = active.iter().enumerate().max_by_key(|&(_, &w)| intervals[w].end).unwrap();
+    let w_value = intervals[w].value;
+
+    if intervals[w].end > v.end {
+      locs[r(v.value)] = locs[r(w_value)];
+      spill(&mut locs, w_value);
+      active[j as u32] = k;
+    } else {
+      spill(&mut locs, v.value);
+    }
+  }
+
+  return Alloc { locs, slots };
+}
+
+struct Ctx {
+  out: String,
+  pos: u32,
+  locs: Arr<Option<Loc>>,
+  traps: Buf<(u32, u32)>,
+}
+
+impl Ctx {
+  fn loc(&self, x: u32) -> Loc {
+    return self.locs[x - self.pos].unwrap();
+  }
+
+  fn mov(&mut self, a: Loc, b: Loc) {
+    if a == b { return; }
+    if a.is_mem() && b.is_mem() {
+      emit!(self.out, "  movq {}, %rax\n", a);
+      emit!(self.out, "  movq %rax, {}\n", b);
+    } else {
+      emit!(self.out, "  movq {}, {}\n", a, b);
+    }
+  }
+
+  // Performs all of the moves simultaneously.
+
+  fn parallel_move(&mut self, moves: &[(Loc, Loc)]) {
+    let moves: Buf<(Loc, Loc)> = moves.iter().copied().filter(|&(a, b)| a != b).collect();
+    let overlap = moves.iter().any(|&(_, b)| moves.iter().any(|&(a, _)| a == b));
+
+    if ! overlap {
+      for &(a, b) in moves.iter() {
+        self.mov(a, b);
+      }
+    } else {
+      for &(a, _) in moves.iter() {
+        emit!(self.out, "  pushq {}\n", a);
+      }
+      for &(_, b) in moves.iter().collect::<Box<[_]>>().iter().rev() {
+        emit!(self.out, "  popq {}\n", b);
+      }
+    }
+  }
+
+  fn trap(&mut self, kind: u32, i: u32) -> String {
+    self.traps.push((kind, i));
+    return format!(".Ltrap{}_{}", kind, i);
+  }
+
+  // Replaces %rax with `i64::MIN` if it is negative, and with `i64::MAX`
+  // otherwise.
+
+  fn saturate(&mut self) {
+    emit!(self.out, "  sarq $63, %rax\n");
+    emit!(self.out, "  movabsq ${}, %rcx\n", i64::MAX);
+    emit!(self.out, "  xorq %rcx, %rax\n");
+  }
+
+  fn prim_op1(&mut self, op: PrimOp1, x: u32, i: u32) {
+    emit!(self.out, "  movq {}, %rax\n", self.loc(x));
+    match op {
+      PrimOp1::DecI64 => emit!(self.out, "  subq $1, %rax\n"),
+      PrimOp1::IncI64 => emit!(self.out, "  addq $1, %rax\n"),
+      PrimOp1::NegI64 => emit!(self.out, "  negq %rax\n"),
+      PrimOp1::NotBool => emit!(self.out, "  xorq $1, %rax\n"),
+    }
+    emit!(self.out, "  movq %rax, {}\n", self.loc(i));
+  }
+
+  fn prim_op2(&mut self, op: PrimOp2, x: u32, y: u32, i: u32) {
+    let (x, y, z) = (self.loc(x), self.loc(y), self.loc(i));
+
+    let simple = |s| (s, "");
+    let compare = |s| ("cmpq", s);
+
+    let (inst, cc) =
+      match op {
+        PrimOp2::AddI64 => simple("addq"),
+        PrimOp2::BitAndI64 => simple("andq"),
+        PrimOp2::BitOrI64 => simple("orq"),
+        PrimOp2::BitXorI64 => simple("xorq"),
+        PrimOp2::MulI64 => simple("imulq"),
+        PrimOp2::SubI64 => simple("subq"),
+        PrimOp2::CmpEqI64 => compare("e"),
+        PrimOp2::CmpGeI64 => compare("ge"),
+        PrimOp2::CmpGtI64 => compare("g"),
+        PrimOp2::CmpLeI64 => compare("le"),
+        PrimOp2::CmpLtI64 => compare("l"),
+        PrimOp2::CmpNeI64 => compare("ne"),
+        PrimOp2::AddSatI64 | PrimOp2::MulSatI64 | PrimOp2::SubSatI64 => {
+          let inst =
+            match op {
+              PrimOp2::AddSatI64 => "addq",
+              PrimOp2::MulSatI64 => "imulq",
+              _ => "subq",
+            };
+          emit!(self.out, "  movq {}, %rax\n", x);
+          emit!(self.out, "  {} {}, %rax\n", inst, y);
+          emit!(self.out, "  jno .L{}_done\n", i);
+          // NB: the exact result has the sign of `x`, or for a product, of
+          // `x ^ y`.
+          emit!(self.out, "  movq {}, %rax\n", x);
+          if op == PrimOp2::MulSatI64 {
+            emit!(self.out, "  xorq {}, %rax\n", y);
+          }
+          self.saturate();
+          emit!(self.out, ".L{}_done:\n", i);
+          emit!(self.out, "  movq %rax, {}\n", z);
+          return;
+        }
+        PrimOp2::DivI64 | PrimOp2::DivSatI64 | PrimOp2::DivWrapI64 | PrimOp2::RemI64 => {
+          let trap = self.trap(0, i);
+          emit!(self.out, "  movq {}, %rcx\n", y);
+          emit!(self.out, "  testq %rcx, %rcx\n");
+          emit!(self.out, "  jz {}\n", trap);
+          emit!(self.out, "  movq {}, %rax\n", x);
+          emit!(self.out, "  cmpq $-1, %rcx\n");
+          emit!(self.out, "  jne .L{}_div\n", i);
+          // NB: dividing the minimum value by -1 would fault, and negating it
+          // overflows instead.
+          match op {
+            PrimOp2::DivI64 => {
+              let trap = self.trap(3, i);
+              emit!(self.out, "  negq %rax\n");
+              emit!(self.out, "  jo {}\n", trap);
+            }
+            PrimOp2::DivSatI64 => {
+              emit!(self.out, "  negq %rax\n");
+              emit!(self.out, "  jno .L{}_done\n", i);
+              emit!(self.out, "  notq %rax\n");
+            }
+            PrimOp2::DivWrapI64 => {
+              emit!(self.out, "  negq %rax\n");
+            }
+            _ => {
+              emit!(self.out, "  xorl %eax, %eax\n");
+            }
+          }
+          emit!(self.out, "  jmp .L{}_done\n", i);
+          emit!(self.out, ".L{}_div:\n", i);
+          emit!(self.out, "  cqto\n");
+          emit!(self.out, "  idivq %rcx\n");
+          if op == PrimOp2::RemI64 {
+            emit!(self.out, "  movq %rdx, %rax\n");
+          }
+          emit!(self.out, ".L{}_done:\n", i);
+          emit!(self.out, "  movq %rax, {}\n", z);
+          return;
+        }
+        PrimOp2::ShlI64 | PrimOp2::ShlWrapI64 | PrimOp2::ShrI64 | PrimOp2::ShrWrapI64 => {
+          let inst = if matches!(op, PrimOp2::ShlI64 | PrimOp2::ShlWrapI64) { "shlq" } else { "sarq" };
+          emit!(self.out, "  movq {}, %rcx\n", y);
+          // NB: the hardware takes the amount modulo 64, which is what the
+          // wrapping shifts do.
+          if matches!(op, PrimOp2::ShlI64 | PrimOp2::ShrI64) {
+            let trap = self.trap(4, i);
+            emit!(self.out, "  cmpq $63, %rcx\n");
+            emit!(self.out, "  ja {}\n", trap);
+          }
+          emit!(self.out, "  movq {}, %rax\n", x);
+          emit!(self.out, "  {} %cl, %rax\n", inst);
+          emit!(self.out, "  movq %rax, {}\n", z);
+          return;
+        }
+        PrimOp2::ShlSatI64 => {
+          // NB: shifting by 63 saturates like shifting by more, so the amount
+          // is clamped to it, and the result saturates if shifting it back
+          // does not give `x`.
+          let trap = self.trap(4, i);
+          emit!(self.out, "  movq {}, %rcx\n", y);
+          emit!(self.out, "  testq %rcx, %rcx\n");
+          emit!(self.out, "  js {}\n", trap);
+          emit!(self.out, "  movl $63, %eax\n");
+          emit!(self.out, "  cmpq %rax, %rcx\n");
+          emit!(self.out, "  cmovaq %rax, %rcx\n");
+          emit!(self.out, "  movq {}, %rax\n", x);
+          emit!(self.out, "  shlq %cl, %rax\n");
+          emit!(self.out, "  movq %rax, %rdx\n");
+          emit!(self.out, "  sarq %cl, %rdx\n");
+          emit!(self.out, "  cmpq {}, %rdx\n", x);
+          emit!(self.out, "  je .L{}_done\n", i);
+          emit!(self.out, "  movq {}, %rax\n", x);
+          self.saturate();
+          emit!(self.out, ".L{}_done:\n", i);
+          emit!(self.out, "  movq %rax, {}\n", z);
+          return;
+        }
+      };
+
+    emit!(self.out, "  movq {}, %rax\n", x);
+    emit!(self.out, "  {} {}, %rax\n", inst, y);
+    if cc != "" {
+      emit!(self.out, "  set{} %al\n", cc);
+      emit!(self.out, "  movzbq %al, %rax\n");
+    }
+    emit!(self.out, "  movq %rax, {}\n", z);
+  }
+
+  // Loads the array `x` into %rax and the index `y` into %rcx, and checks the
+  // bounds.
+
+  fn index(&mut self, x: u32, y: u32, i: u32) {
+    let trap = self.trap(1, i);
+    emit!(self.out, "  movq {}, %rax\n", self.loc(x));
+    emit!(self.out, "  movq {}, %rcx\n", self.loc(y));
+    emit!(self.out, "  movl (%rax), %edx\n");
+    emit!(self.out, "  cmpq %rdx, %rcx\n");
+    emit!(self.out, "  jae {}\n", trap);
+  }
+}
+
+// The size of the elements of the array `x`.
+
+fn elt_size(module: &Module, value_types: &Arr<Option<TypeId>>, x: u32) -> u32 {
+  let Type::Array(u) = module.types[value_types[x].unwrap()] else { unreachable!() };
+  return layout::repr(&module.types, u).size;
+}
+
+// Whether values of type `t` are references into the heap.
+
+fn is_ref(module: &Module, t: TypeId) -> bool {
+  return matches!(module.types[t], Type::Array(_));
+}
+
+// Passes the program point of a call in %rdx, unless the callee is known not
+// to be a builtin. Builtins use it to report traps.
+
+fn pass_pc(out: &mut String, module: &Module, g: u32, i: u32) {
+  if let Inst::ConstFun(_) = module.code[g] { return; }
+  emit!(out, "  movl ${}, %edx\n", i);
+}
+
+fn decl_fun(out: &mut String, maps: &mut String, module: &Module, k: u32, value_types: &Arr<Option<TypeId>>) -> Result<(), Error> {
+  let code = &module.code;
+  let f = &module.decl[k];
+  let alloc = allocate(module, f.pos, f.len, value_types);
+
+  let mut ctx =
+    Ctx {
+      out: String::new(),
+      pos: f.pos,
+      locs: alloc.locs,
+      traps: Buf::new(),
+    };
+
+  // The `Get`s of every label, by label.
+
+  let mut gets: Buf<(u32, u32, u32)> = Buf::new();
+  let mut label = f.pos;
+
+  for i in f.pos .. f.pos + f.len {
+    match code[i] {
+      Inst::Label(_) => { label = i; }
+      Inst::Get(j, _) => { gets.push((label, j, i)); }
+      _ => {}
+    }
+  }
+
+  let get_moves = |ctx: &Ctx, a: u32, srcs: &dyn Fn(u32) -> Loc| -> Buf<(Loc, Loc)> {
+    gets.iter().filter(|&&(b, _, _)| a == b).map(|&(_, j, x)| (srcs(j), ctx.loc(x))).collect()
+  };
+
+  let frame = (alloc.slots * 8 + 15) / 16 * 16;
+
+  // The frame slots that hold references, as offsets from %rbp.
+
+  let mut refs: Buf<i64> = Buf::new();
+
+  for i in f.pos .. f.pos + f.len {
+    if let Some(Loc::Stack(j)) = ctx.locs[i - f.pos] && is_ref(module, value_types[i].unwrap()) {
+      refs.push(- 8 * (j as i64 + 1));
+    }
+  }
+
+  emit!(ctx.out, "\n# {}\n", f.name);
+  emit!(ctx.out, "  .globl lilac_f{}\n", k);
+  emit!(ctx.out, "lilac_f{}:\n", k);
+  emit!(ctx.out, "  pushq %rbp\n");
+  emit!(ctx.out, "  movq %rsp, %rbp\n");
+  if frame != 0 {
+    emit!(ctx.out, "  subq ${}, %rsp\n", frame);
+  }
+  for &r in refs.iter() {
+    emit!(ctx.out, "  movq $0, {}(%rbp)\n", r);
+  }
+
+  let moves = get_moves(&ctx, f.pos, &|j| Loc::Reg(ARG_REGS[j as usize]));
+  ctx.parallel_move(&moves.iter().copied().collect::<Box<[_]>>());
+
+  let mut puts: Buf<u32> = Buf::new();
+  let mut i = f.pos;
+
+  // Moves the pending outgoing values, and the callee, into registers.
+
+  let call_moves = |ctx: &Ctx, puts: &mut Buf<u32>, g: Option<u32>, i: u32| -> Result<Box<[(Loc, Loc)]>, Error> {
+    if puts.len() as usize > ARG_REGS.len() { return Err(Error::Unsupported(i)); }
+    let mut moves: Buf<(Loc, Loc)> = puts.iter().enumerate().map(|(j, &x)| (ctx.loc(x), Loc::Reg(ARG_REGS[j]))).collect();
+    if let Some(g) = g { moves.push((ctx.loc(g), Loc::Reg(RAX))); }
+    puts.clear();
+    return Ok(moves.iter().copied().collect());
+  };
+
+  while i < f.pos + f.len {
+    match code[i] {
+      Inst::GotoStaticError => {
+        unreachable!()
+      }
+      Inst::Label(_) => {
+        if i != f.pos { emit!(ctx.out, ".L{}:\n", i); }
+      }
+      Inst::Get(..) => {
+      }
+      Inst::Put(_, x) => {
+        puts.push(x);
+      }
+      Inst::Goto(a) => {
+        let moves: Buf<(Loc, Loc)> = get_moves(&ctx, a, &|j| ctx.loc(puts[j]));
+        puts.clear();
+        ctx.parallel_move(&moves.iter().copied().collect::<Box<[_]>>());
+        if a != i + 1 { emit!(ctx.out, "  jmp .L{}\n", a); }
+      }
+      Inst::Cond(x) => {
+        let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+        let Inst::Goto(b) = code[i + 2] else { unreachable!() };
+        emit!(ctx.out, "  cmpq $0, {}\n", ctx.loc(x));
+        emit!(ctx.out, "  jne .L{}\n", b);
+        if a != i + 3 { emit!(ctx.out, "  jmp .L{}\n", a); }
+        i += 2;
+      }
+      Inst::Ret => {
+        let moves = call_moves(&ctx, &mut puts, None, i)?;
+        ctx.parallel_move(&moves);
+        emit!(ctx.out, "  leave\n");
+        emit!(ctx.out, "  ret\n");
+      }
+      Inst::Call(g) => {
+        // NB: the call is followed by a `Goto` to its continuation.
+        let moves = call_moves(&ctx, &mut puts, Some(g), i)?;
+        ctx.parallel_move(&moves);
+        pass_pc(&mut ctx.out, module, g, i);
+        emit!(ctx.out, "  call *%rax\n");
+        emit!(ctx.out, ".Lret{}:\n", i);
+        emit!(maps, "  .quad .Lret{}, {}, {}", i, i, refs.len());
+        for &r in refs.iter() { emit!(maps, ", {}", r); }
+        emit!(maps, "\n");
+        let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+        let moves = get_moves(&ctx, a, &|j| Loc::Reg(ARG_REGS[j as usize]));
+        if moves.len() as usize > ARG_REGS.len() { return Err(Error::Unsupported(i)); }
+        ctx.parallel_move(&moves.iter().copied().collect::<Box<[_]>>());
+        if a != i + 2 { emit!(ctx.out, "  jmp .L{}\n", a); }
+        i += 1;
+      }
+      Inst::TailCall(g) => {
+        let moves = call_moves(&ctx, &mut puts, Some(g), i)?;
+        ctx.parallel_move(&moves);
+        pass_pc(&mut ctx.out, module, g, i);
+        emit!(ctx.out, "  leave\n");
+        emit!(ctx.out, "  jmp *%rax\n");
+      }
+      Inst::Const(s, t) => {
+        let name =
+          match Builtin::from_symbol(s) {
+            None => return Err(Error::UnboundVariable(s)),
+            Some(Builtin::Len) => "lilac_len",
+            Some(Builtin::Array) => {
+              let Type::Fun(_, b) = module.types[t] else { unreachable!() };
+              let Some(Type::Array(u)) = module.types.tuple_elts(b).next().map(|a| module.types[a]) else { unreachable!() };
+              match layout::repr(&module.types, u).class {
+                Class::Ref => "lilac_array_refs",
+                Class::Bool => "lilac_array_bytes",
+                _ => "lilac_array",
+              }
+            }
+          };
+        emit!(ctx.out, "  leaq {}(%rip), %rax\n", name);
+        emit!(ctx.out, "  movq %rax, {}\n", ctx.loc(i));
+      }
+      Inst::ConstFun(g) => {
+        emit!(ctx.out, "  leaq lilac_f{}(%rip), %rax\n", g);
+        emit!(ctx.out, "  movq %rax, {}\n", ctx.loc(i));
+      }
+      Inst::ConstBool(p) => {
+        emit!(ctx.out, "  movq ${}, {}\n", p as u32, ctx.loc(i));
+      }
+      Inst::ConstInt(n) => {
+        if i32::try_from(n).is_ok() {
+          emit!(ctx.out, "  movq ${}, {}\n", n, ctx.loc(i));
+        } else {
+          emit!(ctx.out, "  movabsq ${}, %rax\n", n);
+          emit!(ctx.out, "  movq %rax, {}\n", ctx.loc(i));
+        }
+      }
+      Inst::Index(x, y) => {
+        ctx.index(x, y, i);
+        if elt_size(module, value_types, x) == 1 {
+          emit!(ctx.out, "  movzbl 8(%rax,%rcx,1), %eax\n");
+        } else {
+          emit!(ctx.out, "  movq 8(%rax,%rcx,8), %rax\n");
+        }
+        emit!(ctx.out, "  movq %rax, {}\n", ctx.loc(i));
+      }
+      Inst::PrimOp1(op, x) => {
+        ctx.prim_op1(op, x, i);
+      }
+      Inst::PrimOp2(op, x, y) => {
+        ctx.prim_op2(op, x, y, i);
+      }
+      Inst::Local(x) | Inst::GetLocal(x) => {
+        ctx.mov(ctx.loc(x), ctx.loc(i));
+      }
+      Inst::SetIndex(x, y, z) => {
+        ctx.index(x, y, i);
+        emit!(ctx.out, "  movq {}, %rdx\n", ctx.loc(z));
+        if elt_size(module, value_types, x) == 1 {
+          emit!(ctx.out, "  movb %dl, 8(%rax,%rcx,1)\n");
+        } else {
+          emit!(ctx.out, "  movq %rdx, 8(%rax,%rcx,8)\n");
+        }
+      }
+      Inst::SetLocal(v, x) => {
+        ctx.mov(ctx.loc(x), ctx.loc(v));
+      }
+    }
+
+    i += 1;
+  }
+
+  for &(kind, i) in ctx.traps.iter() {
+    emit!(ctx.out, ".Ltrap{}_{}:\n", kind, i);
+    emit!(ctx.out, "  movl ${}, %esi\n", i);
+    emit!(ctx.out, "  movl ${}, %edi\n", kind);
+    emit!(ctx.out, "  call lilac_trap\n");
+  }
+
+  out.push_str(&ctx.out);
+  return Ok(());
+}
+
+static PRELUDE: &str = r#"  .text
+
+lilac_len:
+  movl (%rdi), %edi
+  ret
+
+# lilac_array(n, x), lilac_array_refs(n, x), and lilac_array_bytes(n, x)
+# allocate an array of `n` copies of `x`, which is a word, a reference, or a
+# byte, with the program point of the call in %rdx. They pass the
+# frame and return address of their caller to the runtime, which walks the
+# stack from there if it collects.
+
+lilac_array:
+  xorl %eax, %eax
+  jmp lilac_array_common
+lilac_array_refs:
+  movl $1, %eax
+  jmp lilac_array_common
+lilac_array_bytes:
+  movl $2, %eax
+lilac_array_common:
+  movq %rdx, %r9
+  movq %rax, %rdx
+  movq %rbp, %rcx
+  movq (%rsp), %r8
+  pushq %rbp
+  movq %rsp, %rbp
+  andq $-16, %rsp
+  call lilac_new_array
+  movq %rax, %rdi
+  leave
+  ret
+
+# lilac_invoke(f, args, results) calls `f` with the arguments in `args`, and
+# stores the results in `results`. Both arrays have room for every register
+# of the calling convention.
+
+  .globl lilac_invoke
+lilac_invoke:
+  pushq %rbp
+  movq %rsp, %rbp
+  pushq %rbx
+  pushq %r12
+  pushq %r13
+  pushq %r14
+  pushq %r15
+  pushq %rdx
+  movq %rdi, %rax
+  movq %rsi, %rcx
+  movq 0(%rcx), %rdi
+  movq 8(%rcx), %rsi
+  movq 16(%rcx), %r8
+  movq 24(%rcx), %r9
+  movq 32(%rcx), %r10
+  movq 40(%rcx), %r11
+  movq 48(%rcx), %r12
+  movq 56(%rcx), %r13
+  call *%rax
+  movq -48(%rbp), %rcx
+  movq %rdi, 0(%rcx)
+  movq %rsi, 8(%rcx)
+  movq %r8, 16(%rcx)
+  movq %r9, 24(%rcx)
+  movq %r10, 32(%rcx)
+  movq %r11, 40(%rcx)
+  movq %r12, 48(%rcx)
+  movq %r13, 56(%rcx)
+  movq -8(%rbp), %rbx
+  movq -16(%rbp), %r12
+  movq -24(%rbp), %r13
+  movq -32(%rbp), %r14
+  movq -40(%rbp), %r15
+  leave
+  ret
+"#;
+
+/// Translates the monomorphized `module` into GNU assembler source.
+
+pub fn emit(module: &Module) -> Result<String, Error> {
+  let mut out = String::new();
+  let value_types = module.value_types();
+
+  let mut maps = String::new();
+
+  out.push_str(PRELUDE);
+
+  for k in 0 .. module.decl.len() {
+    decl_fun(&mut out, &mut maps, module, k, &value_types)?;
+  }
+
+  // Each row of the stack maps is a return address, the program point of its
+  // call, and the number and offsets of the frame slots that hold references.
+
+  out.push_str("\n  .data\n  .globl lilac_stack_maps\nlilac_stack_maps:\n");
+  out.push_str(&maps);
+  out.push_str("  .quad 0\n");
+  out.push_str("\n  .section .note.GNU-stack,\"\",@progbits\n");
+
+  return Ok(out);
+}
+
+// Emits C statements that build the host value `x` of type `t` into the
+// `int64_t` variable `name`. Arrays are allocated outside of the heap of the
+// runtime, and the collector copies them into it like any other object.
+// Every object has room for a forwarding pointer.
+
+pub(crate) fn build(out: &mut String, types: &TypeStore, name: &str, x: &Value, t: TypeId, tmp: &mut u32) -> Option<()> {
+  match (x, types[t]) {
+    (Value::Bool(p), Type::Bool) => {
+      emit!(out, "  {} = {};\n", name, *p as u32);
+    }
+    (Value::Int(n), Type::I64) => {
+      emit!(out, "  {} = (int64_t) UINT64_C({});\n", name, *n as u64);
+    }
+    (Value::Array(a), Type::Array(u)) => {
+      let a = a.borrow();
+      let p = format!("t{}", *tmp);
+      *tmp += 1;
+      let flags =
+        match layout::repr(types, u).class {
+          Class::Ref => (a.len() as u64) << 32,
+          Class::Bool => BYTES,
+          _ => 0,
+        };
+      emit!(out, "  int64_t *{} = calloc({}, sizeof(int64_t));\n", p, 1 + array_words(types, u, a.len()));
+      emit!(out, "  {}[0] = (int64_t) UINT64_C({});\n", p, flags | a.len() as u64);
+      for (j, y) in a.iter().enumerate() {
+        let z = format!("t{}", *tmp);
+        *tmp += 1;
+        emit!(out, "  int64_t {};\n", z);
+        build(out, types, &z, y, u, tmp)?;
+        emit!(out, "  {} = {};\n", element(types, &p, u, &j.to_string()), z);
+      }
+      emit!(out, "  {} = (int64_t) (intptr_t) {};\n", name, p);
+    }
+    _ => {
+      return None;
+    }
+  }
+  return Some(());
+}
+
+// The number of words of the objects that `build` makes for `x` of type `t`.
+
+fn words(types: &TypeStore, x: &Value, t: TypeId) -> u32 {
+  let (Value::Array(a), Type::Array(u)) = (x, types[t]) else { return 0; };
+  let a = a.borrow();
+  return 1 + array_words(types, u, a.len()) + a.iter().map(|y| words(types, y, u)).sum::<u32>();
+}
+
+// The number of words of the elements of an array of `n` elements of type `u`.
+// Every object has room for a forwarding pointer.
+
+fn array_words(types: &TypeStore, u: TypeId, n: u32) -> u32 {
+  return (n * layout::repr(types, u).size).div_ceil(8).max(1);
+}
+
+// The C lvalue of element `j` of the array `p`, an `int64_t *`, whose elements
+// have type `u`.
+
+fn element(types: &TypeStore, p: &str, u: TypeId, j: &str) -> String {
+  if layout::repr(types, u).size == 1 {
+    return format!("((uint8_t *) ({} + 1))[{}]", p, j);
+  }
+  return format!("{}[{} + 1]", p, j);
+}
+
+// Emits C statements that print the `int64_t` expression `x` of type `t` in
+// the same format as the `Display` of interpreter values.
+
+pub(crate) fn print(out: &mut String, types: &TypeStore, x: &str, t: TypeId, depth: u32) {
+  match types[t] {
+    Type::Bool => {
+      emit!(out, "  fputs({} ? \"true\" : \"false\", stdout);\n", x);
+    }
+    Type::I64 => {
+      emit!(out, "  printf(\"%\" PRId64, {});\n", x);
+    }
+    Type::Fun(..) => {
+      emit!(out, "  fputs(\"<fun>\", stdout);\n");
+    }
+    Type::Array(u) => {
+      let a = format!("((int64_t *) (intptr_t) {})", x);
+      emit!(out, "  fputs(\"[\", stdout);\n");
+      emit!(out, "  for (int64_t i{} = 0; i{} < (uint32_t) {}[0]; i{} ++) {{\n", depth, depth, a, depth);
+      emit!(out, "  if (i{} != 0) fputs(\", \", stdout);\n", depth);
+      print(out, types, &element(types, &a, u, &format!("i{}", depth)), u, depth + 1);
+      emit!(out, "  }}\n");
+      emit!(out, "  fputs(\"]\", stdout);\n");
+    }
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => {
+      unreachable!()
+    }
+  }
+}
+
+// The runtime of compiled code: traps, and a copying collector that finds
+// the references in the stack with `lilac_stack_maps`. If the environment
+// variable `LILAC_GC_STRESS` is set, it collects at every allocation.
+
+static RUNTIME: &str = r#"
+void lilac_invoke(void *f, int64_t *args, int64_t *results);
+
+extern int64_t lilac_stack_maps[];
+
+void lilac_trap(int64_t kind, int64_t pc) {
+  static const char *what[] = {
+    "division by zero", "index out of bounds", "negative length", "integer overflow", "shift out of range",
+    "out of memory",
+  };
+  fflush(stdout);
+  fprintf(stderr, "error: %s at %%%" PRId64 "\n", what[kind], pc);
+  exit(1);
+}
+
+#define FORWARDED UINT64_C(0xffffffff)
+#define BYTES (UINT64_C(1) << 63)
+
+static int64_t *lilac_space;
+static int64_t *lilac_free;
+static int64_t *lilac_limit;
+static int64_t lilac_capacity = 1 << 16;
+static int64_t lilac_host;
+static int lilac_stress;
+int64_t lilac_collections;
+
+static void lilac_init(int64_t host) {
+  lilac_host = host;
+  lilac_stress = getenv("LILAC_GC_STRESS") != NULL;
+  lilac_space = malloc(lilac_capacity * sizeof(int64_t));
+  lilac_free = lilac_space;
+  lilac_limit = lilac_space + lilac_capacity;
+}
+
+static int64_t lilac_size(int64_t header) {
+  int64_t len = (uint32_t) header;
+  int64_t words = header & BYTES ? (len + 7) / 8 : len;
+  return 1 + (words > 0 ? words : 1);
+}
+
+static int64_t lilac_refs(int64_t header) {
+  return header & BYTES ? 0 : (int64_t) ((uint64_t) header >> 32);
+}
+
+static int64_t lilac_copy(int64_t x) {
+  if (x == 0) return 0;
+  int64_t *p = (int64_t *) (intptr_t) x;
+  if ((uint64_t) p[0] >> 32 == FORWARDED) return p[1];
+  int64_t size = lilac_size(p[0]);
+  int64_t *q = lilac_free;
+  lilac_free += size;
+  memcpy(q, p, size * sizeof(int64_t));
+  p[0] = (int64_t) (FORWARDED << 32 | (uint32_t) p[0]);
+  p[1] = (int64_t) (intptr_t) q;
+  return (int64_t) (intptr_t) q;
+}
+
+// Returns the stack map of the call that returns to `ret`, if lilac code
+// made it.
+
+static int64_t *lilac_stack_map(int64_t ret) {
+  for (int64_t *m = lilac_stack_maps; m[0] != 0; m += 3 + m[2]) {
+    if (m[0] == ret) return m;
+  }
+  return NULL;
+}
+
+// Copies the objects that are reachable from the `n` references in `x` and
+// from the frames of the stack, starting with the frame `rbp` of the call
+// that returns to `ret`, to a space with room for `need` more words. The
+// objects that the host made are not in the heap until the first collection
+// copies them, so `lilac_host` counts their words. If there is no memory for
+// the new space, it traps at `pc`.
+
+static void lilac_collect(int64_t *x, int64_t n, int64_t *rbp, int64_t ret, int64_t need, int64_t pc) {
+  int64_t size = (lilac_free - lilac_space) + lilac_host + need;
+  if (size < lilac_capacity) size = lilac_capacity;
+  int64_t *from = lilac_space;
+  lilac_space = malloc(size * sizeof(int64_t));
+  if (lilac_space == NULL) lilac_trap(5, pc);
+  lilac_free = lilac_space;
+  lilac_limit = lilac_space + size;
+
+  for (int64_t i = 0; i < n; i ++) x[i] = lilac_copy(x[i]);
+
+  for (int64_t *m; (m = lilac_stack_map(ret)) != NULL; ret = rbp[1], rbp = (int64_t *) (intptr_t) rbp[0]) {
+    for (int64_t i = 0; i < m[2]; i ++) {
+      int64_t *slot = (int64_t *) ((char *) rbp + m[3 + i]);
+      *slot = lilac_copy(*slot);
+    }
+  }
+
+  for (int64_t *scan = lilac_space; scan < lilac_free; scan += lilac_size(*scan)) {
+    for (int64_t i = 0; i < lilac_refs(scan[0]); i ++) {
+      scan[1 + i] = lilac_copy(scan[1 + i]);
+    }
+  }
+
+  free(from);
+  lilac_host = 0;
+  lilac_collections ++;
+
+  int64_t used = (lilac_free - lilac_space) + need;
+  if (2 * used > lilac_capacity) lilac_capacity = 2 * used;
+}
+
+// Allocates an array of `n` copies of `x`, whose elements are words (kind 0),
+// references (kind 1), or bytes (kind 2). An array may have at most
+// `LILAC_MAX_LEN` elements, which is `heap::MAX_LEN`.
+
+int64_t lilac_new_array(int64_t n, int64_t x, int64_t kind, int64_t *rbp, int64_t ret, int64_t pc) {
+  if (n < 0) lilac_trap(2, pc);
+  if (n > LILAC_MAX_LEN) lilac_trap(5, pc);
+  uint64_t flags = kind == 1 ? (uint64_t) n << 32 : kind == 2 ? BYTES : 0;
+  int64_t header = (int64_t) (flags | (uint64_t) n);
+  int64_t size = lilac_size(header);
+  if (lilac_stress || lilac_free + size > lilac_limit) {
+    lilac_collect(&x, kind == 1, rbp, ret, size, pc);
+  }
+  int64_t *p = lilac_free;
+  lilac_free += size;
+  p[0] = header;
+  memset(p + 1, 0, (size - 1) * sizeof(int64_t));
+  if (kind == 2) {
+    memset(p + 1, (int) x, n);
+  } else {
+    for (int64_t i = 0; i < n; i ++) p[1 + i] = x;
+  }
+  return (int64_t) (intptr_t) p;
+}
+
+"#;
+
+/// Emits a C host program, to be linked with the output of `emit`, that calls
+/// the first function named `name` with the given arguments and prints its
+/// results.
+
+pub fn emit_main(module: &Module, name: Symbol, args: &[Value]) -> Result<String, Error> {
+  let Some(k) = module.decl.iter().position(|f| f.name == name) else {
+    return Err(Error::UnboundVariable(name));
+  };
+
+  let types = &module.types;
+  let Type::Fun(a, b) = types[module.decl[k as u32].scheme.1] else { unreachable!() };
+  let n = types.tuple_elts(a).len();
+
+  if n != args.len() {
+    return Err(Error::ArgumentMismatch(n.min(args.len()) as u32));
+  }
+
+  if n > ARG_REGS.len() || types.tuple_elts(b).len() > ARG_REGS.len() {
+    return Err(Error::Unsupported(module.decl[k as u32].pos));
+  }
+
+  let mut out = String::new();
+  let mut tmp = 0;
+
+  emit!(out, "#include <inttypes.h>\n");
+  emit!(out, "#include <stdint.h>\n");
+  emit!(out, "#include <stdio.h>\n");
+  emit!(out, "#include <stdlib.h>\n");
+  emit!(out, "#include <string.h>\n\n");
+  emit!(out, "extern char lilac_f{}[];\n", k);
+  emit!(out, "#define LILAC_MAX_LEN {}\n", heap::MAX_LEN);
+  emit!(out, "{}", RUNTIME);
+  emit!(out, "int main(void) {{\n");
+  emit!(out, "  lilac_init({});\n", types.tuple_elts(a).zip(args).map(|(t, x)| words(types, x, t)).sum::<u32>());
+  emit!(out, "  int64_t args[{}] = {{ 0 }};\n", ARG_REGS.len());
+  emit!(out, "  int64_t results[{}];\n", ARG_REGS.len());
+
+  for (j, (t, x)) in types.tuple_elts(a).zip(args).enumerate() {
+    let Some(()) = build(&mut out, types, &format!("args[{}]", j), x, t, &mut tmp) else {
+      return Err(Error::ArgumentMismatch(j as u32));
+    };
+  }
+
+  emit!(out, "  lilac_invoke(lilac_f{}, args, results);\n", k);
+
+  for (j, t) in types.tuple_elts(b).enumerate() {
+    if j != 0 {
+      emit!(out, "  fputs(\", \", stdout);\n");
+    }
+    print(&mut out, types, &format!("results[{}]", j), t, 0);
+  }
+
+  emit!(out, "  fputs(\"\\n\", stdout);\n");
+  emit!(out, "  return 0;\n");
+  emit!(out, "}}\n");
+
+  return Ok(out);
+}
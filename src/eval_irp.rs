@@ -0,0 +1,520 @@
+//! typed interpreter
+//!
+//! monomorphic typed bytecode -> result values
+//!
+//! Because every value has a static type, values are stored unboxed as raw
+//! 64-bit words in per-frame register slots, without dynamic tags. A `bool` is
+//! `0` or `1`, an `i64` is itself, a function is its index into `decl`, and an
+//! array is a reference into the garbage collected heap.
+//!
+//! Slots are not sized by their `PrimType`. Every type fits in a word, and a
+//! uniform width lets `Get`, `Put`, and the moves at calls copy a slot without
+//! looking at its type, and lets a frame's slots be addressed by index.
+//! Narrower slots for `bool` would save a little frame space and nothing else.
+//!
+//! The static types also tell which slots of each frame hold references, so
+//! the collector's roots are precise: the reference slots of every frame, and
+//! the arrays that the host passed in.
+//!
+//! Before running, the code is translated into instructions whose operands are
+//! slot offsets relative to the frame, and whose constants are already
+//! resolved. Program points are preserved, so errors refer to the original
+//! code.
+//!
+//! Like in the reference interpreter, frames live on an explicit stack, so
+//! `TailCall` runs in constant space, and only memory limits how deep other
+//! calls can go.
+//!
+//! The module is expected to be monomorphized, and is assumed to be well
+//! typed. Only errors that cannot be ruled out statically are checked.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::eval_iru::Builtin;
+use crate::eval_iru::Value;
+use crate::heap::Heap;
+use crate::heap::MAX_LEN;
+use crate::heap::Roots;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::prim::PrimOp1;
+use crate::prim::PrimOp2;
+use crate::prim::Trap;
+use crate::symbol::Symbol;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use std::cell::RefCell;
+use std::mem::swap;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The argument at the given position does not match the parameter type.
+  ArgumentMismatch(u32),
+  IndexOutOfBounds(u32),
+  Trap(u32, Trap),
+  UnboundVariable(Symbol),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::ArgumentMismatch(i) => write!(f, "argument mismatch at position {}", i),
+      Self::IndexOutOfBounds(i) => write!(f, "index out of bounds at %{}", i),
+      Self::Trap(i, e) => write!(f, "{} at %{}", e, i),
+      Self::UnboundVariable(s) => write!(f, "unbound variable {}", s),
+    }
+  }
+}
+
+// NB: value operands are slot offsets within the current frame, and labels are
+// absolute program points.
+
+#[derive(Clone, Copy)]
+enum Op {
+  Nop,
+  Get(u32, u32),
+  Put(u32),
+  Goto(u32),
+  Cond(u32),
+  Ret,
+  Call(u32),
+  TailCall(u32),
+  Const(u32, u64),
+  Copy(u32, u32),
+  Index(u32, u32, u32),
+  PrimOp1(u32, PrimOp1, u32),
+  PrimOp2(u32, PrimOp2, u32, u32),
+  SetIndex(u32, u32, u32),
+}
+
+#[derive(Clone, Copy)]
+struct FunInfo {
+  pos: u32,
+  len: u32,
+}
+
+struct Frame {
+  fun: u32,
+  base: u32,
+  len: u32,
+  ret: u32,
+}
+
+struct Machine {
+  code: Arr<Op>,
+  funs: Arr<FunInfo>,
+  frames: Buf<Frame>,
+  // The most frames that the stack has held.
+  depth: u32,
+  slots: Buf<u64>,
+  args: Buf<u64>,
+  outs: Buf<u64>,
+  heap: Heap,
+  // The frame-relative slots of each function that hold references.
+  refs: Arr<Arr<u32>>,
+  // The arrays that the host passed in or got back.
+  pinned: Buf<u64>,
+}
+
+// The roots of the machine, and `extra` references held by the interpreter.
+
+struct MachineRoots<'a> {
+  frames: &'a Buf<Frame>,
+  slots: &'a mut Buf<u64>,
+  refs: &'a Arr<Arr<u32>>,
+  pinned: &'a mut Buf<u64>,
+  extra: &'a mut [u64],
+}
+
+impl<'a> Roots for MachineRoots<'a> {
+  fn visit(&mut self, f: &mut dyn FnMut(&mut u64)) {
+    for frame in self.frames.iter() {
+      for &i in self.refs[frame.fun].iter() {
+        f(&mut self.slots[frame.base + i]);
+      }
+    }
+    for i in 0 .. self.pinned.len() {
+      f(&mut self.pinned[i]);
+    }
+    self.extra.visit(f);
+  }
+}
+
+
+fn translate(module: &Module) -> Result<Arr<Op>, Error> {
+  let mut code = Buf::new();
+  let n = module.decl.len() as u64;
+
+  for f in module.decl.iter() {
+    let r = |x: u32| x - f.pos;
+
+    for i in f.pos .. f.pos + f.len {
+      let op =
+        match module.code[i] {
+          Inst::GotoStaticError => unreachable!(),
+          Inst::Label(_) => Op::Nop,
+          Inst::Get(k, _) => Op::Get(r(i), k),
+          Inst::Put(_, x) => Op::Put(r(x)),
+          Inst::Goto(a) => Op::Goto(a),
+          Inst::Cond(x) => Op::Cond(r(x)),
+          Inst::Ret => Op::Ret,
+          Inst::Call(x) => Op::Call(r(x)),
+          Inst::TailCall(x) => Op::TailCall(r(x)),
+          Inst::Const(s, t) => {
+            let Some(g) = Builtin::from_symbol(s) else {
+              return Err(Error::UnboundVariable(s));
+            };
+            Op::Const(r(i), n + builtin_code(module, g, t))
+          }
+          Inst::ConstFun(k) => Op::Const(r(i), k as u64),
+          Inst::ConstBool(p) => Op::Const(r(i), p as u64),
+          Inst::ConstInt(n) => Op::Const(r(i), n as u64),
+          Inst::Index(x, y) => Op::Index(r(i), r(x), r(y)),
+          Inst::PrimOp1(op, x) => Op::PrimOp1(r(i), op, r(x)),
+          Inst::PrimOp2(op, x, y) => Op::PrimOp2(r(i), op, r(x), r(y)),
+          Inst::Local(x) => Op::Copy(r(i), r(x)),
+          Inst::GetLocal(v) => Op::Copy(r(i), r(v)),
+          Inst::SetIndex(x, y, z) => Op::SetIndex(r(x), r(y), r(z)),
+          Inst::SetLocal(v, x) => Op::Copy(r(v), r(x)),
+        };
+      code.push(op);
+    }
+  }
+
+  return Ok(Arr::from(code.drain()));
+}
+
+impl Machine {
+  fn push_frame(&mut self, k: u32, ret: u32) -> (u32, u32) {
+    let f = self.funs[k];
+    let base = self.slots.len();
+    for _ in 0 .. f.len { self.slots.push(0); }
+    self.frames.push(Frame { fun: k, base, len: f.len, ret });
+    self.depth = self.depth.max(self.frames.len());
+    return (f.pos, base);
+  }
+
+  // Returns the continuation and the base of the caller's frame, or `None` if
+  // the outermost frame was popped.
+
+  fn pop_frame(&mut self) -> Option<(u32, u32)> {
+    let f = self.frames.pop();
+    let _ = self.slots.pop_list(f.len);
+    if self.frames.is_empty() { return None; }
+    return Some((f.ret, self.frames.top().base));
+  }
+
+  fn alloc(&mut self, len: u32, refs: u32, extra: &mut [u64]) -> Option<u64> {
+    let mut roots =
+      MachineRoots {
+        frames: &self.frames,
+        slots: &mut self.slots,
+        refs: &self.refs,
+        pinned: &mut self.pinned,
+        extra,
+      };
+    return self.heap.alloc(len, refs, &mut roots);
+  }
+
+  fn call_builtin(&mut self, pc: u32, f: u64) -> Result<(), Error> {
+    let r =
+      match f - self.funs.len() as u64 {
+        BUILTIN_LEN => {
+          self.heap.len(self.outs[0]) as u64
+        }
+        code => {
+          let n = self.outs[0] as i64;
+          let mut x = [self.outs[1]];
+          if n < 0 { return Err(Error::Trap(pc, Trap::NegativeLength)); }
+          if n > MAX_LEN as i64 { return Err(Error::Trap(pc, Trap::OutOfMemory)); }
+          let n = n as u32;
+          let Some(a) = self.alloc(n, if code == BUILTIN_ARRAY_OF_REFS { n } else { 0 }, &mut x) else {
+            return Err(Error::Trap(pc, Trap::OutOfMemory));
+          };
+          for i in 0 .. n { self.heap.set(a, i, x[0]); }
+          a
+        }
+      };
+    self.outs.clear();
+    self.outs.push(r);
+    return Ok(());
+  }
+
+  fn builtin(&self, f: u64) -> Option<Builtin> {
+    let n = self.funs.len() as u64;
+    if f < n { return None; }
+    match f - n {
+      BUILTIN_LEN => Some(Builtin::Len),
+      BUILTIN_ARRAY | BUILTIN_ARRAY_OF_REFS => Some(Builtin::Array),
+      _ => unreachable!(),
+    }
+  }
+
+  fn run(&mut self, entry: u32) -> Result<(), Error> {
+    let (mut pc, mut base) = self.push_frame(entry, u32::MAX);
+
+    loop {
+      match self.code[pc] {
+        Op::Nop => {
+          pc += 1;
+        }
+        Op::Get(x, k) => {
+          self.slots[base + x] = self.args[k];
+          pc += 1;
+        }
+        Op::Put(x) => {
+          self.outs.push(self.slots[base + x]);
+          pc += 1;
+        }
+        Op::Goto(a) => {
+          swap(&mut self.args, &mut self.outs);
+          self.outs.clear();
+          pc = a;
+        }
+        Op::Cond(x) => {
+          pc += if self.slots[base + x] != 0 { 2 } else { 1 };
+        }
+        Op::Ret => {
+          let Some((ret, b)) = self.pop_frame() else { return Ok(()); };
+          // NB: `ret` is the `Goto` to the continuation of the call
+          pc = ret;
+          base = b;
+        }
+        Op::Call(x) => {
+          let f = self.slots[base + x];
+          match self.builtin(f) {
+            None => {
+              swap(&mut self.args, &mut self.outs);
+              self.outs.clear();
+              (pc, base) = self.push_frame(f as u32, pc + 1);
+            }
+            Some(_) => {
+              self.call_builtin(pc, f)?;
+              pc += 1;
+            }
+          }
+        }
+        Op::TailCall(x) => {
+          let f = self.slots[base + x];
+          match self.builtin(f) {
+            None => {
+              let ret = self.frames.top().ret;
+              let _ = self.pop_frame();
+              swap(&mut self.args, &mut self.outs);
+              self.outs.clear();
+              (pc, base) = self.push_frame(f as u32, ret);
+            }
+            Some(_) => {
+              self.call_builtin(pc, f)?;
+              let Some((ret, b)) = self.pop_frame() else { return Ok(()); };
+              pc = ret;
+              base = b;
+            }
+          }
+        }
+        Op::Const(x, n) => {
+          self.slots[base + x] = n;
+          pc += 1;
+        }
+        Op::Copy(x, y) => {
+          self.slots[base + x] = self.slots[base + y];
+          pc += 1;
+        }
+        Op::Index(x, y, z) => {
+          let a = self.slots[base + y];
+          let i = self.slots[base + z];
+          if ! (i < self.heap.len(a) as u64) { return Err(Error::IndexOutOfBounds(pc)); }
+          self.slots[base + x] = self.heap.get(a, i as u32);
+          pc += 1;
+        }
+        Op::PrimOp1(x, op, y) => {
+          let y = self.slots[base + y] as i64;
+          let z = op.eval(y).map_err(|e| Error::Trap(pc, e))?;
+          self.slots[base + x] = z as u64;
+          pc += 1;
+        }
+        Op::PrimOp2(x, op, y, z) => {
+          let y = self.slots[base + y] as i64;
+          let z = self.slots[base + z] as i64;
+          let w = op.eval(y, z).map_err(|e| Error::Trap(pc, e))?;
+          self.slots[base + x] = w as u64;
+          pc += 1;
+        }
+        Op::SetIndex(x, y, z) => {
+          let i = self.slots[base + y];
+          let w = self.slots[base + z];
+          let a = self.slots[base + x];
+          if ! (i < self.heap.len(a) as u64) { return Err(Error::IndexOutOfBounds(pc)); }
+          self.heap.set(a, i as u32, w);
+          pc += 1;
+        }
+      }
+    }
+  }
+}
+
+// Converts between host values and their unboxed representation, guided by
+// the static type. Host arrays are copied into the heap on the way in, and
+// are pinned so that they can be updated in place afterwards. The `arrays` of
+// the host correspond to the `pinned` references of the machine.
+
+struct Host<'a> {
+  module: &'a Module,
+  arrays: Buf<(Rc<RefCell<Arr<Value>>>, TypeId)>,
+}
+
+impl<'a> Host<'a> {
+  fn import(&mut self, m: &mut Machine, x: &Value, t: TypeId) -> Option<u64> {
+    match (x, self.module.types[t]) {
+      (Value::Bool(p), Type::Bool) => Some(*p as u64),
+      (Value::Int(n), Type::I64) => Some(*n as u64),
+      (Value::Array(a), Type::Array(u)) => {
+        if let Some(j) = self.arrays.iter().position(|(b, _)| Rc::ptr_eq(a, b)) {
+          return Some(m.pinned[j as u32]);
+        }
+        // NB: the references being imported are not roots yet, so the heap
+        // must not collect.
+        let n = a.borrow().len();
+        let refs = if is_ref(self.module, u) { n } else { 0 };
+        let h = m.heap.alloc_uncollected(n, refs)?;
+        m.pinned.push(h);
+        self.arrays.push((a.clone(), u));
+        for (i, y) in a.borrow().iter().enumerate() {
+          let z = self.import(m, y, u)?;
+          m.heap.set(h, i as u32, z);
+        }
+        Some(h)
+      }
+      _ => None,
+    }
+  }
+
+  fn export(&mut self, m: &mut Machine, x: u64, t: TypeId) -> Value {
+    match self.module.types[t] {
+      Type::Bool => Value::Bool(x != 0),
+      Type::I64 => Value::Int(x as i64),
+      Type::Fun(..) =>
+        match m.builtin(x) {
+          None => Value::Fun(x as u32),
+          Some(g) => Value::Builtin(g),
+        },
+      Type::Array(u) => {
+        if let Some(j) = m.pinned.iter().position(|&h| h == x) {
+          return Value::Array(self.arrays[j as u32].0.clone());
+        }
+        let a = Rc::new(RefCell::new(Arr::EMPTY));
+        m.pinned.push(x);
+        self.arrays.push((a.clone(), u));
+        let elts = Arr::from((0 .. m.heap.len(x)).map(|i| self.export(m, m.heap.get(x, i), u)));
+        *a.borrow_mut() = elts;
+        Value::Array(a)
+      }
+      Type::Tuple(..) | Type::TupleElt(..) | Type::Var(..) => unreachable!(),
+    }
+  }
+
+  fn write_back(&mut self, m: &mut Machine) {
+    let mut j = 0;
+    while j < self.arrays.len() {
+      let h = m.pinned[j];
+      let (ref a, u) = self.arrays[j];
+      let a = a.clone();
+      let elts = Arr::from((0 .. m.heap.len(h)).map(|i| self.export(m, m.heap.get(h, i), u)));
+      *a.borrow_mut() = elts;
+      j += 1;
+    }
+  }
+}
+
+// Whether values of type `t` are references into the heap.
+
+fn is_ref(module: &Module, t: TypeId) -> bool {
+  return matches!(module.types[t], Type::Array(_));
+}
+
+const BUILTIN_LEN: u64 = 0;
+const BUILTIN_ARRAY: u64 = 1;
+const BUILTIN_ARRAY_OF_REFS: u64 = 2;
+
+// The code of the builtin `g` at type `t`, which is added to the number of
+// functions to get its value. Arrays of references are allocated by a separate
+// builtin, because the element type is not known at runtime.
+
+fn builtin_code(module: &Module, g: Builtin, t: TypeId) -> u64 {
+  match g {
+    Builtin::Len => {
+      return BUILTIN_LEN;
+    }
+    Builtin::Array => {
+      let Type::Fun(_, b) = module.types[t] else { unreachable!() };
+      let Some(Type::Array(u)) = module.types.tuple_elts(b).next().map(|a| module.types[a]) else { unreachable!() };
+      return if is_ref(module, u) { BUILTIN_ARRAY_OF_REFS } else { BUILTIN_ARRAY };
+    }
+  }
+}
+
+/// Calls the first function named `name` in the monomorphized `module` with
+/// the given arguments, and returns its results.
+///
+/// Arrays that are passed in reflect any updates made by the callee. Function
+/// values that are returned refer to `module.decl`.
+
+pub fn run(module: &Module, name: Symbol, args: impl IntoIterator<Item = Value>) -> Result<Arr<Value>, Error> {
+  return run_with_depth(module, name, args).map(|(results, _)| results);
+}
+
+/// Runs like `run`, and also returns the most frames that the stack held.
+
+pub fn run_with_depth(module: &Module, name: Symbol, args: impl IntoIterator<Item = Value>) -> Result<(Arr<Value>, u32), Error> {
+  let Some(entry) = module.decl.iter().position(|f| f.name == name) else {
+    return Err(Error::UnboundVariable(name));
+  };
+  let entry = entry as u32;
+  let value_types = module.value_types();
+
+  let mut m =
+    Machine {
+      code: translate(module)?,
+      funs: Arr::from(module.decl.iter().map(|f| FunInfo { pos: f.pos, len: f.len })),
+      frames: Buf::new(),
+      depth: 0,
+      slots: Buf::new(),
+      args: Buf::new(),
+      outs: Buf::new(),
+      heap: Heap::new(),
+      refs: Arr::from(module.decl.iter().map(|f| {
+        let mut refs = Buf::new();
+        for i in 0 .. f.len {
+          if let Some(t) = value_types[f.pos + i] && is_ref(module, t) { refs.push(i); }
+        }
+        Arr::from(refs.drain())
+      })),
+      pinned: Buf::new(),
+    };
+
+  let mut host = Host { module, arrays: Buf::new() };
+
+  let Type::Fun(a, b) = module.types[module.decl[entry].scheme.1] else { unreachable!() };
+  let mut params = module.types.tuple_elts(a);
+
+  for (i, x) in args.into_iter().enumerate() {
+    let i = i as u32;
+    let Some(t) = params.next() else { return Err(Error::ArgumentMismatch(i)); };
+    let Some(x) = host.import(&mut m, &x, t) else { return Err(Error::ArgumentMismatch(i)); };
+    m.args.push(x);
+  }
+
+  if params.len() != 0 {
+    return Err(Error::ArgumentMismatch(m.args.len()));
+  }
+
+  let r = m.run(entry);
+
+  host.write_back(&mut m);
+  r?;
+
+  let results: Buf<u64> = m.outs.iter().copied().collect();
+  let mut outs: Buf<Value> = module.types.tuple_elts(b).zip(results.iter()).map(|(t, &x)| host.export(&mut m, x, t)).collect();
+  return Ok((Arr::from(outs.drain()), m.depth));
+}
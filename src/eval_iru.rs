@@ -0,0 +1,396 @@
+//! reference interpreter
+//!
+//! untyped bytecode -> result values
+//!
+//! Values carry dynamic tags, so the interpreter runs any module, including
+//! ones that don't typecheck, and reports misuse as a runtime error. It is
+//! meant to be simple rather than fast, and serves as the specification that
+//! the other execution engines are tested against.
+//!
+//! Arrays are shared, mutable `Rc<RefCell<Arr<Value>>>`s rather than objects in
+//! the garbage collected heap of `heap`, so a cycle of arrays is never freed.
+//! They are still limited to `heap::MAX_LEN` elements, like in every other
+//! engine.
+//!
+//! Calls and returns use an explicit stack of frames, so `TailCall` runs in
+//! constant space. The stack is not on the native stack, so only memory limits
+//! how deep other calls can go.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::heap::MAX_LEN;
+use crate::iru::Inst;
+use crate::iru::Module;
+use crate::prim::PrimType;
+use crate::prim::Trap;
+use crate::symbol::Symbol;
+use crate::typecheck::lower_op1;
+use crate::typecheck::lower_op2;
+use std::cell::RefCell;
+use std::mem::swap;
+use std::rc::Rc;
+use tangerine::map::HashMap;
+
+#[derive(Clone)]
+pub enum Value {
+  Array(Rc<RefCell<Arr<Value>>>),
+  Bool(bool),
+  Builtin(Builtin),
+  Fun(u32),
+  Int(i64),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Builtin {
+  Array,
+  Len,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// A block or function received the wrong number of arguments.
+  ArityMismatch(u32),
+  IndexOutOfBounds(u32),
+  /// Reached a static error that was detected during lowering.
+  StaticError(u32),
+  Trap(u32, Trap),
+  /// An operand had the wrong dynamic type.
+  TypeError(u32),
+  UnboundVariable(Symbol),
+  Unsupported(u32),
+}
+
+impl Value {
+  pub fn array(elts: impl IntoIterator<Item = Value>) -> Self {
+    let mut elts: Buf<Value> = elts.into_iter().collect();
+    return Value::Array(Rc::new(RefCell::new(Arr::from(elts.drain()))));
+  }
+}
+
+impl Builtin {
+  pub fn from_symbol(s: Symbol) -> Option<Self> {
+    if s == Symbol::from_str("array") { return Some(Self::Array); }
+    if s == Symbol::from_str("len") { return Some(Self::Len); }
+    return None;
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Array => "array",
+      Self::Len => "len",
+    }
+  }
+}
+
+struct Frame {
+  pos: u32,
+  base: u32,
+  len: u32,
+  ret: u32,
+}
+
+struct Machine<'a> {
+  module: &'a Module,
+  funs: HashMap<Symbol, u32>,
+  frames: Buf<Frame>,
+  // The most frames that the stack has held.
+  depth: u32,
+  values: Buf<Value>,
+  args: Buf<Value>,
+  outs: Buf<Value>,
+}
+
+impl<'a> Machine<'a> {
+  fn get(&self, x: u32) -> Value {
+    let f = self.frames.top();
+    return self.values[f.base + (x - f.pos)].clone();
+  }
+
+  fn set(&mut self, x: u32, value: Value) {
+    let f = self.frames.top();
+    self.values[f.base + (x - f.pos)] = value;
+  }
+
+  fn push_frame(&mut self, k: u32, ret: u32) -> u32 {
+    let f = &self.module.decl[k];
+    let base = self.values.len();
+    for _ in 0 .. f.len { self.values.push(Value::Int(0)); }
+    self.frames.push(Frame { pos: f.pos, base, len: f.len, ret });
+    self.depth = self.depth.max(self.frames.len());
+    return f.pos;
+  }
+
+  fn pop_frame(&mut self) -> u32 {
+    let f = self.frames.pop();
+    let _ = self.values.pop_list(f.len);
+    return f.ret;
+  }
+
+  // Moves the outgoing values into the incoming arguments of the next block.
+
+  fn transfer(&mut self) {
+    swap(&mut self.args, &mut self.outs);
+    self.outs.clear();
+  }
+
+  fn call_builtin(&mut self, pc: u32, f: Builtin) -> Result<(), Error> {
+    let r =
+      match f {
+        Builtin::Array => {
+          if self.outs.len() != 2 { return Err(Error::ArityMismatch(pc)); }
+          let Value::Int(n) = self.outs[0] else { return Err(Error::TypeError(pc)); };
+          if n < 0 { return Err(Error::Trap(pc, Trap::NegativeLength)); }
+          if n > MAX_LEN as i64 { return Err(Error::Trap(pc, Trap::OutOfMemory)); }
+          let x = self.outs[1].clone();
+          Value::array((0 .. n).map(|_| x.clone()))
+        }
+        Builtin::Len => {
+          if self.outs.len() != 1 { return Err(Error::ArityMismatch(pc)); }
+          let Value::Array(ref a) = self.outs[0] else { return Err(Error::TypeError(pc)); };
+          Value::Int(a.borrow().len() as i64)
+        }
+      };
+    self.outs.clear();
+    self.outs.push(r);
+    return Ok(());
+  }
+
+  fn run(&mut self, entry: u32) -> Result<Arr<Value>, Error> {
+    let module = self.module;
+    let code = &module.code;
+    let mut pc = self.push_frame(entry, u32::MAX);
+
+    loop {
+      match code[pc] {
+        Inst::GotoStaticError => {
+          return Err(Error::StaticError(pc));
+        }
+        Inst::Label(n) => {
+          if self.args.len() != n { return Err(Error::ArityMismatch(pc)); }
+          pc += 1;
+        }
+        Inst::Get(k) => {
+          if k >= self.args.len() { return Err(Error::ArityMismatch(pc)); }
+          let x = self.args[k].clone();
+          self.set(pc, x);
+          pc += 1;
+        }
+        Inst::Put(k, x) => {
+          if k != self.outs.len() { return Err(Error::ArityMismatch(pc)); }
+          let x = self.get(x);
+          self.outs.push(x);
+          pc += 1;
+        }
+        Inst::Goto(a) => {
+          self.transfer();
+          pc = a;
+        }
+        Inst::Cond(x) => {
+          let Value::Bool(p) = self.get(x) else { return Err(Error::TypeError(pc)); };
+          pc += if p { 2 } else { 1 };
+        }
+        Inst::Ret => {
+          let ret = self.pop_frame();
+          if self.frames.is_empty() {
+            return Ok(Arr::from(self.outs.drain()));
+          }
+          // NB: `ret` is the `Goto` to the continuation of the call
+          pc = ret;
+        }
+        Inst::Call(f) => {
+          match self.get(f) {
+            Value::Fun(k) => {
+              self.transfer();
+              pc = self.push_frame(k, pc + 1);
+            }
+            Value::Builtin(f) => {
+              self.call_builtin(pc, f)?;
+              pc += 1;
+            }
+            _ => {
+              return Err(Error::TypeError(pc));
+            }
+          }
+        }
+        Inst::TailCall(f) => {
+          match self.get(f) {
+            Value::Fun(k) => {
+              let ret = self.pop_frame();
+              self.transfer();
+              pc = self.push_frame(k, ret);
+            }
+            Value::Builtin(f) => {
+              self.call_builtin(pc, f)?;
+              let ret = self.pop_frame();
+              if self.frames.is_empty() {
+                return Ok(Arr::from(self.outs.drain()));
+              }
+              pc = ret;
+            }
+            _ => {
+              return Err(Error::TypeError(pc));
+            }
+          }
+        }
+        Inst::Const(s) => {
+          let x =
+            if let Some(&k) = self.funs.get(s) {
+              Value::Fun(k)
+            } else if let Some(f) = Builtin::from_symbol(s) {
+              Value::Builtin(f)
+            } else {
+              return Err(Error::UnboundVariable(s));
+            };
+          self.set(pc, x);
+          pc += 1;
+        }
+        Inst::ConstBool(p) => {
+          self.set(pc, Value::Bool(p));
+          pc += 1;
+        }
+        Inst::ConstInt(n) => {
+          self.set(pc, Value::Int(n));
+          pc += 1;
+        }
+        Inst::Field(..) | Inst::SetField(..) => {
+          return Err(Error::Unsupported(pc));
+        }
+        Inst::Index(x, y) => {
+          let (Value::Array(a), Value::Int(i)) = (self.get(x), self.get(y)) else {
+            return Err(Error::TypeError(pc));
+          };
+          let a = a.borrow();
+          if ! (0 <= i && i < a.len() as i64) { return Err(Error::IndexOutOfBounds(pc)); }
+          let z = a[i as u32].clone();
+          drop(a);
+          self.set(pc, z);
+          pc += 1;
+        }
+        Inst::Op1(op, x) => {
+          let op = lower_op1(op);
+          let Some(x) = to_bits(self.get(x), op.arg_type()) else { return Err(Error::TypeError(pc)); };
+          let z = op.eval(x).map_err(|e| Error::Trap(pc, e))?;
+          self.set(pc, from_bits(z, op.out_type()));
+          pc += 1;
+        }
+        Inst::Op2(op, x, y) => {
+          let op = lower_op2(op);
+          let Some(x) = to_bits(self.get(x), op.arg_type().0) else { return Err(Error::TypeError(pc)); };
+          let Some(y) = to_bits(self.get(y), op.arg_type().1) else { return Err(Error::TypeError(pc)); };
+          let z = op.eval(x, y).map_err(|e| Error::Trap(pc, e))?;
+          self.set(pc, from_bits(z, op.out_type()));
+          pc += 1;
+        }
+        Inst::Local(x) | Inst::GetLocal(x) => {
+          let x = self.get(x);
+          self.set(pc, x);
+          pc += 1;
+        }
+        Inst::SetIndex(x, y, z) => {
+          let (Value::Array(a), Value::Int(i)) = (self.get(x), self.get(y)) else {
+            return Err(Error::TypeError(pc));
+          };
+          let mut a = a.borrow_mut();
+          if ! (0 <= i && i < a.len() as i64) { return Err(Error::IndexOutOfBounds(pc)); }
+          a[i as u32] = self.get(z);
+          pc += 1;
+        }
+        Inst::SetLocal(v, x) => {
+          let x = self.get(x);
+          self.set(v, x);
+          pc += 1;
+        }
+      }
+    }
+  }
+}
+
+fn to_bits(x: Value, t: PrimType) -> Option<i64> {
+  match (x, t) {
+    (Value::Bool(p), PrimType::Bool) => Some(p as i64),
+    (Value::Int(n), PrimType::I64) => Some(n),
+    _ => None,
+  }
+}
+
+fn from_bits(x: i64, t: PrimType) -> Value {
+  match t {
+    PrimType::Bool => Value::Bool(x != 0),
+    PrimType::I64 => Value::Int(x),
+  }
+}
+
+/// Calls the function `name` in `module` with the given arguments, and returns
+/// its results.
+
+pub fn run(module: &Module, name: Symbol, args: impl IntoIterator<Item = Value>) -> Result<Arr<Value>, Error> {
+  return run_with_depth(module, name, args).map(|(results, _)| results);
+}
+
+/// Runs like `run`, and also returns the most frames that the stack held.
+
+pub fn run_with_depth(module: &Module, name: Symbol, args: impl IntoIterator<Item = Value>) -> Result<(Arr<Value>, u32), Error> {
+  let mut funs = HashMap::new();
+
+  for (i, f) in module.decl.iter().enumerate() {
+    funs.insert(f.name, i as u32);
+  }
+
+  let Some(&entry) = funs.get(name) else {
+    return Err(Error::UnboundVariable(name));
+  };
+
+  let mut m =
+    Machine {
+      module,
+      funs,
+      frames: Buf::new(),
+      depth: 0,
+      values: Buf::new(),
+      args: args.into_iter().collect(),
+      outs: Buf::new(),
+    };
+
+  let results = m.run(entry)?;
+  return Ok((results, m.depth));
+}
+
+impl std::fmt::Display for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Array(a) => {
+        write!(f, "[")?;
+        for (i, x) in a.borrow().iter().enumerate() {
+          if i != 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{}", x)?;
+        }
+        write!(f, "]")?;
+      }
+      Self::Bool(p) =>
+        write!(f, "{}", p)?,
+      Self::Builtin(g) =>
+        write!(f, "<builtin {}>", g.as_str())?,
+      Self::Fun(k) =>
+        write!(f, "<fun #{}>", k)?,
+      Self::Int(n) =>
+        write!(f, "{}", n)?,
+    }
+    return Ok(());
+  }
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::ArityMismatch(i) => write!(f, "arity mismatch at %{}", i),
+      Self::IndexOutOfBounds(i) => write!(f, "index out of bounds at %{}", i),
+      Self::StaticError(i) => write!(f, "static error at %{}", i),
+      Self::Trap(i, e) => write!(f, "{} at %{}", e, i),
+      Self::TypeError(i) => write!(f, "type error at %{}", i),
+      Self::UnboundVariable(s) => write!(f, "unbound variable {}", s),
+      Self::Unsupported(i) => write!(f, "unsupported instruction at %{}", i),
+    }
+  }
+}
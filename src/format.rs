@@ -0,0 +1,577 @@
+//! source formatter
+//!
+//! source text -> source text in canonical layout
+//!
+//! The syntax tree is printed back with one statement per line, two spaces of
+//! indentation per block, spaces around binary operators, and only the
+//! parentheses that the precedence of `parse::P` requires. Functions are
+//! separated by one blank line, and a single blank line between statements is
+//! kept.
+//!
+//! The tree has no comments, so they are recovered from the source text. The
+//! printer emits the tokens of the source in order, up to parentheses, so each
+//! comment is attached to a token: a comment that follows a token on its line
+//! trails the line where that token is printed, and any other comment is
+//! printed on its own lines before the next token. If that token is in the
+//! middle of a line, its comments are moved up before the line.
+//!
+//! Only a source without syntax errors can be formatted.
+
+use crate::arr::Arr;
+use crate::ast::Binding;
+use crate::ast::Expr;
+use crate::ast::Item;
+use crate::ast::Stmt;
+use crate::buf::Buf;
+use crate::lexer::Lexer;
+use crate::operator::Op1;
+use crate::parse;
+use crate::parse::P;
+use crate::token::Token;
+
+/// Formats `source`, or returns its first syntax error.
+
+pub fn format(source: &str) -> Result<String, parse::Error> {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(source.as_bytes(), store.arena());
+
+  if errors.len() != 0 {
+    return Err(errors[0]);
+  }
+
+  let mut t = Printer::new(source);
+
+  for (i, Item::Fun(f)) in items.iter().enumerate() {
+    if i != 0 {
+      t.newline();
+      t.blank();
+    }
+    t.token("fun");
+    t.space();
+    t.token(&f.name.to_string());
+    t.punct("(");
+    t.bindings(f.args);
+    t.punct(")");
+    t.space();
+    t.block(f.body);
+  }
+
+  return Ok(t.finish());
+}
+
+// A token of the source other than a parenthesis.
+
+struct SourceToken {
+  start: u32,
+  stop: u32,
+  blank_before: bool,
+}
+
+struct Comment {
+  pos: u32,
+  len: u32,
+  /// The index of the next source token.
+  before: u32,
+  /// Whether the comment follows a token on its line.
+  trailing: bool,
+  blank_before: bool,
+}
+
+struct Line {
+  depth: u32,
+  text: String,
+  trailing: Option<String>,
+}
+
+struct Printer<'a> {
+  source: &'a str,
+  tokens: Arr<SourceToken>,
+  comments: Arr<Comment>,
+  next_token: u32,
+  next_comment: u32,
+  depth: u32,
+  lines: Buf<Line>,
+  line: Line,
+  hoisted: Buf<String>,
+}
+
+// Whether `text` contains an empty line, that is, two line feeds.
+
+fn has_blank_line(text: &str) -> bool {
+  return text.matches('\n').count() >= 2;
+}
+
+impl<'a> Printer<'a> {
+  fn new(source: &'a str) -> Self {
+    let mut tokens = Buf::new();
+    let mut comments = Buf::new();
+    let mut lexer = Lexer::new(source.as_bytes());
+    let mut stop = 0;
+    let mut after_token = false;
+
+    loop {
+      // NB: the gap between two tokens only has spaces and comments.
+
+      let gap = &source[stop .. lexer.token_start()];
+      let mut i = 0;
+
+      while let Some(j) = gap[i ..].find('#') {
+        let start = i + j;
+        let end = gap[start ..].find('\n').map_or(gap.len(), |k| start + k);
+        let before = &gap[i .. start];
+        comments.push(Comment {
+          pos: (stop + start) as u32,
+          len: (end - start) as u32,
+          before: tokens.len(),
+          trailing: after_token && ! before.contains('\n'),
+          blank_before: has_blank_line(before),
+        });
+        after_token = false;
+        i = end;
+      }
+
+      if lexer.token() == Token::Eof { break; }
+
+      if ! matches!(lexer.token(), Token::LParen | Token::RParen) {
+        tokens.push(SourceToken {
+          start: lexer.token_start() as u32,
+          stop: lexer.token_stop() as u32,
+          blank_before: has_blank_line(&gap[i ..]),
+        });
+      }
+
+      after_token = true;
+      stop = lexer.token_stop();
+      lexer.next();
+    }
+
+    return Self {
+      source,
+      tokens: Arr::from(tokens.drain()),
+      comments: Arr::from(comments.drain()),
+      next_token: 0,
+      next_comment: 0,
+      depth: 0,
+      lines: Buf::new(),
+      line: Line { depth: 0, text: String::new(), trailing: None },
+      hoisted: Buf::new(),
+    };
+  }
+
+  fn comment_text(&self, k: u32) -> String {
+    let c = &self.comments[k];
+    return self.source[c.pos as usize .. (c.pos + c.len) as usize].trim_end().to_string();
+  }
+
+  // Starts a blank line, unless the last line is blank or opens a block.
+
+  fn blank(&mut self) {
+    if let Some(last) = self.lines.iter().last()
+      && ! (last.text.is_empty() && last.trailing.is_none())
+      && ! last.text.ends_with('{')
+    {
+      self.lines.push(Line { depth: 0, text: String::new(), trailing: None });
+    }
+  }
+
+  // Emits the comments that come before the next token, at the given depth if
+  // the line is empty, or else moves them up before the line.
+
+  fn leading_comments(&mut self, depth: u32) {
+    while self.next_comment < self.comments.len() && self.comments[self.next_comment].before <= self.next_token {
+      let k = self.next_comment;
+      let text = self.comment_text(k);
+      self.next_comment += 1;
+      if self.line.text.is_empty() {
+        if self.comments[k].blank_before { self.blank(); }
+        self.lines.push(Line { depth, text: String::new(), trailing: Some(text) });
+      } else {
+        self.hoist(text);
+      }
+    }
+  }
+
+  // Moves a comment up before the current line, after the comment that trails
+  // the line so far, if any, which keeps them in order.
+
+  fn hoist(&mut self, text: String) {
+    if let Some(t) = self.line.trailing.take() {
+      self.hoisted.push(t);
+    }
+    self.hoisted.push(text);
+  }
+
+  // Attaches the comments that trail the token that was just emitted.
+
+  fn trailing_comments(&mut self) {
+    while self.next_comment < self.comments.len()
+      && self.comments[self.next_comment].before == self.next_token
+      && self.comments[self.next_comment].trailing
+    {
+      let text = self.comment_text(self.next_comment);
+      self.next_comment += 1;
+      if let Some(t) = self.line.trailing.replace(text) {
+        self.hoisted.push(t);
+      }
+    }
+  }
+
+  fn has_leading_comments(&self) -> bool {
+    return self.next_comment < self.comments.len() && self.comments[self.next_comment].before <= self.next_token;
+  }
+
+  // Emits the next token of the source.
+
+  fn emit_token(&mut self, text: &str, depth: u32, allow_blank: bool) {
+    self.leading_comments(depth);
+
+    let t = &self.tokens[self.next_token];
+    debug_assert!(text == &self.source[t.start as usize .. t.stop as usize] || text.parse::<i64>().is_ok());
+
+    if self.line.text.is_empty() && allow_blank && t.blank_before {
+      self.blank();
+    }
+
+    self.line.text.push_str(text);
+    self.next_token += 1;
+    self.trailing_comments();
+  }
+
+  fn token(&mut self, text: &str) {
+    self.emit_token(text, self.depth, true);
+  }
+
+  // Emits a parenthesis, which need not be in the source.
+
+  fn punct(&mut self, text: &str) {
+    self.line.text.push_str(text);
+  }
+
+  fn space(&mut self) {
+    self.line.text.push(' ');
+  }
+
+  fn newline(&mut self) {
+    let line = std::mem::replace(&mut self.line, Line { depth: self.depth, text: String::new(), trailing: None });
+    for text in self.hoisted.drain() {
+      self.lines.push(Line { depth: line.depth, text: String::new(), trailing: Some(text) });
+    }
+    self.lines.push(line);
+  }
+
+  fn finish(mut self) -> String {
+    if ! self.line.text.is_empty() { self.newline(); }
+    self.leading_comments(0);
+
+    let mut out = String::new();
+
+    for line in self.lines.iter() {
+      if ! line.text.is_empty() || line.trailing.is_some() {
+        for _ in 0 .. line.depth { out.push_str("  "); }
+      }
+      out.push_str(&line.text);
+      if let Some(text) = &line.trailing {
+        if ! line.text.is_empty() { out.push(' '); }
+        out.push_str(text);
+      }
+      out.push('\n');
+    }
+
+    return out;
+  }
+
+  fn bindings(&mut self, xs: &[Binding]) {
+    for (i, x) in xs.iter().enumerate() {
+      if i != 0 {
+        self.token(",");
+        self.space();
+      }
+      match x.name {
+        None => self.token("_"),
+        Some(s) => self.token(&s.to_string()),
+      }
+    }
+  }
+
+  fn block(&mut self, xs: &[Stmt<'_>]) {
+    self.token("{");
+
+    if xs.is_empty() && ! self.has_leading_comments() && self.line.trailing.is_none() {
+      self.token("}");
+      return;
+    }
+
+    self.depth += 1;
+
+    for x in xs.iter() {
+      self.newline();
+      self.stmt(x);
+    }
+
+    self.depth -= 1;
+    self.newline();
+    self.emit_token("}", self.depth + 1, false);
+  }
+
+  fn exprs(&mut self, xs: &[Expr<'_>]) {
+    for (i, x) in xs.iter().enumerate() {
+      if i != 0 {
+        self.token(",");
+        self.space();
+      }
+      self.expr(x, P::Any);
+    }
+  }
+
+  // A keyword followed by a possibly empty list of expressions.
+
+  fn keyword_exprs(&mut self, keyword: &str, xs: &[Expr<'_>]) {
+    self.token(keyword);
+    if ! xs.is_empty() {
+      self.space();
+      self.exprs(xs);
+    }
+  }
+
+  fn stmt(&mut self, x: &Stmt<'_>) {
+    match *x {
+      Stmt::ExprList(xs) => {
+        // NB: a statement that starts with `-`, `++`, or `--` would continue
+        // the expression that ends the statement before it.
+        if let [x, ..] = xs && starts_with_operator(x) {
+          self.punct("(");
+          self.expr(x, P::Any);
+          self.punct(")");
+          for x in xs[1 ..].iter() {
+            self.token(",");
+            self.space();
+            self.expr(x, P::Any);
+          }
+        } else {
+          self.exprs(xs);
+        }
+      }
+      Stmt::Break(xs) => {
+        self.keyword_exprs("break", xs);
+      }
+      Stmt::Continue => {
+        self.token("continue");
+      }
+      Stmt::Let(xs, ys) => {
+        self.token("let");
+        self.space();
+        self.bindings(xs);
+        if ! xs.is_empty() { self.space(); }
+        self.token("=");
+        self.space();
+        self.exprs(ys);
+      }
+      Stmt::Return(xs) => {
+        self.keyword_exprs("return", xs);
+      }
+      Stmt::Set(s, ref y) => {
+        self.token(&s.to_string());
+        self.space();
+        self.token("=");
+        self.space();
+        self.expr(y, P::Any);
+      }
+      Stmt::SetField(ref x, s, ref y) => {
+        self.expr(x, P::Postfix);
+        self.token(&format!(".{}", s));
+        self.space();
+        self.token("=");
+        self.space();
+        self.expr(y, P::Any);
+      }
+      Stmt::SetIndex(ref x, ref i, ref y) => {
+        self.expr(x, P::Postfix);
+        self.token("[");
+        self.expr(i, P::Any);
+        self.token("]");
+        self.space();
+        self.token("=");
+        self.space();
+        self.expr(y, P::Any);
+      }
+      Stmt::Var(s, _, ref y) => {
+        self.token("var");
+        self.space();
+        self.token(&s.to_string());
+        self.space();
+        self.token("=");
+        self.space();
+        self.expr(y, P::Any);
+      }
+      Stmt::While(ref p, xs) => {
+        self.token("while");
+        self.space();
+        self.expr(p, P::Any);
+        self.space();
+        self.block(xs);
+      }
+    }
+  }
+
+  // Prints `x` where the parser expects an expression of precedence at least
+  // `min`, with parentheses if it has less.
+
+  fn expr(&mut self, x: &Expr<'_>, min: P) {
+    if prec(x) < min {
+      self.punct("(");
+      self.expr(x, P::Any);
+      self.punct(")");
+      return;
+    }
+
+    match *x {
+      Expr::And((x, y)) => {
+        self.binary(x, "&&", y, P::And);
+      }
+      Expr::Bool(p) => {
+        self.token(if p { "true" } else { "false" });
+      }
+      Expr::Call((f, xs)) => {
+        self.expr(f, P::Postfix);
+        self.punct("(");
+        self.exprs(xs);
+        self.punct(")");
+      }
+      Expr::Field((x, s)) => {
+        self.expr(x, P::Postfix);
+        self.token(&format!(".{}", s));
+      }
+      Expr::If((p, xs)) => {
+        self.token("if");
+        self.space();
+        self.expr(p, P::Any);
+        self.space();
+        self.block(xs);
+      }
+      Expr::IfElse((p, xs, ys)) => {
+        self.token("if");
+        self.space();
+        self.expr(p, P::Any);
+        self.space();
+        self.block(xs);
+        self.space();
+        self.token("else");
+        self.space();
+        self.block(ys);
+      }
+      Expr::Index((x, i)) => {
+        self.expr(x, P::Postfix);
+        self.token("[");
+        self.expr(i, P::Any);
+        self.token("]");
+      }
+      Expr::Int(n) => {
+        self.token(&n.to_string());
+      }
+      Expr::Loop(xs) => {
+        self.token("loop");
+        self.space();
+        self.block(xs);
+      }
+      Expr::Op1((op, x)) => {
+        self.token(op.as_str());
+        // NB: an operand that starts with an operator character would lex
+        // together with the operator, like `--x` or `-1`.
+        match *x {
+          Expr::Int(_) | Expr::Op1(_) | Expr::PreOp(_) => {
+            self.punct("(");
+            self.expr(x, P::Any);
+            self.punct(")");
+          }
+          _ => {
+            self.expr(x, P::Prefix);
+          }
+        }
+      }
+      Expr::Op2((op, x, y)) => {
+        self.binary(x, op.as_str(), y, P::of_op2(*op));
+      }
+      Expr::Or((x, y)) => {
+        self.binary(x, "||", y, P::Or);
+      }
+      Expr::PostOp((s, op)) => {
+        self.token(&s.to_string());
+        self.token(op.as_str());
+      }
+      Expr::PreOp((s, op)) => {
+        self.token(op.as_str());
+        self.token(&s.to_string());
+      }
+      Expr::Ternary((p, x, y)) => {
+        self.expr(p, P::Or);
+        self.space();
+        self.token("?");
+        self.space();
+        self.expr(x, P::Any);
+        self.space();
+        self.token(":");
+        self.space();
+        self.expr(y, P::Ternary);
+      }
+      Expr::Undefined => {
+        // NB: only a syntax error gives an undefined expression.
+        unreachable!()
+      }
+      Expr::Variable(s, _) => {
+        self.token(&s.to_string());
+      }
+    }
+  }
+
+  // Prints a left-associative binary operator at precedence `p`.
+
+  fn binary(&mut self, x: &Expr<'_>, op: &str, y: &Expr<'_>, p: P) {
+    self.expr(x, p);
+    self.space();
+    self.token(op);
+    self.space();
+    self.expr_above(y, p);
+  }
+
+  // Prints `y` where the parser expects an expression of precedence greater
+  // than `p`.
+
+  fn expr_above(&mut self, y: &Expr<'_>, p: P) {
+    if prec(y) <= p {
+      self.punct("(");
+      self.expr(y, P::Any);
+      self.punct(")");
+    } else {
+      self.expr(y, p);
+    }
+  }
+}
+
+// Whether `x` is printed starting with a prefix operator.
+
+fn starts_with_operator(x: &Expr<'_>) -> bool {
+  match *x {
+    Expr::Op1((Op1::Neg, _)) | Expr::PreOp(_) => true,
+    Expr::And((x, _)) | Expr::Or((x, _)) | Expr::Op2((_, x, _)) | Expr::Ternary((x, ..)) => starts_with_operator(x),
+    _ => false,
+  }
+}
+
+// The precedence of the outermost operator of `x`.
+//
+// NB: an `if` or `loop` expression could be an operand, but it is given the
+// lowest precedence so that it is parenthesized wherever it is not on its own.
+
+fn prec(x: &Expr<'_>) -> P {
+  match *x {
+    Expr::If(_) | Expr::IfElse(_) | Expr::Loop(_) => P::Any,
+    Expr::Ternary(_) => P::Ternary,
+    Expr::Or(_) => P::Or,
+    Expr::And(_) => P::And,
+    Expr::Op2((op, ..)) => P::of_op2(*op),
+    Expr::Op1(_) | Expr::PreOp(_) => P::Prefix,
+    // NB: these are atoms, but not ones that a postfix operator can follow
+    Expr::Int(_) | Expr::Bool(_) | Expr::PostOp(_) => P::Prefix,
+    Expr::Call(_) | Expr::Field(_) | Expr::Index(_) | Expr::Variable(..) | Expr::Undefined => P::Postfix,
+  }
+}
@@ -0,0 +1,269 @@
+//! garbage collected heap
+//!
+//! allocation requests + roots -> objects
+//!
+//! The heap is a space of 64-bit words. An object is a header word followed by
+//! its fields. The header holds the number of fields in its low half, and the
+//! number of leading fields that are references in its high half; the rest of
+//! the fields are plain data. An array of arrays has only references, an array
+//! of integers has none, and a closure puts its captured references before its
+//! code pointer and other captured data.
+//!
+//! A reference is the index of an object's header, so that `0` is never a
+//! valid object and can be used for fields that have not been initialized.
+//!
+//! An object can hold sub-objects inline, each of which is an inner header
+//! followed by its own fields, within the leading reference fields of its
+//! parent. A reference to a sub-object is an interior pointer: the index of
+//! its inner header. The inner header holds the distance back to the header
+//! of the outermost object, so that the collector keeps the whole object
+//! alive through an interior pointer, and it holds the length and number of
+//! references of the sub-object, so that the collector scans only the
+//! references of the sub-object and skips its plain data. Inner headers have
+//! their top bit set, which no reference has.
+//!
+//! When the space is full, live objects are copied to a fresh space with
+//! Cheney's algorithm, starting from the roots that the caller enumerates.
+//! Every root must be precise: it must be `0` or a reference. After a
+//! collection the space is grown if less than half of it is free.
+
+use crate::buf::Buf;
+
+/// The greatest length of an array. Every interpreter and backend raises
+/// `Trap::OutOfMemory` for a longer one.
+
+pub const MAX_LEN: u32 = 1 << 24;
+
+/// A set of roots that the collector can enumerate and update in place.
+
+pub trait Roots {
+  fn visit(&mut self, f: &mut dyn FnMut(&mut u64));
+}
+
+impl Roots for [u64] {
+  fn visit(&mut self, f: &mut dyn FnMut(&mut u64)) {
+    for x in self.iter_mut() { f(x); }
+  }
+}
+
+// NB: an object that has been copied has this many references in its header,
+// and its new location in place of its length. Its fields are left alone, so
+// that the inner headers of its sub-objects stay readable.
+
+const FORWARDED: u64 = 0xffff_ffff;
+
+// NB: an inner header is this bit, the distance to its outermost object in the
+// rest of the high half, and the number of references and fields in the two
+// quarters of the low half.
+
+const INNER: u64 = 1 << 63;
+
+pub struct Heap {
+  space: Buf<u64>,
+  capacity: u32,
+  collections: u32,
+}
+
+fn header(len: u32, refs: u32) -> u64 {
+  return (refs as u64) << 32 | len as u64;
+}
+
+fn inner_header(offset: u32, len: u32, refs: u32) -> u64 {
+  return INNER | (offset as u64) << 32 | (refs as u64) << 16 | len as u64;
+}
+
+// The number of fields and references of the object or sub-object with the
+// header `h`.
+
+fn shape(h: u64) -> (u32, u32) {
+  if h & INNER != 0 {
+    return (h as u16 as u32, (h >> 16) as u16 as u32);
+  }
+  return (h as u32, (h >> 32) as u32);
+}
+
+impl Heap {
+  pub fn new() -> Self {
+    return Self::with_capacity(1 << 16);
+  }
+
+  /// Creates a heap that collects once `capacity` words are in use.
+
+  pub fn with_capacity(capacity: u32) -> Self {
+    let mut space = Buf::new();
+    space.push(0);
+    return Self { space, capacity: capacity.max(2), collections: 0 };
+  }
+
+  /// The number of collections so far.
+
+  pub fn collections(&self) -> u32 {
+    return self.collections;
+  }
+
+  /// The number of words in use, including headers.
+
+  pub fn used(&self) -> u32 {
+    return self.space.len() - 1;
+  }
+
+  pub fn len(&self, r: u64) -> u32 {
+    return shape(self.space[r as u32]).0;
+  }
+
+  pub fn refs(&self, r: u64) -> u32 {
+    return shape(self.space[r as u32]).1;
+  }
+
+  /// The outermost object that contains `r`, which is `r` itself unless it is
+  /// an interior pointer.
+
+  pub fn base(&self, r: u64) -> u64 {
+    let h = self.space[r as u32];
+    if h & INNER == 0 { return r; }
+    return r - ((h & ! INNER) >> 32);
+  }
+
+  /// Makes field `i` of `r` the inner header of a sub-object with `len`
+  /// fields, of which the first `refs` are references, and returns an
+  /// interior pointer to it. The sub-object must fit within the leading
+  /// reference fields of `r`, which must be `0`.
+
+  pub fn set_inline(&mut self, r: u64, i: u32, len: u32, refs: u32) -> u64 {
+    debug_assert!(i + 1 + len <= self.refs(r) && refs <= len && len < 1 << 16);
+    debug_assert!(r + 1 + (i as u64) - self.base(r) < FORWARDED >> 1);
+    let p = r + 1 + i as u64;
+    let offset = p - self.base(r);
+    self.space[p as u32] = inner_header(offset as u32, len, refs);
+    return p;
+  }
+
+  /// The interior pointer to the sub-object whose inner header is field `i`
+  /// of `r`.
+
+  pub fn inline(&self, r: u64, i: u32) -> u64 {
+    debug_assert!(self.space[r as u32 + 1 + i] & INNER != 0);
+    return r + 1 + i as u64;
+  }
+
+  pub fn get(&self, r: u64, i: u32) -> u64 {
+    debug_assert!(i < self.len(r));
+    return self.space[r as u32 + 1 + i];
+  }
+
+  pub fn set(&mut self, r: u64, i: u32, x: u64) {
+    debug_assert!(i < self.len(r));
+    self.space[r as u32 + 1 + i] = x;
+  }
+
+  /// Allocates an object with `len` fields, of which the first `refs` are
+  /// references, and all of which are `0`. This may collect, which updates
+  /// `roots` and invalidates every other reference. Returns `None` if the
+  /// space would outgrow its 32-bit indices.
+
+  pub fn alloc(&mut self, len: u32, refs: u32, roots: &mut (impl Roots + ?Sized)) -> Option<u64> {
+    let size = len.checked_add(1)?;
+
+    if self.used().checked_add(size).is_none_or(|n| n > self.capacity) {
+      self.collect(roots);
+    }
+
+    return self.alloc_uncollected(len, refs);
+  }
+
+  /// Allocates like `alloc`, but grows the space instead of collecting. This
+  /// is for callers that hold references that are not roots.
+
+  pub fn alloc_uncollected(&mut self, len: u32, refs: u32) -> Option<u64> {
+    debug_assert!(refs <= len && (refs as u64) < FORWARDED);
+
+    let size = len.checked_add(1)?;
+    let r = self.space.len();
+    let end = r.checked_add(size)?;
+    self.space.push(header(len, refs));
+    while self.space.len() < end { self.space.push(0); }
+    self.capacity = self.capacity.max(self.used());
+    return Some(r as u64);
+  }
+
+  /// Copies the objects that are reachable from `roots` to a fresh space.
+
+  pub fn collect(&mut self, roots: &mut (impl Roots + ?Sized)) {
+    let mut to = Buf::new();
+    to.push(0);
+
+    roots.visit(&mut |x| *x = copy(&mut self.space, &mut to, *x));
+
+    let mut scan = 1;
+
+    while scan < to.len() {
+      let (len, refs) = shape(to[scan]);
+      scan_refs(&mut self.space, &mut to, scan + 1, refs);
+      scan += 1 + len;
+    }
+
+    self.space = to;
+    self.collections += 1;
+
+    if 2 * self.used() > self.capacity {
+      self.capacity = 2 * self.used();
+    }
+  }
+}
+
+impl Default for Heap {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+// Copies the object at `r` unless it has already been copied, and returns its
+// new location.
+
+fn copy(from: &mut Buf<u64>, to: &mut Buf<u64>, r: u64) -> u64 {
+  if r == 0 { return 0; }
+
+  let h = from[r as u32];
+
+  // NB: the high half of a forwarded header has the top bit set too.
+
+  if h >> 32 == FORWARDED {
+    return h as u32 as u64;
+  }
+
+  if h & INNER != 0 {
+    let offset = (h & ! INNER) >> 32;
+    return copy(from, to, r - offset) + offset;
+  }
+
+  let r = r as u32;
+
+  let len = h as u32;
+  let s = to.len();
+
+  for i in 0 .. 1 + len {
+    to.push(from[r + i]);
+  }
+
+  from[r] = header(s, FORWARDED as u32);
+  return s as u64;
+}
+
+// Copies the objects that the `refs` reference fields starting at `i` refer
+// to, skipping the plain data of the sub-objects among them.
+
+fn scan_refs(from: &mut Buf<u64>, to: &mut Buf<u64>, i: u32, refs: u32) {
+  let mut j = i;
+
+  while j < i + refs {
+    let x = to[j];
+    if x & INNER != 0 {
+      let (len, refs) = shape(x);
+      scan_refs(from, to, j + 1, refs);
+      j += 1 + len;
+    } else {
+      to[j] = copy(from, to, x);
+      j += 1;
+    }
+  }
+}
@@ -0,0 +1,292 @@
+//! inlining
+//!
+//! monomorphic typed bytecode -> equivalent monomorphic typed bytecode
+//!
+//! A call to a known function is replaced by a copy of the body of the callee,
+//! which saves the call and the return to its continuation, and exposes the
+//! body to the optimizations of the caller. A callee is inlined if it is small,
+//! or if this is the only place that calls it, but never into a function that
+//! it calls itself, directly or not, according to the call graph.
+//!
+//! The inliner works in rounds, each of which inlines the calls that are in the
+//! code at its start. An argument that is a known function is a known callee in
+//! the copy, which the next round can inline in turn, so that combinators like
+//! `apply1(id, x)` go away completely. The number of rounds and the size of a
+//! function are bounded, so recursion through function values can not unroll
+//! forever.
+//!
+//! In a copy, a `Ret` becomes a `Goto` to the continuation of the call, and a
+//! `TailCall` becomes a `Call` with that same continuation. If the call was a
+//! tail call itself, both stay as they are. The entry block of the callee is
+//! appended to the block of the call, with its arguments replaced by the values
+//! that were put for them, unless the callee jumps back to it.
+//!
+//! Functions are kept even if nothing calls them anymore, since they may still
+//! be entry points, or used as values.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::irp::Fun;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::util::components;
+
+const NONE: u32 = u32::MAX;
+
+// A callee with at most this many instructions is inlined at every call.
+
+const SMALL: u32 = 16;
+
+// Nothing more is inlined into a function with this many instructions.
+
+const MAX_SIZE: u32 = 1024;
+
+const MAX_ROUNDS: u32 = 8;
+
+/// Inlines calls to known functions in `module`, which must be monomorphic.
+
+pub fn inline(module: Module) -> Module {
+  let Module { code, decl, types } = module;
+
+  // NB: every body starts at point `0`, like in the optimization passes.
+
+  let mut bodies = Arr::new(decl.len(), |f| {
+    let f = &decl[f];
+    return Arr::new(f.len, |i| code[f.pos + i].map_points(|x| x - f.pos));
+  });
+
+  for _ in 0 .. MAX_ROUNDS {
+    let graph = CallGraph::new(&bodies);
+    let mut changed = false;
+
+    let new_bodies =
+      Arr::new(bodies.len(), |f| {
+        match inline_calls(&bodies, &graph, f) {
+          None => bodies[f].clone(),
+          Some(body) => {
+            changed = true;
+            body
+          }
+        }
+      });
+
+    bodies = new_bodies;
+    if ! changed { break; }
+  }
+
+  let mut out_code = Buf::new();
+  let mut out_decl = Buf::new();
+
+  for (f, body) in decl.iter().zip(bodies.iter()) {
+    let pos = out_code.len();
+    for &inst in body.iter() { out_code.push(inst.map_points(|x| x + pos)); }
+    out_decl.push(Fun { name: f.name, pos, len: body.len(), scheme: f.scheme });
+  }
+
+  return Module {
+    code: Arr::from(out_code.drain()),
+    decl: Arr::from(out_decl.drain()),
+    types,
+  };
+}
+
+// Which functions refer to which, and how often each one is called.
+
+struct CallGraph {
+  /// The number of calls to each function.
+  calls: Arr<u32>,
+  /// The number of references to each function, including the calls.
+  refs: Arr<u32>,
+  /// The strongly connected component of each function, where a function
+  /// refers to every function whose value it uses.
+  component: Arr<u32>,
+}
+
+impl CallGraph {
+  fn new(bodies: &Arr<Arr<Inst>>) -> Self {
+    let n = bodies.len();
+    let mut calls = Arr::new(n, |_| 0);
+    let mut refs = Arr::new(n, |_| 0);
+
+    let succs =
+      Arr::new(n, |f| {
+        let code = &bodies[f];
+        let mut out = Buf::new();
+        for &inst in code.iter() {
+          match inst {
+            Inst::ConstFun(k) => {
+              refs[k] += 1;
+              out.push(k);
+            }
+            Inst::Call(x) | Inst::TailCall(x) => {
+              if let Inst::ConstFun(k) = code[x] { calls[k] += 1; }
+            }
+            _ => {
+            }
+          }
+        }
+        return out;
+      });
+
+    return Self { calls, refs, component: components(&succs) };
+  }
+}
+
+// Inlines the calls in the function `f` that are worth it, if any.
+
+fn inline_calls(bodies: &Arr<Arr<Inst>>, graph: &CallGraph, f: u32) -> Option<Arr<Inst>> {
+  let code = &bodies[f];
+  let mut size = code.len();
+  let mut site = Arr::new(code.len(), |_| NONE);
+  let mut callee = Buf::new();
+
+  for i in 0 .. code.len() {
+    let (Inst::Call(x) | Inst::TailCall(x)) = code[i] else { continue; };
+    let Inst::ConstFun(k) = code[x] else { continue; };
+    let len = bodies[k].len();
+
+    if graph.component[k] == graph.component[f] { continue; }
+    if len > SMALL && (graph.calls[k] != 1 || graph.refs[k] != 1) { continue; }
+    if size + len > MAX_SIZE { continue; }
+
+    site[i] = callee.len();
+    callee.push(k);
+    size += len;
+  }
+
+  if callee.is_empty() { return None; }
+
+  // The values put for the arguments of each call, which replace them in the
+  // entry block of the callee if it is appended to the block of the call.
+
+  let mut args = Arr::new(callee.len(), |s| {
+    let Inst::Label(n) = bodies[callee[s]][0] else { unreachable!() };
+    return Arr::new(n, |_| NONE);
+  });
+
+  let mut put = Arr::new(code.len(), |_| NONE);
+
+  for i in 0 .. code.len() {
+    if site[i] == NONE { continue; }
+    let mut j = i;
+    while let Inst::Put(k, x) = code[j - 1] {
+      args[site[i]][k] = x;
+      put[j - 1] = site[i];
+      j -= 1;
+    }
+  }
+
+  let entry = Arr::new(callee.len(), |s| {
+    let body = &bodies[callee[s]];
+    let mut j = 1;
+    while let Inst::Get(..) = body[j] { j += 1; }
+    return j;
+  });
+
+  let appended = Arr::new(callee.len(), |s| {
+    return ! bodies[callee[s]].iter().any(|&inst| matches!(inst, Inst::Goto(0)));
+  });
+
+  let inliner = Inliner { bodies, code, site, callee, args, put, entry, appended };
+  let mut at = Arr::new(code.len(), |_| NONE);
+  let mut copy_at = Arr::new(inliner.callee.len(), |s| Arr::new(bodies[inliner.callee[s]].len(), |_| NONE));
+  let mut out = Buf::new();
+
+  // NB: the first pass finds where everything goes, so that the second one
+  // can refer to points further on.
+
+  inliner.emit(&mut at, &mut copy_at, &mut out);
+  out.clear();
+  inliner.emit(&mut at, &mut copy_at, &mut out);
+
+  return Some(Arr::from(out.drain()));
+}
+
+struct Inliner<'a> {
+  bodies: &'a Arr<Arr<Inst>>,
+  code: &'a Arr<Inst>,
+  /// The call that each point is, if it is inlined.
+  site: Arr<u32>,
+  /// The function that each inlined call calls.
+  callee: Buf<u32>,
+  /// The values put for the arguments of each inlined call.
+  args: Arr<Arr<u32>>,
+  /// The inlined call that each point puts an argument for, if any.
+  put: Arr<u32>,
+  /// The number of points of the label and arguments of each callee.
+  entry: Arr<u32>,
+  /// Whether the entry block of each callee is appended to the block of its
+  /// call.
+  appended: Arr<bool>,
+}
+
+impl<'a> Inliner<'a> {
+  fn emit(&self, at: &mut Arr<u32>, copy_at: &mut Arr<Arr<u32>>, out: &mut Buf<Inst>) {
+    let code = self.code;
+    let mut i = 0;
+
+    while i < code.len() {
+      let s = self.site[i];
+
+      if s == NONE {
+        if self.put[i] == NONE || ! self.appended[self.put[i]] {
+          let inst = code[i].map_points(|x| at[x]);
+          at[i] = out.len();
+          out.push(inst);
+        }
+        i += 1;
+        continue;
+      }
+
+      let body = &self.bodies[self.callee[s]];
+      let appended = self.appended[s];
+      let entry = if appended { self.entry[s] } else { 0 };
+
+      // NB: the continuation of a tail call is the one of the caller.
+
+      let cont =
+        match code[i] {
+          Inst::Call(_) => {
+            let Inst::Goto(a) = code[i + 1] else { unreachable!() };
+            Some(at[a])
+          }
+          _ => None,
+        };
+
+      if ! appended {
+        out.push(Inst::Goto(copy_at[s][0]));
+      }
+
+      for j in 0 .. body.len() {
+        let value = |x: u32| {
+          if x < entry && let Inst::Get(k, _) = body[x] {
+            return at[self.args[s][k]];
+          }
+          return copy_at[s][x];
+        };
+
+        if j < entry { continue; }
+
+        match (body[j], cont) {
+          (Inst::Ret, Some(a)) => {
+            copy_at[s][j] = out.len();
+            out.push(Inst::Goto(a));
+          }
+          (Inst::TailCall(x), Some(a)) => {
+            let x = value(x);
+            copy_at[s][j] = out.len();
+            out.push(Inst::Call(x));
+            out.push(Inst::Goto(a));
+          }
+          (inst, _) => {
+            let inst = inst.map_points(value);
+            copy_at[s][j] = out.len();
+            out.push(inst);
+          }
+        }
+      }
+
+      i += if cont.is_some() { 2 } else { 1 };
+    }
+  }
+}
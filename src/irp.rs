@@ -3,12 +3,23 @@
 // - bytecode
 // - typed
 // - polymorphic
+//
+// On elaboration, program points are numbered exactly as in the untyped
+// representation that the module was produced from. Only `Get` and `Const`
+// carry their types explicitly; the type of every other value follows from its
+// operands.
+//
+// After monomorphization, every function is monomorphic, and references to
+// functions are `ConstFun` indices into `decl` rather than symbols.
 
 use crate::arr::Arr;
 use crate::prim::PrimOp1;
 use crate::prim::PrimOp2;
-use crate::prim::PrimType;
 use crate::symbol::Symbol;
+use crate::typecheck::TypeScheme;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
 
 type Arity = u32;
 type Index = u32;
@@ -16,12 +27,12 @@ type Label = u32;
 type Local = u32;
 type Value = u32;
 
-type TupleType = u32;
-type ValueType = u32;
+type ValueType = TypeId;
 
 pub struct Module {
   pub code: Arr<Inst>,
   pub decl: Arr<Fun>,
+  pub types: TypeStore,
 }
 
 #[derive(Debug)]
@@ -29,15 +40,7 @@ pub struct Fun {
   pub name: Symbol,
   pub pos: u32,
   pub len: u32,
-}
-
-#[derive(Clone, Copy)]
-pub enum Type {
-  Array(ValueType),
-  Fun(TupleType, TupleType),
-  Prim(PrimType),
-  Tuple(Arity),
-  TupleElt(ValueType),
+  pub scheme: TypeScheme,
 }
 
 #[derive(Clone, Copy)]
@@ -50,13 +53,143 @@ pub enum Inst {
   Cond(Value),
   Ret,
   Call(Value),
+  /// Runs in constant space, like `iru::Inst::TailCall`.
   TailCall(Value),
   Const(Symbol, ValueType),
+  ConstFun(Index),
   ConstBool(bool),
   ConstInt(i64),
+  Index(Value, Value),
   PrimOp1(PrimOp1, Value),
   PrimOp2(PrimOp2, Value, Value),
   Local(Value),
   GetLocal(Local),
+  SetIndex(Value, Value, Value),
   SetLocal(Local, Value),
 }
+
+impl Inst {
+  /// Applies `f` to every operand that refers to a program point, i.e., to
+  /// every value, label, and local.
+
+  pub fn map_points(self, f: impl FnMut(u32) -> u32) -> Self {
+    let mut f = f;
+    match self {
+      | Self::GotoStaticError
+      | Self::Label(..)
+      | Self::Get(..)
+      | Self::Ret
+      | Self::Const(..)
+      | Self::ConstFun(..)
+      | Self::ConstBool(..)
+      | Self::ConstInt(..) =>
+        self,
+      Self::Put(i, x) => Self::Put(i, f(x)),
+      Self::Goto(a) => Self::Goto(f(a)),
+      Self::Cond(x) => Self::Cond(f(x)),
+      Self::Call(x) => Self::Call(f(x)),
+      Self::TailCall(x) => Self::TailCall(f(x)),
+      Self::Index(x, y) => Self::Index(f(x), f(y)),
+      Self::PrimOp1(op, x) => Self::PrimOp1(op, f(x)),
+      Self::PrimOp2(op, x, y) => Self::PrimOp2(op, f(x), f(y)),
+      Self::Local(x) => Self::Local(f(x)),
+      Self::GetLocal(v) => Self::GetLocal(f(v)),
+      Self::SetIndex(x, y, z) => Self::SetIndex(f(x), f(y), f(z)),
+      Self::SetLocal(v, x) => Self::SetLocal(f(v), f(x)),
+    }
+  }
+}
+
+impl Module {
+  /// Computes the type of every value in the module, indexed by program
+  /// point. Points that do not define a value have no type.
+
+  pub fn value_types(&self) -> Arr<Option<TypeId>> {
+    let mut out = Arr::new(self.code.len(), |_| None);
+
+    for (i, &inst) in self.code.iter().enumerate() {
+      let i = i as u32;
+      out[i] =
+        match inst {
+          Inst::Get(_, t) | Inst::Const(_, t) =>
+            Some(t),
+          Inst::ConstFun(k) =>
+            Some(self.decl[k].scheme.1),
+          Inst::ConstBool(_) =>
+            Some(TypeStore::BOOL),
+          Inst::ConstInt(_) =>
+            Some(TypeStore::I64),
+          Inst::Index(x, _) => {
+            let Some(t) = out[x] else { unreachable!() };
+            let Type::Array(a) = self.types[t] else { unreachable!() };
+            Some(a)
+          }
+          Inst::PrimOp1(f, _) =>
+            Some(self.types.prim(f.out_type())),
+          Inst::PrimOp2(f, _, _) =>
+            Some(self.types.prim(f.out_type())),
+          Inst::Local(x) | Inst::GetLocal(x) =>
+            out[x],
+          | Inst::GotoStaticError
+          | Inst::Label(..)
+          | Inst::Put(..)
+          | Inst::Goto(..)
+          | Inst::Cond(..)
+          | Inst::Ret
+          | Inst::Call(..)
+          | Inst::TailCall(..)
+          | Inst::SetIndex(..)
+          | Inst::SetLocal(..) =>
+            None,
+        };
+    }
+
+    return out;
+  }
+}
+
+impl std::fmt::Display for Inst {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Label(n) => write!(f, "LABEL {}", n),
+      Self::Get(i, _) => write!(f, "= GET {}", i),
+      Self::Put(i, x) => write!(f, "PUT {} %{}", i, x),
+      Self::Goto(x) => write!(f, "==> GOTO %{}", x),
+      Self::GotoStaticError => write!(f, "==> GOTO-STATIC-ERROR"),
+      Self::Cond(x) => write!(f, "COND %{}", x),
+      Self::Ret => write!(f, "RET"),
+      Self::Call(x) => write!(f, "CALL %{}", x),
+      Self::TailCall(x) => write!(f, "TAIL-CALL %{}", x),
+      Self::Const(s, _) => write!(f, "= CONST {}", s),
+      Self::ConstFun(k) => write!(f, "= FUN #{}", k),
+      Self::ConstBool(p) => write!(f, "= {}", p),
+      Self::ConstInt(n) => write!(f, "= {}", n),
+      Self::Index(x, y) => write!(f, "= %{} [ %{} ]", x, y),
+      Self::PrimOp1(op, x) => write!(f, "= {} %{}", op, x),
+      Self::PrimOp2(op, x, y) => write!(f, "= {} %{} %{}", op, x, y),
+      Self::Local(x) => write!(f, "= LOCAL %{}", x),
+      Self::GetLocal(v) => write!(f, "= [ %{} ]", v),
+      Self::SetIndex(x, y, z) => write!(f, "%{} [ %{} ] <- %{}", x, y, z),
+      Self::SetLocal(v, x) => write!(f, "[ %{} ] <- %{}", v, x),
+    }
+  }
+}
+
+impl std::fmt::Display for Module {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let value_types = self.value_types();
+
+    for g in self.decl.iter() {
+      write!(f, "=== fun {} : {} ===\n", g.name, g.scheme.display(&self.types))?;
+
+      for i in g.pos .. g.pos + g.len {
+        match value_types[i] {
+          None => write!(f, "%{} {}\n", i, self.code[i])?,
+          Some(t) => write!(f, "%{} {} : {}\n", i, self.code[i], self.types.display(t))?,
+        }
+      }
+    }
+
+    return Ok(());
+  }
+}
@@ -15,21 +15,45 @@ type Label = u32;
 type Local = u32;
 type Value = u32;
 
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Module {
   pub code: Arr<Inst>,
   pub decl: Arr<Fun>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Fun {
   pub name: Symbol,
   pub pos: u32,
   pub len: u32,
 }
 
+/// Where the code of a module came from in its source.
+
+pub struct SourceMap {
+  /// The byte offset of each instruction, which is that of the nearest name
+  /// that was lowered before it in its function.
+  pub pos: Arr<u32>,
+  /// Every name in the source, in order of lowering.
+  pub names: Arr<Name>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Name {
+  pub symbol: Symbol,
+  /// The byte offset of the name.
+  pub pos: u32,
+  /// The byte offset of the binding of the name, if it is bound. A binding is
+  /// its own definition.
+  pub def: Option<u32>,
+  /// The program point whose value the name refers to, if there is one. The
+  /// name of a function that is being defined has no value.
+  pub value: Option<Value>,
+}
+
 // TODO: add type ascription
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Inst {
   GotoStaticError,
   Label(Arity),
@@ -39,6 +63,11 @@ pub enum Inst {
   Cond(Value),
   Ret,
   Call(Value),
+  /// Calls a function with the values put before it, and returns its results
+  /// from the current function. The frame of the current function is gone by
+  /// the time that the callee runs, so every execution engine runs a chain of
+  /// tail calls of any length in constant space, whether the callee is named
+  /// or a function value, and whether or not it tail calls back.
   TailCall(Value),
   Const(Symbol),
   ConstBool(bool),
@@ -54,6 +83,53 @@ pub enum Inst {
   SetLocal(Local, Value),
 }
 
+impl Inst {
+  /// Applies `f` to every operand that refers to a program point, i.e., to
+  /// every value, label, and local.
+
+  pub fn map_points(self, f: impl FnMut(u32) -> u32) -> Self {
+    let mut f = f;
+    match self {
+      | Self::GotoStaticError
+      | Self::Label(..)
+      | Self::Get(..)
+      | Self::Ret
+      | Self::Const(..)
+      | Self::ConstBool(..)
+      | Self::ConstInt(..) =>
+        self,
+      Self::Put(i, x) => Self::Put(i, f(x)),
+      Self::Goto(a) => Self::Goto(f(a)),
+      Self::Cond(x) => Self::Cond(f(x)),
+      Self::Call(x) => Self::Call(f(x)),
+      Self::TailCall(x) => Self::TailCall(f(x)),
+      Self::Field(x, s) => Self::Field(f(x), s),
+      Self::Index(x, y) => Self::Index(f(x), f(y)),
+      Self::Op1(op, x) => Self::Op1(op, f(x)),
+      Self::Op2(op, x, y) => Self::Op2(op, f(x), f(y)),
+      Self::Local(x) => Self::Local(f(x)),
+      Self::GetLocal(v) => Self::GetLocal(f(v)),
+      Self::SetField(x, s, y) => Self::SetField(f(x), s, f(y)),
+      Self::SetIndex(x, y, z) => Self::SetIndex(f(x), f(y), f(z)),
+      Self::SetLocal(v, x) => Self::SetLocal(f(v), f(x)),
+    }
+  }
+}
+
+// NB: `parse_iru` reads this format back.
+
+impl std::fmt::Display for Module {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for g in self.decl.iter() {
+      writeln!(f, "=== fun {} ===", g.name)?;
+      for i in g.pos .. g.pos + g.len {
+        writeln!(f, "%{} {}", i, self.code[i])?;
+      }
+    }
+    Ok(())
+  }
+}
+
 impl std::fmt::Display for Inst {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
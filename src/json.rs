@@ -0,0 +1,311 @@
+//! JSON values
+//!
+//! text <-> values
+//!
+//! Just enough of JSON for the language server. Numbers are `f64`, like in
+//! JavaScript, and are printed without a fraction when they are integers.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+
+#[derive(Clone, Debug)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Arr<Json>),
+  Object(Arr<(String, Json)>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Error {
+  /// The byte offset where the text stopped being JSON.
+  pub pos: usize,
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "invalid JSON at byte {}", self.pos)
+  }
+}
+
+impl Json {
+  pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Json), IntoIter: ExactSizeIterator>) -> Self {
+    return Json::Object(Arr::from(fields.into_iter().map(|(k, v)| (k.to_string(), v))));
+  }
+
+  pub fn array(elts: impl IntoIterator<Item = Json, IntoIter: ExactSizeIterator>) -> Self {
+    return Json::Array(Arr::from(elts));
+  }
+
+  /// The field `key` of an object.
+
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    if let Json::Object(fields) = self {
+      for (k, v) in fields.iter() {
+        if k == key { return Some(v); }
+      }
+    }
+    return None;
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    if let Json::String(s) = self { return Some(s); }
+    return None;
+  }
+
+  pub fn as_u32(&self) -> Option<u32> {
+    if let &Json::Number(n) = self && n >= 0. && n <= u32::MAX as f64 && n.fract() == 0. {
+      return Some(n as u32);
+    }
+    return None;
+  }
+
+  pub fn as_array(&self) -> Option<&Arr<Json>> {
+    if let Json::Array(a) = self { return Some(a); }
+    return None;
+  }
+
+  pub fn parse(text: &str) -> Result<Json, Error> {
+    let mut t = Parser { text: text.as_bytes(), pos: 0 };
+    let x = t.value()?;
+    t.space();
+    if t.pos != t.text.len() { return Err(Error { pos: t.pos }); }
+    return Ok(x);
+  }
+}
+
+impl From<&str> for Json {
+  fn from(s: &str) -> Self {
+    return Json::String(s.to_string());
+  }
+}
+
+impl From<String> for Json {
+  fn from(s: String) -> Self {
+    return Json::String(s);
+  }
+}
+
+impl From<u32> for Json {
+  fn from(n: u32) -> Self {
+    return Json::Number(n as f64);
+  }
+}
+
+impl From<bool> for Json {
+  fn from(p: bool) -> Self {
+    return Json::Bool(p);
+  }
+}
+
+impl std::fmt::Display for Json {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(p) => write!(f, "{}", p),
+      Json::Number(n) => {
+        if n.fract() == 0. && n.abs() < (1u64 << 53) as f64 {
+          write!(f, "{}", *n as i64)
+        } else {
+          write!(f, "{}", n)
+        }
+      }
+      Json::String(s) => string(f, s),
+      Json::Array(a) => {
+        write!(f, "[")?;
+        for (i, x) in a.iter().enumerate() {
+          if i != 0 { write!(f, ",")?; }
+          write!(f, "{}", x)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(a) => {
+        write!(f, "{{")?;
+        for (i, (k, v)) in a.iter().enumerate() {
+          if i != 0 { write!(f, ",")?; }
+          string(f, k)?;
+          write!(f, ":{}", v)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+fn string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+  write!(f, "\"")?;
+  for c in s.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      '\0' ..= '\x1f' => write!(f, "\\u{:04x}", c as u32)?,
+      _ => write!(f, "{}", c)?,
+    }
+  }
+  return write!(f, "\"");
+}
+
+struct Parser<'a> {
+  text: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn error<T>(&self) -> Result<T, Error> {
+    return Err(Error { pos: self.pos });
+  }
+
+  fn peek(&self) -> Option<u8> {
+    return self.text.get(self.pos).copied();
+  }
+
+  fn space(&mut self) {
+    while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+      self.pos += 1;
+    }
+  }
+
+  fn keyword(&mut self, word: &[u8], x: Json) -> Result<Json, Error> {
+    if ! self.text[self.pos ..].starts_with(word) { return self.error(); }
+    self.pos += word.len();
+    return Ok(x);
+  }
+
+  fn value(&mut self) -> Result<Json, Error> {
+    self.space();
+
+    match self.peek() {
+      Some(b'n') => {
+        return self.keyword(b"null", Json::Null);
+      }
+      Some(b't') => {
+        return self.keyword(b"true", Json::Bool(true));
+      }
+      Some(b'f') => {
+        return self.keyword(b"false", Json::Bool(false));
+      }
+      Some(b'"') => {
+        return Ok(Json::String(self.string()?));
+      }
+      Some(b'[') => {
+        self.pos += 1;
+        let mut elts = Buf::new();
+        self.space();
+        if self.peek() == Some(b']') {
+          self.pos += 1;
+          return Ok(Json::Array(Arr::from(elts.drain())));
+        }
+        loop {
+          elts.push(self.value()?);
+          self.space();
+          match self.peek() {
+            Some(b',') => { self.pos += 1; }
+            Some(b']') => { self.pos += 1; break; }
+            _ => { return self.error(); }
+          }
+        }
+        return Ok(Json::Array(Arr::from(elts.drain())));
+      }
+      Some(b'{') => {
+        self.pos += 1;
+        let mut fields = Buf::new();
+        self.space();
+        if self.peek() == Some(b'}') {
+          self.pos += 1;
+          return Ok(Json::Object(Arr::from(fields.drain())));
+        }
+        loop {
+          self.space();
+          if self.peek() != Some(b'"') { return self.error(); }
+          let k = self.string()?;
+          self.space();
+          if self.peek() != Some(b':') { return self.error(); }
+          self.pos += 1;
+          fields.push((k, self.value()?));
+          self.space();
+          match self.peek() {
+            Some(b',') => { self.pos += 1; }
+            Some(b'}') => { self.pos += 1; break; }
+            _ => { return self.error(); }
+          }
+        }
+        return Ok(Json::Object(Arr::from(fields.drain())));
+      }
+      Some(b'-' | b'0' ..= b'9') => {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0' ..= b'9') = self.peek() {
+          self.pos += 1;
+        }
+        let s = str::from_utf8(&self.text[start .. self.pos]).unwrap();
+        let Ok(n) = s.parse::<f64>() else { return Err(Error { pos: start }); };
+        return Ok(Json::Number(n));
+      }
+      _ => {
+        return self.error();
+      }
+    }
+  }
+
+  fn hex4(&mut self) -> Result<u32, Error> {
+    let Some(s) = self.text.get(self.pos .. self.pos + 4) else { return self.error(); };
+    let Ok(n) = u32::from_str_radix(str::from_utf8(s).unwrap_or("?"), 16) else { return self.error(); };
+    self.pos += 4;
+    return Ok(n);
+  }
+
+  fn string(&mut self) -> Result<String, Error> {
+    let mut s = String::new();
+    self.pos += 1;
+
+    loop {
+      let start = self.pos;
+      while let Some(c) = self.peek() && c != b'"' && c != b'\\' && c >= 0x20 {
+        self.pos += 1;
+      }
+      let Ok(t) = str::from_utf8(&self.text[start .. self.pos]) else { return Err(Error { pos: start }); };
+      s.push_str(t);
+
+      match self.peek() {
+        Some(b'"') => {
+          self.pos += 1;
+          return Ok(s);
+        }
+        Some(b'\\') => {
+          self.pos += 1;
+          let c = self.peek();
+          self.pos += 1;
+          match c {
+            Some(b'"') => s.push('"'),
+            Some(b'\\') => s.push('\\'),
+            Some(b'/') => s.push('/'),
+            Some(b'b') => s.push('\x08'),
+            Some(b'f') => s.push('\x0c'),
+            Some(b'n') => s.push('\n'),
+            Some(b'r') => s.push('\r'),
+            Some(b't') => s.push('\t'),
+            Some(b'u') => {
+              let mut n = self.hex4()?;
+              if (0xd800 .. 0xdc00).contains(&n) && self.text[self.pos ..].starts_with(b"\\u") {
+                self.pos += 2;
+                let m = self.hex4()?;
+                n = 0x10000 + ((n - 0xd800) << 10) + (m.wrapping_sub(0xdc00) & 0x3ff);
+              }
+              s.push(char::from_u32(n).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            _ => {
+              return Err(Error { pos: self.pos - 1 });
+            }
+          }
+        }
+        _ => {
+          return self.error();
+        }
+      }
+    }
+  }
+}
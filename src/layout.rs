@@ -0,0 +1,267 @@
+//! object layout
+//!
+//! record and variant shapes -> field offsets
+//!
+//! A record is laid out as its reference fields, then its inline fields, then
+//! its plain data fields, so that everything the collector must scan is in the
+//! leading reference fields of the object, like the heap requires. An inline
+//! field is a sub-object that is stored by value: its inner header followed by
+//! its own fields, laid out by the same rules. A reference to it is an
+//! interior pointer to its inner header.
+//!
+//! A variant is laid out like a record whose reference fields have room for
+//! those of its largest case, followed by its tag, followed by room for the
+//! data fields of its largest case. Every case starts at the same offsets, so
+//! the reference fields that a case does not use must be `0`, which `init`
+//! takes care of when it sets the case.
+//!
+//! Values of monomorphic types need not be word-sized. `repr` computes the
+//! size, alignment, and ABI class of a type that a value can have: a `bool` is
+//! a byte, and everything else is a word. The x86-64, LLVM, and WebAssembly
+//! backends store array elements at this size, so that an `Array[bool]` is an
+//! array of bytes. The typed interpreter's heap still uses a word per element,
+//! and the C backend a C type per element. Tuples are not values, so there is
+//! no array of tuples to lay out.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::heap::Heap;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Field {
+  /// A plain data word.
+  Data,
+  /// A reference to another object.
+  Ref,
+  /// A sub-object with the given shape, stored by value.
+  Inline(u32),
+}
+
+pub enum Shape {
+  Record(Arr<Field>),
+  Variant(Arr<Arr<Field>>),
+}
+
+#[derive(Debug)]
+pub struct Layout {
+  pub len: u32,
+  pub refs: u32,
+  /// The field that holds the case of a variant.
+  pub tag: Option<u32>,
+  /// The offset of every field of every case, where a record has one case.
+  /// The offset of an inline field is that of its inner header.
+  pub offsets: Arr<Arr<u32>>,
+  /// The offset, length, and number of references of every sub-object of
+  /// every case, including the sub-objects of sub-objects that are records.
+  pub inline: Arr<Arr<(u32, u32, u32)>>,
+}
+
+/// How a value is passed and stored.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Class {
+  /// A byte that is `0` or `1`.
+  Bool,
+  /// A 64-bit integer.
+  Int,
+  /// A reference into the heap.
+  Ref,
+  /// The address of a function.
+  Code,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Repr {
+  /// The size in bytes, which is a multiple of the alignment, so that it is
+  /// also the stride of an array.
+  pub size: u32,
+  pub align: u32,
+  pub class: Class,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The given shape contains itself inline.
+  Cycle(u32),
+  /// The given shape is inline, and has more fields than an inner header can
+  /// describe.
+  TooLarge(u32),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::Cycle(k) => write!(f, "shape {} contains itself inline", k),
+      Self::TooLarge(k) => write!(f, "shape {} is too large to be inline", k),
+    }
+  }
+}
+
+impl Layout {
+  /// Sets the case of the object or sub-object `r` with this layout, which
+  /// clears its reference fields and writes the inner headers of the case.
+
+  pub fn init(&self, heap: &mut Heap, r: u64, case: u32) {
+    for i in 0 .. self.refs {
+      heap.set(r, i, 0);
+    }
+    for &(i, len, refs) in self.inline[case].iter() {
+      let _ = heap.set_inline(r, i, len, refs);
+    }
+    if let Some(i) = self.tag {
+      heap.set(r, i, case as u64);
+    }
+  }
+}
+
+/// The representation of the monomorphic type `t`, which is not a tuple.
+
+pub fn repr(types: &TypeStore, t: TypeId) -> Repr {
+  let word = |class| Repr { size: 8, align: 8, class };
+
+  match types[t] {
+    Type::Bool => {
+      return Repr { size: 1, align: 1, class: Class::Bool };
+    }
+    Type::I64 => {
+      return word(Class::Int);
+    }
+    Type::Array(_) => {
+      return word(Class::Ref);
+    }
+    Type::Fun(..) => {
+      return word(Class::Code);
+    }
+    Type::Tuple(..) | Type::TupleElt(..) | Type::Var(_) => {
+      unreachable!()
+    }
+  }
+}
+
+/// Lays out every shape.
+
+pub fn layout(shapes: &[Shape]) -> Result<Arr<Layout>, Error> {
+  let mut ctx =
+    Ctx {
+      shapes,
+      layouts: Arr::new(shapes.len() as u32, |_| None),
+      active: Arr::new(shapes.len() as u32, |_| false),
+    };
+
+  for k in 0 .. shapes.len() as u32 {
+    ctx.visit(k)?;
+  }
+
+  return Ok(Arr::new(shapes.len() as u32, |k| ctx.layouts[k].take().unwrap()));
+}
+
+struct Ctx<'a> {
+  shapes: &'a [Shape],
+  layouts: Arr<Option<Layout>>,
+  active: Arr<bool>,
+}
+
+// The sizes of a case: its reference fields including its sub-objects, and its
+// data fields.
+
+fn sizes(ctx: &Ctx<'_>, fields: &Arr<Field>) -> (u32, u32) {
+  let mut refs = 0;
+  let mut data = 0;
+
+  for &field in fields.iter() {
+    match field {
+      Field::Data => { data += 1; }
+      Field::Ref => { refs += 1; }
+      Field::Inline(k) => { refs += 1 + ctx.layouts[k].as_ref().unwrap().len; }
+    }
+  }
+
+  return (refs, data);
+}
+
+impl<'a> Ctx<'a> {
+  fn visit(&mut self, k: u32) -> Result<(), Error> {
+    if self.layouts[k].is_some() { return Ok(()); }
+    if self.active[k] { return Err(Error::Cycle(k)); }
+
+    self.active[k] = true;
+
+    let cases: Arr<&Arr<Field>> =
+      match &self.shapes[k as usize] {
+        Shape::Record(fields) => Arr::from([fields]),
+        Shape::Variant(cases) => Arr::from(cases.iter()),
+      };
+
+    for fields in cases.iter() {
+      for &field in fields.iter() {
+        if let Field::Inline(j) = field {
+          self.visit(j)?;
+          if self.layouts[j].as_ref().unwrap().len >= 1 << 16 {
+            return Err(Error::TooLarge(j));
+          }
+        }
+      }
+    }
+
+    let is_variant = matches!(self.shapes[k as usize], Shape::Variant(_));
+    let sizes: Arr<(u32, u32)> = Arr::from(cases.iter().map(|fields| sizes(self, fields)));
+    let refs = sizes.iter().map(|s| s.0).max().unwrap_or(0);
+    let data = sizes.iter().map(|s| s.1).max().unwrap_or(0);
+    let tag = if is_variant { Some(refs) } else { None };
+    let len = refs + tag.map_or(0, |_| 1) + data;
+
+    let mut offsets = Buf::new();
+    let mut inline = Buf::new();
+
+    for fields in cases.iter() {
+      let mut next_ref = 0;
+      let mut next_inline = fields.iter().filter(|&&f| f == Field::Ref).count() as u32;
+      let mut next_data = len - data;
+      let mut case_offsets = Buf::new();
+      let mut case_inline = Buf::new();
+
+      for &field in fields.iter() {
+        match field {
+          Field::Data => {
+            case_offsets.push(next_data);
+            next_data += 1;
+          }
+          Field::Ref => {
+            case_offsets.push(next_ref);
+            next_ref += 1;
+          }
+          Field::Inline(j) => {
+            let child = self.layouts[j].as_ref().unwrap();
+            case_offsets.push(next_inline);
+            case_inline.push((next_inline, child.len, child.refs));
+            if child.tag.is_none() {
+              for &(i, len, refs) in child.inline[0].iter() {
+                case_inline.push((next_inline + 1 + i, len, refs));
+              }
+            }
+            next_inline += 1 + child.len;
+          }
+        }
+      }
+
+      offsets.push(Arr::from(case_offsets.iter().copied()));
+      inline.push(Arr::from(case_inline.iter().copied()));
+    }
+
+    self.layouts[k] =
+      Some(Layout {
+        len,
+        refs,
+        tag,
+        offsets: Arr::from(offsets.drain()),
+        inline: Arr::from(inline.drain()),
+      });
+
+    self.active[k] = false;
+
+    return Ok(());
+  }
+}
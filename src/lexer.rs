@@ -197,17 +197,25 @@ impl<'a> Lexer<'a> {
           },
         2 =>
           match unsafe { self.source.get_unchecked(start .. stop) } {
+            b"+|" => Token::AddSat,
             b"&&" => Token::And,
             b"==" => Token::CmpEq,
             b">=" => Token::CmpGe,
             b"<=" => Token::CmpLe,
             b"!=" => Token::CmpNe,
             b"--" => Token::Dec,
+            b"/|" => Token::DivSat,
+            b"/%" => Token::DivWrap,
             b"..." => Token::DotDotDot,
             b"++" => Token::Inc,
+            b"*|" => Token::MulSat,
             b"||" => Token::Or,
             b"<<" => Token::Shl,
+            b"<<|" => Token::ShlSat,
+            b"<<%" => Token::ShlWrap,
             b">>" => Token::Shr,
+            b">>%" => Token::ShrWrap,
+            b"-|" => Token::SubSat,
             _ => Token::Error,
           },
         9 =>
@@ -3,13 +3,33 @@
 pub mod arr;
 pub mod ast;
 pub mod buf;
+pub mod cfg;
+pub mod driver;
+pub mod emit_c;
+pub mod emit_llvm;
+pub mod emit_wat;
+pub mod emit_x64;
+pub mod eval_irp;
+pub mod eval_iru;
+pub mod format;
+pub mod heap;
+pub mod inline;
 pub mod irp;
 pub mod iru;
+pub mod json;
+pub mod layout;
 pub mod lexer;
+pub mod lsp;
+pub mod make_irp;
 pub mod make_iru;
+pub mod mono;
 pub mod operator;
+pub mod opt;
 pub mod parse;
+pub mod parse_iru;
 pub mod prim;
+pub mod promote;
+pub mod repl;
 pub mod symbol;
 pub mod token;
 pub mod typecheck;
@@ -17,6 +37,7 @@ pub mod typeid;
 pub mod typestore;
 pub mod unionfind;
 pub mod util;
+pub mod verify_iru;
 
 // syntax ideas - use something in { :, @, ` } to denote symbol/label ?
 //
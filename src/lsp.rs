@@ -0,0 +1,403 @@
+//! language server
+//!
+//! JSON-RPC messages -> diagnostics, hovers, and definitions
+//!
+//! The server speaks the Language Server Protocol over a pair of streams, and
+//! keeps the full text of every open document, which it analyzes again
+//! whenever it changes.
+//!
+//! An analysis lowers the document with a source map, which gives every name
+//! its binding and the program point of its value. A hover shows the type that
+//! the solver resolved for that program point, and a definition is the binding
+//! of the name. Diagnostics are the invalid tokens and syntax errors of the
+//! document, or else its static errors, or else its first type error, located
+//! at the position that the source map gives their program points.
+//!
+//! Positions in the protocol are lines and UTF-16 code units, and are
+//! converted to and from byte offsets at the boundary.
+
+use crate::buf::Buf;
+use crate::iru;
+use crate::json::Json;
+use crate::lexer::Lexer;
+use crate::make_irp;
+use crate::make_iru;
+use crate::parse;
+use crate::token::Token;
+use crate::typecheck;
+use crate::typeid::TypeId;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+
+pub struct Server {
+  documents: Buf<Document>,
+  is_shutdown: bool,
+  is_done: bool,
+}
+
+struct Document {
+  uri: String,
+  text: String,
+  analysis: Analysis,
+}
+
+pub struct Analysis {
+  pub diagnostics: Buf<Diagnostic>,
+  pub occurrences: Buf<Occurrence>,
+}
+
+pub struct Diagnostic {
+  pub pos: u32,
+  pub len: u32,
+  pub message: String,
+}
+
+/// An occurrence of a name.
+
+pub struct Occurrence {
+  pub pos: u32,
+  pub len: u32,
+  /// The byte offset of its binding.
+  pub def: Option<u32>,
+  /// What a hover over it shows.
+  pub hover: Option<String>,
+}
+
+// The length of the token at `pos`, rounded up to whole characters, since an
+// invalid token can be one byte of a longer character.
+
+fn token_len(text: &str, pos: u32) -> u32 {
+  let lexer = Lexer::new(&text.as_bytes()[pos as usize ..]);
+  let end = text.ceil_char_boundary(pos as usize + lexer.token_span().len());
+  return end as u32 - pos;
+}
+
+// A diagnostic that covers the token at `pos`.
+
+fn diagnostic(text: &str, pos: u32, message: &str) -> Diagnostic {
+  return Diagnostic { pos, len: token_len(text, pos).max(1), message: message.to_string() };
+}
+
+/// Analyzes the source text of a document.
+
+pub fn analyze(text: &str) -> Analysis {
+  let mut diagnostics = Buf::new();
+  let mut occurrences = Buf::new();
+  let mut lexer = Lexer::new(text.as_bytes());
+
+  while lexer.token() != Token::Eof {
+    // NB: the lexer makes an invalid token of every byte of a character that
+    // it does not know, but the diagnostic covers the whole character.
+
+    if lexer.token() == Token::Error && text.is_char_boundary(lexer.token_start()) {
+      diagnostics.push(diagnostic(text, lexer.token_start() as u32, "invalid token"));
+    }
+    lexer.next();
+  }
+
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(text.as_bytes(), store.arena());
+
+  for e in errors.iter() {
+    diagnostics.push(diagnostic(text, e.pos(), &e.to_string()));
+  }
+
+  let (module, source_map) = make_iru::compile_with_source_map(&items);
+  let (environment, mut solver) = typecheck::typecheck(&module);
+
+  for name in source_map.names.iter() {
+    let len = token_len(text, name.pos);
+    let s = &text[name.pos as usize .. (name.pos + len) as usize];
+    let hover =
+      match name.value {
+        Some(x) => {
+          solver.resolve_value_type(TypeId(x)).ok().map(|t| format!("{} : {}", s, solver.types().display(t)))
+        }
+        None => {
+          environment.get(name.symbol).map(|t| format!("fun {} : {}", s, t.display(solver.types())))
+        }
+      };
+    occurrences.push(Occurrence { pos: name.pos, len, def: name.def, hover });
+  }
+
+  if ! diagnostics.is_empty() {
+    return Analysis { diagnostics, occurrences };
+  }
+
+  for f in module.decl.iter() {
+    for i in f.pos .. f.pos + f.len {
+      if let iru::Inst::GotoStaticError = module.code[i] {
+        diagnostics.push(diagnostic(text, source_map.pos[i], "static error"));
+      }
+    }
+  }
+
+  if ! diagnostics.is_empty() {
+    return Analysis { diagnostics, occurrences };
+  }
+
+  match make_irp::compile(&module, &environment, solver) {
+    Ok(_) | Err(make_irp::Error::StaticError(..)) => {}
+    Err(make_irp::Error::TypeError(_, i)) => {
+      diagnostics.push(diagnostic(text, source_map.pos[i], "type error"));
+    }
+    Err(make_irp::Error::Unsupported(_, i)) => {
+      diagnostics.push(diagnostic(text, source_map.pos[i], "unsupported"));
+    }
+  }
+
+  return Analysis { diagnostics, occurrences };
+}
+
+// The protocol position of the byte offset `pos`.
+
+fn position(text: &str, pos: u32) -> Json {
+  let before = &text[.. text.ceil_char_boundary(pos as usize)];
+  let line = before.matches('\n').count() as u32;
+  let start = before.rfind('\n').map_or(0, |i| i + 1);
+  let character = before[start ..].encode_utf16().count() as u32;
+  return Json::object([("line", Json::from(line)), ("character", Json::from(character))]);
+}
+
+fn range(text: &str, pos: u32, len: u32) -> Json {
+  return Json::object([("start", position(text, pos)), ("end", position(text, pos + len))]);
+}
+
+// The byte offset of a protocol position, clamped to its line.
+
+fn offset(text: &str, position: &Json) -> Option<u32> {
+  let line = position.get("line")?.as_u32()?;
+  let character = position.get("character")?.as_u32()?;
+  let mut start = 0;
+
+  for _ in 0 .. line {
+    start += text[start ..].find('\n')? + 1;
+  }
+
+  let end = text[start ..].find('\n').map_or(text.len(), |i| start + i);
+  let mut units = 0;
+
+  for (i, c) in text[start .. end].char_indices() {
+    if units >= character { return Some((start + i) as u32); }
+    units += c.len_utf16() as u32;
+  }
+
+  return Some(end as u32);
+}
+
+fn response(id: &Json, result: Json) -> Json {
+  return Json::object([("jsonrpc", Json::from("2.0")), ("id", id.clone()), ("result", result)]);
+}
+
+fn notification(method: &str, params: Json) -> Json {
+  return Json::object([("jsonrpc", Json::from("2.0")), ("method", Json::from(method)), ("params", params)]);
+}
+
+fn error(id: &Json, code: i32, message: &str) -> Json {
+  let e = Json::object([("code", Json::Number(code as f64)), ("message", Json::from(message))]);
+  return Json::object([("jsonrpc", Json::from("2.0")), ("id", id.clone()), ("error", e)]);
+}
+
+fn publish_diagnostics(uri: &str, text: &str, analysis: &Analysis) -> Json {
+  let diagnostics =
+    analysis.diagnostics.iter().map(|d| {
+      Json::object([
+        ("range", range(text, d.pos, d.len)),
+        ("severity", Json::from(1)),
+        ("source", Json::from("lilac")),
+        ("message", Json::from(d.message.as_str())),
+      ])
+    });
+  let params = Json::object([("uri", Json::from(uri)), ("diagnostics", Json::array(diagnostics))]);
+  return notification("textDocument/publishDiagnostics", params);
+}
+
+impl Server {
+  pub fn new() -> Self {
+    return Self { documents: Buf::new(), is_shutdown: false, is_done: false };
+  }
+
+  /// Whether the client has asked the server to exit.
+
+  pub fn is_done(&self) -> bool {
+    return self.is_done;
+  }
+
+  fn document(&self, params: &Json) -> Option<&Document> {
+    let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+    return self.documents.iter().find(|d| d.uri == uri);
+  }
+
+  // The occurrence of a name at the position of a request.
+
+  fn occurrence(&self, params: &Json) -> Option<(&Document, &Occurrence)> {
+    let d = self.document(params)?;
+    let pos = offset(&d.text, params.get("position")?)?;
+    let x = d.analysis.occurrences.iter().find(|x| x.pos <= pos && pos <= x.pos + x.len)?;
+    return Some((d, x));
+  }
+
+  fn open(&mut self, uri: &str, text: String, out: &mut Buf<Json>) {
+    let analysis = analyze(&text);
+    out.push(publish_diagnostics(uri, &text, &analysis));
+
+    match self.documents.iter().position(|d| d.uri == uri) {
+      None => {
+        self.documents.push(Document { uri: uri.to_string(), text, analysis });
+      }
+      Some(i) => {
+        self.documents[i as u32] = Document { uri: uri.to_string(), text, analysis };
+      }
+    }
+  }
+
+  /// Handles one message from the client, and pushes the messages to send
+  /// back to it.
+
+  pub fn handle(&mut self, message: &Json, out: &mut Buf<Json>) {
+    let Some(method) = message.get("method").and_then(Json::as_str) else {
+      // NB: a response to a request of the server, which makes none
+      return;
+    };
+
+    let params = message.get("params").unwrap_or(&Json::Null);
+
+    let Some(id) = message.get("id") else {
+      match method {
+        "exit" => {
+          self.is_done = true;
+        }
+        "textDocument/didOpen" => {
+          let Some(d) = params.get("textDocument") else { return; };
+          let (Some(uri), Some(text)) = (d.get("uri").and_then(Json::as_str), d.get("text").and_then(Json::as_str)) else { return; };
+          self.open(uri, text.to_string(), out);
+        }
+        "textDocument/didChange" => {
+          let Some(uri) = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Json::as_str) else { return; };
+          let Some(changes) = params.get("contentChanges").and_then(Json::as_array) else { return; };
+          // NB: the server asks for full synchronization, so the last change
+          // has the whole text.
+          let Some(text) = changes.iter().last().and_then(|c| c.get("text")).and_then(Json::as_str) else { return; };
+          self.open(uri, text.to_string(), out);
+        }
+        "textDocument/didClose" => {
+          let Some(uri) = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Json::as_str) else { return; };
+          let params = Json::object([("uri", Json::from(uri)), ("diagnostics", Json::array([]))]);
+          let mut documents = Buf::new();
+          for d in self.documents.drain() {
+            if d.uri != uri { documents.push(d); }
+          }
+          self.documents = documents;
+          out.push(notification("textDocument/publishDiagnostics", params));
+        }
+        _ => {
+        }
+      }
+      return;
+    };
+
+    if self.is_shutdown && method != "shutdown" {
+      out.push(error(id, -32600, "server is shut down"));
+      return;
+    }
+
+    match method {
+      "initialize" => {
+        let capabilities =
+          Json::object([
+            ("textDocumentSync", Json::from(1)),
+            ("hoverProvider", Json::from(true)),
+            ("definitionProvider", Json::from(true)),
+          ]);
+        let info = Json::object([("name", Json::from("lilac"))]);
+        out.push(response(id, Json::object([("capabilities", capabilities), ("serverInfo", info)])));
+      }
+      "shutdown" => {
+        self.is_shutdown = true;
+        out.push(response(id, Json::Null));
+      }
+      "textDocument/hover" => {
+        let result =
+          match self.occurrence(params) {
+            Some((d, x)) if let Some(hover) = &x.hover => {
+              let contents = Json::object([("kind", Json::from("plaintext")), ("value", Json::from(hover.as_str()))]);
+              Json::object([("contents", contents), ("range", range(&d.text, x.pos, x.len))])
+            }
+            _ => Json::Null,
+          };
+        out.push(response(id, result));
+      }
+      "textDocument/definition" => {
+        let result =
+          match self.occurrence(params) {
+            Some((d, x)) if let Some(def) = x.def => {
+              Json::object([("uri", Json::from(d.uri.as_str())), ("range", range(&d.text, def, token_len(&d.text, def)))])
+            }
+            _ => Json::Null,
+          };
+        out.push(response(id, result));
+      }
+      _ => {
+        out.push(error(id, -32601, "method not found"));
+      }
+    }
+  }
+}
+
+impl Default for Server {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+// Reads the body of the next message, or `None` at the end of the input.
+
+fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<String>> {
+  let mut len = None;
+  let mut line = String::new();
+
+  loop {
+    line.clear();
+    if input.read_line(&mut line)? == 0 { return Ok(None); }
+    let line = line.trim_end();
+    if line.is_empty() { break; }
+    if let Some((k, v)) = line.split_once(':') && k.eq_ignore_ascii_case("content-length") {
+      len = v.trim().parse::<usize>().ok();
+    }
+  }
+
+  let Some(len) = len else {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "missing content length"));
+  };
+
+  let mut body = String::new();
+  let n = input.by_ref().take(len as u64).read_to_string(&mut body)?;
+  if n != len { return Err(std::io::ErrorKind::UnexpectedEof.into()); }
+  return Ok(Some(body));
+}
+
+/// Runs a server until the client tells it to exit, or the input ends.
+
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> std::io::Result<()> {
+  let mut server = Server::new();
+  let mut out = Buf::new();
+
+  while ! server.is_done() {
+    let Some(body) = read_message(input)? else { break; };
+
+    match Json::parse(&body) {
+      Ok(message) => server.handle(&message, &mut out),
+      Err(_) => out.push(error(&Json::Null, -32700, "parse error")),
+    }
+
+    for message in out.drain() {
+      let body = message.to_string();
+      write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    }
+
+    output.flush()?;
+  }
+
+  return Ok(());
+}
@@ -0,0 +1,136 @@
+//! the `lilac` command
+//!
+//! command line -> output, and an exit status
+
+use lilac::driver;
+use lilac::eval_iru::Value;
+use lilac::lsp;
+use lilac::opt::Pass;
+use lilac::repl;
+use lilac::repl::Repl;
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::process::ExitCode;
+
+static USAGE: &str = "\
+usage: lilac lex <file>
+       lilac parse <file>
+       lilac fmt <file> [--check]
+       lilac iru <file>
+       lilac cfg <file>
+       lilac check <file>
+       lilac opt <file> [<pass> ...]
+       lilac run <file> <function> [<integer> ...]
+       lilac repl
+       lilac lsp";
+
+fn main() -> ExitCode {
+  let args: Box<[String]> = std::env::args().skip(1).collect();
+  let args: Box<[&str]> = args.iter().map(|s| s.as_str()).collect();
+
+  let (command, path, rest) =
+    match *args {
+      ["repl"] => return repl(),
+      ["lsp"] => return lsp(),
+      [command, path, ref rest @ ..] => (command, path, rest),
+      _ => return usage(),
+    };
+
+  let source =
+    match std::fs::read_to_string(path) {
+      Ok(source) => source,
+      Err(e) => {
+        eprintln!("error: {}: {}", path, e);
+        return ExitCode::FAILURE;
+      }
+    };
+
+  let mut out = String::new();
+
+  let result =
+    match (command, rest) {
+      ("lex", []) => driver::lex(&source, &mut out),
+      ("parse", []) => driver::parse(&source, &mut out),
+      ("fmt", []) => driver::format(&source, &mut out),
+      ("fmt", ["--check"]) => driver::format_check(&source),
+      ("iru", []) => driver::iru(&source, &mut out),
+      ("cfg", []) => driver::cfg(&source, &mut out),
+      ("check", []) => driver::check(&source, &mut out),
+      ("opt", []) => driver::opt(&source, &Pass::ALL, &mut out),
+      ("opt", names) => {
+        let mut passes = Vec::new();
+        for s in names.iter() {
+          let Some(pass) = Pass::from_str(s) else { return usage(); };
+          passes.push(pass);
+        }
+        driver::opt(&source, &passes, &mut out)
+      }
+      ("run", [name, ints @ ..]) => {
+        let mut values = Vec::new();
+        for s in ints.iter() {
+          let Ok(n) = s.parse::<i64>() else { return usage(); };
+          values.push(Value::Int(n));
+        }
+        driver::run(&source, name, values, &mut out)
+      }
+      _ => return usage(),
+    };
+
+  print!("{}", out);
+
+  if let Err(e) = result {
+    eprintln!("{}: error: {}", path, e);
+    return ExitCode::FAILURE;
+  }
+
+  return ExitCode::SUCCESS;
+}
+
+// Reads inputs from standard input until it ends. An input continues over
+// several lines until its brackets are balanced, and an error is printed but
+// does not end the session.
+
+fn repl() -> ExitCode {
+  let interactive = std::io::stdin().is_terminal();
+  let mut lines = std::io::stdin().lock().lines();
+  let mut repl = Repl::new();
+  let mut input = String::new();
+
+  loop {
+    if interactive {
+      print!("{}", if input.is_empty() { "> " } else { ". " });
+      let _ = std::io::stdout().flush();
+    }
+
+    let Some(Ok(line)) = lines.next() else { break; };
+    input.push_str(&line);
+    input.push('\n');
+
+    if ! repl::is_complete(&input) { continue; }
+
+    let mut out = String::new();
+    let result = repl.eval(&input, &mut out);
+    print!("{}", out);
+    if let Err(e) = result { println!("error: {}", e); }
+    input.clear();
+  }
+
+  return ExitCode::SUCCESS;
+}
+
+// Serves the language server protocol over standard input and output.
+
+fn lsp() -> ExitCode {
+  if let Err(e) = lsp::serve(&mut std::io::stdin().lock(), &mut std::io::stdout().lock()) {
+    eprintln!("error: {}", e);
+    return ExitCode::FAILURE;
+  }
+
+  return ExitCode::SUCCESS;
+}
+
+fn usage() -> ExitCode {
+  eprintln!("{}", USAGE);
+  return ExitCode::from(2);
+}
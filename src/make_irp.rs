@@ -0,0 +1,194 @@
+//! elaboration pass
+//!
+//! linearized bytecode + solved types -> typed bytecode
+//!
+//! Operators are resolved to primitive operations, and every value is given
+//! its type relative to the type scheme of its enclosing function. Modules
+//! that contain static errors or type errors are rejected.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::irp;
+use crate::iru;
+use crate::symbol::Symbol;
+use crate::typecheck::Solver;
+use crate::typecheck::TypeScheme;
+use crate::typecheck::lower_op1;
+use crate::typecheck::lower_op2;
+use crate::typeid::TypeId;
+use tangerine::map::HashMap;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// A static error that was detected during lowering, in the given function,
+  /// at the given program point.
+  StaticError(Symbol, u32),
+  /// The type of the given program point of the given function is
+  /// inconsistent or ambiguous.
+  TypeError(Symbol, u32),
+  /// The instruction at the given program point of the given function is not
+  /// supported yet.
+  Unsupported(Symbol, u32),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::StaticError(s, i) => write!(f, "static error in {} at %{}", s, i),
+      Self::TypeError(s, i) => write!(f, "type error in {} at %{}", s, i),
+      Self::Unsupported(s, i) => write!(f, "unsupported instruction in {} at %{}", s, i),
+    }
+  }
+}
+
+pub fn compile(
+    module: &iru::Module,
+    environment: &HashMap<Symbol, TypeScheme>,
+    solver: Solver,
+  ) -> Result<irp::Module, Error>
+{
+  let mut solver = solver;
+  let (code, decl) = elaborate(module, environment, &mut solver)?;
+  return Ok(irp::Module { code, decl, types: solver.into_types() });
+}
+
+/// Compiles like `compile`, but leaves the types in `solver`, so that they are
+/// kept even if the module is rejected.
+
+pub fn elaborate(
+    module: &iru::Module,
+    environment: &HashMap<Symbol, TypeScheme>,
+    solver: &mut Solver,
+  ) -> Result<(Arr<irp::Inst>, Arr<irp::Fun>), Error>
+{
+  let mut code = Buf::new();
+  let mut decl = Buf::new();
+
+  for f in module.decl.iter() {
+    // NB: program points are preserved, which relies on the functions being
+    // laid out contiguously and in order.
+
+    debug_assert!(code.len() == f.pos);
+
+    decl.push(elaborate_fun(module, f, environment, solver, &mut code)?);
+  }
+
+  return Ok((Arr::from(code.drain()), Arr::from(decl.drain())));
+}
+
+/// Checks every function of `module` like `compile`, and returns the first
+/// error in each one, if any.
+
+pub fn check(
+    module: &iru::Module,
+    environment: &HashMap<Symbol, TypeScheme>,
+    solver: &mut Solver,
+  ) -> Arr<Option<Error>>
+{
+  let mut code = Buf::new();
+
+  return Arr::from(module.decl.iter().map(|f| {
+    code.clear();
+    elaborate_fun(module, f, environment, solver, &mut code).err()
+  }));
+}
+
+// Appends the typed code of the function `f` to `code`.
+
+fn elaborate_fun(
+    module: &iru::Module,
+    f: &iru::Fun,
+    environment: &HashMap<Symbol, TypeScheme>,
+    solver: &mut Solver,
+    code: &mut Buf<irp::Inst>,
+  ) -> Result<irp::Fun, Error>
+{
+  for i in f.pos .. f.pos + f.len {
+    let value_type = |solver: &mut Solver| {
+      solver.resolve_value_type(TypeId(i)).map_err(|()| Error::TypeError(f.name, i))
+    };
+
+    let inst =
+      match module.code[i] {
+        iru::Inst::GotoStaticError => {
+          return Err(Error::StaticError(f.name, i));
+        }
+        iru::Inst::Field(..) | iru::Inst::SetField(..) => {
+          return Err(Error::Unsupported(f.name, i));
+        }
+        iru::Inst::Label(n) => {
+          let _ = solver.resolve_tuple_type(TypeId(i)).map_err(|()| Error::TypeError(f.name, i))?;
+          irp::Inst::Label(n)
+        }
+        iru::Inst::Get(k) => {
+          irp::Inst::Get(k, value_type(solver)?)
+        }
+        iru::Inst::Put(k, x) => {
+          irp::Inst::Put(k, x)
+        }
+        iru::Inst::Goto(a) => {
+          irp::Inst::Goto(a)
+        }
+        iru::Inst::Cond(x) => {
+          irp::Inst::Cond(x)
+        }
+        iru::Inst::Ret => {
+          irp::Inst::Ret
+        }
+        iru::Inst::Call(x) => {
+          irp::Inst::Call(x)
+        }
+        iru::Inst::TailCall(x) => {
+          irp::Inst::TailCall(x)
+        }
+        iru::Inst::Const(s) => {
+          irp::Inst::Const(s, value_type(solver)?)
+        }
+        iru::Inst::ConstBool(p) => {
+          let _ = value_type(solver)?;
+          irp::Inst::ConstBool(p)
+        }
+        iru::Inst::ConstInt(n) => {
+          let _ = value_type(solver)?;
+          irp::Inst::ConstInt(n)
+        }
+        iru::Inst::Index(x, y) => {
+          let _ = value_type(solver)?;
+          irp::Inst::Index(x, y)
+        }
+        iru::Inst::Op1(op, x) => {
+          let _ = value_type(solver)?;
+          irp::Inst::PrimOp1(lower_op1(op), x)
+        }
+        iru::Inst::Op2(op, x, y) => {
+          let _ = value_type(solver)?;
+          irp::Inst::PrimOp2(lower_op2(op), x, y)
+        }
+        iru::Inst::Local(x) => {
+          let _ = value_type(solver)?;
+          irp::Inst::Local(x)
+        }
+        iru::Inst::GetLocal(v) => {
+          let _ = value_type(solver)?;
+          irp::Inst::GetLocal(v)
+        }
+        iru::Inst::SetIndex(x, y, z) => {
+          irp::Inst::SetIndex(x, y, z)
+        }
+        iru::Inst::SetLocal(v, x) => {
+          irp::Inst::SetLocal(v, x)
+        }
+      };
+
+    code.push(inst);
+  }
+
+  // NB: the function type itself might still be inconsistent, e.g., if its
+  // return arities disagree.
+
+  let Some(&scheme) = environment.get(f.name) else {
+    return Err(Error::TypeError(f.name, f.pos));
+  };
+
+  return Ok(irp::Fun { name: f.name, pos: f.pos, len: f.len, scheme });
+}
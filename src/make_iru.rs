@@ -12,6 +12,8 @@ use crate::buf::Buf;
 use crate::iru::Fun;
 use crate::iru::Inst;
 use crate::iru::Module;
+use crate::iru::Name;
+use crate::iru::SourceMap;
 use crate::util::enumerate;
 use crate::symbol::Symbol;
 use std::iter::zip;
@@ -20,18 +22,30 @@ use tangerine::map::HashMap;
 // TODO: consider special lowering for arguments to cond
 
 pub fn compile<'a>(item_list: &Arr<ast::Item<'a>>) -> Module {
+  return compile_with_source_map(item_list).0;
+}
+
+/// Lowers like `compile`, and also returns where the code came from.
+
+pub fn compile_with_source_map<'a>(item_list: &Arr<ast::Item<'a>>) -> (Module, SourceMap) {
   let mut ctx = Ctx::new();
   let mut out = Out::new();
 
+  for ast::Item::Fun(f) in item_list.iter() {
+    ctx.globals.insert(f.name, f.pos);
+  }
+
   for ast::Item::Fun(f) in item_list.iter() {
     let pos = out.code.len();
     push_scope(&mut ctx.scopes);
+    out.name(f.name, f.pos, Some(f.pos), None);
     let _ = out.emit(Inst::Label(f.args.len() as u32));
 
     for (i, x) in enumerate(f.args.iter()) {
       let y = out.emit(Inst::Get(i));
-      if let Some(x) = x.name {
-        push_referent(x, Referent::Value(y), &mut ctx.scopes);
+      if let Some(s) = x.name {
+        out.name(s, x.pos, Some(x.pos), Some(y));
+        push_referent(s, Referent::Value(y, x.pos), &mut ctx.scopes);
       }
     }
 
@@ -40,11 +54,19 @@ pub fn compile<'a>(item_list: &Arr<ast::Item<'a>>) -> Module {
     out.decl.push(Fun { name: f.name, pos, len: out.code.len() - pos });
   }
 
-  return
+  let module =
     Module {
       code: out.code.drain().into(),
       decl: out.decl.drain().into(),
     };
+
+  let source_map =
+    SourceMap {
+      pos: out.spans.drain().into(),
+      names: out.names.drain().into(),
+    };
+
+  return (module, source_map);
 }
 
 enum What {
@@ -52,9 +74,11 @@ enum What {
   NumValues(u32),
 }
 
+// NB: a referent also has the byte offset of its binding.
+
 enum Referent {
-  Local(u32),
-  Value(u32),
+  Local(u32, u32),
+  Value(u32, u32),
 }
 
 enum LoopInfo {
@@ -75,6 +99,7 @@ struct Label {
 }
 
 struct Ctx {
+  globals: HashMap<Symbol, u32>,
   scopes: ScopeStack,
   loops: LoopStack,
   values: Buf<u32>,
@@ -84,6 +109,7 @@ struct Ctx {
 impl Ctx {
   fn new() -> Self {
     return Self {
+      globals: HashMap::new(),
       scopes: ScopeStack::new(),
       loops: LoopStack::new(),
       values: Buf::new(),
@@ -128,8 +154,12 @@ fn push_scope(t: &mut ScopeStack) {
   t.counts.push(0);
 }
 
+// NB: the bindings are undone last first, so that a name that is bound twice
+// in a scope gets back the binding that it had before the scope.
+
 fn pop_scope(t: &mut ScopeStack) {
-  for (s, x) in t.undo.pop_list(t.counts.pop()) {
+  for _ in 0 .. t.counts.pop() {
+    let (s, x) = t.undo.pop();
     match x {
       None => {
         t.table.remove(s);
@@ -175,6 +205,9 @@ fn pop_loop_tail(t: &mut LoopStack) {
 struct Out {
   code: Buf<Inst>,
   decl: Buf<Fun>,
+  pos: u32,
+  spans: Buf<u32>,
+  names: Buf<Name>,
 }
 
 impl Out {
@@ -182,35 +215,71 @@ impl Out {
     Self {
       code: Buf::new(),
       decl: Buf::new(),
+      pos: 0,
+      spans: Buf::new(),
+      names: Buf::new(),
     }
   }
 
   fn emit(&mut self, inst: Inst) -> u32 {
     let n = self.code.len();
     self.code.push(inst);
+    self.spans.push(self.pos);
     return n;
   }
 
+  // Records a name, which is also the position of the code that follows.
+
+  fn name(&mut self, symbol: Symbol, pos: u32, def: Option<u32>, value: Option<u32>) {
+    self.pos = pos;
+    self.names.push(Name { symbol, pos, def, value });
+  }
+
+  // Emits an instruction at the position of a name, and records the name with
+  // that instruction as its value.
+
+  fn emit_name(&mut self, inst: Inst, symbol: Symbol, pos: u32, def: Option<u32>) -> u32 {
+    self.pos = pos;
+    let x = self.emit(inst);
+    self.names.push(Name { symbol, pos, def, value: Some(x) });
+    return x;
+  }
+
   fn emit_point(&mut self, arity: Option<u32>) -> Point {
     let i = self.emit(Inst::Goto(u32::MAX));
     return Point { index: i, arity };
   }
 
   fn emit_label(&mut self, arity: u32, ps: impl IntoIterator<Item = Point>) -> Label {
+    let mut ps: Buf<Point> = ps.into_iter().filter(|p| ! divert_point(p, arity, self)).collect();
     let a = self.emit(Inst::Label(arity));
     let a = Label { index: a, arity };
-    patch_point_list(a, ps, self);
+    patch_point_list(a, ps.drain(), self);
     return a;
   }
 }
 
+// If the point `p` passes a number of values other than `arity`, points it at
+// a new block that is a static error, and returns true.
+//
+// NB: the point keeps its `Goto`, which a `Cond` needs, and the new block must
+// be emitted between blocks.
+
+fn divert_point(p: &Point, arity: u32, out: &mut Out) -> bool {
+  let Some(n) = p.arity else { return false; };
+  if n == arity { return false; }
+
+  // error, arity mismatch
+  let b = out.emit(Inst::Label(n));
+  let _ = out.emit(Inst::GotoStaticError);
+  out.code[p.index] = Inst::Goto(b);
+  return true;
+}
+
 fn patch_point_list(a: Label, ps: impl IntoIterator<Item = Point>, out: &mut Out) {
-  for i in ps {
-    if let Some(n) = i.arity && n != a.arity {
-      // error, arity mismatch
-      out.code[i.index] = Inst::GotoStaticError;
-    } else {
-      out.code[i.index] = Inst::Goto(a.index);
+  for p in ps {
+    if ! divert_point(&p, a.arity, out) {
+      out.code[p.index] = Inst::Goto(a.index);
     }
   }
 }
@@ -411,7 +480,7 @@ fn compile_expr<'a>(x: &Expr<'a>, ctx: &mut Ctx, out: &mut Out) -> What {
       return What::NumPoints(n + 1);
     }
     Expr::PostOp(&(s, f)) => {
-      if let Some(&Referent::Local(v)) = get_referent(s, &ctx.scopes) {
+      if let Some(&Referent::Local(v, _)) = get_referent(s, &ctx.scopes) {
         let x = out.emit(Inst::GetLocal(v));
         let y = out.emit(Inst::Op1(f, x));
         let _ = out.emit(Inst::SetLocal(v, y));
@@ -426,7 +495,7 @@ fn compile_expr<'a>(x: &Expr<'a>, ctx: &mut Ctx, out: &mut Out) -> What {
       return What::NumValues(1);
     }
     Expr::PreOp(&(s, f)) => {
-      if let Some(&Referent::Local(v)) = get_referent(s, &ctx.scopes) {
+      if let Some(&Referent::Local(v, _)) = get_referent(s, &ctx.scopes) {
         let x = out.emit(Inst::GetLocal(v));
         let y = out.emit(Inst::Op1(f, x));
         let _ = out.emit(Inst::SetLocal(v, y));
@@ -459,17 +528,18 @@ fn compile_expr<'a>(x: &Expr<'a>, ctx: &mut Ctx, out: &mut Out) -> What {
       ctx.values.push(x);
       return What::NumValues(1);
     }
-    Expr::Variable(s) => {
+    Expr::Variable(s, pos) => {
       match get_referent(s, &ctx.scopes) {
         None => {
-          let x = out.emit(Inst::Const(s));
+          let x = out.emit_name(Inst::Const(s), s, pos, ctx.globals.get(s).copied());
           ctx.values.push(x);
         }
-        Some(&Referent::Value(x)) => {
+        Some(&Referent::Value(x, def)) => {
+          out.name(s, pos, Some(def), Some(x));
           ctx.values.push(x);
         }
-        Some(&Referent::Local(v)) => {
-          let x = out.emit(Inst::GetLocal(v));
+        Some(&Referent::Local(v, def)) => {
+          let x = out.emit_name(Inst::GetLocal(v), s, pos, Some(def));
           ctx.values.push(x);
         }
       }
@@ -615,8 +685,9 @@ fn compile_stmt<'a>(x: &Stmt<'a>, ctx: &mut Ctx, out: &mut Out) -> What {
       // earlier ones.
       compile_expr_list(ys, ctx, out).into_value_list(n, ctx, out);
       for (x, y) in zip(xs, ctx.values.pop_list(n)) {
-        if let Some(x) = x.name {
-          push_referent(x, Referent::Value(y), &mut ctx.scopes);
+        if let Some(s) = x.name {
+          out.name(s, x.pos, Some(x.pos), Some(y));
+          push_referent(s, Referent::Value(y, x.pos), &mut ctx.scopes);
         }
       }
       return What::NIL;
@@ -627,7 +698,7 @@ fn compile_stmt<'a>(x: &Stmt<'a>, ctx: &mut Ctx, out: &mut Out) -> What {
     }
     Stmt::Set(s, ref x) => {
       let x = compile_expr(x, ctx, out).into_value(ctx, out);
-      if let Some(&Referent::Local(v)) = get_referent(s, &ctx.scopes) {
+      if let Some(&Referent::Local(v, _)) = get_referent(s, &ctx.scopes) {
         let _ = out.emit(Inst::SetLocal(v, x));
       } else {
         // error, symbol does not refer to local variable
@@ -649,10 +720,10 @@ fn compile_stmt<'a>(x: &Stmt<'a>, ctx: &mut Ctx, out: &mut Out) -> What {
       let _ = out.emit(Inst::SetIndex(x, y, z));
       return What::NIL;
     }
-    Stmt::Var(s, ref x) => {
+    Stmt::Var(s, pos, ref x) => {
       let x = compile_expr(x, ctx, out).into_value(ctx, out);
-      let x = out.emit(Inst::Local(x));
-      push_referent(s, Referent::Local(x), &mut ctx.scopes);
+      let x = out.emit_name(Inst::Local(x), s, pos, Some(pos));
+      push_referent(s, Referent::Local(x, pos), &mut ctx.scopes);
       return What::NIL;
     }
     Stmt::While(ref x, ys) => {
@@ -687,11 +758,15 @@ fn compile_stmt_tail<'a>(x: &Stmt<'a>, ctx: &mut Ctx, out: &mut Out) {
     | Stmt::Set(..)
     | Stmt::SetField(..)
     | Stmt::SetIndex(..)
-    | Stmt::Var(..)
-    | Stmt::While(..) => {
+    | Stmt::Var(..) => {
       let What::NumValues(0) = compile_stmt(x, ctx, out) else { unreachable!() };
       let _ = out.emit(Inst::Ret);
     }
+    Stmt::While(..) => {
+      // NB: the loop exits through points that must be joined first.
+      compile_stmt(x, ctx, out).into_nil(ctx, out);
+      let _ = out.emit(Inst::Ret);
+    }
   }
 }
 
@@ -0,0 +1,323 @@
+//! monomorphization pass
+//!
+//! polymorphic typed bytecode -> monomorphic typed bytecode
+//!
+//! Starting from a set of monomorphic entry points, every function that is
+//! reachable is copied once for each distinct instantiation of its type
+//! scheme. References to functions are rewritten into references to the
+//! specialized copies, and functions that are unreachable are dropped.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::irp::Fun;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::symbol::Symbol;
+use crate::typecheck::TypeScheme;
+use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+use crate::util::components;
+use std::num::NonZeroU64;
+use tangerine::map::HashMap;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The entry point is not a function in the module.
+  UnknownEntry(Symbol),
+  /// The entry point has a polymorphic type, so we cannot choose an instance.
+  PolymorphicEntry(Symbol),
+  /// The function is instantiated at ever larger types.
+  PolymorphicRecursion(Symbol),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::UnknownEntry(s) => write!(f, "unknown entry point {}", s),
+      Self::PolymorphicEntry(s) => write!(f, "entry point {} is polymorphic", s),
+      Self::PolymorphicRecursion(s) => write!(f, "polymorphic recursion in {}", s),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct Instance(NonZeroU64);
+
+impl Instance {
+  fn new(fun: u32, t: TypeId) -> Self {
+    let n = (fun as u64) << 32 | t.0 as u64;
+    return Self(NonZeroU64::new(n.wrapping_add(1)).unwrap());
+  }
+}
+
+unsafe impl tangerine::key::IntoKey for Instance {
+  type Key = NonZeroU64;
+
+  #[inline(always)]
+  fn inject(Self(n): Self) -> Self::Key {
+    n
+  }
+
+  #[inline(always)]
+  unsafe fn project(n: Self::Key) -> Self {
+    Self(n)
+  }
+}
+
+struct Ctx {
+  types: TypeStore,
+  funs: HashMap<Symbol, u32>,
+  instances: HashMap<Instance, u32>,
+  queue: Buf<(u32, TypeId)>,
+}
+
+impl Ctx {
+  // Returns the index of the specialized copy of function `f` at the
+  // monomorphic type `t`, scheduling the copy if it is new.
+
+  fn instance(&mut self, f: u32, t: TypeId) -> u32 {
+    let key = Instance::new(f, t);
+    if let Some(&k) = self.instances.get(key) {
+      return k;
+    }
+    let k = self.queue.len();
+    self.instances.insert(key, k);
+    self.queue.push((f, t));
+    return k;
+  }
+}
+
+/// Monomorphizes `module`, keeping the functions reachable from `entry`. The
+/// entry points are the first functions of the result, in order.
+
+pub fn monomorphize(module: Module, entry: &[Symbol]) -> Result<Module, Error> {
+  let Module { code, decl, types } = module;
+
+  let mut ctx =
+    Ctx {
+      types,
+      funs: HashMap::new(),
+      instances: HashMap::new(),
+      queue: Buf::new(),
+    };
+
+  for (i, f) in decl.iter().enumerate() {
+    ctx.funs.insert(f.name, i as u32);
+  }
+
+  for &s in entry {
+    let Some(&f) = ctx.funs.get(s) else {
+      return Err(Error::UnknownEntry(s));
+    };
+    let TypeScheme(n, t) = decl[f].scheme;
+    if n != 0 {
+      return Err(Error::PolymorphicEntry(s));
+    }
+    let _ = ctx.instance(f, t);
+  }
+
+  check_recursion(&code, &decl, &ctx.funs, &ctx.types, ctx.queue.iter().map(|&(f, _)| f))?;
+
+  let mut out_code = Buf::new();
+  let mut out_decl = Buf::new();
+  let mut k = 0;
+
+  while k < ctx.queue.len() {
+    let (f, t) = ctx.queue[k];
+    let g = &decl[f];
+
+    let mut args = Arr::new(g.scheme.0, |_| None);
+    match_type(&ctx.types, g.scheme.1, t, &mut args);
+    let args = Arr::from(args.iter().map(|a| a.unwrap()));
+
+    let pos = out_code.len();
+
+    for i in g.pos .. g.pos + g.len {
+      let inst =
+        match code[i] {
+          Inst::Get(j, a) => {
+            Inst::Get(j, subst(&mut ctx.types, &args, a))
+          }
+          Inst::Const(s, a) => {
+            let a = subst(&mut ctx.types, &args, a);
+            match ctx.funs.get(s) {
+              None => Inst::Const(s, a),
+              Some(&h) => Inst::ConstFun(ctx.instance(h, a)),
+            }
+          }
+          inst => {
+            inst.map_points(|x| x - g.pos + pos)
+          }
+        };
+      out_code.push(inst);
+    }
+
+    out_decl.push(Fun { name: g.name, pos, len: g.len, scheme: TypeScheme(0, t) });
+    k += 1;
+  }
+
+  return Ok(
+    Module {
+      code: Arr::from(out_code.drain()),
+      decl: Arr::from(out_decl.drain()),
+      types: ctx.types,
+    });
+}
+
+// Rejects polymorphic recursion among the functions reachable from `entry`,
+// which would otherwise be instantiated without end.
+//
+// Every type variable of a scheme is a node. A reference from `f` to `g` at a
+// type gives an edge from each variable of `f` to each variable of `g` whose
+// instance mentions it, and the edge grows if that instance is not just the
+// variable. The instances are finite if and only if no edge that grows is on
+// a cycle.
+
+fn check_recursion(
+    code: &Arr<Inst>,
+    decl: &Arr<Fun>,
+    funs: &HashMap<Symbol, u32>,
+    types: &TypeStore,
+    entry: impl Iterator<Item = u32>,
+  ) -> Result<(), Error>
+{
+  let mut reachable = Arr::new(decl.len(), |_| false);
+  let mut stack: Buf<u32> = entry.collect();
+
+  for &f in stack.iter() { reachable[f] = true; }
+
+  let mut base = Arr::new(decl.len(), |_| 0);
+  let mut n = 0;
+
+  for (f, g) in decl.iter().enumerate() {
+    base[f as u32] = n;
+    n += g.scheme.0;
+  }
+
+  let mut succs = Arr::new(n, |_| Buf::new());
+  let mut grows = Buf::new();
+
+  while ! stack.is_empty() {
+    let f = stack.pop();
+    let g = &decl[f];
+
+    for i in g.pos .. g.pos + g.len {
+      let Inst::Const(s, a) = code[i] else { continue; };
+      let Some(&h) = funs.get(s) else { continue; };
+
+      if ! reachable[h] {
+        reachable[h] = true;
+        stack.push(h);
+      }
+
+      let mut args = Arr::new(decl[h].scheme.0, |_| None);
+      match_type(types, decl[h].scheme.1, a, &mut args);
+
+      for (j, t) in args.iter().enumerate() {
+        let t = t.unwrap();
+        let mut vars = Buf::new();
+        type_vars(types, t, &mut HashMap::new(), &mut vars);
+        for &v in vars.iter() {
+          let x = base[f] + v;
+          let y = base[h] + j as u32;
+          succs[x].push(y);
+          if types[t] != Type::Var(TypeId(v)) { grows.push((x, y, decl[h].name)); }
+        }
+      }
+    }
+  }
+
+  let component = components(&succs);
+
+  for &(x, y, s) in grows.iter() {
+    if component[x] == component[y] {
+      return Err(Error::PolymorphicRecursion(s));
+    }
+  }
+
+  return Ok(());
+}
+
+// Lists the type variables that occur in `t`, each once.
+
+fn type_vars(types: &TypeStore, t: TypeId, seen: &mut HashMap<TypeId, ()>, out: &mut Buf<u32>) {
+  if seen.get(t).is_some() { return; }
+  seen.insert(t, ());
+
+  match types[t] {
+    Type::Array(a) => {
+      type_vars(types, a, seen, out);
+    }
+    Type::Bool | Type::I64 => {
+    }
+    Type::Fun(a, b) => {
+      type_vars(types, a, seen, out);
+      type_vars(types, b, seen, out);
+    }
+    Type::Tuple(..) => {
+      for a in types.tuple_elts(t) { type_vars(types, a, seen, out); }
+    }
+    Type::TupleElt(..) => {
+      unreachable!()
+    }
+    Type::Var(a) => {
+      out.push(a.0);
+    }
+  }
+}
+
+// Binds the type variables of the pattern `t` by matching it against the
+// monomorphic type `u`.
+
+fn match_type(types: &TypeStore, t: TypeId, u: TypeId, args: &mut Arr<Option<TypeId>>) {
+  match (types[t], types[u]) {
+    (Type::Var(a), _) => {
+      debug_assert!(args[a.0].is_none_or(|b| b == u));
+      args[a.0] = Some(u);
+    }
+    (Type::Array(a), Type::Array(b)) => {
+      match_type(types, a, b, args);
+    }
+    (Type::Fun(a, b), Type::Fun(c, d)) => {
+      match_type(types, a, c, args);
+      match_type(types, b, d, args);
+    }
+    (Type::Tuple(..), Type::Tuple(..)) => {
+      for (a, b) in std::iter::zip(types.tuple_elts(t), types.tuple_elts(u)) {
+        match_type(types, a, b, args);
+      }
+    }
+    _ => {
+      debug_assert!(t == u);
+    }
+  }
+}
+
+fn subst(types: &mut TypeStore, args: &Arr<TypeId>, t: TypeId) -> TypeId {
+  match types[t] {
+    Type::Array(a) => {
+      let a = subst(types, args, a);
+      types.array(a)
+    }
+    Type::Bool | Type::I64 => {
+      t
+    }
+    Type::Fun(a, b) => {
+      let a = subst(types, args, a);
+      let b = subst(types, args, b);
+      types.fun(a, b)
+    }
+    Type::Tuple(..) => {
+      let u: Buf<TypeId> = types.tuple_elts(t).collect();
+      let u: Buf<TypeId> = u.iter().map(|&a| subst(types, args, a)).collect();
+      types.tuple(u.iter().copied())
+    }
+    Type::TupleElt(..) => {
+      unreachable!()
+    }
+    Type::Var(a) => {
+      args[a.0]
+    }
+  }
+}
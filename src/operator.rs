@@ -13,6 +13,7 @@ pub enum Op1 {
 #[repr(u8)]
 pub enum Op2 {
   Add,
+  AddSat,
   BitAnd,
   BitOr,
   BitXor,
@@ -23,11 +24,18 @@ pub enum Op2 {
   CmpLt,
   CmpNe,
   Div,
+  DivSat,
+  DivWrap,
   Mul,
+  MulSat,
   Rem,
   Shl,
+  ShlSat,
+  ShlWrap,
   Shr,
+  ShrWrap,
   Sub,
+  SubSat,
 }
 
 impl Op1 {
@@ -39,6 +47,16 @@ impl Op1 {
       Self::Not => "!",
     }
   }
+
+  pub fn from_str(s: &str) -> Option<Self> {
+    match s {
+      "--" => Some(Self::Dec),
+      "++" => Some(Self::Inc),
+      "-" => Some(Self::Neg),
+      "!" => Some(Self::Not),
+      _ => None,
+    }
+  }
 }
 
 impl std::fmt::Display for Op1 {
@@ -51,6 +69,7 @@ impl Op2 {
   pub fn as_str(&self) -> &'static str {
     match self {
       Self::Add => "+",
+      Self::AddSat => "+|",
       Self::BitAnd => "&",
       Self::BitOr => "|",
       Self::BitXor => "^",
@@ -61,11 +80,48 @@ impl Op2 {
       Self::CmpLt => "<",
       Self::CmpNe => "!=",
       Self::Div => "/",
+      Self::DivSat => "/|",
+      Self::DivWrap => "/%",
       Self::Mul => "*",
+      Self::MulSat => "*|",
       Self::Rem => "%",
       Self::Shl => "<<",
+      Self::ShlSat => "<<|",
+      Self::ShlWrap => "<<%",
       Self::Shr => ">>",
+      Self::ShrWrap => ">>%",
       Self::Sub => "-",
+      Self::SubSat => "-|",
+    }
+  }
+
+  pub fn from_str(s: &str) -> Option<Self> {
+    match s {
+      "+" => Some(Self::Add),
+      "+|" => Some(Self::AddSat),
+      "&" => Some(Self::BitAnd),
+      "|" => Some(Self::BitOr),
+      "^" => Some(Self::BitXor),
+      "==" => Some(Self::CmpEq),
+      ">=" => Some(Self::CmpGe),
+      ">" => Some(Self::CmpGt),
+      "<=" => Some(Self::CmpLe),
+      "<" => Some(Self::CmpLt),
+      "!=" => Some(Self::CmpNe),
+      "/" => Some(Self::Div),
+      "/|" => Some(Self::DivSat),
+      "/%" => Some(Self::DivWrap),
+      "*" => Some(Self::Mul),
+      "*|" => Some(Self::MulSat),
+      "%" => Some(Self::Rem),
+      "<<" => Some(Self::Shl),
+      "<<|" => Some(Self::ShlSat),
+      "<<%" => Some(Self::ShlWrap),
+      ">>" => Some(Self::Shr),
+      ">>%" => Some(Self::ShrWrap),
+      "-" => Some(Self::Sub),
+      "-|" => Some(Self::SubSat),
+      _ => None,
     }
   }
 }
@@ -0,0 +1,480 @@
+//! optimization passes
+//!
+//! typed bytecode -> equivalent typed bytecode
+//!
+//! The passes clean up the trivial structure that lowering leaves behind. Each
+//! of them can be run on its own, and `optimize` runs the ones that it is given
+//! in order, over and over, until none of them changes anything more.
+//!
+//! - `fold` evaluates primitive operations on constants, with the same
+//!   semantics as at run time, and leaves alone the ones that would trap. A
+//!   `Cond` on a constant becomes a `Goto` to the branch that it takes.
+//!
+//! - `copy` replaces a block argument with the value that every predecessor
+//!   passes for it, like a `Get` that only ever receives one value.
+//!
+//! - `thread` sends a `Goto` to a block that only passes its arguments on to
+//!   another block straight to that block.
+//!
+//! - `merge` appends a block to its only predecessor, when the predecessor
+//!   ends with a plain `Goto` to it, and replaces the arguments of the block
+//!   with the values that were put for it.
+//!
+//! - `dce` removes the blocks that can not be reached, and the values that are
+//!   not used and whose computation can not trap.
+//!
+//! A block that is appended to another one moves to after it, which keeps the
+//! definition of every value before its uses in the code. The `Get`s of a
+//! block stay right after its `Label`, as the back ends expect.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::cfg::Cfg;
+use crate::cfg::Dominators;
+use crate::cfg::EdgeKind;
+use crate::irp::Fun;
+use crate::irp::Inst;
+use crate::irp::Module;
+use crate::prim::PrimType;
+
+const NONE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pass {
+  Fold,
+  Copy,
+  Thread,
+  Merge,
+  Dce,
+}
+
+impl Pass {
+  /// Every pass, in the order in which they work best.
+  pub const ALL: [Self; 5] = [Self::Fold, Self::Copy, Self::Thread, Self::Merge, Self::Dce];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Fold => "fold",
+      Self::Copy => "copy",
+      Self::Thread => "thread",
+      Self::Merge => "merge",
+      Self::Dce => "dce",
+    }
+  }
+
+  pub fn from_str(s: &str) -> Option<Self> {
+    return Self::ALL.iter().copied().find(|p| p.as_str() == s);
+  }
+}
+
+impl std::fmt::Display for Pass {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/// Runs `passes` on every function of `module` until they change nothing.
+
+pub fn optimize(module: Module, passes: &[Pass]) -> Module {
+  let Module { code, decl, types } = module;
+  let mut out_code = Buf::new();
+  let mut out_decl = Buf::new();
+
+  for f in decl.iter() {
+    // NB: the passes see the function on its own, starting at point `0`.
+
+    let mut body = Arr::new(f.len, |i| code[f.pos + i].map_points(|x| x - f.pos));
+    let mut changed = true;
+
+    while changed {
+      changed = false;
+      for &pass in passes.iter() {
+        let result =
+          match pass {
+            Pass::Fold => fold(&body),
+            Pass::Copy => copy(&body),
+            Pass::Thread => thread(&body),
+            Pass::Merge => merge(&body),
+            Pass::Dce => dce(&body),
+          };
+        if let Some(new_body) = result {
+          body = new_body;
+          changed = true;
+        }
+      }
+    }
+
+    let pos = out_code.len();
+    for &inst in body.iter() { out_code.push(inst.map_points(|x| x + pos)); }
+    out_decl.push(Fun { name: f.name, pos, len: body.len(), scheme: f.scheme });
+  }
+
+  return Module {
+    code: Arr::from(out_code.drain()),
+    decl: Arr::from(out_decl.drain()),
+    types,
+  };
+}
+
+// The changes that a pass makes to a function, which `finish` applies all at
+// once.
+
+struct Edit {
+  code: Arr<Inst>,
+  cfg: Cfg,
+  /// The program points to remove.
+  dead: Arr<bool>,
+  /// The value that replaces each value, which is itself if it is kept.
+  replace: Arr<u32>,
+  /// The block that is appended to each block, if any.
+  next: Arr<u32>,
+  /// Whether each block is appended to another one.
+  moved: Arr<bool>,
+  changed: bool,
+}
+
+impl Edit {
+  fn new(code: &Arr<Inst>) -> Self {
+    let n = code.len();
+    let cfg = Cfg::new(code, 0, n);
+    let m = cfg.len();
+
+    return Self {
+      code: code.clone(),
+      cfg,
+      dead: Arr::new(n, |_| false),
+      replace: Arr::new(n, |i| i),
+      next: Arr::new(m, |_| NONE),
+      moved: Arr::new(m, |_| false),
+      changed: false,
+    };
+  }
+
+  fn resolve(&self, x: u32) -> u32 {
+    let mut x = x;
+    while self.replace[x] != x { x = self.replace[x]; }
+    return x;
+  }
+
+  // The program points of the block `b`.
+
+  fn points(&self, b: u32) -> std::ops::Range<u32> {
+    let block = &self.cfg.blocks[b];
+    return block.pos .. block.pos + block.len;
+  }
+
+  // Whether the block `b` ends with a `Goto` that is not one of those after a
+  // `Cond` or a `Call`.
+
+  fn ends_with_jump(&self, b: u32) -> bool {
+    let last = self.points(b).end - 1;
+    return
+      matches!(self.code[last], Inst::Goto(_))
+        && ! matches!(self.code[last - 1], Inst::Cond(_) | Inst::Call(_) | Inst::Goto(_));
+  }
+
+  fn finish(self) -> Option<Arr<Inst>> {
+    if ! self.changed { return None; }
+
+    let mut order = Buf::new();
+
+    for b in 0 .. self.cfg.len() {
+      if self.moved[b] { continue; }
+      let mut c = b;
+      while c != NONE {
+        for i in self.points(c) {
+          if ! self.dead[i] { order.push(i); }
+        }
+        c = self.next[c];
+      }
+    }
+
+    let mut at = Arr::new(self.code.len(), |_| NONE);
+    for (j, &i) in order.iter().enumerate() { at[i] = j as u32; }
+
+    return Some(Arr::new(order.len(), |j| self.code[order[j]].map_points(|x| at[self.resolve(x)])));
+  }
+}
+
+fn constant(code: &Arr<Inst>, x: u32) -> Option<i64> {
+  return match code[x] {
+    Inst::ConstBool(p) => Some(p as i64),
+    Inst::ConstInt(n) => Some(n),
+    _ => None,
+  };
+}
+
+fn constant_inst(t: PrimType, n: i64) -> Inst {
+  return match t {
+    PrimType::Bool => Inst::ConstBool(n != 0),
+    PrimType::I64 => Inst::ConstInt(n),
+  };
+}
+
+/// Folds primitive operations and conditions on constants.
+
+pub fn fold(code: &Arr<Inst>) -> Option<Arr<Inst>> {
+  let mut e = Edit::new(code);
+
+  for i in 0 .. code.len() {
+    match e.code[i] {
+      Inst::PrimOp1(op, x) => {
+        if let Some(a) = constant(&e.code, x) && let Ok(n) = op.eval(a) {
+          e.code[i] = constant_inst(op.out_type(), n);
+          e.changed = true;
+        }
+      }
+      Inst::PrimOp2(op, x, y) => {
+        if let Some(a) = constant(&e.code, x) && let Some(b) = constant(&e.code, y) && let Ok(n) = op.eval(a, b) {
+          e.code[i] = constant_inst(op.out_type(), n);
+          e.changed = true;
+        }
+      }
+      Inst::Cond(x) => {
+        if let Inst::ConstBool(p) = e.code[x] {
+          // NB: the first `Goto` is taken when the condition is false.
+          e.dead[i] = true;
+          e.dead[if p { i + 1 } else { i + 2 }] = true;
+          e.changed = true;
+        }
+      }
+      _ => {
+      }
+    }
+  }
+
+  return e.finish();
+}
+
+/// Replaces each block argument that every predecessor passes the same value
+/// for with that value.
+
+pub fn copy(code: &Arr<Inst>) -> Option<Arr<Inst>> {
+  let mut e = Edit::new(code);
+
+  for c in 1 .. e.cfg.len() {
+    // NB: the arguments after a call are its results.
+
+    let preds = &e.cfg.blocks[c].preds;
+    let is_call = |p: u32| e.cfg.blocks[p].succs.iter().any(|s| s.block == c && s.kind == EdgeKind::Return);
+    if preds.len() == 0 || preds.iter().any(|&p| is_call(p)) { continue; }
+
+    for g in e.points(c) {
+      let Inst::Get(k, _) = e.code[g] else { continue; };
+      let mut unique = None;
+      let mut trivial = true;
+
+      for j in 0 .. e.cfg.blocks[c].preds.len() {
+        let p = e.cfg.blocks[c].preds[j];
+        let put = e.points(p).find_map(|i| match e.code[i] { Inst::Put(m, x) if m == k => Some(x), _ => None });
+        let x = e.resolve(put.unwrap());
+        if x == g || unique == Some(x) { continue; }
+        if unique.is_some() { trivial = false; break; }
+        unique = Some(x);
+      }
+
+      // NB: a value from every predecessor dominates the block, unless it is
+      // defined in the block, which only a loop can do.
+
+      if trivial && let Some(x) = unique && e.cfg.block_at(x) != c {
+        e.replace[g] = x;
+        e.dead[g] = true;
+        e.changed = true;
+      }
+    }
+  }
+
+  return e.finish();
+}
+
+/// Sends each `Goto` to a block that passes its arguments on unchanged to the
+/// block that they end up in.
+
+pub fn thread(code: &Arr<Inst>) -> Option<Arr<Inst>> {
+  let mut e = Edit::new(code);
+  let n = e.cfg.len();
+
+  // Whether each value is used outside of its block.
+
+  let mut escapes = Arr::new(code.len(), |_| false);
+
+  for i in 0 .. code.len() {
+    if let Inst::Goto(_) = code[i] { continue; }
+    let _ = code[i].map_points(|x| {
+      if e.cfg.block_at(x) != e.cfg.block_at(i) { escapes[x] = true; }
+      return x;
+    });
+  }
+
+  // The label that each block passes its arguments on to, if it does nothing
+  // else with them.
+
+  let forward =
+    Arr::new(n, |b| {
+      let r = e.points(b);
+      let Inst::Label(arity) = code[r.start] else { unreachable!() };
+      let mut i = r.start + 1;
+      while let Inst::Get(..) = code[i] {
+        if escapes[i] { return NONE; }
+        i += 1;
+      }
+      for k in 0 .. arity {
+        if i + k >= r.end { return NONE; }
+        let Inst::Put(m, x) = code[i + k] else { return NONE; };
+        if m != k || ! matches!(code[x], Inst::Get(j, _) if j == k) || e.cfg.block_at(x) != b { return NONE; }
+      }
+      i += arity;
+      if i + 1 != r.end || b == 0 { return NONE; }
+      let Inst::Goto(a) = code[i] else { return NONE; };
+      if e.cfg.block_at(a) == b { return NONE; }
+      return a;
+    });
+
+  for i in 0 .. code.len() {
+    let Inst::Goto(a) = code[i] else { continue; };
+
+    // NB: blocks that forward to each other in a loop are left as they are.
+
+    let mut target = a;
+    let mut steps = 0;
+    while forward[e.cfg.block_at(target)] != NONE && steps <= n {
+      target = forward[e.cfg.block_at(target)];
+      steps += 1;
+    }
+
+    if target != a && forward[e.cfg.block_at(target)] == NONE {
+      e.code[i] = Inst::Goto(target);
+      e.changed = true;
+    }
+  }
+
+  return e.finish();
+}
+
+/// Appends each block to its only predecessor, if that ends with a plain
+/// `Goto` to it.
+
+pub fn merge(code: &Arr<Inst>) -> Option<Arr<Inst>> {
+  let mut e = Edit::new(code);
+  let dom = Dominators::new(&e.cfg);
+
+  for a in 0 .. e.cfg.len() {
+    if ! dom.is_reachable(a) || ! e.ends_with_jump(a) { continue; }
+
+    let last = e.points(a).end - 1;
+    let Inst::Goto(label) = code[last] else { unreachable!() };
+    let b = e.cfg.block_at(label);
+    if b == 0 || b == a || e.cfg.blocks[b].preds.len() != 1 { continue; }
+
+    let Inst::Label(arity) = code[label] else { unreachable!() };
+    let mut values = Arr::new(arity, |_| NONE);
+
+    for i in e.points(a) {
+      if let Inst::Put(k, x) = code[i] {
+        values[k] = x;
+        e.dead[i] = true;
+      }
+    }
+
+    for i in e.points(b) {
+      if let Inst::Get(k, _) = code[i] {
+        e.replace[i] = values[k];
+        e.dead[i] = true;
+      }
+    }
+
+    e.dead[last] = true;
+    e.dead[label] = true;
+    e.next[a] = b;
+    e.moved[b] = true;
+    e.changed = true;
+  }
+
+  return e.finish();
+}
+
+/// Removes unreachable blocks, and values that are unused and can not trap.
+
+pub fn dce(code: &Arr<Inst>) -> Option<Arr<Inst>> {
+  let mut e = Edit::new(code);
+  let dom = Dominators::new(&e.cfg);
+  let mut live = Arr::new(code.len(), |_| false);
+  let mut stack = Buf::new();
+
+  for b in 0 .. e.cfg.len() {
+    if ! dom.is_reachable(b) { continue; }
+    for i in e.points(b) {
+      if has_effect(code, i) {
+        live[i] = true;
+        stack.push(i);
+      }
+    }
+  }
+
+  while ! stack.is_empty() {
+    let i = stack.pop();
+
+    let _ =
+      code[i].map_points(|x| {
+        if ! live[x] {
+          live[x] = true;
+          stack.push(x);
+        }
+        return x;
+      });
+
+    // NB: an unreachable block that a live value comes from is kept whole.
+
+    let b = e.cfg.block_at(i);
+
+    if ! dom.is_reachable(b) && ! live[e.points(b).start] {
+      for j in e.points(b) {
+        if ! live[j] {
+          live[j] = true;
+          stack.push(j);
+        }
+      }
+    }
+  }
+
+  for i in 0 .. code.len() {
+    if ! live[i] {
+      e.dead[i] = true;
+      e.changed = true;
+    }
+  }
+
+  return e.finish();
+}
+
+// Whether the instruction at `i` must stay even if its value is unused, since
+// it transfers control, has a side effect, or can trap.
+
+fn has_effect(code: &Arr<Inst>, i: u32) -> bool {
+  return match code[i] {
+    | Inst::GotoStaticError
+    | Inst::Label(..)
+    | Inst::Put(..)
+    | Inst::Goto(..)
+    | Inst::Cond(..)
+    | Inst::Ret
+    | Inst::Call(..)
+    | Inst::TailCall(..)
+    | Inst::Index(..)
+    | Inst::SetIndex(..)
+    | Inst::SetLocal(..) =>
+      true,
+    Inst::PrimOp2(op, _, y) if op.can_trap(constant(code, y)) =>
+      true,
+    | Inst::Get(..)
+    | Inst::Const(..)
+    | Inst::ConstFun(..)
+    | Inst::ConstBool(..)
+    | Inst::ConstInt(..)
+    | Inst::PrimOp1(..)
+    | Inst::PrimOp2(..)
+    | Inst::Local(..)
+    | Inst::GetLocal(..) =>
+      false,
+  };
+}
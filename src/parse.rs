@@ -12,10 +12,50 @@ use crate::symbol::Symbol;
 use crate::token::Token;
 use oxcart::Arena;
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The given token was expected at the given byte offset.
+  Expected(u32, Token),
+  /// An expression was expected at the given byte offset.
+  ExpectedExpr(u32),
+  /// The number at the given byte offset does not fit in an `i64`.
+  NumberOutOfRange(u32),
+}
+
+impl Error {
+  /// The byte offset of the token where the error was detected.
+
+  pub fn pos(&self) -> u32 {
+    match *self {
+      Self::Expected(pos, _) | Self::ExpectedExpr(pos) | Self::NumberOutOfRange(pos) => pos,
+    }
+  }
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::Expected(_, token) => write!(f, "expected {:?}", token),
+      Self::ExpectedExpr(_) => write!(f, "expected expression"),
+      Self::NumberOutOfRange(_) => write!(f, "number out of range"),
+    }
+  }
+}
+
 pub fn parse<'a>(source: &[u8], arena: Arena<'a>) -> (Arr<Item<'a>>, Arena<'a>) {
+  let (items, _, arena) = parse_with_errors(source, arena);
+  return (items, arena);
+}
+
+/// Parses like `parse`, and also returns the syntax errors, in order.
+///
+/// NB: the parser recovers from every error, so there may be several, and the
+/// part of the tree that an error affects is `undefined`.
+
+pub fn parse_with_errors<'a>(source: &[u8], arena: Arena<'a>) -> (Arr<Item<'a>>, Arr<Error>, Arena<'a>) {
   let mut t = T::new(source, arena);
   t.parse_item_list();
-  return (Arr::from(t.items.drain()), t.arena);
+  return (Arr::from(t.items.drain()), Arr::from(t.errors.drain()), t.arena);
 }
 
 struct T<'a, 'b> {
@@ -25,10 +65,11 @@ struct T<'a, 'b> {
   binds: Buf<Binding>,
   exprs: Buf<Expr<'a>>,
   stmts: Buf<Stmt<'a>>,
+  errors: Buf<Error>,
 }
 
-#[derive(Eq, Ord, PartialEq, PartialOrd)]
-enum P {
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum P {
   Any,
   Ternary,
   Or,
@@ -41,6 +82,23 @@ enum P {
   Add,
   Mul,
   Prefix,
+  // NB: the precedence of an atom or of a postfix expression, which only the
+  // formatter needs
+  Postfix,
+}
+
+impl P {
+  pub(crate) fn of_op2(op: Op2) -> Self {
+    match op {
+      Op2::CmpEq | Op2::CmpGe | Op2::CmpGt | Op2::CmpLe | Op2::CmpLt | Op2::CmpNe => P::Cmp,
+      Op2::BitOr => P::BitOr,
+      Op2::BitXor => P::BitXor,
+      Op2::BitAnd => P::BitAnd,
+      Op2::Shl | Op2::ShlSat | Op2::ShlWrap | Op2::Shr | Op2::ShrWrap => P::Shift,
+      Op2::Add | Op2::AddSat | Op2::Sub | Op2::SubSat => P::Add,
+      Op2::Div | Op2::DivSat | Op2::DivWrap | Op2::Mul | Op2::MulSat | Op2::Rem => P::Mul,
+    }
+  }
 }
 
 impl<'a, 'b> T<'a, 'b> {
@@ -52,6 +110,7 @@ impl<'a, 'b> T<'a, 'b> {
       binds: Buf::new(),
       exprs: Buf::new(),
       stmts: Buf::new(),
+      errors: Buf::new(),
     }
   }
 
@@ -67,6 +126,10 @@ impl<'a, 'b> T<'a, 'b> {
     return self.lexer.token_start();
   }
 
+  fn token_pos(&self) -> u32 {
+    return self.lexer.token_start() as u32;
+  }
+
   fn token_span(&self) -> &'b [u8] {
     return self.lexer.token_span();
   }
@@ -103,15 +166,16 @@ impl<'a, 'b> T<'a, 'b> {
         }
         Token::Fun => {
           self.next();
+          let pos = self.token_pos();
           let name = self.expect_symbol();
           self.expect(Token::LParen);
           let m = self.parse_binding_list(Token::RParen);
           self.expect(Token::RParen);
           let n = self.parse_block();
-          self.on_fun(name, m, n);
+          self.on_fun(name, pos, m, n);
         }
         _ => {
-          // TODO: error?
+          self.on_error_missing_expected_token(Token::Fun);
           break;
         }
       }
@@ -122,16 +186,16 @@ impl<'a, 'b> T<'a, 'b> {
     match self.token() {
       Token::Symbol => {
         let s = self.token_span();
-        self.on_binding(Some(s));
+        self.on_binding(Some(s), self.token_pos());
         self.next();
       }
       Token::Underscore => {
-        self.on_binding(None);
+        self.on_binding(None, self.token_pos());
         self.next();
       }
       _ => {
         self.on_error_missing_expected_token(Token::Symbol);
-        self.on_binding(None);
+        self.on_binding(None, self.token_pos());
       }
     }
   }
@@ -189,11 +253,13 @@ impl<'a, 'b> T<'a, 'b> {
       }
       Token::Number => {
         let value = self.token_span();
+        let pos = self.token_pos();
         self.next();
-        self.on_literal_number(value);
+        self.on_literal_number(value, pos);
       }
       Token::Symbol => {
         let symbol = self.token_span();
+        let pos = self.token_pos();
         self.next();
         match self.token() {
           Token::Equal if is_stmt => {
@@ -208,11 +274,10 @@ impl<'a, 'b> T<'a, 'b> {
           }
           Token::Inc => {
             self.next();
-            self.on_variable(symbol);
             self.on_post_op(symbol, Op1::Inc);
           }
           _ => {
-            self.on_variable(symbol);
+            self.on_variable(symbol, pos);
           }
         }
       }
@@ -327,31 +392,71 @@ impl<'a, 'b> T<'a, 'b> {
           self.parse_expr_prec(P::Shift);
           self.on_op2(Op2::Shl);
         }
+        Token::ShlSat if p < P::Shift => {
+          self.next();
+          self.parse_expr_prec(P::Shift);
+          self.on_op2(Op2::ShlSat);
+        }
+        Token::ShlWrap if p < P::Shift => {
+          self.next();
+          self.parse_expr_prec(P::Shift);
+          self.on_op2(Op2::ShlWrap);
+        }
         Token::Shr if p < P::Shift => {
           self.next();
           self.parse_expr_prec(P::Shift);
           self.on_op2(Op2::Shr);
         }
+        Token::ShrWrap if p < P::Shift => {
+          self.next();
+          self.parse_expr_prec(P::Shift);
+          self.on_op2(Op2::ShrWrap);
+        }
         Token::Add if p < P::Add => {
           self.next();
           self.parse_expr_prec(P::Add);
           self.on_op2(Op2::Add);
         }
+        Token::AddSat if p < P::Add => {
+          self.next();
+          self.parse_expr_prec(P::Add);
+          self.on_op2(Op2::AddSat);
+        }
         Token::Hyphen if p < P::Add => {
           self.next();
           self.parse_expr_prec(P::Add);
           self.on_op2(Op2::Sub);
         }
+        Token::SubSat if p < P::Add => {
+          self.next();
+          self.parse_expr_prec(P::Add);
+          self.on_op2(Op2::SubSat);
+        }
         Token::Div if p < P::Mul => {
           self.next();
           self.parse_expr_prec(P::Mul);
           self.on_op2(Op2::Div);
         }
+        Token::DivSat if p < P::Mul => {
+          self.next();
+          self.parse_expr_prec(P::Mul);
+          self.on_op2(Op2::DivSat);
+        }
+        Token::DivWrap if p < P::Mul => {
+          self.next();
+          self.parse_expr_prec(P::Mul);
+          self.on_op2(Op2::DivWrap);
+        }
         Token::Mul if p < P::Mul => {
           self.next();
           self.parse_expr_prec(P::Mul);
           self.on_op2(Op2::Mul);
         }
+        Token::MulSat if p < P::Mul => {
+          self.next();
+          self.parse_expr_prec(P::Mul);
+          self.on_op2(Op2::MulSat);
+        }
         Token::Rem if p < P::Mul => {
           self.next();
           self.parse_expr_prec(P::Mul);
@@ -450,10 +555,11 @@ impl<'a, 'b> T<'a, 'b> {
         }
         Token::Var => {
           self.next();
+          let pos = self.token_pos();
           let symbol = self.expect_symbol();
           self.expect(Token::Equal);
           self.parse_expr();
-          self.on_var(symbol);
+          self.on_var(symbol, pos);
           n_stmts += 1;
         }
         Token::While => {
@@ -535,32 +641,33 @@ impl<'a, 'b> T<'a, 'b> {
     return self.arena.slice_from_iter(self.stmts.pop_list(n));
   }
 
-  fn on_fun(&mut self, name: &[u8], n_args: u32, n_stmts: u32) {
+  fn on_fun(&mut self, name: &[u8], pos: u32, n_args: u32, n_stmts: u32) {
     let z = self.pop_stmt_list(n_stmts);
     let y = self.pop_bind_list(n_args);
     let x = Symbol::from_bytes(name);
-    let x = Item::Fun(Fun { name: x, args: y, body: z });
+    let x = Item::Fun(Fun { name: x, pos, args: y, body: z });
     self.push_item(x);
   }
 
-  fn on_binding(&mut self, name: Option<&[u8]>) {
-    let x = Binding { name: name.map(Symbol::from_bytes) };
+  fn on_binding(&mut self, name: Option<&[u8]>, pos: u32) {
+    let x = Binding { name: name.map(Symbol::from_bytes), pos };
     self.push_bind(x);
   }
 
-  fn on_variable(&mut self, symbol: &[u8]) {
+  fn on_variable(&mut self, symbol: &[u8], pos: u32) {
     let s = Symbol::from_bytes(symbol);
-    self.push_expr(Expr::Variable(s));
+    self.push_expr(Expr::Variable(s, pos));
   }
 
   fn on_literal_bool(&mut self, value: bool) {
     self.push_expr(Expr::Bool(value));
   }
 
-  fn on_literal_number(&mut self, x: &[u8]) {
+  fn on_literal_number(&mut self, x: &[u8], pos: u32) {
     let n =
       match i64::from_str_radix(str::from_utf8(x).unwrap(), 10) {
         Err(_) => {
+          self.errors.push(Error::NumberOutOfRange(pos));
           self.push_expr(Expr::Undefined);
           return;
         }
@@ -702,10 +809,10 @@ impl<'a, 'b> T<'a, 'b> {
     self.push_stmt(Stmt::SetIndex(x, y, z));
   }
 
-  fn on_var(&mut self, symbol: &[u8]) {
+  fn on_var(&mut self, symbol: &[u8], pos: u32) {
     let s = Symbol::from_bytes(symbol);
     let x = self.pop_expr();
-    self.push_stmt(Stmt::Var(s, x));
+    self.push_stmt(Stmt::Var(s, pos, x));
   }
 
   fn on_while(&mut self, n_stmts: u32) {
@@ -715,12 +822,11 @@ impl<'a, 'b> T<'a, 'b> {
   }
 
   fn on_error_missing_expected_token(&mut self, token: Token) {
-    let _ = token;
-    // TODO: report error on missing expected token
+    self.errors.push(Error::Expected(self.token_pos(), token));
   }
 
   fn on_error_missing_expr(&mut self) {
-    // TODO: report error on missing expected expression
+    self.errors.push(Error::ExpectedExpr(self.token_pos()));
     self.push_expr(Expr::Undefined);
   }
 }
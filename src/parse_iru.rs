@@ -0,0 +1,119 @@
+//! bytecode parser
+//!
+//! text of untyped bytecode -> linearized bytecode
+//!
+//! Reads back what `iru::Module` displays: a header `=== fun name ===` for
+//! each function, followed by its instructions, one per line, each numbered
+//! with its program point, like `%3 = %1 + %2`. So printing a module and
+//! parsing the text gives back the same module, and tests can be written
+//! directly in bytecode.
+//!
+//! Blank lines and the indentation of lines are ignored. The program points
+//! that instructions refer to are not checked.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::iru::Fun;
+use crate::iru::Inst;
+use crate::iru::Module;
+use crate::operator::Op1;
+use crate::operator::Op2;
+use crate::symbol::Symbol;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The given line is neither an instruction nor a function header.
+  InvalidLine(u32),
+  /// The instruction on the given line is not numbered with its program point.
+  WrongProgramPoint(u32),
+  /// The instruction on the given line comes before any function header.
+  OutsideFunction(u32),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::InvalidLine(n) => write!(f, "invalid line {}", n),
+      Self::WrongProgramPoint(n) => write!(f, "wrong program point at line {}", n),
+      Self::OutsideFunction(n) => write!(f, "instruction outside a function at line {}", n),
+    }
+  }
+}
+
+pub fn parse(text: &str) -> Result<Module, Error> {
+  let mut code = Buf::new();
+  let mut decl = Buf::new();
+
+  for (k, line) in text.lines().enumerate() {
+    let n = k as u32 + 1;
+    let words: Box<[&str]> = line.split_whitespace().collect();
+
+    match *words {
+      [] => {
+      }
+      ["===", "fun", name, "==="] => {
+        let Some(name) = Symbol::parse(name) else { return Err(Error::InvalidLine(n)); };
+        decl.push(Fun { name, pos: code.len(), len: 0 });
+      }
+      [point, ref rest @ ..] => {
+        let Some(i) = value(point) else { return Err(Error::InvalidLine(n)); };
+        let Some(inst) = inst(rest) else { return Err(Error::InvalidLine(n)); };
+        if i != code.len() { return Err(Error::WrongProgramPoint(n)); }
+        if decl.is_empty() { return Err(Error::OutsideFunction(n)); }
+        code.push(inst);
+        decl.top_mut().len += 1;
+      }
+    }
+  }
+
+  return Ok(Module { code: Arr::from(code.drain()), decl: Arr::from(decl.drain()) });
+}
+
+// A reference to a program point, like `%3`.
+
+fn value(word: &str) -> Option<u32> {
+  return word.strip_prefix('%')?.parse().ok();
+}
+
+fn field(word: &str) -> Option<Symbol> {
+  return Symbol::parse(word.strip_prefix('.')?);
+}
+
+fn inst(words: &[&str]) -> Option<Inst> {
+  let inst =
+    match *words {
+      ["LABEL", n] => Inst::Label(n.parse().ok()?),
+      ["=", "GET", i] => Inst::Get(i.parse().ok()?),
+      ["PUT", i, x] => Inst::Put(i.parse().ok()?, value(x)?),
+      ["==>", "GOTO", x] => Inst::Goto(value(x)?),
+      ["==>", "GOTO-STATIC-ERROR"] => Inst::GotoStaticError,
+      ["COND", x] => Inst::Cond(value(x)?),
+      ["RET"] => Inst::Ret,
+      ["CALL", x] => Inst::Call(value(x)?),
+      ["TAIL-CALL", x] => Inst::TailCall(value(x)?),
+      ["=", "CONST", s] => Inst::Const(Symbol::parse(s)?),
+      ["=", "LOCAL", x] => Inst::Local(value(x)?),
+      ["=", "true"] => Inst::ConstBool(true),
+      ["=", "false"] => Inst::ConstBool(false),
+      ["=", n] => Inst::ConstInt(n.parse().ok()?),
+      ["=", x, "[", i, "]"] => {
+        match field(i) {
+          Some(s) => Inst::Field(value(x)?, s),
+          None => Inst::Index(value(x)?, value(i)?),
+        }
+      }
+      ["=", "[", v, "]"] => Inst::GetLocal(value(v)?),
+      ["=", op, x] => Inst::Op1(Op1::from_str(op)?, value(x)?),
+      ["=", x, op, y] => Inst::Op2(Op2::from_str(op)?, value(x)?, value(y)?),
+      [x, "[", i, "]", "<-", y] => {
+        match field(i) {
+          Some(s) => Inst::SetField(value(x)?, s, value(y)?),
+          None => Inst::SetIndex(value(x)?, value(i)?, value(y)?),
+        }
+      }
+      ["[", v, "]", "<-", x] => Inst::SetLocal(value(v)?, value(x)?),
+      _ => return None,
+    };
+
+  return Some(inst);
+}
@@ -7,6 +7,31 @@ pub enum PrimType {
 
 use PrimType::*;
 
+/// A dynamic error raised by a primitive operation.
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Trap {
+  DivisionByZero,
+  IntegerOverflow,
+  NegativeLength,
+  OutOfMemory,
+  ShiftOutOfRange,
+}
+
+impl std::fmt::Display for Trap {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let s =
+      match self {
+        &Self::DivisionByZero => "division by zero",
+        &Self::IntegerOverflow => "integer overflow",
+        &Self::NegativeLength => "negative length",
+        &Self::OutOfMemory => "out of memory",
+        &Self::ShiftOutOfRange => "shift out of range",
+      };
+    f.write_str(s)
+  }
+}
+
 impl std::fmt::Display for PrimType {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let s =
@@ -48,6 +73,22 @@ impl PrimOp1 {
   }
 }
 
+impl PrimOp1 {
+  /// Evaluates the operation on the bit representation of its argument, in
+  /// which a `bool` is either `0` or `1`.
+
+  pub fn eval(&self, x: i64) -> Result<i64, Trap> {
+    let r =
+      match self {
+        Self::DecI64 => x.wrapping_sub(1),
+        Self::IncI64 => x.wrapping_add(1),
+        Self::NegI64 => x.wrapping_neg(),
+        Self::NotBool => x ^ 1,
+      };
+    return Ok(r);
+  }
+}
+
 impl std::fmt::Display for PrimOp1 {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_str(self.as_str())
@@ -58,6 +99,7 @@ impl std::fmt::Display for PrimOp1 {
 #[repr(u8)]
 pub enum PrimOp2 {
   AddI64,
+  AddSatI64,
   BitAndI64,
   BitOrI64,
   BitXorI64,
@@ -68,15 +110,23 @@ pub enum PrimOp2 {
   CmpLtI64,
   CmpNeI64,
   DivI64,
+  DivSatI64,
+  DivWrapI64,
   MulI64,
+  MulSatI64,
   RemI64,
   ShlI64,
+  ShlSatI64,
+  ShlWrapI64,
   ShrI64,
+  ShrWrapI64,
   SubI64,
+  SubSatI64,
 }
 
-static OP2_TABLE: [(&'static str, (PrimType, PrimType), PrimType); 16] = [
+static OP2_TABLE: [(&'static str, (PrimType, PrimType), PrimType); 24] = [
   ("add.i64", (I64, I64), I64),
+  ("addsat.i64", (I64, I64), I64),
   ("bitand.i64", (I64, I64), I64),
   ("bitor.i64", (I64, I64), I64),
   ("bitxor.i64", (I64, I64), I64),
@@ -87,11 +137,18 @@ static OP2_TABLE: [(&'static str, (PrimType, PrimType), PrimType); 16] = [
   ("cmplt.i64", (I64, I64), Bool),
   ("cmpne.i64", (I64, I64), Bool),
   ("div.i64", (I64, I64), I64),
+  ("divsat.i64", (I64, I64), I64),
+  ("divwrap.i64", (I64, I64), I64),
   ("mul.i64", (I64, I64), I64),
+  ("mulsat.i64", (I64, I64), I64),
   ("rem.i64", (I64, I64), I64),
   ("shl.i64", (I64, I64), I64),
+  ("shlsat.i64", (I64, I64), I64),
+  ("shlwrap.i64", (I64, I64), I64),
   ("shr.i64", (I64, I64), I64),
+  ("shrwrap.i64", (I64, I64), I64),
   ("sub.i64", (I64, I64), I64),
+  ("subsat.i64", (I64, I64), I64),
 ];
 
 impl PrimOp2 {
@@ -108,6 +165,101 @@ impl PrimOp2 {
   }
 }
 
+impl PrimOp2 {
+  /// Evaluates the operation on the bit representation of its arguments, in
+  /// which a `bool` is either `0` or `1`.
+  ///
+  /// - `add`, `sub`, and `mul` wrap on overflow.
+  /// - `div` and `rem` trap on a zero divisor. `div` traps on the one quotient
+  ///   that overflows, `i64::MIN / -1`, while `divwrap` gives `i64::MIN` and
+  ///   `divsat` gives `i64::MAX`. The remainder `i64::MIN % -1` is `0`.
+  /// - `shl` and `shr` trap unless the shift amount is in `0 .. 64`, while
+  ///   `shlwrap` and `shrwrap` take it modulo 64. `shr` is arithmetic.
+  /// - The `sat` variants clamp the exact result to the range of `i64`. For
+  ///   `shlsat`, that is `x * 2^y`, and a negative amount traps.
+  ///
+  /// The interpreters, the constant folder, and the code emitted by every back
+  /// end all follow these rules.
+
+  pub fn eval(&self, x: i64, y: i64) -> Result<i64, Trap> {
+    let r =
+      match self {
+        Self::AddI64 => x.wrapping_add(y),
+        Self::AddSatI64 => x.saturating_add(y),
+        Self::BitAndI64 => x & y,
+        Self::BitOrI64 => x | y,
+        Self::BitXorI64 => x ^ y,
+        Self::CmpEqI64 => (x == y) as i64,
+        Self::CmpGeI64 => (x >= y) as i64,
+        Self::CmpGtI64 => (x > y) as i64,
+        Self::CmpLeI64 => (x <= y) as i64,
+        Self::CmpLtI64 => (x < y) as i64,
+        Self::CmpNeI64 => (x != y) as i64,
+        Self::DivI64 => {
+          if y == 0 { return Err(Trap::DivisionByZero); }
+          let Some(z) = x.checked_div(y) else { return Err(Trap::IntegerOverflow); };
+          z
+        }
+        Self::DivSatI64 => {
+          if y == 0 { return Err(Trap::DivisionByZero); }
+          x.saturating_div(y)
+        }
+        Self::DivWrapI64 => {
+          if y == 0 { return Err(Trap::DivisionByZero); }
+          x.wrapping_div(y)
+        }
+        Self::MulI64 => x.wrapping_mul(y),
+        Self::MulSatI64 => x.saturating_mul(y),
+        Self::RemI64 => {
+          if y == 0 { return Err(Trap::DivisionByZero); }
+          x.wrapping_rem(y)
+        }
+        Self::ShlI64 => {
+          if ! (0 .. 64).contains(&y) { return Err(Trap::ShiftOutOfRange); }
+          x << y
+        }
+        Self::ShlSatI64 => {
+          if y < 0 { return Err(Trap::ShiftOutOfRange); }
+          shl_sat(x, y)
+        }
+        Self::ShlWrapI64 => x.wrapping_shl(y as u32),
+        Self::ShrI64 => {
+          if ! (0 .. 64).contains(&y) { return Err(Trap::ShiftOutOfRange); }
+          x >> y
+        }
+        Self::ShrWrapI64 => x.wrapping_shr(y as u32),
+        Self::SubI64 => x.wrapping_sub(y),
+        Self::SubSatI64 => x.saturating_sub(y),
+      };
+    return Ok(r);
+  }
+
+  /// Whether the operation can trap when its second argument is `y`, or is
+  /// not known.
+
+  pub fn can_trap(&self, y: Option<i64>) -> bool {
+    match (self, y) {
+      (Self::DivI64, Some(y)) => y == 0 || y == -1,
+      (Self::DivSatI64 | Self::DivWrapI64 | Self::RemI64, Some(y)) => y == 0,
+      (Self::ShlI64 | Self::ShrI64, Some(y)) => ! (0 .. 64).contains(&y),
+      (Self::ShlSatI64, Some(y)) => y < 0,
+      (Self::DivI64 | Self::DivSatI64 | Self::DivWrapI64 | Self::RemI64, None) => true,
+      (Self::ShlI64 | Self::ShlSatI64 | Self::ShrI64, None) => true,
+      _ => false,
+    }
+  }
+}
+
+// `x * 2^y` clamped to the range of `i64`, for `y >= 0`. Shifting by 63 and by
+// more saturates the same way, except for `0`, which stays `0` either way.
+
+fn shl_sat(x: i64, y: i64) -> i64 {
+  let s = y.min(63);
+  let z = x << s;
+  if z >> s == x { return z; }
+  return if x < 0 { i64::MIN } else { i64::MAX };
+}
+
 impl std::fmt::Display for PrimOp2 {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_str(self.as_str())
@@ -0,0 +1,422 @@
+//! promotion of locals
+//!
+//! untyped bytecode -> untyped bytecode that passes values in block arguments
+//!
+//! A `var` is lowered to a `Local` cell that `GetLocal` reads and `SetLocal`
+//! writes. A local whose cell is used for nothing else is promoted: its reads
+//! are replaced by the values that reach them, and where different values
+//! reach the start of a block from its predecessors, the block receives the
+//! value as an extra argument after its own ones. This is the construction of
+//! Braun et al., "Simple and Efficient Construction of Static Single Assignment
+//! Form", with the block arguments as its phi functions.
+//!
+//! - The value of a local at the end of a block is the last value that the
+//!   block stores to it, or else its value at the start of the block.
+//!
+//! - The value at the start of a block with one predecessor is its value at
+//!   the end of that predecessor. A block with several predecessors gets an
+//!   argument, whose operands are the values at the end of each predecessor.
+//!   An argument whose operands are all the same value, or itself, is replaced
+//!   by that value.
+//!
+//! - The entry block, and a block that is not reachable from it, has no value
+//!   for the local to start with. Since no read there can be reached before
+//!   the `Local`, the initial value of the local stands in for it.
+//!
+//! A plain `Goto` passes the extra arguments with more `Put`s. The `Goto`s
+//! after a `Cond` or a `Call` can not, since their `Put`s are shared or are
+//! the results of the call, so they go through a new block after the block
+//! that forwards the arguments that it receives and adds the extra ones.
+//!
+//! A function whose entry block is the target of a `Goto` is left as it is,
+//! since extra arguments there would change its arity.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::cfg;
+use crate::cfg::Cfg;
+use crate::cfg::Dominators;
+use crate::iru::Fun;
+use crate::iru::Inst;
+use crate::iru::Module;
+
+const NONE: u32 = u32::MAX;
+
+// A value, before the arguments that are replaced have been resolved.
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Val {
+  /// The value of the given program point of the input.
+  Point(u32),
+  /// The extra argument with the given index.
+  Arg(u32),
+}
+
+struct Arg {
+  block: u32,
+  /// The value at the end of each predecessor of the block, in order.
+  operands: Arr<Val>,
+  replaced: Option<Val>,
+}
+
+struct Promoter<'a> {
+  code: &'a Arr<Inst>,
+  pos: u32,
+  cfg: Cfg,
+  dom: Dominators,
+  /// The promoted local that each program point creates, if any.
+  var: Arr<u32>,
+  /// The initial value of each promoted local.
+  init: Buf<u32>,
+  /// The last value that each block stores to each local that it stores to.
+  defs: Arr<Buf<(u32, Val)>>,
+  /// The value of each local at the start of each block, once it is known.
+  start: Arr<Buf<(u32, Val)>>,
+  /// The value that each read of a promoted local is replaced with.
+  reads: Arr<Val>,
+  args: Buf<Arg>,
+  /// The extra arguments of each block that are not replaced, in order.
+  block_args: Arr<Buf<u32>>,
+}
+
+/// Promotes every local that is only read and written.
+
+pub fn promote(module: &Module) -> Module {
+  let mut code = Buf::new();
+  let mut decl = Buf::new();
+
+  for f in module.decl.iter() {
+    let pos = code.len();
+    let mut t = Promoter::new(&module.code, f);
+    t.run();
+
+    // NB: the second pass sees where every value and label ends up in the
+    // first one, including the ones that come after their uses.
+
+    let mut at = Arr::new(f.len, |_| 0);
+    let mut arg_at = Arr::new(t.args.len(), |_| 0);
+    let mut trampoline_at = Arr::new(f.len, |_| 0);
+
+    for _ in 0 .. 2 {
+      let _ = code.pop_list(code.len() - pos);
+      t.emit(&mut at, &mut arg_at, &mut trampoline_at, &mut code);
+    }
+
+    decl.push(Fun { name: f.name, pos, len: code.len() - pos });
+  }
+
+  let module =
+    Module {
+      code: code.drain().into(),
+      decl: decl.drain().into(),
+    };
+
+  return module;
+}
+
+impl<'a> Promoter<'a> {
+  fn new(code: &'a Arr<Inst>, f: &Fun) -> Self {
+    let cfg = Cfg::new(code, f.pos, f.len);
+    let dom = Dominators::new(&cfg);
+    let n = cfg.len();
+
+    return Self {
+      code,
+      pos: f.pos,
+      cfg,
+      dom,
+      var: Arr::new(f.len, |_| NONE),
+      init: Buf::new(),
+      defs: Arr::new(n, |_| Buf::new()),
+      start: Arr::new(n, |_| Buf::new()),
+      reads: Arr::new(f.len, |_| Val::Point(NONE)),
+      args: Buf::new(),
+      block_args: Arr::new(n, |_| Buf::new()),
+    };
+  }
+
+  fn run(&mut self) {
+    let code = self.code;
+    let pos = self.pos;
+    let len = self.var.len();
+
+    if self.cfg.is_empty() || self.cfg.blocks[0].preds.len() != 0 { return; }
+
+    // Find the locals whose cells are used for nothing else.
+
+    let mut escapes = Arr::new(len, |_| false);
+
+    for i in pos .. pos + len {
+      match code[i] {
+        Inst::GetLocal(_) => {
+        }
+        Inst::SetLocal(_, x) => {
+          if x >= pos { escapes[x - pos] = true; }
+        }
+        inst => {
+          cfg::Inst::for_each_use(inst, |x| if x >= pos { escapes[x - pos] = true; });
+        }
+      }
+    }
+
+    for i in pos .. pos + len {
+      if let Inst::Local(x) = code[i] {
+        if ! escapes[i - pos] {
+          self.var[i - pos] = self.init.len();
+          self.init.push(x);
+        }
+      }
+    }
+
+    if self.init.is_empty() { return; }
+
+    // Find the value of each local at the end of each block that stores to
+    // it, then what each read sees.
+
+    for b in 0 .. self.cfg.len() {
+      let block = &self.cfg.blocks[b];
+      for i in block.pos .. block.pos + block.len {
+        match code[i] {
+          Inst::Local(x) if self.var[i - pos] != NONE => {
+            set(&mut self.defs[b], self.var[i - pos], Val::Point(x));
+          }
+          Inst::SetLocal(v, x) if self.var[v - pos] != NONE => {
+            set(&mut self.defs[b], self.var[v - pos], Val::Point(x));
+          }
+          _ => {
+          }
+        }
+      }
+    }
+
+    for b in 0 .. self.cfg.len() {
+      let (start, len) = (self.cfg.blocks[b].pos, self.cfg.blocks[b].len);
+      let mut current = Buf::new();
+      for i in start .. start + len {
+        match code[i] {
+          Inst::Local(x) if self.var[i - pos] != NONE => {
+            set(&mut current, self.var[i - pos], Val::Point(x));
+          }
+          Inst::SetLocal(v, x) if self.var[v - pos] != NONE => {
+            set(&mut current, self.var[v - pos], Val::Point(x));
+          }
+          Inst::GetLocal(v) if self.var[v - pos] != NONE => {
+            let var = self.var[v - pos];
+            let value = match get(&current, var) { Some(x) => x, None => self.read_start(var, b) };
+            self.reads[i - pos] = value;
+          }
+          _ => {
+          }
+        }
+      }
+    }
+
+    // Replace the arguments that only ever see one value, until there are no
+    // more.
+
+    let mut changed = true;
+
+    while changed {
+      changed = false;
+      for k in 0 .. self.args.len() {
+        if self.args[k].replaced.is_some() { continue; }
+        let mut unique = None;
+        let mut trivial = true;
+        for j in 0 .. self.args[k].operands.len() {
+          let x = self.resolve(self.args[k].operands[j]);
+          if x == Val::Arg(k) || unique == Some(x) { continue; }
+          if unique.is_some() { trivial = false; break; }
+          unique = Some(x);
+        }
+        if trivial && let Some(x) = unique {
+          self.args[k].replaced = Some(x);
+          changed = true;
+        }
+      }
+    }
+
+    for k in 0 .. self.args.len() {
+      if self.args[k].replaced.is_none() {
+        self.block_args[self.args[k].block].push(k);
+      }
+    }
+  }
+
+  fn read_end(&mut self, var: u32, b: u32) -> Val {
+    if let Some(x) = get(&self.defs[b], var) { return x; }
+    return self.read_start(var, b);
+  }
+
+  fn read_start(&mut self, var: u32, b: u32) -> Val {
+    if let Some(x) = get(&self.start[b], var) { return x; }
+
+    let n = self.cfg.blocks[b].preds.len();
+
+    if n == 0 || ! self.dom.is_reachable(b) {
+      return Val::Point(self.init[var]);
+    }
+
+    if n == 1 {
+      let x = self.read_end(var, self.cfg.blocks[b].preds[0]);
+      set(&mut self.start[b], var, x);
+      return x;
+    }
+
+    // NB: the argument is recorded before its operands are read, which ends
+    // the search at loops.
+
+    let k = self.args.len();
+    self.args.push(Arg { block: b, operands: Arr::default(), replaced: None });
+    set(&mut self.start[b], var, Val::Arg(k));
+    let operands = Arr::new(n, |j| self.read_end(var, self.cfg.blocks[b].preds[j]));
+    self.args[k].operands = operands;
+    return Val::Arg(k);
+  }
+
+  fn resolve(&self, x: Val) -> Val {
+    let mut x = x;
+    loop {
+      match x {
+        Val::Point(i) if self.is_read(i) => {
+          x = self.reads[i - self.pos];
+        }
+        Val::Arg(k) if let Some(y) = self.args[k].replaced => {
+          x = y;
+        }
+        _ => {
+          return x;
+        }
+      }
+    }
+  }
+
+  fn is_read(&self, i: u32) -> bool {
+    return matches!(self.code[i], Inst::GetLocal(v) if self.var[v - self.pos] != NONE);
+  }
+
+  // The extra arguments that a `Goto` from block `b` to the label at `a`
+  // passes.
+
+  fn extra(&self, b: u32, a: u32) -> impl Iterator<Item = Val> + '_ {
+    let c = self.cfg.block_at(a);
+    let j = self.cfg.blocks[c].preds.iter().position(|&p| p == b).unwrap() as u32;
+    return self.block_args[c].iter().map(move |&k| self.resolve(self.args[k].operands[j]));
+  }
+
+  fn emit(&self, at: &mut Arr<u32>, arg_at: &mut Arr<u32>, trampoline_at: &mut Arr<u32>, out: &mut Buf<Inst>) {
+    let code = self.code;
+    let pos = self.pos;
+
+    for b in 0 .. self.cfg.len() {
+      let (start, len) = (self.cfg.blocks[b].pos, self.cfg.blocks[b].len);
+      let mut trampolines = Buf::new();
+
+      for i in start .. start + len {
+        let value = |x: u32| {
+          return match self.resolve(Val::Point(x)) {
+            Val::Point(y) => at[y - pos],
+            Val::Arg(k) => arg_at[k],
+          };
+        };
+
+        let inst =
+          match code[i] {
+            Inst::Label(n) => {
+              at[i - pos] = out.len();
+              out.push(Inst::Label(n + self.block_args[b].len()));
+              for (j, &k) in self.block_args[b].iter().enumerate() {
+                arg_at[k] = out.len();
+                out.push(Inst::Get(n + j as u32));
+              }
+              continue;
+            }
+            Inst::Local(_) if self.var[i - pos] != NONE => {
+              continue;
+            }
+            Inst::GetLocal(v) | Inst::SetLocal(v, _) if self.var[v - pos] != NONE => {
+              continue;
+            }
+            Inst::Goto(a) => {
+              let c = self.cfg.block_at(a);
+              let shared = matches!(code[i - 1], Inst::Cond(_) | Inst::Call(_)) || i >= 2 && matches!(code[i - 2], Inst::Cond(_));
+              if self.block_args[c].is_empty() {
+                Inst::Goto(at[a - pos])
+              } else if shared {
+                trampolines.push(i);
+                Inst::Goto(trampoline_at[i - pos])
+              } else {
+                let Inst::Label(n) = code[a] else { unreachable!() };
+                let extra: Buf<Val> = self.extra(b, a).collect();
+                for (j, &x) in extra.iter().enumerate() {
+                  out.push(Inst::Put(n + j as u32, self.at(at, arg_at, x)));
+                }
+                Inst::Goto(at[a - pos])
+              }
+            }
+            Inst::GotoStaticError => Inst::GotoStaticError,
+            Inst::Get(k) => Inst::Get(k),
+            Inst::Put(k, x) => Inst::Put(k, value(x)),
+            Inst::Cond(x) => Inst::Cond(value(x)),
+            Inst::Ret => Inst::Ret,
+            Inst::Call(x) => Inst::Call(value(x)),
+            Inst::TailCall(x) => Inst::TailCall(value(x)),
+            Inst::Const(s) => Inst::Const(s),
+            Inst::ConstBool(p) => Inst::ConstBool(p),
+            Inst::ConstInt(n) => Inst::ConstInt(n),
+            Inst::Field(x, s) => Inst::Field(value(x), s),
+            Inst::Index(x, y) => Inst::Index(value(x), value(y)),
+            Inst::Op1(op, x) => Inst::Op1(op, value(x)),
+            Inst::Op2(op, x, y) => Inst::Op2(op, value(x), value(y)),
+            Inst::Local(x) => Inst::Local(value(x)),
+            Inst::GetLocal(v) => Inst::GetLocal(at[v - pos]),
+            Inst::SetField(x, s, y) => Inst::SetField(value(x), s, value(y)),
+            Inst::SetIndex(x, y, z) => Inst::SetIndex(value(x), value(y), value(z)),
+            Inst::SetLocal(v, x) => Inst::SetLocal(at[v - pos], value(x)),
+          };
+
+        at[i - pos] = out.len();
+        out.push(inst);
+      }
+
+      // Forward the arguments of each branch or continuation that gets extra
+      // ones.
+
+      for &i in trampolines.iter() {
+        let Inst::Goto(a) = code[i] else { unreachable!() };
+        let Inst::Label(n) = code[a] else { unreachable!() };
+
+        trampoline_at[i - pos] = out.len();
+        out.push(Inst::Label(n));
+        let first = out.len();
+        for k in 0 .. n { out.push(Inst::Get(k)); }
+        for k in 0 .. n { out.push(Inst::Put(k, first + k)); }
+        let extra: Buf<Val> = self.extra(b, a).collect();
+        for (j, &x) in extra.iter().enumerate() {
+          out.push(Inst::Put(n + j as u32, self.at(at, arg_at, x)));
+        }
+        out.push(Inst::Goto(at[a - pos]));
+      }
+    }
+  }
+
+  fn at(&self, at: &Arr<u32>, arg_at: &Arr<u32>, x: Val) -> u32 {
+    return match x {
+      Val::Point(y) => at[y - self.pos],
+      Val::Arg(k) => arg_at[k],
+    };
+  }
+}
+
+fn get(entries: &Buf<(u32, Val)>, var: u32) -> Option<Val> {
+  return entries.iter().find(|e| e.0 == var).map(|e| e.1);
+}
+
+fn set(entries: &mut Buf<(u32, Val)>, var: u32, x: Val) {
+  for j in 0 .. entries.len() {
+    if entries[j].0 == var {
+      entries[j].1 = x;
+      return;
+    }
+  }
+  entries.push((var, x));
+}
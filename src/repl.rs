@@ -0,0 +1,434 @@
+//! read-eval-print loop
+//!
+//! definitions and expressions, one input at a time -> types and values
+//!
+//! The session is the definitions accepted so far, each lowered on its own,
+//! and the global environment of their type schemes, along with the types that
+//! the schemes refer to. An input is checked against the environment, which
+//! it then extends, so a definition can call any earlier one, and the session
+//! is not checked again. An input that defines a function again replaces the
+//! earlier input that defined it, and only the later definitions that call a
+//! replaced function, directly or not, are checked again. An input that fails
+//! to check leaves the session as it was.
+//!
+//! An expression is compiled as the body of a fresh function with no
+//! parameters, which the interpreter then runs, linked after the code of the
+//! session. A function value is shown by its name and its type scheme.
+//!
+//! Errors refer to the input: a byte offset is relative to the input, and a
+//! program point is replaced by the name of the function that it is in, unless
+//! it is in the input itself.
+
+use crate::buf::Buf;
+use crate::driver;
+use crate::eval_iru;
+use crate::eval_iru::Builtin;
+use crate::eval_iru::Value;
+use crate::iru;
+use crate::lexer::Lexer;
+use crate::make_iru;
+use crate::make_irp;
+use crate::parse;
+use crate::symbol::Symbol;
+use crate::token::Token;
+use crate::typecheck;
+use crate::typecheck::TypeScheme;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
+use std::fmt::Write;
+use tangerine::map::HashMap;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  Driver(driver::Error),
+  /// An error at a program point, which is not shown. The point is in the
+  /// given function of the session, or else in the input itself.
+  Hidden(driver::Error, Option<Symbol>),
+  /// The session does not define the given function.
+  UnboundFunction(Symbol),
+  UnknownCommand,
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::Driver(e) => write!(f, "{}", e),
+      Self::Hidden(e, s) => {
+        describe(f, e)?;
+        if let Some(s) = s { write!(f, " in {}", s)?; }
+        Ok(())
+      }
+      Self::UnboundFunction(s) => write!(f, "unbound function {}", s),
+      Self::UnknownCommand => write!(f, "unknown command"),
+    }
+  }
+}
+
+// Writes what the driver error `e` is, but not where.
+
+fn describe(f: &mut std::fmt::Formatter<'_>, e: driver::Error) -> std::fmt::Result {
+  use driver::Error::RuntimeError;
+  use driver::Error::TypeError;
+
+  let s =
+    match e {
+      driver::Error::StaticError(..) => "static error",
+      TypeError(make_irp::Error::StaticError(..)) => "static error",
+      TypeError(make_irp::Error::TypeError(..)) => "type error",
+      TypeError(make_irp::Error::Unsupported(..)) => "unsupported instruction",
+      RuntimeError(eval_iru::Error::ArityMismatch(_)) => "arity mismatch",
+      RuntimeError(eval_iru::Error::IndexOutOfBounds(_)) => "index out of bounds",
+      RuntimeError(eval_iru::Error::StaticError(_)) => "static error",
+      RuntimeError(eval_iru::Error::Trap(_, t)) => return write!(f, "{}", t),
+      RuntimeError(eval_iru::Error::TypeError(_)) => "type error",
+      RuntimeError(eval_iru::Error::Unsupported(_)) => "unsupported instruction",
+      _ => return write!(f, "{}", e),
+    };
+  f.write_str(s)
+}
+
+// The program point of the driver error `e`, if it has one.
+
+fn point(e: driver::Error) -> Option<u32> {
+  use driver::Error::RuntimeError;
+  use driver::Error::TypeError;
+
+  match e {
+    driver::Error::StaticError(_, i) => Some(i),
+    TypeError(make_irp::Error::StaticError(_, i) | make_irp::Error::TypeError(_, i) | make_irp::Error::Unsupported(_, i)) => Some(i),
+    RuntimeError(
+      eval_iru::Error::ArityMismatch(i)
+      | eval_iru::Error::IndexOutOfBounds(i)
+      | eval_iru::Error::StaticError(i)
+      | eval_iru::Error::Trap(i, _)
+      | eval_iru::Error::TypeError(i)
+      | eval_iru::Error::Unsupported(i)
+    ) => Some(i),
+    _ => None,
+  }
+}
+
+// Rewrites an error at a program point of `module` so that it names the
+// function that the point is in, unless that is `hidden`.
+
+fn locate(module: &iru::Module, hidden: Option<Symbol>, e: driver::Error) -> Error {
+  let Some(i) = point(e) else { return Error::Driver(e); };
+  let f = module.decl.iter().find(|f| f.pos <= i && i < f.pos + f.len).map(|f| f.name);
+  return Error::Hidden(e, f.filter(|&s| Some(s) != hidden));
+}
+
+// Lowers `text` to untyped bytecode, and rejects it if it has an invalid token
+// or a syntax error. The input is `text[start .. stop]`, and byte offsets are
+// made relative to it.
+
+fn lower(text: &str, start: usize, stop: usize) -> Result<iru::Module, Error> {
+  let mut lexer = Lexer::new(text.as_bytes());
+
+  while lexer.token() != Token::Eof {
+    if lexer.token() == Token::Error {
+      return Err(Error::Driver(driver::Error::InvalidToken(lexer.token_start() - start)));
+    }
+    lexer.next();
+  }
+
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = parse::parse_with_errors(text.as_bytes(), store.arena());
+
+  if errors.len() != 0 {
+    // NB: an error past the end of an expression is found in the text that
+    // follows it, so it is put at the end of the input.
+
+    let pos = (errors[0].pos() as usize).clamp(start, stop) - start;
+    let pos = pos as u32;
+    let e =
+      match errors[0] {
+        parse::Error::Expected(_, t) => parse::Error::Expected(pos, t),
+        parse::Error::ExpectedExpr(_) => parse::Error::ExpectedExpr(pos),
+        parse::Error::NumberOutOfRange(_) => parse::Error::NumberOutOfRange(pos),
+      };
+    return Err(Error::Driver(driver::Error::SyntaxError(e)));
+  }
+
+  return Ok(make_iru::compile(&items));
+}
+
+// Checks `module` in `environment`, whose schemes are in `types`, and returns
+// the schemes of its functions. The store is kept even if the check fails.
+
+fn check(
+    module: &iru::Module,
+    hidden: Option<Symbol>,
+    environment: &HashMap<Symbol, TypeScheme>,
+    types: &mut TypeStore,
+  ) -> Result<HashMap<Symbol, TypeScheme>, Error>
+{
+  // NB: an unbound name is lowered to a constant that would fail to typecheck.
+
+  for &inst in module.code.iter() {
+    if let iru::Inst::Const(s) = inst
+      && Builtin::from_symbol(s).is_none()
+      && environment.get(s).is_none()
+      && ! module.decl.iter().any(|f| f.name == s) {
+      return Err(Error::UnboundFunction(s));
+    }
+  }
+
+  let (schemes, mut solver) = typecheck::typecheck_in(module, environment, std::mem::take(types));
+  let result = make_irp::elaborate(module, &schemes, &mut solver);
+  *types = solver.into_types();
+
+  if let Err(e) = result {
+    return Err(locate(module, hidden, driver::Error::TypeError(e)));
+  }
+
+  return Ok(schemes);
+}
+
+// Whether the code of `module` refers to any of `names`.
+
+fn calls(module: &iru::Module, names: &Buf<Symbol>) -> bool {
+  return module.code.iter().any(|&inst| {
+    if let iru::Inst::Const(s) = inst { names.iter().any(|&t| s == t) } else { false }
+  });
+}
+
+// Lays out the code of `modules` one after another, in order.
+
+fn link<'a>(modules: impl IntoIterator<Item = &'a iru::Module>) -> iru::Module {
+  let mut code = Buf::new();
+  let mut decl = Buf::new();
+
+  for m in modules {
+    let pos = code.len();
+    for &inst in m.code.iter() { code.push(inst.map_points(|x| x + pos)); }
+    for f in m.decl.iter() { decl.push(iru::Fun { name: f.name, pos: f.pos + pos, len: f.len }); }
+  }
+
+  return iru::Module { code: code.drain().into(), decl: decl.drain().into() };
+}
+
+pub struct Repl {
+  // The code of each definition of the session, lowered on its own.
+  session: Buf<iru::Module>,
+  // The code of the session, linked.
+  module: iru::Module,
+  global_environment: HashMap<Symbol, TypeScheme>,
+  types: TypeStore,
+}
+
+/// Whether `input` is a complete input, that is, whether its brackets are
+/// balanced, so that a caller that reads lines knows when to stop.
+
+pub fn is_complete(input: &str) -> bool {
+  let mut lexer = Lexer::new(input.as_bytes());
+  let mut depth = 0;
+
+  while lexer.token() != Token::Eof {
+    match lexer.token() {
+      Token::LBrace | Token::LParen | Token::LBracket => { depth += 1; }
+      Token::RBrace | Token::RParen | Token::RBracket => { depth -= 1; }
+      _ => {}
+    }
+    lexer.next();
+  }
+
+  return depth <= 0;
+}
+
+impl Repl {
+  pub fn new() -> Self {
+    return Self {
+      session: Buf::new(),
+      module: link([]),
+      global_environment: HashMap::new(),
+      types: TypeStore::new(),
+    };
+  }
+
+  /// Reads one input, which is one or more definitions, an expression, or a
+  /// command, and writes what it prints to `out`.
+
+  pub fn eval(&mut self, input: &str, out: &mut String) -> Result<(), Error> {
+    let input = input.trim();
+
+    if input.is_empty() {
+      return Ok(());
+    }
+
+    if let Some(rest) = input.strip_prefix(':') {
+      let (command, argument) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+      let argument = argument.trim();
+      match command {
+        "type" => { return self.type_of(argument, out); }
+        "iru" => { return self.iru(argument, out); }
+        _ => { return Err(Error::UnknownCommand); }
+      }
+    }
+
+    if Lexer::new(input.as_bytes()).token() == Token::Fun {
+      return self.define(input, out);
+    }
+
+    let (module, name, _) = self.compile_expr(input)?;
+    let module = link([&self.module, &module]);
+    let results =
+      eval_iru::run(&module, name, []).map_err(|e| locate(&module, Some(name), driver::Error::RuntimeError(e)))?;
+
+    for (i, x) in results.iter().enumerate() {
+      if i != 0 { out.push_str(", "); }
+      self.write_value(&module, x, out);
+    }
+
+    out.push('\n');
+    return Ok(());
+  }
+
+  fn define(&mut self, input: &str, out: &mut String) -> Result<(), Error> {
+    let module = lower(input, 0, input.len())?;
+    let names: Buf<Symbol> = module.decl.iter().map(|f| f.name).collect();
+    let replaces = |m: &iru::Module| m.decl.iter().any(|f| names.iter().any(|&s| f.name == s));
+
+    match self.session.iter().position(replaces) {
+      None => {
+        let schemes = check(&module, None, &self.global_environment, &mut self.types)?;
+        for f in module.decl.iter() {
+          self.global_environment.insert(f.name, *schemes.get(f.name).unwrap());
+        }
+        self.session.push(module);
+      }
+      Some(index) => {
+        // NB: functions are checked in order, so a definition that replaces
+        // an earlier one goes where the first of them was, before its callers.
+        // Then the later definitions that call a function that has changed are
+        // checked again.
+
+        let index = index as u32;
+        let mut session = Buf::new();
+        let mut changed: Buf<Symbol> = names.iter().copied().collect();
+
+        for (i, m) in self.session.iter().enumerate() {
+          if i as u32 == index { session.push(module.clone()); }
+          if ! replaces(m) {
+            session.push(m.clone());
+          } else {
+            for f in m.decl.iter() { changed.push(f.name); }
+          }
+        }
+
+        let mut environment = HashMap::new();
+
+        for i in 0 .. session.len() {
+          let m = &session[i];
+
+          if i == index || i > index && calls(m, &changed) {
+            let schemes = check(m, None, &environment, &mut self.types)?;
+            for f in m.decl.iter() {
+              environment.insert(f.name, *schemes.get(f.name).unwrap());
+              changed.push(f.name);
+            }
+          } else {
+            for f in m.decl.iter() {
+              environment.insert(f.name, *self.global_environment.get(f.name).unwrap());
+            }
+          }
+        }
+
+        self.session = session;
+        self.global_environment = environment;
+      }
+    }
+
+    self.module = link(self.session.iter());
+
+    for &name in names.iter() {
+      let scheme = self.global_environment.get(name).unwrap();
+      writeln!(out, "fun {} : {}", name, scheme.display(&self.types)).unwrap();
+    }
+
+    return Ok(());
+  }
+
+  // Prints the scheme of a function of the session, or else the type of the
+  // results of an expression.
+
+  fn type_of(&mut self, input: &str, out: &mut String) -> Result<(), Error> {
+    if let Some(scheme) = self.global_environment.get(Symbol::from_str(input)) {
+      writeln!(out, "{}", scheme.display(&self.types)).unwrap();
+      return Ok(());
+    }
+
+    let (_, name, schemes) = self.compile_expr(input)?;
+    let TypeScheme(n, t) = *schemes.get(name).unwrap();
+    let Type::Fun(_, b) = self.types[t] else { unreachable!() };
+    let b = if let Type::Tuple(1, _) = self.types[b] { self.types.tuple_elts(b).next().unwrap() } else { b };
+
+    writeln!(out, "{}", TypeScheme(n, b).display(&self.types)).unwrap();
+    return Ok(());
+  }
+
+  fn iru(&self, input: &str, out: &mut String) -> Result<(), Error> {
+    let name = Symbol::from_str(input);
+    let Some(f) = self.module.decl.iter().find(|f| f.name == name) else {
+      return Err(Error::UnboundFunction(name));
+    };
+
+    for i in f.pos .. f.pos + f.len {
+      writeln!(out, "%{} {}", i, self.module.code[i]).unwrap();
+    }
+
+    return Ok(());
+  }
+
+  // Compiles a function whose body returns `input`, and which is named so as
+  // not to clash with the session, and checks it against the session.
+
+  fn compile_expr(&mut self, input: &str) -> Result<(iru::Module, Symbol, HashMap<Symbol, TypeScheme>), Error> {
+    let mut name = String::from("it");
+    let mut k = 0;
+
+    while self.global_environment.get(Symbol::from_str(&name)).is_some() {
+      k += 1;
+      name = format!("it{}", k);
+    }
+
+    let mut text = String::new();
+    write!(text, "fun {}() {{\n  return ", name).unwrap();
+    let start = text.len();
+    write!(text, "{}\n}}\n", input).unwrap();
+
+    let name = Symbol::from_str(&name);
+    let module = lower(&text, start, start + input.len())?;
+    let schemes = check(&module, Some(name), &self.global_environment, &mut self.types)?;
+    return Ok((module, name, schemes));
+  }
+
+  // Writes the value `x`, where a function is shown by its name in `module`
+  // and its type scheme.
+
+  fn write_value(&self, module: &iru::Module, x: &Value, out: &mut String) {
+    match x {
+      Value::Array(a) => {
+        out.push('[');
+        for (i, y) in a.borrow().iter().enumerate() {
+          if i != 0 { out.push_str(", "); }
+          self.write_value(module, y, out);
+        }
+        out.push(']');
+      }
+      &Value::Fun(k) => {
+        let name = module.decl[k].name;
+        match self.global_environment.get(name) {
+          Some(scheme) => write!(out, "<fun {} : {}>", name, scheme.display(&self.types)).unwrap(),
+          None => write!(out, "<fun {}>", name).unwrap(),
+        }
+      }
+      _ => write!(out, "{}", x).unwrap(),
+    }
+  }
+}
+
+impl Default for Repl {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
@@ -27,6 +27,19 @@ impl Symbol {
   pub fn from_str(s: &str) -> Self {
     Self::from_bytes(s.as_bytes())
   }
+
+  /// Reads a symbol back from how it is displayed, which for a long symbol is
+  /// its hash. A long symbol can also be written out in full.
+
+  pub fn parse(s: &str) -> Option<Self> {
+    if let Some(h) = s.strip_prefix("Symbol(0x").and_then(|h| h.strip_suffix(')')) {
+      let n = u64::from_str_radix(h, 16).ok()?;
+      if n >> 63 == 0 { return None; }
+      return Some(Self(NonZeroU64::new(n)?));
+    }
+    if s.is_empty() || s.contains('\0') { return None; }
+    Some(Self::from_str(s))
+  }
 }
 
 unsafe impl tangerine::key::IntoKey for Symbol {
@@ -46,7 +59,7 @@ unsafe impl tangerine::key::IntoKey for Symbol {
 impl std::fmt::Display for Symbol {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let n = self.0.get();
-    if n as isize >= 0 {
+    if n >> 63 == 0 {
       let buf = n.to_le_bytes();
       let mut i = 0;
       while i < 8 && buf[i] != 0 { i += 1; }
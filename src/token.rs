@@ -34,17 +34,25 @@ pub enum Token {
   Tilde      = 0x7e, // ~
   Eof        = 0xa0,
   Error,
+  AddSat,            // +|
   And,               // &&
   CmpEq,             // ==
   CmpGe,             // >=
   CmpLe,             // <=
   CmpNe,             // !=
   Dec,               // --
+  DivSat,            // /|
+  DivWrap,           // /%
   DotDotDot,         // ...
   Inc,               // ++
+  MulSat,            // *|
   Or,                // ||
   Shl,               // <<
+  ShlSat,            // <<|
+  ShlWrap,           // <<%
   Shr,               // >>
+  ShrWrap,           // >>%
+  SubSat,            // -|
   Field,             // .foo
   StaticField,       // :foo
   Break,
@@ -15,28 +15,14 @@ use crate::prim::PrimType::I64;
 use crate::prim::PrimType;
 use crate::symbol::Symbol;
 use crate::typeid::TypeId;
+use crate::typestore::Type;
+use crate::typestore::TypeStore;
 use crate::unionfind::UnionFind;
 use std::iter::zip;
 use tangerine::map::HashMap;
 
-pub enum ValueType {
-  Array(Box<ValueType>),
-  Fun(TupleType, TupleType),
-  Prim(PrimType),
-  Var(TypeId),
-}
-
-pub enum TupleType {
-  Tuple(Arr<ValueType>),
-  Var(TypeId),
-}
-
-pub enum SeqType {
-  Nil,
-  Cons(Box<(ValueType, SeqType)>),
-}
-
-pub struct TypeScheme(/* arity */ pub u32, pub ValueType);
+#[derive(Clone, Copy, Debug)]
+pub struct TypeScheme(/* arity */ pub u32, pub TypeId);
 
 pub enum TypeState {
   Array(TypeId),
@@ -48,7 +34,8 @@ pub enum TypeState {
   Var(TypeId),
 }
 
-struct Ctx {
+struct Ctx<'a> {
+  outer_environment: &'a HashMap<Symbol, TypeScheme>,
   global_environment: HashMap<Symbol, TypeScheme>,
   letrec_environment: HashMap<Symbol, TypeId>,
   solver: Solver,
@@ -60,11 +47,22 @@ struct Ctx {
 pub struct Solver {
   union_find: UnionFind<TypeState>,
   to_unify: Buf<(TypeId, TypeId)>,
+  types: TypeStore,
 }
 
 impl Solver {
-  fn new() -> Self {
-    return Self { union_find: UnionFind::new(), to_unify: Buf::new() };
+  fn new(types: TypeStore) -> Self {
+    return Self { union_find: UnionFind::new(), to_unify: Buf::new(), types };
+  }
+
+  /// The store that holds type schemes and resolved types.
+
+  pub fn types(&self) -> &TypeStore {
+    return &self.types;
+  }
+
+  pub fn into_types(self) -> TypeStore {
+    return self.types;
   }
 
   fn unify(&mut self, x: TypeId, y: TypeId) {
@@ -104,6 +102,10 @@ impl Solver {
     }
   }
 
+  fn constrain_error(&mut self, x: TypeId) {
+    self.union_find[x.0] = TypeState::Error;
+  }
+
   fn constrain_array(&mut self, x: TypeId, a: TypeId) {
     match &mut self.union_find[x.0] {
       state @ &mut TypeState::Fresh => {
@@ -187,36 +189,35 @@ impl Solver {
 
   fn instantiate(&mut self, t: &TypeScheme) -> TypeId {
     let bound_type_vars = Arr::new(t.0, |_| self.fresh());
-    return self.instantiate_value_type(&bound_type_vars, &t.1);
+    return self.instantiate_type(&bound_type_vars, t.1);
   }
 
-  fn instantiate_value_type(&mut self, bound_type_vars: &Arr<TypeId>, t: &ValueType) -> TypeId {
-    match t {
-      &ValueType::Array(ref a) => {
-        let a = self.instantiate_value_type(bound_type_vars, a);
+  fn instantiate_type(&mut self, bound_type_vars: &Arr<TypeId>, t: TypeId) -> TypeId {
+    match self.types[t] {
+      Type::Array(a) => {
+        let a = self.instantiate_type(bound_type_vars, a);
         self.construct_array(a)
       }
-      &ValueType::Fun(ref a, ref b) => {
-        let a = self.instantiate_tuple_type(bound_type_vars, a);
-        let b = self.instantiate_tuple_type(bound_type_vars, b);
-        self.construct_fun(a, b)
+      Type::Bool => {
+        self.construct_prim(Bool)
       }
-      &ValueType::Prim(t) => {
-        self.construct_prim(t)
+      Type::Fun(a, b) => {
+        let a = self.instantiate_type(bound_type_vars, a);
+        let b = self.instantiate_type(bound_type_vars, b);
+        self.construct_fun(a, b)
       }
-      &ValueType::Var(x) => {
-        bound_type_vars[x.0]
+      Type::I64 => {
+        self.construct_prim(I64)
       }
-    }
-  }
-
-  fn instantiate_tuple_type(&mut self, bound_type_vars: &Arr<TypeId>, t: &TupleType) -> TypeId {
-    match t {
-      &TupleType::Tuple(ref u) => {
-        let u = Arr::from(u.iter().map(|a| self.instantiate_value_type(bound_type_vars, a)));
+      Type::Tuple(..) => {
+        let u: Buf<TypeId> = self.types.tuple_elts(t).collect();
+        let u = Arr::from(u.iter().map(|&a| self.instantiate_type(bound_type_vars, a)));
         self.construct_tuple(u)
       }
-      &TupleType::Var(a) => {
+      Type::TupleElt(..) => {
+        unreachable!()
+      }
+      Type::Var(a) => {
         bound_type_vars[a.0]
       }
     }
@@ -235,28 +236,28 @@ impl Solver {
     return Ok(TypeScheme(count, t));
   }
 
-  fn generalize_value_type(&mut self, count: &mut u32, t: TypeId) -> Result<ValueType, ()> {
+  fn generalize_value_type(&mut self, count: &mut u32, t: TypeId) -> Result<TypeId, ()> {
     match &mut self.union_find[t.0] {
       state @ &mut TypeState::Fresh => {
         let a = TypeId(*count);
         *count += 1;
         *state = TypeState::Var(a);
-        Ok(ValueType::Var(a))
+        Ok(self.types.var(a))
       }
       &mut TypeState::Var(a) => {
-        Ok(ValueType::Var(a))
+        Ok(self.types.var(a))
       }
       &mut TypeState::Array(a) => {
         let a = self.generalize_value_type(count, a)?;
-        Ok(ValueType::Array(Box::new(a)))
+        Ok(self.types.array(a))
       }
       &mut TypeState::Fun(a, b) => {
         let a = self.generalize_tuple_type(count, a)?;
         let b = self.generalize_tuple_type(count, b)?;
-        Ok(ValueType::Fun(a, b))
+        Ok(self.types.fun(a, b))
       }
       &mut TypeState::Prim(a) => {
-        Ok(ValueType::Prim(a))
+        Ok(self.types.prim(a))
       }
       _ => {
         Err(())
@@ -264,22 +265,22 @@ impl Solver {
     }
   }
 
-  fn generalize_tuple_type(&mut self, count: &mut u32, t: TypeId) -> Result<TupleType, ()> {
+  fn generalize_tuple_type(&mut self, count: &mut u32, t: TypeId) -> Result<TypeId, ()> {
     match &mut self.union_find[t.0] {
       state @ &mut TypeState::Fresh => {
         let a = TypeId(*count);
         *count += 1;
         *state = TypeState::Var(a);
-        Ok(TupleType::Var(a))
+        Ok(self.types.var(a))
       }
       &mut TypeState::Var(a) => {
-        Ok(TupleType::Var(a))
+        Ok(self.types.var(a))
       }
       &mut TypeState::Tuple(ref u) => {
         let u = u.clone(); // ???
         let mut buf = Buf::new();
         for &a in &u { buf.push(self.generalize_value_type(count, a)?); }
-        Ok(TupleType::Tuple(Arr::from(buf.drain())))
+        Ok(self.types.tuple(buf.drain()))
       }
       _ => {
         Err(())
@@ -287,16 +288,26 @@ impl Solver {
     }
   }
 
-  pub fn resolve_value_type(&self, t: TypeId) -> Result<ValueType, ()> {
+  /// Resolves the type of the program point `t` into the type store.
+  ///
+  /// Type variables are numbered relative to the type scheme of the enclosing
+  /// function.
+
+  pub fn resolve_value_type(&mut self, t: TypeId) -> Result<TypeId, ()> {
     match self.union_find[t.0] {
       TypeState::Var(a) =>
-        Ok(ValueType::Var(a)),
-      TypeState::Array(a) =>
-        Ok(ValueType::Array(Box::new(self.resolve_value_type(a)?))),
-      TypeState::Fun(a, b) =>
-        Ok(ValueType::Fun(self.resolve_tuple_type(a)?, self.resolve_tuple_type(b)?)),
+        Ok(self.types.var(a)),
+      TypeState::Array(a) => {
+        let a = self.resolve_value_type(a)?;
+        Ok(self.types.array(a))
+      }
+      TypeState::Fun(a, b) => {
+        let a = self.resolve_tuple_type(a)?;
+        let b = self.resolve_tuple_type(b)?;
+        Ok(self.types.fun(a, b))
+      }
       TypeState::Prim(a) =>
-        Ok(ValueType::Prim(a)),
+        Ok(self.types.prim(a)),
       TypeState::Fresh =>
         Err(()),
       _ =>
@@ -304,14 +315,15 @@ impl Solver {
     }
   }
 
-  pub fn resolve_tuple_type(&self, t: TypeId) -> Result<TupleType, ()> {
+  pub fn resolve_tuple_type(&mut self, t: TypeId) -> Result<TypeId, ()> {
     match self.union_find[t.0] {
       TypeState::Var(a) =>
-        Ok(TupleType::Var(a)),
+        Ok(self.types.var(a)),
       TypeState::Tuple(ref u) => {
+        let u = u.clone();
         let mut buf = Buf::new();
-        for &a in u { buf.push(self.resolve_value_type(a)?); }
-        Ok(TupleType::Tuple(Arr::from(buf.drain())))
+        for &a in &u { buf.push(self.resolve_value_type(a)?); }
+        Ok(self.types.tuple(buf.drain()))
       }
       _ =>
         Err(())
@@ -319,33 +331,55 @@ impl Solver {
   }
 }
 
-impl Ctx {
-  fn new() -> Self {
+impl<'a> Ctx<'a> {
+  fn new(outer_environment: &'a HashMap<Symbol, TypeScheme>, types: TypeStore) -> Self {
     let mut ctx =
       Self {
+        outer_environment,
         global_environment: HashMap::new(),
         letrec_environment: HashMap::new(),
-        solver: Solver::new(),
+        solver: Solver::new(types),
         block_args: Buf::new(),
         block_outs: Buf::new(),
         block_call_ret: None,
       };
 
-    ctx.global_environment.insert(
-      Symbol::from_str("len"),
-      TypeScheme(
-        1,
-        ValueType::Fun(
-          TupleType::Tuple(Arr::from([ValueType::Var(TypeId(0))])),
-          TupleType::Tuple(Arr::from([ValueType::Prim(I64)]))))
-    );
+    let types = &mut ctx.solver.types;
+    let a = types.var(TypeId(0));
+    let a = types.array(a);
+    let a = types.tuple([a]);
+    let b = types.i64();
+    let b = types.tuple([b]);
+    let f = types.fun(a, b);
+    ctx.global_environment.insert(Symbol::from_str("len"), TypeScheme(1, f));
+
+    let a = types.var(TypeId(0));
+    let b = types.array(a);
+    let b = types.tuple([b]);
+    let n = types.i64();
+    let a = types.tuple([n, a]);
+    let f = types.fun(a, b);
+    ctx.global_environment.insert(Symbol::from_str("array"), TypeScheme(1, f));
 
     return ctx;
   }
 }
 
 pub fn typecheck(module: &iru::Module) -> (HashMap<Symbol, TypeScheme>, Solver) {
-  let mut ctx = Ctx::new();
+  return typecheck_in(module, &HashMap::new(), TypeStore::new());
+}
+
+/// Typechecks like `typecheck`, where the functions of `environment`, whose
+/// schemes are in `types`, are also in scope. The returned environment only
+/// has the builtins and the functions of `module`.
+
+pub fn typecheck_in(
+    module: &iru::Module,
+    environment: &HashMap<Symbol, TypeScheme>,
+    types: TypeStore,
+  ) -> (HashMap<Symbol, TypeScheme>, Solver)
+{
+  let mut ctx = Ctx::new(environment, types);
 
   // allocate a fresh type variable for each program point, starting from zero
 
@@ -447,17 +481,19 @@ pub fn typecheck(module: &iru::Module) -> (HashMap<Symbol, TypeScheme>, Solver)
         Inst::Const(symbol) => {
           if let Some(&t) = ctx.letrec_environment.get(symbol) {
             ctx.solver.unify(TypeId(i), t);
-          } else if let Some(t) = ctx.global_environment.get(symbol) {
+          } else if let Some(t) = ctx.global_environment.get(symbol).or(ctx.outer_environment.get(symbol)) {
             let t = ctx.solver.instantiate(t);
             ctx.solver.unify(TypeId(i), t);
           } else {
-            // TODO: error unbound variable
-            unimplemented!()
+            // error, unbound variable
+            ctx.solver.constrain_error(TypeId(i));
           }
         }
-        Inst::Field(..) | Inst::GotoStaticError | Inst::SetField(..) => {
-          // TODO: unsupported instructions
-          unimplemented!()
+        Inst::Field(..) => {
+          // TODO: records are not supported yet
+          ctx.solver.constrain_error(TypeId(i));
+        }
+        Inst::GotoStaticError | Inst::SetField(..) => {
         }
       }
     }
@@ -468,9 +504,12 @@ pub fn typecheck(module: &iru::Module) -> (HashMap<Symbol, TypeScheme>, Solver)
 
     // generalize
 
-    // TODO: handle type error on failed generalization
+    // NB: if generalization fails then the function is left out of the
+    // environment, and any later reference to it is an unbound variable.
 
-    ctx.global_environment.insert(f.name, ctx.solver.generalize(funtypevar).unwrap());
+    if let Ok(t) = ctx.solver.generalize(funtypevar) {
+      ctx.global_environment.insert(f.name, t);
+    }
     ctx.letrec_environment.clear();
   }
 
@@ -479,7 +518,7 @@ pub fn typecheck(module: &iru::Module) -> (HashMap<Symbol, TypeScheme>, Solver)
 
 // TODO: operator overloading
 
-fn lower_op1(op: Op1) -> PrimOp1 {
+pub(crate) fn lower_op1(op: Op1) -> PrimOp1 {
   match op {
     Op1::Dec => PrimOp1::DecI64,
     Op1::Inc => PrimOp1::IncI64,
@@ -488,9 +527,10 @@ fn lower_op1(op: Op1) -> PrimOp1 {
   }
 }
 
-fn lower_op2(op: Op2) -> PrimOp2 {
+pub(crate) fn lower_op2(op: Op2) -> PrimOp2 {
   match op {
     Op2::Add => PrimOp2::AddI64,
+    Op2::AddSat => PrimOp2::AddSatI64,
     Op2::BitAnd => PrimOp2::BitAndI64,
     Op2::BitOr => PrimOp2::BitOrI64,
     Op2::BitXor => PrimOp2::BitXorI64,
@@ -501,57 +541,40 @@ fn lower_op2(op: Op2) -> PrimOp2 {
     Op2::CmpLt => PrimOp2::CmpLtI64,
     Op2::CmpNe => PrimOp2::CmpNeI64,
     Op2::Div => PrimOp2::DivI64,
+    Op2::DivSat => PrimOp2::DivSatI64,
+    Op2::DivWrap => PrimOp2::DivWrapI64,
     Op2::Mul => PrimOp2::MulI64,
+    Op2::MulSat => PrimOp2::MulSatI64,
     Op2::Rem => PrimOp2::RemI64,
     Op2::Shl => PrimOp2::ShlI64,
+    Op2::ShlSat => PrimOp2::ShlSatI64,
+    Op2::ShlWrap => PrimOp2::ShlWrapI64,
     Op2::Shr => PrimOp2::ShrI64,
+    Op2::ShrWrap => PrimOp2::ShrWrapI64,
     Op2::Sub => PrimOp2::SubI64,
+    Op2::SubSat => PrimOp2::SubSatI64,
   }
 }
 
-impl std::fmt::Display for ValueType {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    match *self {
-      Self::Array(ref a) =>
-        write!(f, "Array[{}]", a)?,
-      Self::Fun(ref a, ref b) =>
-        write!(f, "Fun{} -> {}", a, b)?,
-      Self::Prim(a) =>
-        write!(f, "{}", a)?,
-      Self::Var(a) =>
-        write!(f, "'{}", a.0)?,
-    }
-    return Ok(());
+impl TypeScheme {
+  pub fn display<'a>(&'a self, types: &'a TypeStore) -> DisplayTypeScheme<'a> {
+    return DisplayTypeScheme { scheme: self, types };
   }
 }
 
-impl std::fmt::Display for TupleType {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    match *self {
-      Self::Var(a) =>
-        write!(f, "'{}", a.0)?,
-      Self::Tuple(ref a) => {
-        write!(f, "(")?;
-        for (i, a) in a.iter().enumerate() {
-          if i != 0 {
-            write!(f, ", ")?;
-          }
-          write!(f, "{}", a)?;
-        }
-        write!(f, ")")?;
-      }
-    }
-    return Ok(());
-  }
+pub struct DisplayTypeScheme<'a> {
+  scheme: &'a TypeScheme,
+  types: &'a TypeStore,
 }
 
-impl std::fmt::Display for TypeScheme {
+impl<'a> std::fmt::Display for DisplayTypeScheme<'a> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    if self.0 != 0 {
+    let TypeScheme(n, t) = *self.scheme;
+    if n != 0 {
       write!(f, "forall")?;
-      for i in 0 .. self.0 { write!(f, " '{}", i)?; }
+      for i in 0 .. n { write!(f, " '{}", i)?; }
       write!(f, " . ")?;
     }
-    return self.1.fmt(f);
+    return write!(f, "{}", self.types.display(t));
   }
 }
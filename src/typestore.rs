@@ -1,10 +1,25 @@
+//! hash-consed types
+//!
+//! Every distinct type is stored exactly once, so two types are equal if and
+//! only if their ids are equal.
+//!
+//! Tuples are represented as a cons-list of `TupleElt` cells, so that every
+//! type node has a fixed size and tuples with a common suffix share storage.
+
+use crate::buf::Buf;
+use crate::prim::PrimType;
 use crate::typeid::TypeId;
+use std::num::NonZeroU64;
+use tangerine::map::HashMap;
 
 type Arity = u32;
 
 pub struct TypeStore {
+  types: Buf<Type>,
+  table: HashMap<Hash, TypeId>,
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Type {
   Array(TypeId),
   Bool,
@@ -14,3 +29,221 @@ pub enum Type {
   TupleElt(TypeId, /* next */ TypeId),
   Var(TypeId),
 }
+
+// NB: the `next` of the last element of a tuple, and the element list of the
+// empty tuple. It is never dereferenced, because tuples carry their arity.
+
+const NIL: TypeId = TypeId(u32::MAX);
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct Hash(NonZeroU64);
+
+const HASHER: foldhash::quality::FixedState =
+  foldhash::quality::FixedState::with_seed(0);
+
+unsafe impl tangerine::key::IntoKey for Hash {
+  type Key = NonZeroU64;
+
+  #[inline(always)]
+  fn inject(Self(n): Self) -> Self::Key {
+    n
+  }
+
+  #[inline(always)]
+  unsafe fn project(n: Self::Key) -> Self {
+    Self(n)
+  }
+}
+
+fn hash(t: Type, probe: u32) -> Hash {
+  let n = <foldhash::quality::FixedState as std::hash::BuildHasher>::hash_one(&HASHER, (t, probe));
+  let n = n | 1;
+  return Hash(NonZeroU64::new(n).unwrap());
+}
+
+impl TypeStore {
+  // NB: the primitive types are interned up front with fixed ids, so that
+  // they can be named without mutable access to the store.
+
+  pub const BOOL: TypeId = TypeId(0);
+
+  pub const I64: TypeId = TypeId(1);
+
+  pub fn new() -> Self {
+    let mut t = Self { types: Buf::new(), table: HashMap::new() };
+    let _ = t.intern(Type::Bool);
+    let _ = t.intern(Type::I64);
+    return t;
+  }
+
+  pub fn len(&self) -> u32 {
+    return self.types.len();
+  }
+
+  /// Returns the unique id for `t`, adding it to the store if it is not
+  /// already present.
+
+  pub fn intern(&mut self, t: Type) -> TypeId {
+    // NB: on a hash collision between distinct types we probe again with a
+    // different salt. Such collisions are vanishingly rare.
+
+    let mut probe = 0;
+
+    loop {
+      let h = hash(t, probe);
+      match self.table.get(h) {
+        None => {
+          let x = TypeId(self.types.len());
+          self.types.push(t);
+          self.table.insert(h, x);
+          return x;
+        }
+        Some(&x) if self.types[x.0] == t => {
+          return x;
+        }
+        Some(_) => {
+          probe += 1;
+        }
+      }
+    }
+  }
+
+  pub fn array(&mut self, a: TypeId) -> TypeId {
+    return self.intern(Type::Array(a));
+  }
+
+  pub fn bool(&self) -> TypeId {
+    return Self::BOOL;
+  }
+
+  pub fn fun(&mut self, a: TypeId, b: TypeId) -> TypeId {
+    return self.intern(Type::Fun(a, b));
+  }
+
+  pub fn i64(&self) -> TypeId {
+    return Self::I64;
+  }
+
+  pub fn prim(&self, t: PrimType) -> TypeId {
+    match t {
+      PrimType::Bool => Self::BOOL,
+      PrimType::I64 => Self::I64,
+    }
+  }
+
+  pub fn tuple(&mut self, elts: impl IntoIterator<Item = TypeId>) -> TypeId {
+    let mut buf: Buf<TypeId> = elts.into_iter().collect();
+    let n = buf.len();
+    let mut next = NIL;
+    while ! buf.is_empty() {
+      next = self.intern(Type::TupleElt(buf.pop(), next));
+    }
+    return self.intern(Type::Tuple(n, next));
+  }
+
+  pub fn var(&mut self, a: TypeId) -> TypeId {
+    return self.intern(Type::Var(a));
+  }
+
+  /// Returns the primitive type that `t` represents, if any.
+
+  pub fn as_prim(&self, t: TypeId) -> Option<PrimType> {
+    match self[t] {
+      Type::Bool => Some(PrimType::Bool),
+      Type::I64 => Some(PrimType::I64),
+      _ => None,
+    }
+  }
+
+  /// Iterates over the elements of the tuple type `t`.
+  ///
+  /// Panics if `t` is not a tuple type.
+
+  pub fn tuple_elts(&self, t: TypeId) -> TupleElts<'_> {
+    let Type::Tuple(n, a) = self[t] else { panic!("not a tuple type") };
+    return TupleElts { store: self, next: a, len: n };
+  }
+
+  pub fn display(&self, t: TypeId) -> DisplayType<'_> {
+    return DisplayType { store: self, t };
+  }
+}
+
+impl Default for TypeStore {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl std::ops::Index<TypeId> for TypeStore {
+  type Output = Type;
+
+  #[inline(always)]
+  fn index(&self, index: TypeId) -> &Type {
+    &self.types[index.0]
+  }
+}
+
+pub struct TupleElts<'a> {
+  store: &'a TypeStore,
+  next: TypeId,
+  len: u32,
+}
+
+impl<'a> Iterator for TupleElts<'a> {
+  type Item = TypeId;
+
+  fn next(&mut self) -> Option<TypeId> {
+    if self.len == 0 { return None; }
+    let Type::TupleElt(a, b) = self.store[self.next] else { unreachable!() };
+    self.next = b;
+    self.len -= 1;
+    return Some(a);
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (self.len as usize, Some(self.len as usize))
+  }
+}
+
+impl<'a> ExactSizeIterator for TupleElts<'a> {
+  fn len(&self) -> usize {
+    self.len as usize
+  }
+}
+
+pub struct DisplayType<'a> {
+  store: &'a TypeStore,
+  t: TypeId,
+}
+
+impl<'a> std::fmt::Display for DisplayType<'a> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let store = self.store;
+    match store[self.t] {
+      Type::Array(a) =>
+        write!(f, "Array[{}]", store.display(a))?,
+      Type::Bool =>
+        write!(f, "{}", PrimType::Bool)?,
+      Type::Fun(a, b) =>
+        write!(f, "Fun{} -> {}", store.display(a), store.display(b))?,
+      Type::I64 =>
+        write!(f, "{}", PrimType::I64)?,
+      Type::Tuple(..) => {
+        write!(f, "(")?;
+        for (i, a) in store.tuple_elts(self.t).enumerate() {
+          if i != 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{}", store.display(a))?;
+        }
+        write!(f, ")")?;
+      }
+      Type::TupleElt(..) =>
+        write!(f, "<tuple-elt>")?,
+      Type::Var(a) =>
+        write!(f, "'{}", a.0)?,
+    }
+    return Ok(());
+  }
+}
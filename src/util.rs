@@ -1,3 +1,8 @@
+use crate::arr::Arr;
+use crate::buf::Buf;
+
+const NONE: u32 = u32::MAX;
+
 pub const fn usize_u32_saturating_cast(x: usize) -> u32 {
   if (x as u32) as usize == x { x as u32 } else { u32::MAX }
 }
@@ -23,3 +28,67 @@ impl<T: Iterator> Iterator for Enumerate<T> {
     Some((i, a))
   }
 }
+
+// Numbers the strongly connected components of a graph, with Tarjan's
+// algorithm.
+
+pub fn components(succs: &Arr<Buf<u32>>) -> Arr<u32> {
+  let n = succs.len();
+  let mut index = Arr::new(n, |_| NONE);
+  let mut low = Arr::new(n, |_| NONE);
+  let mut on_stack = Arr::new(n, |_| false);
+  let mut component = Arr::new(n, |_| NONE);
+  let mut stack = Buf::new();
+  let mut work: Buf<(u32, u32)> = Buf::new();
+  let mut count = 0;
+  let mut next = 0;
+
+  for root in 0 .. n {
+    if index[root] != NONE { continue; }
+
+    index[root] = next;
+    low[root] = next;
+    next += 1;
+    stack.push(root);
+    on_stack[root] = true;
+    work.push((root, 0));
+
+    while ! work.is_empty() {
+      let (v, e) = *work.top();
+
+      if e < succs[v].len() {
+        work.top_mut().1 += 1;
+        let w = succs[v][e];
+        if index[w] == NONE {
+          index[w] = next;
+          low[w] = next;
+          next += 1;
+          stack.push(w);
+          on_stack[w] = true;
+          work.push((w, 0));
+        } else if on_stack[w] {
+          low[v] = low[v].min(index[w]);
+        }
+        continue;
+      }
+
+      let _ = work.pop();
+      if ! work.is_empty() {
+        let u = work.top().0;
+        low[u] = low[u].min(low[v]);
+      }
+
+      if low[v] == index[v] {
+        loop {
+          let w = stack.pop();
+          on_stack[w] = false;
+          component[w] = count;
+          if w == v { break; }
+        }
+        count += 1;
+      }
+    }
+  }
+
+  return component;
+}
@@ -0,0 +1,239 @@
+//! bytecode verifier
+//!
+//! untyped bytecode -> violations of its invariants
+//!
+//! The code of a function is a sequence of blocks. A block starts with a
+//! `Label` that gives the number of arguments that it receives, which `Get`
+//! reads, and ends with a terminator: `Goto`, `Ret`, `TailCall`, or
+//! `GotoStaticError`. A `Cond` is followed by the two `Goto`s that it chooses
+//! between, and a `Call` by the `Goto` to its continuation, whose arguments are
+//! the results of the call.
+//!
+//! Before a transfer, the values to be passed are given by `Put`s numbered in
+//! order from `0`, and a `Goto` passes them to a `Label` of the same arity.
+//! The arity of the callee of a `Call` or `TailCall`, and so the number of
+//! results that the `Goto` after a `Call` passes on, is only known at run time:
+//! calling a function with the wrong number of arguments is a type error, not
+//! malformed bytecode.
+//!
+//! An operand refers to an earlier value of the same function, and the operand
+//! of `GetLocal` or `SetLocal` to a `Local`. That the definition of a value
+//! dominates its uses is not checked.
+
+use crate::arr::Arr;
+use crate::buf::Buf;
+use crate::iru::Fun;
+use crate::iru::Inst;
+use crate::iru::Module;
+use crate::symbol::Symbol;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+  /// The code of the given function is empty or not within the module.
+  InvalidFunction(Symbol),
+  /// The instruction at the given point is not in a block, because it comes
+  /// after a terminator or first in its function.
+  OutsideBlock(u32),
+  /// The block that the given point ends does not end with a terminator.
+  Unterminated(u32),
+  /// The `Cond` or `Call` at the given point is not followed by a `Goto`.
+  ExpectedGoto(u32),
+  /// The `Goto` at the given point does not target a `Label` of its function.
+  NotLabel(u32),
+  /// The transfer at the given point passes a number of values other than the
+  /// arity of its target.
+  ArityMismatch(u32),
+  /// The `Get` at the given point reads past the arguments of its block.
+  GetOutOfRange(u32),
+  /// The `Put` at the given point is not numbered after the previous one.
+  PutOutOfOrder(u32),
+  /// An operand at the given point is not an earlier value of its function.
+  UseBeforeDef(u32),
+  /// An operand at the given point is not a `Local`, or is one where a value
+  /// is expected.
+  NotLocal(u32),
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::InvalidFunction(s) => write!(f, "invalid function {}", s),
+      Self::OutsideBlock(i) => write!(f, "instruction outside a block at %{}", i),
+      Self::Unterminated(i) => write!(f, "unterminated block at %{}", i),
+      Self::ExpectedGoto(i) => write!(f, "expected goto after %{}", i),
+      Self::NotLabel(i) => write!(f, "goto to a non-label at %{}", i),
+      Self::ArityMismatch(i) => write!(f, "arity mismatch at %{}", i),
+      Self::GetOutOfRange(i) => write!(f, "get out of range at %{}", i),
+      Self::PutOutOfOrder(i) => write!(f, "put out of order at %{}", i),
+      Self::UseBeforeDef(i) => write!(f, "use before definition at %{}", i),
+      Self::NotLocal(i) => write!(f, "misused local at %{}", i),
+    }
+  }
+}
+
+struct Verifier<'a> {
+  module: &'a Module,
+  errors: Buf<Error>,
+}
+
+/// Checks the invariants of `module`, and returns every violation, in order of
+/// program point.
+
+pub fn verify(module: &Module) -> Result<(), Arr<Error>> {
+  let mut t = Verifier { module, errors: Buf::new() };
+
+  for f in module.decl.iter() {
+    if f.len == 0 || f.pos > module.code.len() || f.len > module.code.len() - f.pos {
+      t.errors.push(Error::InvalidFunction(f.name));
+    } else {
+      t.function(f);
+    }
+  }
+
+  if t.errors.is_empty() { return Ok(()); }
+  return Err(Arr::from(t.errors.drain()));
+}
+
+impl<'a> Verifier<'a> {
+  fn operand(&mut self, f: &Fun, i: u32, x: u32) {
+    if x < f.pos || x >= i {
+      self.errors.push(Error::UseBeforeDef(i));
+      return;
+    }
+
+    match self.module.code[x] {
+      Inst::Get(_)
+      | Inst::Const(_)
+      | Inst::ConstBool(_)
+      | Inst::ConstInt(_)
+      | Inst::Field(..)
+      | Inst::Index(..)
+      | Inst::Op1(..)
+      | Inst::Op2(..)
+      | Inst::GetLocal(_) => {
+      }
+      Inst::Local(_) => {
+        self.errors.push(Error::NotLocal(i));
+      }
+      _ => {
+        self.errors.push(Error::UseBeforeDef(i));
+      }
+    }
+  }
+
+  fn local(&mut self, f: &Fun, i: u32, v: u32) {
+    if v < f.pos || v >= i {
+      self.errors.push(Error::UseBeforeDef(i));
+    } else if ! matches!(self.module.code[v], Inst::Local(_)) {
+      self.errors.push(Error::NotLocal(i));
+    }
+  }
+
+  fn is_goto(&self, f: &Fun, i: u32) -> bool {
+    return i < f.pos + f.len && matches!(self.module.code[i], Inst::Goto(_));
+  }
+
+  fn function(&mut self, f: &Fun) {
+    let module = self.module;
+    let code = &module.code;
+
+    // The arity of the current block, if any, and the number of values put
+    // since the last transfer.
+
+    let mut block = None;
+    let mut puts = 0;
+
+    for i in f.pos .. f.pos + f.len {
+      let inst = code[i];
+
+      if let Inst::Label(n) = inst {
+        if block.is_some() { self.errors.push(Error::Unterminated(i - 1)); }
+        block = Some(n);
+        puts = 0;
+        continue;
+      }
+
+      let Some(arity) = block else {
+        self.errors.push(Error::OutsideBlock(i));
+        continue;
+      };
+
+      match inst {
+        Inst::Label(_) => {
+          unreachable!()
+        }
+        Inst::GotoStaticError | Inst::Ret => {
+          block = None;
+        }
+        Inst::Get(k) => {
+          if k >= arity { self.errors.push(Error::GetOutOfRange(i)); }
+        }
+        Inst::Put(k, x) => {
+          if k != puts { self.errors.push(Error::PutOutOfOrder(i)); }
+          puts += 1;
+          self.operand(f, i, x);
+        }
+        Inst::Goto(a) => {
+          let after_cond = i > f.pos && matches!(code[i - 1], Inst::Cond(_));
+          let after_call = i > f.pos && matches!(code[i - 1], Inst::Call(_));
+
+          if ! (f.pos <= a && a < f.pos + f.len) {
+            self.errors.push(Error::NotLabel(i));
+          } else if let Inst::Label(n) = code[a] {
+            // NB: the results of a call are only known at run time.
+            if ! after_call && n != puts { self.errors.push(Error::ArityMismatch(i)); }
+          } else {
+            self.errors.push(Error::NotLabel(i));
+          }
+
+          // NB: the first `Goto` after a `Cond` is the branch not taken.
+          if ! after_cond { block = None; }
+        }
+        Inst::Cond(x) => {
+          self.operand(f, i, x);
+          if ! (self.is_goto(f, i + 1) && self.is_goto(f, i + 2)) {
+            self.errors.push(Error::ExpectedGoto(i));
+          }
+        }
+        Inst::Call(x) | Inst::TailCall(x) => {
+          self.operand(f, i, x);
+          puts = 0;
+          if let Inst::TailCall(_) = inst {
+            block = None;
+          } else if ! self.is_goto(f, i + 1) {
+            self.errors.push(Error::ExpectedGoto(i));
+          }
+        }
+        Inst::Const(_) | Inst::ConstBool(_) | Inst::ConstInt(_) => {
+        }
+        Inst::Field(x, _) | Inst::Op1(_, x) | Inst::Local(x) => {
+          self.operand(f, i, x);
+        }
+        Inst::Index(x, y) | Inst::Op2(_, x, y) => {
+          self.operand(f, i, x);
+          self.operand(f, i, y);
+        }
+        Inst::GetLocal(v) => {
+          self.local(f, i, v);
+        }
+        Inst::SetField(x, _, y) => {
+          self.operand(f, i, x);
+          self.operand(f, i, y);
+        }
+        Inst::SetIndex(x, y, z) => {
+          self.operand(f, i, x);
+          self.operand(f, i, y);
+          self.operand(f, i, z);
+        }
+        Inst::SetLocal(v, x) => {
+          self.local(f, i, v);
+          self.operand(f, i, x);
+        }
+      }
+    }
+
+    if block.is_some() {
+      self.errors.push(Error::Unterminated(f.pos + f.len - 1));
+    }
+  }
+}
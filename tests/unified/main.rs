@@ -1,10 +1,33 @@
 //! unified tests
 
+mod test_arith;
 mod test_array;
+mod test_cfg;
 mod test_combinator;
+mod test_driver;
+mod test_emit_c;
+mod test_emit_llvm;
+mod test_emit_wat;
+mod test_emit_x64;
+mod test_eval_irp;
+mod test_eval_iru;
 mod test_fib;
+mod test_format;
+mod test_heap;
 mod test_incdec;
+mod test_inline;
+mod test_irp;
+mod test_layout;
 mod test_loop;
+mod test_lsp;
+mod test_mono;
+mod test_opt;
+mod test_parse_iru;
+mod test_promote;
+mod test_repl;
+mod test_tail_call;
 mod test_tak;
+mod test_typestore;
 mod test_union_find;
+mod test_verify_iru;
 mod util;
@@ -0,0 +1,158 @@
+use crate::util;
+use crate::util::agree;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+use lilac::opt;
+use lilac::opt::Pass;
+use lilac::symbol::Symbol;
+
+const MIN: i64 = i64::MIN;
+const MAX: i64 = i64::MAX;
+
+static OPS: [&str; 16] = ["+", "+|", "-", "-|", "*", "*|", "/", "/|", "/%", "%", "<<", "<<|", "<<%", ">>", ">>%", "&"];
+
+static PAIRS: [(i64, i64); 12] = [
+  (7, 2),
+  (-7, 2),
+  (7, -2),
+  (MIN, -1),
+  (MIN, 1),
+  (MAX, -1),
+  (MAX, 2),
+  (MIN, 2),
+  (-3, 63),
+  (3, 64),
+  (-3, 65),
+  (1, 0),
+];
+
+// Applies each of `ops` to each of `pairs` in a single program, which every
+// interpreter and back end runs, and shows the results of each operator on a
+// line. None of them may trap.
+
+fn table(ops: &[&str], pairs: &[(i64, i64)]) -> String {
+  let mut source = String::from("
+    fun each(f, xs, ys, zs, k) {
+      let n = len(xs)
+      var i = 0
+      while i < n {
+        zs[k * n + i] = f(xs[i], ys[i])
+        i = i + 1
+      }
+    }
+  ");
+  let mut main = String::from("fun main(xs, ys, zs) {\n");
+  for (k, op) in ops.iter().enumerate() {
+    source.push_str(&format!("fun op{}(x, y) {{ return x {} y }}\n", k, op));
+    main.push_str(&format!("each(op{}, xs, ys, zs, {})\n", k, k));
+  }
+  main.push_str("return zs\n}\n");
+  source.push_str(&main);
+
+  let xs = Value::array(pairs.iter().map(|&(x, _)| Value::Int(x)));
+  let ys = Value::array(pairs.iter().map(|&(_, y)| Value::Int(y)));
+  let zs = Value::array((0 .. ops.len() * pairs.len()).map(|_| Value::Int(0)));
+  let out = agree(&source, "main", &[xs, ys, zs]);
+
+  let zs: Vec<&str> = out.trim_start_matches('[').trim_end_matches(']').split(", ").collect();
+  return ops.iter().zip(zs.chunks(pairs.len())).map(|(op, zs)| format!("{}: {}", op, zs.join(" "))).collect::<Vec<_>>().join("\n");
+}
+
+// Runs `x op y` with every interpreter and back end.
+
+fn apply(op: &str, x: i64, y: i64) -> String {
+  let source = format!("fun f(x, y) {{ return x {} y }}", op);
+  return agree(&source, "f", &[Value::Int(x), Value::Int(y)]);
+}
+
+#[test]
+fn test_wrap() {
+  let pairs = &PAIRS[.. 11];
+
+  expect![[r#"
+      +: 9 -5 5 9223372036854775807 -9223372036854775807 9223372036854775806 -9223372036854775807 -9223372036854775806 60 67 62
+      -: 5 -9 9 -9223372036854775807 9223372036854775807 -9223372036854775808 9223372036854775805 9223372036854775806 -66 -61 -68
+      *: 14 -14 -14 -9223372036854775808 -9223372036854775808 -9223372036854775807 -2 0 -189 192 -195
+      /%: 3 -3 -3 -9223372036854775808 -9223372036854775808 -9223372036854775807 4611686018427387903 -4611686018427387904 0 0 0
+      %: 1 -1 1 0 0 0 1 0 -3 3 -3
+      <<%: 28 -28 -4611686018427387904 0 0 -9223372036854775808 -4 0 -9223372036854775808 3 -6
+      >>%: 1 -2 0 -1 -4611686018427387904 0 2305843009213693951 -2305843009213693952 -1 3 -2"#]].assert_eq(&table(&["+", "-", "*", "/%", "%", "<<%", ">>%"], pairs));
+}
+
+#[test]
+fn test_saturate() {
+  let pairs = &PAIRS[.. 11];
+
+  expect![[r#"
+      +|: 9 -5 5 -9223372036854775808 -9223372036854775807 9223372036854775806 9223372036854775807 -9223372036854775806 60 67 62
+      -|: 5 -9 9 -9223372036854775807 -9223372036854775808 9223372036854775807 9223372036854775805 -9223372036854775808 -66 -61 -68
+      *|: 14 -14 -14 9223372036854775807 -9223372036854775808 -9223372036854775807 9223372036854775807 -9223372036854775808 -189 192 -195
+      /|: 3 -3 -3 9223372036854775807 -9223372036854775808 -9223372036854775807 4611686018427387903 -4611686018427387904 0 0 0"#]].assert_eq(&table(&["+|", "-|", "*|", "/|"], pairs));
+
+  // NB: a negative amount traps, and a large one saturates anything but `0`.
+
+  let pairs = [(7, 2), (-7, 2), (1, 62), (1, 63), (-1, 63), (-1, 64), (0, 100), (3, 1000), (-3, 1000)];
+
+  expect!["<<|: 28 -28 4611686018427387904 9223372036854775807 -9223372036854775808 -9223372036854775808 0 9223372036854775807 -9223372036854775808"].assert_eq(&table(&["<<|"], &pairs));
+}
+
+#[test]
+fn test_check() {
+  // The checked operators give exact results when they do not trap.
+
+  let pairs = [(7, 2), (-7, 2), (MIN, 1), (MAX, 3), (-3, 1), (-3, 63), (MIN, 63), (1, 62)];
+
+  expect![[r#"
+      /: 3 -3 -9223372036854775808 3074457345618258602 -3 0 -146402730743726600 0
+      <<: 28 -28 0 -8 -6 -9223372036854775808 0 4611686018427387904
+      >>: 1 -2 -4611686018427387904 1152921504606846975 -2 -1 -1 0"#]].assert_eq(&table(&["/", "<<", ">>"], &pairs));
+
+  let out =
+    [
+      apply("/", 1, 0),
+      apply("/", MIN, -1),
+      apply("/|", 1, 0),
+      apply("/%", 1, 0),
+      apply("%", 1, 0),
+      apply("<<", 1, 64),
+      apply(">>", 1, -1),
+      apply("<<|", 1, -1),
+    ].join("\n");
+
+  expect![[r#"
+      error: division by zero at %3
+      error: integer overflow at %3
+      error: division by zero at %3
+      error: division by zero at %3
+      error: division by zero at %3
+      error: shift out of range at %3
+      error: shift out of range at %3
+      error: shift out of range at %3"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_fold() {
+  // The constant folder computes exactly what the interpreters do, and leaves
+  // the operations that trap in place.
+
+  let mut folded = 0;
+
+  for op in OPS {
+    for &(x, y) in PAIRS.iter() {
+      let source = format!("fun f() {{ let x = {}\nlet y = {}\nreturn x {} y }}", x, y, op);
+      let module = opt::optimize(util::mono(&source, "f"), &[Pass::Fold]);
+      let out =
+        match lilac::eval_irp::run(&module, Symbol::from_str("f"), []) {
+          Ok(out) => out.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+          Err(e) => format!("error: {}", e),
+        };
+      let code = module.to_string();
+      assert_eq!(out, util::run(&source, "f", []));
+      assert_eq!(out, util::run_irp(&source, "f", []));
+      assert_eq!(out.starts_with("error"), code.contains(".i64"));
+      folded += ! code.contains(".i64") as u32;
+    }
+  }
+
+  expect!["174"].assert_eq(&folded.to_string());
+}
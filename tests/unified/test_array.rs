@@ -80,3 +80,25 @@ fn test_array() {
       %32 RET
   "#]].assert_eq(out.drain(..).as_ref());
 }
+
+#[test]
+fn test_len() {
+  // `len` takes an array, even where nothing else says what its argument is.
+
+  let mut out = String::new();
+
+  util::dump(&mut out, "
+    fun size(x) {
+      len(x)
+    }
+  ");
+
+  expect![[r#"
+      === fun size : forall '0 . Fun(Array['0]) -> (i64) ===
+      %0 LABEL 1 : (Array['0])
+      %1 = GET 0 : Array['0]
+      %2 = CONST len : Fun(Array['0]) -> (i64)
+      %3 PUT 0 %1
+      %4 TAIL-CALL %2
+  "#]].assert_eq(out.drain(..).as_ref());
+}
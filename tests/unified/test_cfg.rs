@@ -0,0 +1,144 @@
+use expect_test::expect;
+use lilac::arr::Arr;
+use lilac::cfg;
+use lilac::cfg::Cfg;
+use lilac::cfg::Dominators;
+use lilac::cfg::Liveness;
+use lilac::cfg::Loops;
+use lilac::driver;
+
+static SOURCE: &str = "
+  fun g(x) {
+    var y = x
+    loop {
+      if y > 3 { return }
+      y = y + 1
+    }
+  }
+  fun f(n) {
+    var s = 0
+    var i = 0
+    while i < n {
+      var j = 0
+      while j < i {
+        s = s + j
+        g(j)
+        j = j + 1
+      }
+      i = i + 1
+    }
+    return s
+  }
+";
+
+// Lists the blocks of a function with their successors, immediate dominator,
+// loop depth, and live-in values, then its loops.
+
+fn summary<I: cfg::Inst>(code: &Arr<I>, pos: u32, len: u32) -> String {
+  let g = Cfg::new(code, pos, len);
+  let dom = Dominators::new(&g);
+  let loops = Loops::new(&g, &dom);
+  let live = Liveness::new(code, &g);
+  let mut out = String::new();
+
+  for (b, block) in g.blocks.iter().enumerate() {
+    let b = b as u32;
+    let succs: Vec<String> = block.succs.iter().map(|e| format!("b{} {:?}", e.block, e.kind)).collect();
+    let live_in: Vec<String> = live.live_in(b).map(|x| format!("%{}", x)).collect();
+    out.push_str(&format!(
+      "b{} %{}: succs [{}] idom {:?} depth {} live [{}]\n",
+      b,
+      block.pos,
+      succs.join(", "),
+      dom.idom(b),
+      loops.depth(b),
+      live_in.join(" "),
+    ));
+  }
+
+  for l in loops.loops.iter() {
+    out.push_str(&format!("loop b{} {:?} parent {:?}\n", l.header, l.blocks, l.parent));
+  }
+
+  return out;
+}
+
+#[test]
+fn test_iru() {
+  let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(SOURCE.as_bytes(), store.arena()).0;
+  let module = lilac::make_iru::compile(&items);
+  let out: String = module.decl.iter().map(|f| summary(&module.code, f.pos, f.len)).collect();
+
+  expect![[r#"
+      b0 %0: succs [b1 Jump] idom Some(0) depth 0 live []
+      b1 %4: succs [b3 False, b2 True] idom Some(0) depth 1 live [%2]
+      b2 %11: succs [] idom Some(1) depth 0 live []
+      b3 %13: succs [b1 Jump] idom Some(1) depth 1 live [%2]
+      loop b1 [1, 3] parent None
+      b0 %19: succs [b1 Jump] idom Some(0) depth 0 live []
+      b1 %26: succs [b7 False, b2 True] idom Some(0) depth 1 live [%20 %22 %24]
+      b2 %32: succs [b3 Jump] idom Some(1) depth 1 live [%20 %22 %24]
+      b3 %36: succs [b6 False, b4 True] idom Some(2) depth 2 live [%20 %22 %24 %34]
+      b4 %43: succs [b5 Return] idom Some(3) depth 2 live [%20 %22 %24 %34]
+      b5 %53: succs [b3 Jump] idom Some(4) depth 2 live [%20 %22 %24 %34]
+      b6 %59: succs [b1 Jump] idom Some(3) depth 1 live [%20 %22 %24]
+      b7 %65: succs [] idom Some(1) depth 0 live [%22]
+      loop b1 [1, 2, 3, 4, 5, 6] parent None
+      loop b3 [3, 4, 5] parent Some(0)
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_irp() {
+  // NB: typed bytecode has the same program points, so its analyses agree.
+
+  let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(SOURCE.as_bytes(), store.arena()).0;
+  let module = lilac::make_iru::compile(&items);
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+  let typed = lilac::make_irp::compile(&module, &environment, solver).unwrap();
+
+  for (f, g) in module.decl.iter().zip(typed.decl.iter()) {
+    assert_eq!(summary(&typed.code, g.pos, g.len), summary(&module.code, f.pos, f.len));
+  }
+}
+
+#[test]
+fn test_unreachable() {
+  let module = lilac::parse_iru::parse("
+    === fun f ===
+    %0 LABEL 0
+    %1 ==> GOTO %5
+    %2 LABEL 0
+    %3 = 1
+    %4 ==> GOTO %5
+    %5 LABEL 0
+    %6 RET
+  ").unwrap();
+
+  let out = summary(&module.code, 0, module.code.len());
+
+  expect![[r#"
+      b0 %0: succs [b2 Jump] idom Some(0) depth 0 live []
+      b1 %2: succs [b2 Jump] idom None depth 0 live []
+      b2 %5: succs [] idom Some(0) depth 0 live []
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_dot() {
+  let mut out = String::new();
+  driver::cfg("fun f(x) { if x { return 1 } else { return g(x) } }", &mut out).unwrap();
+
+  expect![[r#"
+      digraph "f" {
+        node [shape=box, fontname=monospace];
+        b0 [label="b0 depth 0 live\l%0 LABEL 1\l%1 = GET 0\l%2 COND %1\l%3 ==> GOTO %5\l%4 ==> GOTO %9\l"];
+        b1 [label="b1 depth 0 live %1\l%5 LABEL 0\l%6 = CONST g\l%7 PUT 0 %1\l%8 TAIL-CALL %6\l"];
+        b2 [label="b2 depth 0 live\l%9 LABEL 0\l%10 = 1\l%11 PUT 0 %10\l%12 RET\l"];
+        b0 -> b1 [label="false"];
+        b0 -> b2 [label="true"];
+      }
+  "#]].assert_eq(&out);
+}
@@ -0,0 +1,187 @@
+use crate::util;
+use expect_test::expect;
+use lilac::driver;
+use lilac::eval_iru::Value;
+
+static SOURCE: &str = "
+  fun add(a, b) { return a + b }
+  fun main(n) {
+    var s = 0
+    var i = 0
+    while i < n {
+      s = s + add(i, 1)
+      i = i + 1
+    }
+    if s > 3 { return s } else { return 0 }
+  }
+";
+
+// Runs a command, and returns what it prints and its diagnostic.
+
+fn command(f: impl FnOnce(&mut String) -> Result<(), driver::Error>) -> String {
+  let mut out = String::new();
+  if let Err(e) = f(&mut out) {
+    out.push_str(&format!("error: {}\n", e));
+  }
+  return out;
+}
+
+#[test]
+fn test_lex() {
+  let out = command(|out| driver::lex("fun f(x) { x ` 1 }", out));
+
+  expect![[r#"
+      0 Fun "fun"
+      4 Symbol "f"
+      5 LParen "("
+      6 Symbol "x"
+      7 RParen ")"
+      9 LBrace "{"
+      11 Symbol "x"
+      13 Error "`"
+      15 Number "1"
+      17 RBrace "}"
+      error: invalid token at byte 13
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_parse() {
+  let out = command(|out| driver::parse(SOURCE, out));
+
+  expect![[r#"
+      (fun add (a b)
+        (return (+ a b)))
+      (fun main (n)
+        (var s 0)
+        (var i 0)
+        (while (< i n)
+          (set s (+ s (call add i 1)))
+          (set i (+ i 1)))
+        (do (if (> s 3)
+          (return s)
+        else
+          (return 0))))
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_iru() {
+  let out = command(|out| driver::iru("fun f(x) { return x + 1 }", out));
+
+  expect![[r#"
+      === fun f ===
+      %0 LABEL 1
+      %1 = GET 0
+      %2 = 1
+      %3 = %1 + %2
+      %4 PUT 0 %3
+      %5 RET
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_check() {
+  let out =
+    [
+      command(|out| driver::check(SOURCE, out)),
+      command(|out| driver::check("fun f(x) { return x + true }", out)),
+      command(|out| driver::check("fun f(x) { return x }\nfun g(x) { return x + true }\nfun h(x) { return f(x) }", out)),
+      command(|out| driver::check("fun f(x) { let a, b = x\n return a }", out)),
+      command(|out| driver::check("fun f(x) { if x { return 1 } return 1, 2 }", out)),
+      command(|out| driver::check("fun f(x) { return x.foo }", out)),
+      command(|out| driver::check("fun f(x { return x }", out)),
+      command(|out| driver::check("fun f(x) { return 99999999999999999999 }", out)),
+    ].concat();
+
+  expect![[r#"
+      fun add : Fun(i64, i64) -> (i64)
+      fun main : Fun(i64) -> (i64)
+      fun f : <type error>
+      error: type error in f at %2
+      fun f : forall '0 . Fun('0) -> ('0)
+      fun g : <type error>
+      fun h : forall '0 . Fun('0) -> ('0)
+      error: type error in g at %6
+      fun f : forall '0 '1 . Fun('0) -> ('1)
+      error: static error in f at %2
+      fun f : <type error>
+      error: type error in f at %0
+      fun f : <type error>
+      error: unsupported instruction in f at %2
+      fun f : forall '0 . Fun('0) -> ('0)
+      error: expected RParen at byte 8
+      fun f : forall '0 '1 . Fun('0) -> ('1)
+      error: number out of range at byte 18
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_run() {
+  let out =
+    [
+      command(|out| driver::run(SOURCE, "main", [Value::Int(5)], out)),
+      command(|out| driver::run(SOURCE, "main", [Value::Int(1)], out)),
+      command(|out| driver::run("fun f(x) { return 1 / x }", "f", [Value::Int(0)], out)),
+      command(|out| driver::run(SOURCE, "nope", [], out)),
+    ].concat();
+
+  expect![[r#"
+      15
+      0
+      error: division by zero at %3
+      error: unbound variable nope
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_binary() {
+  let path = util::temp_path("main.lil");
+  std::fs::write(&path, SOURCE).unwrap();
+
+  let bad = util::temp_path("bad.lil");
+  std::fs::write(&bad, "fun f(x) { return x }\nfoo bar").unwrap();
+
+  let path_str = path.to_str().unwrap();
+  let bad_str = bad.to_str().unwrap();
+
+  // Each run shows its exit status, and its output or else the first line of
+  // its diagnostics.
+
+  let lilac = |args: &[&str]| {
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_lilac")).args(args).output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap().replace(path_str, "main.lil").replace(bad_str, "bad.lil");
+    let text = if stdout.is_empty() { stderr } else { stdout };
+    return format!("status {}: {}", out.status.code().unwrap(), text.lines().next().unwrap());
+  };
+
+  let out =
+    [
+      lilac(&["run", path_str, "main", "5"]),
+      lilac(&["run", path_str, "add", "1"]),
+      lilac(&["run", path_str, "main", "five"]),
+      lilac(&["fmt", path_str]),
+      lilac(&["fmt", path_str, "--check"]),
+      lilac(&["opt", path_str, "fold", "dce"]),
+      lilac(&["opt", path_str, "inline"]),
+      lilac(&["frobnicate", path_str]),
+      lilac(&["check", bad_str]),
+      lilac(&["run", bad_str, "f", "1"]),
+    ].join("\n");
+
+  let _ = std::fs::remove_file(&path);
+  let _ = std::fs::remove_file(&bad);
+
+  expect![[r#"
+      status 0: 15
+      status 1: main.lil: error: arity mismatch at %0
+      status 2: usage: lilac lex <file>
+      status 0: fun add(a, b) {
+      status 1: main.lil: error: not formatted
+      status 0: === fun add : Fun(i64, i64) -> (i64) ===
+      status 2: usage: lilac lex <file>
+      status 2: usage: lilac lex <file>
+      status 1: fun f : forall '0 . Fun('0) -> ('0)
+      status 1: bad.lil: error: expected Fun at byte 22"#]].assert_eq(&out);
+}
@@ -0,0 +1,182 @@
+use crate::test_eval_iru::FIB_LOOP;
+use crate::test_eval_iru::FIB_REC;
+use crate::test_eval_iru::FIB_TAIL;
+use crate::test_eval_iru::TAK;
+use crate::test_eval_iru::UNION_FIND;
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+
+// Runs `name` compiled to C, and checks that it agrees with the interpreter.
+
+fn agree(source: &str, name: &str, args: &[Value]) -> String {
+  let x = util::run_irp(source, name, args.iter().cloned());
+  let y = util::run_c(source, name, args);
+  assert_eq!(x, y);
+  return y;
+}
+
+#[test]
+fn test_emit() {
+  let module = util::mono("
+    fun sum(a) {
+      var s = 0
+      var i = 0
+      loop {
+        if i == len(a) { return s }
+        s = s + a[i]
+        i = i + 1
+      }
+    }
+  ", "sum");
+
+  let out = lilac::emit_c::emit(&module).unwrap();
+  let out = &out[out.find("typedef struct { int64_t len; int64_t").unwrap() ..];
+
+  expect![[r#"
+      typedef struct { int64_t len; int64_t elts[]; } arr13;
+
+      static lilac_reg lilac_regs[1];
+
+      static lilac_cont lilac_len(void) {
+        lilac_regs[0].i = * (int64_t *) lilac_regs[0].p;
+        return (lilac_cont) { 0 };
+      }
+
+      static lilac_cont f0(void); // sum
+
+      // sum
+      static lilac_cont f0(void) {
+        arr13 * a0_0 = lilac_regs[0].p;
+        int64_t a13_0;
+        arr13 * v1;
+        int64_t v2;
+        int64_t v3;
+        int64_t v4;
+        int64_t v5;
+        int64_t v8;
+        lilac_fun v9;
+        int64_t v14;
+        bool v15;
+        int64_t v20;
+        int64_t v24;
+        int64_t v25;
+        int64_t v26;
+        int64_t v27;
+        int64_t v29;
+        int64_t v30;
+        int64_t v31;
+        v1 = a0_0;
+        v2 = INT64_C(0);
+        v3 = v2;
+        v4 = INT64_C(0);
+        v5 = v4;
+        goto L7;
+       L7:;
+        v8 = v5;
+        v9 = lilac_len;
+        lilac_regs[0].p = v1;
+        lilac_call(v9);
+        a13_0 = lilac_regs[0].i;
+        goto L13;
+       L13:;
+        v14 = a13_0;
+        v15 = lilac_cmpeq_i64(v8, v14, 15);
+        if (v15) goto L19; else goto L23;
+       L19:;
+        v20 = v3;
+        lilac_regs[0].i = v20;
+        return (lilac_cont) { 0 };
+       L23:;
+        v24 = v3;
+        v25 = v5;
+        v26 = v1->elts[lilac_check_index(v25, v1->len, 26)];
+        v27 = lilac_add_i64(v24, v26, 27);
+        v3 = v27;
+        v29 = v5;
+        v30 = INT64_C(1);
+        v31 = lilac_add_i64(v29, v30, 31);
+        v5 = v31;
+        goto L7;
+      }
+  "#]].assert_eq(out);
+}
+
+#[test]
+fn test_fib() {
+  let out =
+    [FIB_LOOP, FIB_REC, FIB_TAIL].iter().map(|source| {
+      agree(source, "fib", &[Value::Int(30)])
+    }).collect::<Vec<_>>().join(" ");
+
+  expect!["832040 832040 832040"].assert_eq(&out);
+}
+
+#[test]
+fn test_deep_tail_call() {
+  let out = agree(FIB_TAIL, "fib", &[Value::Int(1_000_000)]);
+
+  expect!["-4249520595888827205"].assert_eq(&out);
+}
+
+#[test]
+fn test_tak() {
+  let out = agree(TAK, "tak", &[Value::Int(18), Value::Int(12), Value::Int(6)]);
+
+  expect!["7"].assert_eq(&out);
+}
+
+#[test]
+fn test_union_find() {
+  let source = format!("{}{}", UNION_FIND, "
+    fun main(parent) {
+      let a = union(parent, 0, 1)
+      let b = union(parent, 2, 3)
+      let c = union(parent, 1, 3)
+      let d = union(parent, 0, 2)
+      return a, b, c, d, count(parent), parent
+    }
+  ");
+
+  // NB: the interpreter updates the array in place, so each run gets a copy.
+
+  let parent = || Value::array((0 .. 8).map(Value::Int));
+  let out = util::run_c(&source, "main", &[parent()]);
+  assert_eq!(out, util::run_irp(&source, "main", [parent()]));
+
+  expect!["true, true, true, false, 5, [0, 0, 0, 0, 4, 5, 6, 7]"].assert_eq(&out);
+}
+
+#[test]
+fn test_arith() {
+  let source = "
+    fun arith(x, y) {
+      return x + y, x - y, x * y, x /% y, x % y, x <<% y, x >>% y, - x, x < y, ! (x < y)
+    }
+  ";
+
+  let out =
+    [(7, 2), (-7, 2), (i64::MIN, -1), (i64::MAX, 1), (1, 65)].iter().map(|&(x, y)| {
+      agree(source, "arith", &[Value::Int(x), Value::Int(y)])
+    }).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      9, 5, 14, 3, 1, 28, 1, -7, false, true
+      -5, -9, -14, -3, -1, -28, -2, 7, true, false
+      9223372036854775807, -9223372036854775807, -9223372036854775808, -9223372036854775808, 0, 0, -1, -9223372036854775808, true, false
+      -9223372036854775808, 9223372036854775806, 9223372036854775807, 9223372036854775807, 0, -2, 4611686018427387903, -9223372036854775807, false, true
+      66, -64, 65, 0, 1, 2, 0, -1, true, false"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_runtime_error() {
+  let out =
+    [
+      agree("fun f(a, i) { a[i] + 0 }", "f", &[Value::array([Value::Int(1)]), Value::Int(1)]),
+      agree("fun f(x, y) { x / y }", "f", &[Value::Int(1), Value::Int(0)]),
+    ].join("\n");
+
+  expect![[r#"
+      error: index out of bounds at %3
+      error: division by zero at %3"#]].assert_eq(&out);
+}
@@ -0,0 +1,291 @@
+use crate::test_eval_iru::FIB_LOOP;
+use crate::test_eval_iru::FIB_REC;
+use crate::test_eval_iru::FIB_TAIL;
+use crate::test_eval_iru::TAK;
+use crate::test_eval_iru::UNION_FIND;
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+
+// Runs `name` compiled with LLVM, and checks that it agrees with the
+// interpreter. Without `llc`, only the interpreter runs.
+
+fn agree(source: &str, name: &str, args: &[Value]) -> String {
+  let x = util::run_irp(source, name, args.iter().cloned());
+  if let Some(y) = util::run_llvm(source, name, args) {
+    assert_eq!(x, y);
+  }
+  return x;
+}
+
+#[test]
+fn test_emit() {
+  let module = util::mono("
+    fun sum(a) {
+      var s = 0
+      var i = 0
+      loop {
+        if i == len(a) { return s }
+        s = s + a[i]
+        i = i + 1
+      }
+    }
+  ", "sum");
+
+  let out = lilac::emit_llvm::emit(&module).unwrap();
+  let out = &out[out.find("; sum").unwrap() ..];
+
+  expect![[r#"
+      ; sum
+      define internal tailcc i64 @f0(i64* %a0) {
+      entry:
+        %v3 = alloca i64
+        %v5 = alloca i64
+        br label %L0
+      L0:
+        %v1 = phi i64* [ %a0, %entry ]
+        store i64 0, i64* %v3
+        store i64 0, i64* %v5
+        br label %L7
+      L7:
+        %v8 = load i64, i64* %v5
+        %c11 = call tailcc i64 @lilac_len(i64* %v1)
+        br label %L13
+      L13:
+        %v14 = phi i64 [ %c11, %L7 ]
+        %v15 = icmp eq i64 %v8, %v14
+        br i1 %v15, label %L19, label %L23
+      L19:
+        %v20 = load i64, i64* %v3
+        ret i64 %v20
+      L23:
+        %v24 = load i64, i64* %v3
+        %v25 = load i64, i64* %v5
+        %v26.hdr = load i64, i64* %v1
+        %v26.len = and i64 %v26.hdr, 4294967295
+        %v26.ok = icmp ult i64 %v25, %v26.len
+        br i1 %v26.ok, label %I26, label %T26
+      T26:
+        call void @lilac_trap(i64 1, i64 26)
+        unreachable
+      I26:
+        %v26.j = add i64 %v25, 1
+        %v26.p = getelementptr i64, i64* %v1, i64 %v26.j
+        %v26 = load i64, i64* %v26.p
+        %v27 = add i64 %v24, %v26
+        store i64 %v27, i64* %v3
+        %v29 = load i64, i64* %v5
+        %v31 = add i64 %v29, 1
+        store i64 %v31, i64* %v5
+        br label %L7
+      }
+
+      define void @lilac_invoke_f0(i64* %args, i64* %results) {
+        %p0 = getelementptr i64, i64* %args, i64 0
+        %w0 = load i64, i64* %p0
+        %x0 = inttoptr i64 %w0 to i64*
+        %r = call tailcc i64 @f0(i64* %x0)
+        %q0 = getelementptr i64, i64* %results, i64 0
+        store i64 %r, i64* %q0
+        ret void
+      }
+  "#]].assert_eq(out);
+}
+
+#[test]
+fn test_emit_results() {
+  let module = util::mono("
+    fun swap(x, y) {
+      return y, x
+    }
+    fun main(x) {
+      let a, b = swap(x, x + 1)
+      return swap(b, a)
+    }
+  ", "main");
+
+  let out = lilac::emit_llvm::emit(&module).unwrap();
+  let out = &out[out.find("; main").unwrap() ..];
+
+  expect![[r#"
+      ; main
+      define internal tailcc { i64, i64 } @f0(i64 %a0) {
+      entry:
+        br label %L0
+      L0:
+        %v1 = phi i64 [ %a0, %entry ]
+        %v3 = add i64 %v1, 1
+        %c7 = call tailcc { i64, i64 } @f1(i64 %v1, i64 %v3)
+        %c7.0 = extractvalue { i64, i64 } %c7, 0
+        %c7.1 = extractvalue { i64, i64 } %c7, 1
+        br label %L9
+      L9:
+        %v10 = phi i64 [ %c7.0, %L0 ]
+        %v11 = phi i64 [ %c7.1, %L0 ]
+        %c15 = musttail call tailcc { i64, i64 } @f1(i64 %v11, i64 %v10)
+        ret { i64, i64 } %c15
+      }
+
+      ; swap
+      define internal tailcc { i64, i64 } @f1(i64 %a0, i64 %a1) {
+      entry:
+        br label %L16
+      L16:
+        %v17 = phi i64 [ %a0, %entry ]
+        %v18 = phi i64 [ %a1, %entry ]
+        %r21.0 = insertvalue { i64, i64 } undef, i64 %v18, 0
+        %r21.1 = insertvalue { i64, i64 } %r21.0, i64 %v17, 1
+        ret { i64, i64 } %r21.1
+      }
+
+      define void @lilac_invoke_f0(i64* %args, i64* %results) {
+        %p0 = getelementptr i64, i64* %args, i64 0
+        %w0 = load i64, i64* %p0
+        %r = call tailcc { i64, i64 } @f0(i64 %w0)
+        %y0 = extractvalue { i64, i64 } %r, 0
+        %q0 = getelementptr i64, i64* %results, i64 0
+        store i64 %y0, i64* %q0
+        %y1 = extractvalue { i64, i64 } %r, 1
+        %q1 = getelementptr i64, i64* %results, i64 1
+        store i64 %y1, i64* %q1
+        ret void
+      }
+
+      define void @lilac_invoke_f1(i64* %args, i64* %results) {
+        %p0 = getelementptr i64, i64* %args, i64 0
+        %w0 = load i64, i64* %p0
+        %p1 = getelementptr i64, i64* %args, i64 1
+        %w1 = load i64, i64* %p1
+        %r = call tailcc { i64, i64 } @f1(i64 %w0, i64 %w1)
+        %y0 = extractvalue { i64, i64 } %r, 0
+        %q0 = getelementptr i64, i64* %results, i64 0
+        store i64 %y0, i64* %q0
+        %y1 = extractvalue { i64, i64 } %r, 1
+        %q1 = getelementptr i64, i64* %results, i64 1
+        store i64 %y1, i64* %q1
+        ret void
+      }
+  "#]].assert_eq(out);
+}
+
+#[test]
+fn test_fib() {
+  let out =
+    [FIB_LOOP, FIB_REC, FIB_TAIL].iter().map(|source| {
+      agree(source, "fib", &[Value::Int(30)])
+    }).collect::<Vec<_>>().join(" ");
+
+  expect!["832040 832040 832040"].assert_eq(&out);
+}
+
+#[test]
+fn test_deep_tail_call() {
+  let out = agree(FIB_TAIL, "fib", &[Value::Int(1_000_000)]);
+
+  expect!["-4249520595888827205"].assert_eq(&out);
+}
+
+#[test]
+fn test_tak() {
+  let out = agree(TAK, "tak", &[Value::Int(18), Value::Int(12), Value::Int(6)]);
+
+  expect!["7"].assert_eq(&out);
+}
+
+#[test]
+fn test_union_find() {
+  let source = format!("{}{}", UNION_FIND, "
+    fun main(parent) {
+      let a = union(parent, 0, 1)
+      let b = union(parent, 2, 3)
+      let c = union(parent, 1, 3)
+      let d = union(parent, 0, 2)
+      return a, b, c, d, count(parent), parent
+    }
+  ");
+
+  // NB: the interpreter updates the array in place, so each run gets a copy.
+
+  let parent = || Value::array((0 .. 8).map(Value::Int));
+  let out = util::run_irp(&source, "main", [parent()]);
+  if let Some(y) = util::run_llvm(&source, "main", &[parent()]) {
+    assert_eq!(out, y);
+  }
+
+  expect!["true, true, true, false, 5, [0, 0, 0, 0, 4, 5, 6, 7]"].assert_eq(&out);
+}
+
+#[test]
+fn test_arith() {
+  let source = "
+    fun arith(x, y) {
+      return x + y, x - y, x * y, x /% y, x % y, x <<% y, x >>% y, - x, x < y, x <= y, x == y, x != y, x >= y, x > y, ! (x < y)
+    }
+  ";
+
+  let out =
+    [(7, 2), (-7, 2), (i64::MIN, -1), (i64::MAX, 1), (1, 65), (3, 3)].iter().map(|&(x, y)| {
+      agree(source, "arith", &[Value::Int(x), Value::Int(y)])
+    }).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      9, 5, 14, 3, 1, 28, 1, -7, false, false, false, true, true, true, true
+      -5, -9, -14, -3, -1, -28, -2, 7, true, true, false, true, false, false, false
+      9223372036854775807, -9223372036854775807, -9223372036854775808, -9223372036854775808, 0, 0, -1, -9223372036854775808, true, true, false, true, false, false, false
+      -9223372036854775808, 9223372036854775806, 9223372036854775807, 9223372036854775807, 0, -2, 4611686018427387903, -9223372036854775807, false, false, false, true, true, true, true
+      66, -64, 65, 0, 1, 2, 0, -1, true, true, false, true, false, false, false
+      6, 0, 9, 1, 0, 24, 0, -3, false, true, true, false, true, false, true"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_higher_order() {
+  let source = "
+    fun twice(f, x) {
+      return f(f(x))
+    }
+    fun inc(x) {
+      return x + 1
+    }
+    fun main(x) {
+      return twice(inc, x)
+    }
+  ";
+
+  let out = agree(source, "main", &[Value::Int(1)]);
+
+  expect!["3"].assert_eq(&out);
+}
+
+#[test]
+fn test_runtime_error() {
+  let source = "
+    fun get(a, i) {
+      return a[i] + 0
+    }
+    fun div(x, y) {
+      return x / y
+    }
+  ";
+
+  let out = [
+    agree(source, "get", &[Value::array([Value::Int(1)]), Value::Int(1)]),
+    agree(source, "div", &[Value::Int(1), Value::Int(0)]),
+  ];
+
+  expect![[r#"
+      error: index out of bounds at %3
+      error: division by zero at %3"#]].assert_eq(&out.join("\n"));
+}
+
+#[test]
+fn test_bool_array() {
+  let source = "fun flip(a, i) { a[i] = ! a[i]\n return a, len(a) }";
+
+  let a = || Value::array((0 .. 10).map(|i| Value::Bool(i % 3 == 0)));
+  let out = util::run_irp(source, "flip", [a(), Value::Int(9)]);
+  if let Some(y) = util::run_llvm(source, "flip", &[a(), Value::Int(9)]) {
+    assert_eq!(out, y);
+  }
+
+  expect!["[true, false, false, true, false, false, true, false, false, false], 10"].assert_eq(&out);
+}
@@ -0,0 +1,724 @@
+use crate::test_eval_iru::FIB_REC;
+use crate::test_eval_iru::FIB_TAIL;
+use crate::test_eval_iru::UNION_FIND;
+use crate::util;
+use expect_test::expect;
+
+// Returns the text of the function `name` in the module emitted for the entry
+// point `entry`, without the indentation of the module.
+
+fn emit(source: &str, entry: &str, name: &str) -> String {
+  let module = util::mono(source, entry);
+  let out = lilac::emit_wat::emit(&module).unwrap();
+  let start = out.find(&format!("  ;; {}\n", name)).unwrap();
+  let end = out[start + 1 ..].find("  ;; ").map_or(out.len() - 2, |i| start + i);
+  return out[start .. end].lines().map(|s| format!("{}\n", &s[2 ..])).collect();
+}
+
+#[test]
+fn test_module() {
+  let module = util::mono("fun id(x) { return x + 0 }", "id");
+  let out = lilac::emit_wat::emit(&module).unwrap();
+  expect![[r#"
+      (module
+        (type $t13 (func (param i64) (result i64)))
+
+        (import "lilac" "trap" (func $lilac_trap (param i32 i32)))
+
+        (memory (export "memory") 1)
+
+        (func $lilac_len (param $a i32) (result i64)
+          local.get $a
+          i64.load)
+
+        (func $lilac_index (param $a i32) (param $i i64) (param $size i32) (param $pc i32) (result i32)
+          local.get $i
+          local.get $a
+          i64.load
+          i64.ge_u
+          if
+            i32.const 1
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $a
+          i32.const 8
+          i32.add
+          local.get $i
+          i32.wrap_i64
+          local.get $size
+          i32.mul
+          i32.add)
+
+        (func $lilac_div_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          local.get $y
+          i64.eqz
+          if
+            i32.const 0
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $x
+          i64.const -9223372036854775808
+          i64.eq
+          local.get $y
+          i64.const -1
+          i64.eq
+          i32.and
+          if
+            i32.const 3
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $x
+          local.get $y
+          i64.div_s)
+
+        (func $lilac_divsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          local.get $y
+          i64.eqz
+          if
+            i32.const 0
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $y
+          i64.const -1
+          i64.eq
+          if
+            i64.const 9223372036854775807
+            i64.const 0
+            local.get $x
+            i64.sub
+            local.get $x
+            i64.const -9223372036854775808
+            i64.eq
+            select
+            return
+          end
+          local.get $x
+          local.get $y
+          i64.div_s)
+
+        (func $lilac_divwrap_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          local.get $y
+          i64.eqz
+          if
+            i32.const 0
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $y
+          i64.const -1
+          i64.eq
+          if
+            i64.const 0
+            local.get $x
+            i64.sub
+            return
+          end
+          local.get $x
+          local.get $y
+          i64.div_s)
+
+        (func $lilac_rem_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          local.get $y
+          i64.eqz
+          if
+            i32.const 0
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $x
+          local.get $y
+          i64.rem_s)
+
+        (func $lilac_addsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          (local $z i64)
+          local.get $x
+          local.get $y
+          i64.add
+          local.set $z
+          local.get $x
+          i64.const 63
+          i64.shr_s
+          i64.const 9223372036854775807
+          i64.xor
+          local.get $z
+          local.get $x
+          local.get $z
+          i64.xor
+          local.get $y
+          local.get $z
+          i64.xor
+          i64.and
+          i64.const 0
+          i64.lt_s
+          select)
+
+        (func $lilac_subsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          (local $z i64)
+          local.get $x
+          local.get $y
+          i64.sub
+          local.set $z
+          local.get $x
+          i64.const 63
+          i64.shr_s
+          i64.const 9223372036854775807
+          i64.xor
+          local.get $z
+          local.get $x
+          local.get $y
+          i64.xor
+          local.get $x
+          local.get $z
+          i64.xor
+          i64.and
+          i64.const 0
+          i64.lt_s
+          select)
+
+        (func $lilac_mulsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          (local $z i64)
+          local.get $x
+          local.get $y
+          i64.mul
+          local.set $z
+          local.get $x
+          i64.const 1
+          i64.add
+          i64.const 1
+          i64.le_u
+          if
+            local.get $x
+            i64.const -1
+            i64.eq
+            local.get $y
+            i64.const -9223372036854775808
+            i64.eq
+            i32.and
+            if
+              i64.const 9223372036854775807
+              return
+            end
+            local.get $z
+            return
+          end
+          local.get $x
+          local.get $y
+          i64.xor
+          i64.const 63
+          i64.shr_s
+          i64.const 9223372036854775807
+          i64.xor
+          local.get $z
+          local.get $z
+          local.get $x
+          i64.div_s
+          local.get $y
+          i64.ne
+          select)
+
+        (func $lilac_shl_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          local.get $y
+          i64.const 64
+          i64.ge_u
+          if
+            i32.const 4
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $x
+          local.get $y
+          i64.shl)
+
+        (func $lilac_shr_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          local.get $y
+          i64.const 64
+          i64.ge_u
+          if
+            i32.const 4
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $x
+          local.get $y
+          i64.shr_s)
+
+        (func $lilac_shlsat_i64 (param $x i64) (param $y i64) (param $pc i32) (result i64)
+          (local $z i64)
+          local.get $y
+          i64.const 0
+          i64.lt_s
+          if
+            i32.const 4
+            local.get $pc
+            call $lilac_trap
+            unreachable
+          end
+          local.get $y
+          i64.const 63
+          local.get $y
+          i64.const 63
+          i64.lt_s
+          select
+          local.set $y
+          local.get $x
+          local.get $y
+          i64.shl
+          local.set $z
+          local.get $z
+          local.get $x
+          i64.const 63
+          i64.shr_s
+          i64.const 9223372036854775807
+          i64.xor
+          local.get $z
+          local.get $y
+          i64.shr_s
+          local.get $x
+          i64.eq
+          select)
+
+        (table 2 funcref)
+        (elem (i32.const 0) func $f0 $lilac_len)
+
+        ;; id
+        (func $f0 (export "id") (type $t13) (param $p0 i64) (result i64)
+          (local $v1 i64)
+          (local $v2 i64)
+          (local $v3 i64)
+          local.get $p0
+          local.set $v1
+          i64.const 0
+          local.set $v2
+          local.get $v1
+          local.get $v2
+          i64.add
+          local.set $v3
+          local.get $v3
+          return
+          unreachable)
+      )
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_loop() {
+  expect![[r#"
+      ;; sum
+      (func $f0 (export "sum") (type $t16) (param $p0 i32) (result i64)
+        (local $v1 i32)
+        (local $v2 i64)
+        (local $v3 i64)
+        (local $v4 i64)
+        (local $v5 i64)
+        (local $v8 i64)
+        (local $v9 i32)
+        (local $v14 i64)
+        (local $v15 i32)
+        (local $v20 i64)
+        (local $v24 i64)
+        (local $v25 i64)
+        (local $v26 i64)
+        (local $v27 i64)
+        (local $v29 i64)
+        (local $v30 i64)
+        (local $v31 i64)
+        local.get $p0
+        local.set $v1
+        i64.const 0
+        local.set $v2
+        local.get $v2
+        local.set $v3
+        i64.const 0
+        local.set $v4
+        local.get $v4
+        local.set $v5
+        loop $L7
+          local.get $v5
+          local.set $v8
+          i32.const 1
+          local.set $v9
+          local.get $v1
+          call $lilac_len
+          local.set $v14
+          local.get $v8
+          local.get $v14
+          i64.eq
+          local.set $v15
+          local.get $v15
+          if
+            local.get $v3
+            local.set $v20
+            local.get $v20
+            return
+          else
+            local.get $v3
+            local.set $v24
+            local.get $v5
+            local.set $v25
+            local.get $v1
+            local.get $v25
+            i32.const 8
+            i32.const 26
+            call $lilac_index
+            i64.load
+            local.set $v26
+            local.get $v24
+            local.get $v26
+            i64.add
+            local.set $v27
+            local.get $v27
+            local.set $v3
+            local.get $v5
+            local.set $v29
+            i64.const 1
+            local.set $v30
+            local.get $v29
+            local.get $v30
+            i64.add
+            local.set $v31
+            local.get $v31
+            local.set $v5
+            br $L7
+          end
+        end
+        unreachable)
+  "#]].assert_eq(&emit("
+    fun sum(a) {
+      var s = 0
+      var i = 0
+      loop {
+        if i == len(a) { return s }
+        s = s + a[i]
+        i = i + 1
+      }
+    }
+  ", "sum", "sum"));
+}
+
+#[test]
+fn test_merge() {
+  expect![[r#"
+      ;; fib
+      (func $f0 (export "fib") (type $t13) (param $p0 i64) (result i64)
+        (local $v1 i64)
+        (local $v2 i64)
+        (local $v3 i32)
+        (local $v11 i64)
+        (local $v12 i64)
+        (local $v13 i32)
+        (local $v18 i64)
+        (local $v19 i64)
+        (local $v20 i64)
+        (local $v21 i32)
+        (local $v26 i64)
+        (local $v27 i64)
+        local.get $p0
+        local.set $v1
+        i64.const 1
+        local.set $v2
+        local.get $v1
+        local.get $v2
+        i64.le_s
+        local.set $v3
+        local.get $v3
+        if
+          local.get $v1
+          return
+        else
+          i64.const 1
+          local.set $v11
+          local.get $v1
+          local.get $v11
+          i64.sub
+          local.set $v12
+          i32.const 0
+          local.set $v13
+          local.get $v12
+          call $f0
+          local.set $v18
+          i64.const 2
+          local.set $v19
+          local.get $v1
+          local.get $v19
+          i64.sub
+          local.set $v20
+          i32.const 0
+          local.set $v21
+          local.get $v20
+          call $f0
+          local.set $v26
+          local.get $v18
+          local.get $v26
+          i64.add
+          local.set $v27
+          local.get $v27
+          return
+        end
+        unreachable)
+  "#]].assert_eq(&emit(FIB_REC, "fib", "fib"));
+}
+
+#[test]
+fn test_tail_call() {
+  expect![[r#"
+      ;; fib_iter
+      (func $f1 (export "fib_iter") (type $t16) (param $p0 i64) (param $p1 i64) (param $p2 i64) (result i64)
+        (local $v10 i64)
+        (local $v11 i64)
+        (local $v12 i64)
+        (local $v13 i64)
+        (local $v14 i32)
+        (local $v22 i64)
+        (local $v23 i64)
+        (local $v24 i64)
+        (local $v25 i32)
+        local.get $p0
+        local.get $p1
+        local.get $p2
+        local.set $v12
+        local.set $v11
+        local.set $v10
+        i64.const 0
+        local.set $v13
+        local.get $v12
+        local.get $v13
+        i64.eq
+        local.set $v14
+        local.get $v14
+        if
+          local.get $v11
+          return
+        else
+          local.get $v10
+          local.get $v11
+          i64.add
+          local.set $v22
+          i64.const 1
+          local.set $v23
+          local.get $v12
+          local.get $v23
+          i64.sub
+          local.set $v24
+          i32.const 1
+          local.set $v25
+          local.get $v11
+          local.get $v22
+          local.get $v24
+          return_call $f1
+        end
+        unreachable)
+  "#]].assert_eq(&emit(FIB_TAIL, "fib", "fib_iter"));
+}
+
+#[test]
+fn test_call_indirect() {
+  expect![[r#"
+      ;; apply
+      (func $f2 (export "apply") (type $t22) (param $p0 i32) (param $p1 i64) (result i64)
+        (local $v14 i32)
+        (local $v15 i64)
+        local.get $p0
+        local.get $p1
+        local.set $v15
+        local.set $v14
+        local.get $v15
+        local.get $v14
+        return_call_indirect (type $t19)
+        unreachable)
+  "#]].assert_eq(&emit("
+    fun apply(f, x) {
+      return f(x)
+    }
+    fun inc(x) {
+      return x + 1
+    }
+    fun main(x) {
+      return apply(inc, x)
+    }
+  ", "main", "apply"));
+}
+
+#[test]
+fn test_union_find() {
+  expect![[r#"
+      ;; find
+      (func $f1 (export "find") (type $t16) (param $p0 i32) (param $p1 i64) (result i64)
+        (local $v50 i32)
+        (local $v51 i64)
+        (local $v52 i64)
+        (local $v55 i64)
+        (local $v56 i64)
+        (local $v57 i64)
+        (local $v58 i32)
+        (local $v63 i64)
+        (local $v67 i64)
+        (local $v68 i64)
+        local.get $p0
+        local.get $p1
+        local.set $v51
+        local.set $v50
+        local.get $v51
+        local.set $v52
+        loop $L54
+          local.get $v52
+          local.set $v55
+          local.get $v50
+          local.get $v55
+          i32.const 8
+          i32.const 56
+          call $lilac_index
+          i64.load
+          local.set $v56
+          local.get $v52
+          local.set $v57
+          local.get $v56
+          local.get $v57
+          i64.eq
+          local.set $v58
+          local.get $v58
+          if
+            local.get $v52
+            local.set $v63
+            local.get $v63
+            return
+          else
+            local.get $v52
+            local.set $v67
+            local.get $v50
+            local.get $v56
+            i32.const 8
+            i32.const 68
+            call $lilac_index
+            i64.load
+            local.set $v68
+            local.get $v50
+            local.get $v67
+            i32.const 8
+            i32.const 69
+            call $lilac_index
+            local.get $v68
+            i64.store
+            local.get $v56
+            local.set $v52
+            br $L54
+          end
+        end
+        unreachable)
+  "#]].assert_eq(&emit(UNION_FIND, "count", "find"));
+}
+
+#[test]
+fn test_block() {
+  expect![[r#"
+      ;; count
+      (func $f0 (export "count") (type $t25) (param $p0 i32) (result i64)
+        (local $v1 i32)
+        (local $v2 i64)
+        (local $v3 i64)
+        (local $v4 i64)
+        (local $v5 i64)
+        (local $v8 i64)
+        (local $v9 i32)
+        (local $v14 i64)
+        (local $v15 i32)
+        (local $v20 i64)
+        (local $v24 i64)
+        (local $v25 i32)
+        (local $v31 i64)
+        (local $v32 i64)
+        (local $v33 i32)
+        (local $v38 i64)
+        (local $v39 i64)
+        (local $v40 i64)
+        (local $v44 i64)
+        (local $v45 i64)
+        (local $v46 i64)
+        local.get $p0
+        local.set $v1
+        i64.const 0
+        local.set $v2
+        local.get $v2
+        local.set $v3
+        i64.const 0
+        local.set $v4
+        local.get $v4
+        local.set $v5
+        loop $L7
+          local.get $v5
+          local.set $v8
+          i32.const 2
+          local.set $v9
+          local.get $v1
+          call $lilac_len
+          local.set $v14
+          local.get $v8
+          local.get $v14
+          i64.eq
+          local.set $v15
+          local.get $v15
+          if
+            local.get $v3
+            local.set $v20
+            local.get $v20
+            return
+          else
+            local.get $v5
+            local.set $v24
+            i32.const 1
+            local.set $v25
+            local.get $v1
+            local.get $v24
+            call $f1
+            local.set $v31
+            block $B43
+              local.get $v5
+              local.set $v32
+              local.get $v31
+              local.get $v32
+              i64.eq
+              local.set $v33
+              local.get $v33
+              if
+                local.get $v3
+                local.set $v38
+                i64.const 1
+                local.set $v39
+                local.get $v38
+                local.get $v39
+                i64.add
+                local.set $v40
+                local.get $v40
+                local.set $v3
+                br $B43
+              else
+                br $B43
+              end
+            end
+            local.get $v5
+            local.set $v44
+            i64.const 1
+            local.set $v45
+            local.get $v44
+            local.get $v45
+            i64.add
+            local.set $v46
+            local.get $v46
+            local.set $v5
+            br $L7
+          end
+        end
+        unreachable)
+  "#]].assert_eq(&emit(UNION_FIND, "count", "count"));
+}
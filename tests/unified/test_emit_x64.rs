@@ -0,0 +1,319 @@
+use crate::test_eval_iru::FIB_LOOP;
+use crate::test_eval_iru::FIB_REC;
+use crate::test_eval_iru::FIB_TAIL;
+use crate::test_eval_iru::TAK;
+use crate::test_eval_iru::UNION_FIND;
+use crate::test_heap::TABLE;
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+
+// Runs `name` compiled to native code, and checks that it agrees with the
+// interpreter.
+
+fn agree(source: &str, name: &str, args: &[Value]) -> String {
+  let x = util::run_irp(source, name, args.iter().cloned());
+  let y = util::run_x64(source, name, args);
+  let z = util::run_x64_stress(source, name, args);
+  assert_eq!(x, y);
+  assert_eq!(x, z);
+  return y;
+}
+
+#[test]
+fn test_emit() {
+  let module = util::mono("
+    fun sum(a) {
+      var s = 0
+      var i = 0
+      loop {
+        if i == len(a) { return s }
+        s = s + a[i]
+        i = i + 1
+      }
+    }
+  ", "sum");
+
+  let out = lilac::emit_x64::emit(&module).unwrap();
+  let out = &out[out.find("# sum").unwrap() ..];
+
+  expect![[r##"
+      # sum
+        .globl lilac_f0
+      lilac_f0:
+        pushq %rbp
+        movq %rsp, %rbp
+        subq $32, %rsp
+        movq $0, -8(%rbp)
+        movq %rdi, -8(%rbp)
+        movq $0, %rbx
+        movq %rbx, -16(%rbp)
+        movq $0, %rbx
+        movq %rbx, -24(%rbp)
+      .L7:
+        movq -24(%rbp), %rax
+        movq %rax, -32(%rbp)
+        leaq lilac_len(%rip), %rax
+        movq %rax, %rbx
+        movq -8(%rbp), %rdi
+        movq %rbx, %rax
+        movl $11, %edx
+        call *%rax
+      .Lret11:
+        movq %rdi, %rbx
+      .L13:
+        movq -32(%rbp), %rax
+        cmpq %rbx, %rax
+        sete %al
+        movzbq %al, %rax
+        movq %rax, %rbx
+        cmpq $0, %rbx
+        jne .L19
+        jmp .L23
+      .L19:
+        movq -16(%rbp), %rbx
+        movq %rbx, %rdi
+        leave
+        ret
+      .L23:
+        movq -16(%rbp), %rbx
+        movq -24(%rbp), %rsi
+        movq -8(%rbp), %rax
+        movq %rsi, %rcx
+        movl (%rax), %edx
+        cmpq %rdx, %rcx
+        jae .Ltrap1_26
+        movq 8(%rax,%rcx,8), %rax
+        movq %rax, %rsi
+        movq %rbx, %rax
+        addq %rsi, %rax
+        movq %rax, %rsi
+        movq %rsi, -16(%rbp)
+        movq -24(%rbp), %rsi
+        movq $1, %rbx
+        movq %rsi, %rax
+        addq %rbx, %rax
+        movq %rax, %rbx
+        movq %rbx, -24(%rbp)
+        jmp .L7
+      .Ltrap1_26:
+        movl $26, %esi
+        movl $1, %edi
+        call lilac_trap
+
+        .data
+        .globl lilac_stack_maps
+      lilac_stack_maps:
+        .quad .Lret11, 11, 1, -8
+        .quad 0
+
+        .section .note.GNU-stack,"",@progbits
+  "##]].assert_eq(out);
+}
+
+#[test]
+fn test_fib() {
+  let out =
+    [FIB_LOOP, FIB_REC, FIB_TAIL].iter().map(|source| {
+      agree(source, "fib", &[Value::Int(30)])
+    }).collect::<Vec<_>>().join(" ");
+
+  expect!["832040 832040 832040"].assert_eq(&out);
+}
+
+#[test]
+fn test_deep_tail_call() {
+  let out = agree(FIB_TAIL, "fib", &[Value::Int(1_000_000)]);
+
+  expect!["-4249520595888827205"].assert_eq(&out);
+}
+
+#[test]
+fn test_tak() {
+  let out = agree(TAK, "tak", &[Value::Int(18), Value::Int(12), Value::Int(6)]);
+
+  expect!["7"].assert_eq(&out);
+}
+
+#[test]
+fn test_union_find() {
+  let source = format!("{}{}", UNION_FIND, "
+    fun main(parent) {
+      let a = union(parent, 0, 1)
+      let b = union(parent, 2, 3)
+      let c = union(parent, 1, 3)
+      let d = union(parent, 0, 2)
+      return a, b, c, d, count(parent), parent
+    }
+  ");
+
+  // NB: the interpreter updates the array in place, so each run gets a copy.
+
+  let parent = || Value::array((0 .. 8).map(Value::Int));
+  let out = util::run_x64(&source, "main", &[parent()]);
+  assert_eq!(out, util::run_irp(&source, "main", [parent()]));
+
+  expect!["true, true, true, false, 5, [0, 0, 0, 0, 4, 5, 6, 7]"].assert_eq(&out);
+}
+
+#[test]
+fn test_arith() {
+  let source = "
+    fun arith(x, y) {
+      return x + y, x - y, x * y, x /% y, x % y, x <<% y, x >>% y, - x
+    }
+    fun compare(x, y) {
+      return x < y, x <= y, x == y, x != y, x >= y, x > y, ! (x < y)
+    }
+  ";
+
+  let out =
+    [(7, 2), (-7, 2), (i64::MIN, -1), (i64::MAX, 1), (1, 65), (3, 3)].iter().map(|&(x, y)| {
+      let args = [Value::Int(x), Value::Int(y)];
+      format!("{} / {}", agree(source, "arith", &args), agree(source, "compare", &args))
+    }).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      9, 5, 14, 3, 1, 28, 1, -7 / false, false, false, true, true, true, true
+      -5, -9, -14, -3, -1, -28, -2, 7 / true, true, false, true, false, false, false
+      9223372036854775807, -9223372036854775807, -9223372036854775808, -9223372036854775808, 0, 0, -1, -9223372036854775808 / true, true, false, true, false, false, false
+      -9223372036854775808, 9223372036854775806, 9223372036854775807, 9223372036854775807, 0, -2, 4611686018427387903, -9223372036854775807 / false, false, false, true, true, true, true
+      66, -64, 65, 0, 1, 2, 0, -1 / true, true, false, true, false, false, false
+      6, 0, 9, 1, 0, 24, 0, -3 / false, true, true, false, true, false, true"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_register_pressure() {
+  // NB: more values are live at once than there are registers.
+
+  let source = "
+    fun f(a, b, c, d, e, f, g, h) {
+      let i = a + b
+      let j = c + d
+      let k = e + f
+      let l = g + h
+      let m = a * b
+      let n = c * d
+      let o = e * f
+      let p = g * h
+      let q = i + j + k + l + m + n + o + p
+      return q, a, b, c, d, e, f, h
+    }
+  ";
+
+  let args: Vec<Value> = (1 .. 9).map(Value::Int).collect();
+  let out = agree(source, "f", &args);
+
+  expect!["136, 1, 2, 3, 4, 5, 6, 8"].assert_eq(&out);
+}
+
+#[test]
+fn test_too_many_values() {
+  let module = util::mono("fun f(x) { return x, x, x, x, x, x, x, x, x + 1 }", "f");
+
+  let out = lilac::emit_x64::emit(&module).err().unwrap().to_string();
+
+  expect!["too many values at %13"].assert_eq(&out);
+}
+
+#[test]
+fn test_runtime_error() {
+  let out =
+    [
+      agree("fun f(a, i) { a[i] + 0 }", "f", &[Value::array([Value::Int(1)]), Value::Int(1)]),
+      agree("fun f(x, y) { x / y }", "f", &[Value::Int(1), Value::Int(0)]),
+    ].join("\n");
+
+  expect![[r#"
+      error: index out of bounds at %3
+      error: division by zero at %3"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_collect() {
+  // NB: the garbage is several times the initial size of the heap, and every
+  // allocation collects in the stress run.
+
+  let out = agree(TABLE, "main", &[Value::Int(40)]);
+
+  expect!["21320, [39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39], [0]"].assert_eq(&out);
+}
+
+#[test]
+fn test_collect_host_arrays() {
+  let source = "
+    fun f(a, n) {
+      let b = array(n, a[0])
+      let c = array(2, b)
+      return c, a, len(b) + a[0][0] * 0
+    }
+  ";
+
+  let a = Value::array([Value::array([Value::Int(1), Value::Int(2)]), Value::array([])]);
+  let out = agree(source, "f", &[a, Value::Int(3)]);
+
+  expect!["[[[1, 2], [1, 2], [1, 2]], [[1, 2], [1, 2], [1, 2]]], [[1, 2], []], 3"].assert_eq(&out);
+}
+
+#[test]
+fn test_negative_length() {
+  let out = agree("fun f(n) { return array(n, true) }", "f", &[Value::Int(-1)]);
+
+  expect!["error: negative length at %6"].assert_eq(&out);
+}
+
+#[test]
+fn test_too_long() {
+  let out = agree("fun f(n) { return array(n, true) }", "f", &[Value::Int(5000000000)]);
+
+  expect!["error: out of memory at %6"].assert_eq(&out);
+}
+
+#[test]
+fn test_bool_array() {
+  let sieve = "
+    fun sieve(n) {
+      let p = array(n, true)
+      p[0] = false
+      p[1] = false
+      var i = 2
+      while i < n {
+        if p[i] {
+          var j = i * i
+          while j < n {
+            p[j] = false
+            j = j + i
+          }
+        }
+        i = i + 1
+      }
+      return p
+    }
+  ";
+
+  // NB: the interpreter updates host arrays in place, so this one is only read.
+
+  let count = "
+    fun count(a) {
+      var n = 0
+      var i = 0
+      while i < len(a) {
+        if a[i] { n = n + 1 }
+        i = i + 1
+      }
+      return a, n, a[9]
+    }
+  ";
+
+  let a = (0 .. 10).map(|i| Value::Bool(i % 3 == 0));
+
+  let out =
+    [
+      agree(sieve, "sieve", &[Value::Int(12)]),
+      agree(count, "count", &[Value::array(a)]),
+    ].join("\n");
+
+  expect![[r#"
+      [false, false, true, true, false, true, false, true, false, false, false, true]
+      [true, false, false, true, false, false, true, false, false, true], 4, true"#]].assert_eq(&out);
+}
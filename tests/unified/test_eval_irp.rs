@@ -0,0 +1,88 @@
+use crate::test_eval_iru::FIB_LOOP;
+use crate::test_eval_iru::FIB_REC;
+use crate::test_eval_iru::FIB_TAIL;
+use crate::test_eval_iru::TAK;
+use crate::test_eval_iru::UNION_FIND;
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+
+// Runs `name` with both interpreters, checks that they agree, and returns the
+// result.
+
+fn agree(source: &str, name: &str, args: &[Value]) -> String {
+  let x = util::run(source, name, args.iter().cloned());
+  let y = util::run_irp(source, name, args.iter().cloned());
+  assert_eq!(x, y);
+  return y;
+}
+
+#[test]
+fn test_fib() {
+  let out =
+    [FIB_LOOP, FIB_REC, FIB_TAIL].iter().map(|source| {
+      (0 .. 10).map(|n| agree(source, "fib", &[Value::Int(n)])).collect::<Vec<_>>().join(" ")
+    }).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      0 1 1 2 3 5 8 13 21 34
+      0 1 1 2 3 5 8 13 21 34
+      0 1 1 2 3 5 8 13 21 34"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_fib_deep_tail_call() {
+  let out = agree(FIB_TAIL, "fib", &[Value::Int(1_000_000)]);
+
+  expect!["-4249520595888827205"].assert_eq(&out);
+}
+
+#[test]
+fn test_tak() {
+  let out = agree(TAK, "tak", &[Value::Int(18), Value::Int(12), Value::Int(6)]);
+
+  expect!["7"].assert_eq(&out);
+}
+
+#[test]
+fn test_union_find() {
+  let parent = Value::array((0 .. 8).map(Value::Int));
+  let mut out = String::new();
+
+  for (x, y) in [(0, 1), (2, 3), (1, 3), (0, 2), (4, 5), (6, 7), (7, 6)] {
+    let r = util::run_irp(UNION_FIND, "union", [parent.clone(), Value::Int(x), Value::Int(y)]);
+    let n = agree(UNION_FIND, "count", &[parent.clone()]);
+    out.push_str(&format!("union({}, {}) = {}, count = {}, parent = {}\n", x, y, r, n, parent));
+  }
+
+  expect![[r#"
+      union(0, 1) = true, count = 7, parent = [0, 0, 2, 3, 4, 5, 6, 7]
+      union(2, 3) = true, count = 6, parent = [0, 0, 2, 2, 4, 5, 6, 7]
+      union(1, 3) = true, count = 5, parent = [0, 0, 0, 0, 4, 5, 6, 7]
+      union(0, 2) = false, count = 5, parent = [0, 0, 0, 0, 4, 5, 6, 7]
+      union(4, 5) = true, count = 4, parent = [0, 0, 0, 0, 4, 4, 6, 7]
+      union(6, 7) = true, count = 3, parent = [0, 0, 0, 0, 4, 4, 6, 6]
+      union(7, 6) = false, count = 3, parent = [0, 0, 0, 0, 4, 4, 6, 6]
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_runtime_error() {
+  let out =
+    [
+      agree("fun f(a, i) { a[i] + 0 }", "f", &[Value::array([Value::Int(1)]), Value::Int(1)]),
+      agree("fun f(a, i) { a[i] + 0 }", "f", &[Value::array([Value::Int(1)]), Value::Int(-1)]),
+      agree("fun f(x, y) { x / y }", "f", &[Value::Int(1), Value::Int(0)]),
+      agree("fun f(x, y) { x % y }", "f", &[Value::Int(1), Value::Int(0)]),
+      util::run_irp("fun f(x) { x + 1 }", "f", [Value::Bool(true)]),
+      util::run_irp("fun f(x) { x + 1 }", "f", []),
+    ].join("\n");
+
+  expect![[r#"
+      error: index out of bounds at %3
+      error: index out of bounds at %3
+      error: division by zero at %3
+      error: division by zero at %3
+      error: argument mismatch at position 0
+      error: argument mismatch at position 0"#]].assert_eq(&out);
+}
@@ -0,0 +1,155 @@
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+
+pub(crate) const FIB_LOOP: &str = "
+  fun fib(n) {
+    var a = 1
+    var b = 0
+    var n = n
+    loop {
+      if n == 0 { return b }
+      let c = a + b
+      a = b
+      b = c
+      n = n - 1
+    }
+  }
+";
+
+pub(crate) const FIB_REC: &str = "
+  fun fib(n) {
+    if n <= 1 { return n }
+    return fib(n - 1) + fib(n - 2)
+  }
+";
+
+pub(crate) const FIB_TAIL: &str = "
+  fun fib_iter(a, b, n) {
+    if n == 0 { return b }
+    return fib_iter(b, a + b, n - 1)
+  }
+  fun fib(n) {
+    return fib_iter(1, 0, n)
+  }
+";
+
+pub(crate) const TAK: &str = "
+  fun tak(x, y, z) {
+    if y < x {
+      return tak(
+        tak(x - 1, y, z),
+        tak(y - 1, z, x),
+        tak(z - 1, x, y)
+      )
+    } else {
+      return z
+    }
+  }
+";
+
+pub(crate) const UNION_FIND: &str = "
+  fun find(parent, x) {
+    var x = x
+    loop {
+      let p = parent[x]
+      if p == x { return x }
+      parent[x] = parent[p]
+      x = p
+    }
+  }
+  fun union(parent, x, y) {
+    let x = find(parent, x)
+    let y = find(parent, y)
+    if x == y { return false }
+    parent[y] = x
+    return true
+  }
+  fun count(parent) {
+    var n = 0
+    var i = 0
+    loop {
+      if i == len(parent) { return n }
+      if find(parent, i) == i { n = n + 1 }
+      i = i + 1
+    }
+  }
+";
+
+#[test]
+fn test_fib() {
+  let out =
+    [FIB_LOOP, FIB_REC, FIB_TAIL].iter().map(|source| {
+      (0 .. 10).map(|n| util::run(source, "fib", [Value::Int(n)])).collect::<Vec<_>>().join(" ")
+    }).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      0 1 1 2 3 5 8 13 21 34
+      0 1 1 2 3 5 8 13 21 34
+      0 1 1 2 3 5 8 13 21 34"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_fib_deep_tail_call() {
+  // NB: wraps around, but runs in constant space.
+  let out = util::run(FIB_TAIL, "fib", [Value::Int(1_000_000)]);
+
+  expect!["-4249520595888827205"].assert_eq(&out);
+}
+
+#[test]
+fn test_tak() {
+  let out = util::run(TAK, "tak", [Value::Int(18), Value::Int(12), Value::Int(6)]);
+
+  expect!["7"].assert_eq(&out);
+}
+
+#[test]
+fn test_union_find() {
+  let mut store = oxcart::Store::new();
+
+  let module = lilac::parse::parse(UNION_FIND.as_bytes(), store.arena()).0;
+  let module = lilac::make_iru::compile(&module);
+  let parent = Value::array((0 .. 8).map(Value::Int));
+  let mut out = String::new();
+
+  for (x, y) in [(0, 1), (2, 3), (1, 3), (0, 2), (4, 5), (6, 7), (7, 6)] {
+    let sym = lilac::symbol::Symbol::from_str;
+    let r = lilac::eval_iru::run(&module, sym("union"), [parent.clone(), Value::Int(x), Value::Int(y)]).unwrap();
+    let n = lilac::eval_iru::run(&module, sym("count"), [parent.clone()]).unwrap();
+    out.push_str(&format!("union({}, {}) = {}, count = {}, parent = {}\n", x, y, r[0], n[0], parent));
+  }
+
+  expect![[r#"
+      union(0, 1) = true, count = 7, parent = [0, 0, 2, 3, 4, 5, 6, 7]
+      union(2, 3) = true, count = 6, parent = [0, 0, 2, 2, 4, 5, 6, 7]
+      union(1, 3) = true, count = 5, parent = [0, 0, 0, 0, 4, 5, 6, 7]
+      union(0, 2) = false, count = 5, parent = [0, 0, 0, 0, 4, 5, 6, 7]
+      union(4, 5) = true, count = 4, parent = [0, 0, 0, 0, 4, 4, 6, 7]
+      union(6, 7) = true, count = 3, parent = [0, 0, 0, 0, 4, 4, 6, 6]
+      union(7, 6) = false, count = 3, parent = [0, 0, 0, 0, 4, 4, 6, 6]
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_runtime_error() {
+  let out =
+    [
+      util::run("fun f(a, i) { a[i] }", "f", [Value::array([Value::Int(1)]), Value::Int(1)]),
+      util::run("fun f(x, y) { x / y }", "f", [Value::Int(1), Value::Int(0)]),
+      util::run("fun f(x) { x + true }", "f", [Value::Int(1)]),
+      util::run("fun f(x) { break }", "f", [Value::Int(1)]),
+      util::run("fun f(x) { g(x) }", "f", [Value::Int(1)]),
+      util::run("fun f(x) { x }", "g", [Value::Int(1)]),
+      util::run("fun f(x) { x }", "f", []),
+    ].join("\n");
+
+  expect![[r#"
+      error: index out of bounds at %3
+      error: division by zero at %3
+      error: type error at %3
+      error: static error at %2
+      error: unbound variable g
+      error: unbound variable g
+      error: arity mismatch at %0"#]].assert_eq(&out);
+}
@@ -0,0 +1,370 @@
+use expect_test::expect;
+use lilac::format::format;
+
+fn parse_tree(source: &str) -> String {
+  let mut store = oxcart::Store::new();
+  let (items, errors, _) = lilac::parse::parse_with_errors(source.as_bytes(), store.arena());
+  assert!(errors.len() == 0, "syntax error in:\n{}", source);
+  let mut out = String::new();
+  for item in items.iter() {
+    out.push_str(&format!("{}\n", item));
+  }
+  return out;
+}
+
+fn formatted(source: &str) -> String {
+  match format(source) {
+    Ok(text) => text,
+    Err(e) => format!("error: {} at byte {}\n", e, e.pos()),
+  }
+}
+
+#[test]
+fn test_layout() {
+  let out = formatted("
+    fun add(a,b) { return a+b }
+    fun main(n) { var s=0
+      var i = 0
+      while i<n { s = s+add(i,1)
+        i++ }
+      let _, t = f(s), a[0].x
+      if s>3 { return s } else { return 0 } }
+    fun nop() { }
+  ");
+
+  expect![[r#"
+      fun add(a, b) {
+        return a + b
+      }
+
+      fun main(n) {
+        var s = 0
+        var i = 0
+        while i < n {
+          s = s + add(i, 1)
+          i++
+        }
+        let _, t = f(s), a[0].x
+        if s > 3 {
+          return s
+        } else {
+          return 0
+        }
+      }
+
+      fun nop() {}
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_comments() {
+  let out = formatted("
+    # A header comment.
+
+    fun f(x) { # trailing the open brace
+      # on its own line
+      let a = x +   # trailing in an expression
+        1
+
+
+      # after blank lines
+      return a   # trailing a statement
+      # before the close brace
+    }
+    fun g() {
+      # only a comment
+    }
+    # at the end
+  ");
+
+  expect![[r#"
+      # A header comment.
+
+      fun f(x) { # trailing the open brace
+        # on its own line
+        let a = x + 1 # trailing in an expression
+
+        # after blank lines
+        return a # trailing a statement
+        # before the close brace
+      }
+
+      fun g() {
+        # only a comment
+      }
+      # at the end
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_parens() {
+  let out = formatted("
+    fun f() {
+      f(((a + b)) * c, a - (b - c), (a - b) - c, a * (b + c) < d)
+      f((a || b) && c, a || (b && c), -(a + b), !(!a), -(1), (-1))
+      f((p ? a : b) ? c : d, p ? (q ? a : b) : (q ? c : d), (p || q) ? a : b)
+      f((-a)[0], (f)(x).y, (x++).y, -f(x), (if p { 1 } else { 2 }) + 1)
+      f(a & b | c ^ d, a << (b + c), (a == b) == c, a - -b)
+      a
+      (-b)
+      (++b)
+    }
+  ");
+
+  expect![[r#"
+      fun f() {
+        f((a + b) * c, a - (b - c), a - b - c, a * (b + c) < d)
+        f((a || b) && c, a || b && c, -(a + b), !(!a), -(1), -1)
+        f((p ? a : b) ? c : d, p ? q ? a : b : q ? c : d, p || q ? a : b)
+        f((-a)[0], f(x).y, (x++).y, -f(x), (if p {
+          1
+        } else {
+          2
+        }) + 1)
+        f(a & b | c ^ d, a << b + c, a == b == c, a - -b)
+        a
+        (-b)
+        (++b)
+      }
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_errors() {
+  let out =
+    [
+      formatted("fun f( {"),
+      formatted("fun f() { return 1 +"),
+      formatted("fun f() { return 99999999999999999999 }"),
+      formatted(""),
+    ].concat();
+
+  expect![[r#"
+      error: expected Symbol at byte 7
+      error: expected expression at byte 20
+      error: number out of range at byte 17
+  "#]].assert_eq(&out);
+}
+
+// A generator of random programs, which are written with random spacing,
+// redundant parentheses, and comments.
+
+struct Gen {
+  state: u64,
+  out: String,
+  comments: u32,
+}
+
+impl Gen {
+  fn below(&mut self, n: u64) -> u64 {
+    // NB: xorshift64
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    return self.state % n;
+  }
+
+  fn pick(&mut self, words: &[&str]) {
+    let i = self.below(words.len() as u64) as usize;
+    self.out.push_str(words[i]);
+  }
+
+  fn space(&mut self) {
+    self.pick(&["", "", " ", "  "]);
+  }
+
+  fn comment(&mut self) {
+    self.out.push_str(&format!("# c{}", self.comments));
+    self.comments += 1;
+  }
+
+  fn newline(&mut self, depth: u64) {
+    if self.below(4) == 0 {
+      self.out.push(' ');
+      self.comment();
+    }
+    self.out.push('\n');
+    if self.below(6) == 0 {
+      self.out.push('\n');
+    }
+    if self.below(6) == 0 {
+      self.comment();
+      self.out.push('\n');
+    }
+    for _ in 0 .. self.below(depth * 2 + 2) {
+      self.out.push(' ');
+    }
+  }
+
+  fn atom(&mut self, depth: u64) {
+    match self.below(if depth == 0 { 3 } else { 11 }) {
+      0 => self.pick(&["a", "b", "x", "y"]),
+      1 => {
+        let n = self.below(200) as i64 - 100;
+        self.out.push_str(&n.to_string());
+      }
+      2 => self.pick(&["true", "false"]),
+      3 => {
+        self.pick(&["f", "g"]);
+        self.out.push('(');
+        self.space();
+        let n = self.below(3);
+        for i in 0 .. n {
+          if i != 0 { self.out.push_str(", "); }
+          self.expr(depth - 1);
+        }
+        self.out.push(')');
+      }
+      4 => {
+        self.pick(&["a", "b"]);
+        self.out.push('[');
+        self.expr(depth - 1);
+        self.out.push(']');
+      }
+      5 => {
+        self.pick(&["a", "b"]);
+        self.pick(&[".x", ".y"]);
+      }
+      6 => {
+        self.pick(&["-", "!"]);
+        if self.below(2) == 0 {
+          self.pick(&["a", "b"]);
+        } else {
+          self.out.push('(');
+          self.expr(depth - 1);
+          self.out.push(')');
+        }
+      }
+      7 => self.pick(&["x++", "y--", "++x", "--y"]),
+      8 => {
+        self.out.push('(');
+        self.expr(depth - 1);
+        self.out.push_str(") ? (");
+        self.expr(depth - 1);
+        self.out.push_str(") : (");
+        self.expr(depth - 1);
+        self.out.push(')');
+      }
+      _ => {
+        self.out.push('(');
+        self.space();
+        self.expr(depth - 1);
+        self.space();
+        self.out.push(')');
+      }
+    }
+  }
+
+  fn expr(&mut self, depth: u64) {
+    self.atom(depth);
+    for _ in 0 .. self.below(3) {
+      self.out.push(' ');
+      self.pick(&["+", "+|", "-", "-|", "*", "*|", "/", "/|", "/%", "%", "<<", "<<|", "<<%", ">>", ">>%", "&", "|", "^", "==", "!=", "<", "<=", ">", ">=", "&&", "||"]);
+      self.out.push(' ');
+      self.atom(depth);
+    }
+  }
+
+  fn block(&mut self, depth: u64) {
+    self.out.push('{');
+    let n = self.below(4);
+    for _ in 0 .. n {
+      self.newline(depth + 1);
+      self.stmt(depth);
+    }
+    if self.below(3) == 0 {
+      self.newline(depth + 1);
+      match self.below(3) {
+        0 => self.out.push_str("continue"),
+        _ => {
+          self.pick(&["return", "break"]);
+          if self.below(2) == 0 {
+            self.out.push(' ');
+            self.expr(depth);
+          }
+        }
+      }
+    }
+    self.newline(depth);
+    self.out.push('}');
+  }
+
+  fn stmt(&mut self, depth: u64) {
+    match self.below(if depth == 0 { 5 } else { 9 }) {
+      0 => {
+        self.pick(&["let a, _", "let x"]);
+        self.space();
+        self.out.push_str("= ");
+        self.expr(depth);
+      }
+      1 => {
+        self.pick(&["var a =", "var b  =  "]);
+        self.out.push(' ');
+        self.expr(depth);
+      }
+      2 => {
+        self.pick(&["a = ", "b.x = ", "a[0] = "]);
+        self.expr(depth);
+      }
+      3 => {
+        self.pick(&["f(", "g("]);
+        self.expr(depth);
+        self.out.push(')');
+      }
+      4 => self.pick(&["x++", "--y"]),
+      5 => {
+        self.out.push_str("while ");
+        self.expr(depth - 1);
+        self.out.push(' ');
+        self.block(depth - 1);
+      }
+      6 => {
+        self.out.push_str("loop ");
+        self.block(depth - 1);
+      }
+      _ => {
+        self.out.push_str("if ");
+        self.expr(depth - 1);
+        self.out.push(' ');
+        self.block(depth - 1);
+        if self.below(2) == 0 {
+          self.out.push_str(" else ");
+          self.block(depth - 1);
+        }
+      }
+    }
+  }
+
+  fn program(&mut self) {
+    for i in 0 .. self.below(3) + 1 {
+      if i != 0 { self.newline(0); }
+      self.out.push_str(&format!("fun f{}(", i));
+      self.pick(&["", "a", "a, b", "_, x"]);
+      self.out.push_str(") ");
+      self.block(2);
+    }
+    self.newline(0);
+  }
+}
+
+fn comments(text: &str) -> Vec<&str> {
+  return text.split('#').skip(1).map(|s| s.split_whitespace().next().unwrap()).collect();
+}
+
+#[test]
+fn test_round_trip() {
+  let mut g = Gen { state: 0x9e37_79b9_7f4a_7c15, out: String::new(), comments: 0 };
+
+  for _ in 0 .. 1000 {
+    g.out.clear();
+    g.comments = 0;
+    g.program();
+
+    let source = &g.out;
+    let text = format(source).unwrap_or_else(|e| panic!("{} at byte {} in:\n{}", e, e.pos(), source));
+
+    assert_eq!(parse_tree(&text), parse_tree(source), "source:\n{}\nformatted:\n{}", source, text);
+    assert_eq!(format(&text).unwrap(), text, "not idempotent:\n{}", source);
+    assert_eq!(comments(&text), comments(source), "source:\n{}\nformatted:\n{}", source, text);
+  }
+}
@@ -0,0 +1,138 @@
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+use lilac::heap::Heap;
+
+#[test]
+fn test_collect() {
+  let mut heap = Heap::with_capacity(64);
+
+  // a -> [b, b], b -> [42], and a cycle c -> [c] that is garbage
+
+  let b = heap.alloc_uncollected(1, 0).unwrap();
+  heap.set(b, 0, 42);
+  let a = heap.alloc_uncollected(2, 2).unwrap();
+  heap.set(a, 0, b);
+  heap.set(a, 1, b);
+  let c = heap.alloc_uncollected(1, 1).unwrap();
+  heap.set(c, 0, c);
+  let _ = heap.alloc_uncollected(100, 0).unwrap();
+
+  let mut roots = [a, 0];
+  heap.collect(&mut roots[..]);
+
+  let a = roots[0];
+  let b = heap.get(a, 0);
+
+  let out = format!(
+    "used {}, collections {}, shared {}, value {}, null root {}",
+    heap.used(),
+    heap.collections(),
+    heap.get(a, 1) == b,
+    heap.get(b, 0),
+    roots[1],
+  );
+
+  expect!["used 5, collections 1, shared true, value 42, null root 0"].assert_eq(&out);
+}
+
+#[test]
+fn test_alloc_collects() {
+  let mut heap = Heap::with_capacity(64);
+  let mut roots = [0];
+
+  for i in 0 .. 1000 {
+    let x = heap.alloc(4, 1, &mut roots[..]).unwrap();
+    heap.set(x, 0, roots[0]);
+    heap.set(x, 1, i);
+    roots[0] = x;
+    if heap.used() > 200 { roots[0] = 0; }
+  }
+
+  assert!(heap.collections() > 0);
+  assert!(heap.used() <= 256);
+}
+
+#[test]
+fn test_alloc_too_large() {
+  let mut heap = Heap::with_capacity(64);
+  let mut roots = [0];
+  let _ = heap.alloc_uncollected(100, 0).unwrap();
+
+  let out = format!(
+    "{:?} {:?}",
+    heap.alloc(u32::MAX, 0, &mut roots[..]),
+    heap.alloc_uncollected(u32::MAX - 50, 0),
+  );
+
+  expect!["None None"].assert_eq(&out);
+}
+
+pub(crate) static TABLE: &str = "
+  fun table(n) {
+    let t = array(n, array(0, 0))
+    var i = 0
+    while i < n {
+      var j = 0
+      while j < 100 {
+        let g = array(50, j)
+        j = j + 1
+      }
+      t[i] = array(i + 1, i)
+      i = i + 1
+    }
+    return t
+  }
+  fun main(n) {
+    let t = table(n)
+    var s = 0
+    var i = 0
+    while i < len(t) {
+      let row = t[i]
+      var j = 0
+      while j < len(row) {
+        s = s + row[j]
+        j = j + 1
+      }
+      i = i + 1
+    }
+    return s, t[n - 1], t[0]
+  }
+";
+
+#[test]
+fn test_interpreters() {
+  // NB: the garbage is several times the initial size of the heap.
+
+  let x = util::run(TABLE, "main", [Value::Int(40)]);
+  let y = util::run_irp(TABLE, "main", [Value::Int(40)]);
+  assert_eq!(x, y);
+
+  expect!["21320, [39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39, 39], [0]"].assert_eq(&y);
+}
+
+#[test]
+fn test_negative_length() {
+  let source = "fun f(n) { return array(n, true) }";
+  let x = util::run(source, "f", [Value::Int(-1)]);
+  let y = util::run_irp(source, "f", [Value::Int(-1)]);
+  assert_eq!(x, y);
+
+  expect!["error: negative length at %6"].assert_eq(&y);
+}
+
+#[test]
+fn test_too_long() {
+  let source = "fun f(n) { return array(n, 0) }";
+  let out =
+    [(1 << 24) + 1, 5000000000].iter().map(|&n| {
+      let x = util::run(source, "f", [Value::Int(n)]);
+      let y = util::run_irp(source, "f", [Value::Int(n)]);
+      assert_eq!(x, y);
+      y
+    }).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      error: out of memory at %6
+      error: out of memory at %6"#]].assert_eq(&out);
+}
@@ -40,3 +40,19 @@ fn test_loop() {
       %19 RET
   "#]].assert_eq(out.drain(..).as_ref());
 }
+
+#[test]
+fn test_parse() {
+  // A postfix operator makes a single expression out of its variable.
+
+  let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(b"fun f(x) { x++\n let a = x++\n g(x++, 1)\n return x++, a }", store.arena()).0;
+  let out = items.iter().map(|x| x.to_string()).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      (fun f (x)
+        (do (post++ x))
+        (let (a) (post++ x))
+        (do (call g (post++ x) 1))
+        (return (post++ x) a))"#]].assert_eq(&out);
+}
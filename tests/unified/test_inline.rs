@@ -0,0 +1,229 @@
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+use lilac::inline;
+use lilac::irp::Inst;
+use lilac::irp::Module;
+use lilac::opt;
+use lilac::opt::Pass;
+use lilac::symbol::Symbol;
+
+// Shows the entry point `name` after inlining, and cleaning up after it.
+
+fn inline(source: &str, name: &str) -> String {
+  // NB: the entry point is the first function, and the others are kept.
+
+  let module = opt::optimize(inline::inline(util::mono(source, name)), &Pass::ALL);
+  return format!("=== fun{}", module.to_string().split("=== fun").nth(1).unwrap());
+}
+
+// Counts the calls in the entry point `name` after inlining.
+
+fn calls(module: &Module) -> usize {
+  let f = &module.decl[0];
+  return (f.pos .. f.pos + f.len).filter(|&i| matches!(module.code[i], Inst::Call(_) | Inst::TailCall(_))).count();
+}
+
+// Runs `name` without and with inlining, which must agree.
+
+fn run(source: &str, name: &str, args: &[Value]) -> String {
+  let module = util::mono(source, name);
+  let name = Symbol::from_str(name);
+  let before = lilac::eval_irp::run(&module, name, args.iter().cloned()).unwrap();
+  let after = lilac::eval_irp::run(&inline::inline(module), name, args.iter().cloned()).unwrap();
+  let before: Vec<String> = before.iter().map(|x| x.to_string()).collect();
+  let after: Vec<String> = after.iter().map(|x| x.to_string()).collect();
+  assert_eq!(before, after);
+  return after.join(", ");
+}
+
+static COMBINATORS: &str = "
+  fun apply1(f, x) { f(x) }
+  fun apply2(f, x, y) { f(x, y) }
+  fun id(x) { x }
+  fun flip(x, y) { y, x }
+  fun foo(x) { apply1(id, x) }
+  fun bar(x, y) { apply2(flip, x, y) }
+  fun baz(x) { apply2(apply1, id, x) }
+";
+
+#[test]
+fn test_combinator() {
+  // NB: each round inlines a function that the previous one made known.
+
+  let source = format!("{}{}", COMBINATORS, "
+    fun f(x) { return foo(x + 1) }
+    fun g(x) { return bar(x + 1, true) }
+    fun h(x) { return baz(x + 1) }
+  ");
+
+  expect![[r#"
+      === fun f : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 1 : i64
+      %3 = add.i64 %1 %2 : i64
+      %4 PUT 0 %3
+      %5 RET
+  "#]].assert_eq(&inline(&source, "f"));
+
+  expect![[r#"
+      === fun g : Fun(i64) -> (bool, i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 1 : i64
+      %3 = add.i64 %1 %2 : i64
+      %4 = true : bool
+      %5 PUT 0 %4
+      %6 PUT 1 %3
+      %7 RET
+  "#]].assert_eq(&inline(&source, "g"));
+
+  expect![[r#"
+      === fun h : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 1 : i64
+      %3 = add.i64 %1 %2 : i64
+      %4 PUT 0 %3
+      %5 RET
+  "#]].assert_eq(&inline(&source, "h"));
+
+  expect!["8"].assert_eq(&run(&source, "h", &[Value::Int(7)]));
+}
+
+#[test]
+fn test_continuation() {
+  // The returns of a callee with several of them go to the continuation of
+  // the call.
+
+  let source = "
+    fun select(p, x, y) { p ? x : y }
+    fun relu(x) { select(x >= 0, x, 0) + 1 }
+  ";
+
+  expect![[r#"
+      === fun relu : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 0 : i64
+      %3 = cmpge.i64 %1 %2 : bool
+      %4 = 0 : i64
+      %5 COND %3
+      %6 ==> GOTO %8
+      %7 ==> GOTO %11
+      %8 LABEL 0
+      %9 PUT 0 %4
+      %10 ==> GOTO %14
+      %11 LABEL 0
+      %12 PUT 0 %1
+      %13 ==> GOTO %14
+      %14 LABEL 1
+      %15 = GET 0 : i64
+      %16 = 1 : i64
+      %17 = add.i64 %15 %16 : i64
+      %18 PUT 0 %17
+      %19 RET
+  "#]].assert_eq(&inline(source, "relu"));
+
+  expect!["6"].assert_eq(&run(source, "relu", &[Value::Int(5)]));
+  expect!["1"].assert_eq(&run(source, "relu", &[Value::Int(-5)]));
+}
+
+#[test]
+fn test_recursion() {
+  // Recursive functions are not unrolled, even through function values, but
+  // the functions that they call are inlined into them.
+
+  let source = "
+    fun dec(n) { return n - 1 }
+    fun fib(n) { if n < 2 { return n } else { return fib(dec(n)) + fib(dec(dec(n))) } }
+    fun apply(f, n) { if n < 1 { return 0 } else { return f(dec(n)) + 1 } }
+    fun count(n) { return apply(count, n) }
+  ";
+
+  expect![[r#"
+      === fun fib : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 2 : i64
+      %3 = cmplt.i64 %1 %2 : bool
+      %4 COND %3
+      %5 ==> GOTO %7
+      %6 ==> GOTO %29
+      %7 LABEL 0
+      %8 = 1 : i64
+      %9 = sub.i64 %1 %8 : i64
+      %10 = FUN #0 : Fun(i64) -> (i64)
+      %11 PUT 0 %9
+      %12 CALL %10
+      %13 ==> GOTO %14
+      %14 LABEL 1
+      %15 = GET 0 : i64
+      %16 = 1 : i64
+      %17 = sub.i64 %1 %16 : i64
+      %18 = 1 : i64
+      %19 = sub.i64 %17 %18 : i64
+      %20 = FUN #0 : Fun(i64) -> (i64)
+      %21 PUT 0 %19
+      %22 CALL %20
+      %23 ==> GOTO %24
+      %24 LABEL 1
+      %25 = GET 0 : i64
+      %26 = add.i64 %15 %25 : i64
+      %27 PUT 0 %26
+      %28 RET
+      %29 LABEL 0
+      %30 PUT 0 %1
+      %31 RET
+  "#]].assert_eq(&inline(source, "fib"));
+
+  expect![[r#"
+      === fun count : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = FUN #0 : Fun(i64) -> (i64)
+      %3 = 1 : i64
+      %4 = cmplt.i64 %1 %3 : bool
+      %5 COND %4
+      %6 ==> GOTO %8
+      %7 ==> GOTO %20
+      %8 LABEL 0
+      %9 = 1 : i64
+      %10 = sub.i64 %1 %9 : i64
+      %11 PUT 0 %10
+      %12 CALL %2
+      %13 ==> GOTO %14
+      %14 LABEL 1
+      %15 = GET 0 : i64
+      %16 = 1 : i64
+      %17 = add.i64 %15 %16 : i64
+      %18 PUT 0 %17
+      %19 RET
+      %20 LABEL 0
+      %21 = 0 : i64
+      %22 PUT 0 %21
+      %23 RET
+  "#]].assert_eq(&inline(source, "count"));
+
+  expect!["55"].assert_eq(&run(source, "fib", &[Value::Int(10)]));
+  expect!["7"].assert_eq(&run(source, "count", &[Value::Int(7)]));
+}
+
+#[test]
+fn test_budget() {
+  // A large function is inlined where it is called once, but not where it is
+  // called twice.
+
+  let body = (0 .. 20).map(|i| format!("s = s * 3 + {}\n", i)).collect::<String>();
+  let source = format!("
+    fun big(x) {{ var s = x\n{}return s }}
+    fun large(x) {{ var s = x\n{}return s }}
+    fun f(x) {{ return big(x) + large(x) + large(x + 1) }}
+  ", body, body);
+
+  let module = inline::inline(util::mono(&source, "f"));
+  assert_eq!(calls(&module), 2);
+
+  expect!["27022579077"].assert_eq(&run(&source, "f", &[Value::Int(2)]));
+}
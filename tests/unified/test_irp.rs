@@ -0,0 +1,130 @@
+use crate::util;
+use expect_test::expect;
+
+#[test]
+fn test_elaborate() {
+  let mut out = String::new();
+
+  util::dump_irp(&mut out, "
+    fun aux(a, b, n) {
+      if n == 0 {
+        b
+      } else {
+        aux(b, a + b, n - 1)
+      }
+    }
+
+    fun sum(x) {
+      var y = 0
+      var i = 0
+      while i < len(x) {
+        y = y + x[i]
+        i = i + 1
+      }
+      y
+    }
+
+    fun apply1(f, x) { f(x) }
+    fun not(x) { ! x }
+    fun foo(x) { apply1(not, x) }
+  ");
+
+  expect![[r#"
+      === fun aux : Fun(i64, i64, i64) -> (i64) ===
+      %0 LABEL 3
+      %1 = GET 0 : i64
+      %2 = GET 1 : i64
+      %3 = GET 2 : i64
+      %4 = 0 : i64
+      %5 = cmpeq.i64 %3 %4 : bool
+      %6 COND %5
+      %7 ==> GOTO %9
+      %8 ==> GOTO %18
+      %9 LABEL 0
+      %10 = add.i64 %1 %2 : i64
+      %11 = 1 : i64
+      %12 = sub.i64 %3 %11 : i64
+      %13 = CONST aux : Fun(i64, i64, i64) -> (i64)
+      %14 PUT 0 %2
+      %15 PUT 1 %10
+      %16 PUT 2 %12
+      %17 TAIL-CALL %13
+      %18 LABEL 0
+      %19 PUT 0 %2
+      %20 RET
+      === fun sum : Fun(Array[i64]) -> (i64) ===
+      %21 LABEL 1
+      %22 = GET 0 : Array[i64]
+      %23 = 0 : i64
+      %24 = LOCAL %23 : i64
+      %25 = 0 : i64
+      %26 = LOCAL %25 : i64
+      %27 ==> GOTO %28
+      %28 LABEL 0
+      %29 = [ %26 ] : i64
+      %30 = CONST len : Fun(Array[i64]) -> (i64)
+      %31 PUT 0 %22
+      %32 CALL %30
+      %33 ==> GOTO %34
+      %34 LABEL 1
+      %35 = GET 0 : i64
+      %36 = cmplt.i64 %29 %35 : bool
+      %37 COND %36
+      %38 ==> GOTO %51
+      %39 ==> GOTO %40
+      %40 LABEL 0
+      %41 = [ %24 ] : i64
+      %42 = [ %26 ] : i64
+      %43 = %22 [ %42 ] : i64
+      %44 = add.i64 %41 %43 : i64
+      %45 [ %24 ] <- %44
+      %46 = [ %26 ] : i64
+      %47 = 1 : i64
+      %48 = add.i64 %46 %47 : i64
+      %49 [ %26 ] <- %48
+      %50 ==> GOTO %28
+      %51 LABEL 0
+      %52 = [ %24 ] : i64
+      %53 PUT 0 %52
+      %54 RET
+      === fun apply1 : forall '0 '1 . Fun(Fun('0) -> '1, '0) -> '1 ===
+      %55 LABEL 2
+      %56 = GET 0 : Fun('0) -> '1
+      %57 = GET 1 : '0
+      %58 PUT 0 %57
+      %59 TAIL-CALL %56
+      === fun not : Fun(bool) -> (bool) ===
+      %60 LABEL 1
+      %61 = GET 0 : bool
+      %62 = not.bool %61 : bool
+      %63 PUT 0 %62
+      %64 RET
+      === fun foo : Fun(bool) -> (bool) ===
+      %65 LABEL 1
+      %66 = GET 0 : bool
+      %67 = CONST not : Fun(bool) -> (bool)
+      %68 = CONST apply1 : Fun(Fun(bool) -> (bool), bool) -> (bool)
+      %69 PUT 0 %67
+      %70 PUT 1 %66
+      %71 TAIL-CALL %68
+  "#]].assert_eq(out.drain(..).as_ref());
+}
+
+#[test]
+fn test_reject() {
+  let mut out = String::new();
+
+  util::dump_irp(&mut out, "fun foo() { break }");
+  util::dump_irp(&mut out, "fun foo(x) { if x { 1 } else { x + 1 } }");
+  util::dump_irp(&mut out, "fun foo(x) { bar(x) }");
+  util::dump_irp(&mut out, "fun foo(x) { x.bar }");
+  util::dump_irp(&mut out, "fun foo(x) { let y = loop { } return 1 }");
+
+  expect![[r#"
+      error: static error in foo at %1
+      error: type error in foo at %0
+      error: type error in foo at %2
+      error: unsupported instruction in foo at %2
+      error: type error in foo at %5
+  "#]].assert_eq(out.drain(..).as_ref());
+}
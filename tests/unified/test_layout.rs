@@ -0,0 +1,150 @@
+use expect_test::expect;
+use lilac::arr::Arr;
+use lilac::heap::Heap;
+use lilac::layout::Field;
+use lilac::layout::Layout;
+use lilac::layout::Shape;
+use lilac::layout::layout;
+use lilac::layout::repr;
+use lilac::typestore::TypeStore;
+
+fn record<const N: usize>(fields: [Field; N]) -> Shape {
+  return Shape::Record(Arr::from(fields));
+}
+
+// 0: point { x, y }
+// 1: node { next: ref, at: inline point, weight }
+// 2: path { first: inline node, last: ref }
+// 3: option { none | some(ref, inline point) }
+
+fn shapes() -> [Shape; 4] {
+  return [
+    record([Field::Data, Field::Data]),
+    record([Field::Ref, Field::Inline(0), Field::Data]),
+    record([Field::Inline(1), Field::Ref]),
+    Shape::Variant(Arr::from([Arr::EMPTY, Arr::from([Field::Ref, Field::Inline(0)])])),
+  ];
+}
+
+fn dump(layout: &Layout) -> String {
+  return format!(
+    "len {}, refs {}, tag {:?}, offsets {:?}, inline {:?}",
+    layout.len,
+    layout.refs,
+    layout.tag,
+    layout.offsets,
+    layout.inline,
+  );
+}
+
+#[test]
+fn test_layout() {
+  let layouts = layout(&shapes()).unwrap();
+  let out = layouts.iter().map(dump).collect::<Vec<_>>().join("\n");
+
+  expect![[r#"
+      len 2, refs 0, tag None, offsets [[0, 1]], inline [[]]
+      len 5, refs 4, tag None, offsets [[0, 1, 4]], inline [[(1, 2, 0)]]
+      len 7, refs 7, tag None, offsets [[1, 0]], inline [[(1, 5, 4), (3, 2, 0)]]
+      len 5, refs 4, tag Some(4), offsets [[], [0, 1]], inline [[], [(1, 2, 0)]]"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_cycle() {
+  let shapes = [record([Field::Inline(1)]), record([Field::Data, Field::Inline(0)])];
+
+  let out = layout(&shapes).err().unwrap().to_string();
+
+  expect!["shape 0 contains itself inline"].assert_eq(&out);
+}
+
+#[test]
+fn test_interior_pointer() {
+  let layouts = layout(&shapes()).unwrap();
+  let path = &layouts[2];
+  let node = &layouts[1];
+  let mut heap = Heap::with_capacity(64);
+  let mut roots = [0];
+
+  // The only root is an interior pointer to the point of the node of a path,
+  // which must keep the whole path alive.
+
+  let last = heap.alloc(1, 0, &mut roots[..]).unwrap();
+  heap.set(last, 0, 7);
+  roots[0] = last;
+  let r = heap.alloc(path.len, path.refs, &mut roots[..]).unwrap();
+  path.init(&mut heap, r, 0);
+  heap.set(r, path.offsets[0][1], roots[0]);
+  let n = heap.inline(r, path.offsets[0][0]);
+  heap.set(n, node.offsets[0][2], 5);
+  let p = heap.inline(n, node.offsets[0][1]);
+  heap.set(p, 0, 3);
+  heap.set(p, 1, 4);
+  roots[0] = p;
+
+  for _ in 0 .. 100 {
+    let _ = heap.alloc(10, 0, &mut roots[..]).unwrap();
+  }
+
+  let p = roots[0];
+  let r = heap.base(p);
+  let n = heap.inline(r, path.offsets[0][0]);
+  let last = heap.get(r, path.offsets[0][1]);
+
+  let out = format!(
+    "collections {}, point ({}, {}), weight {}, last {}, interior {}",
+    heap.collections() > 0,
+    heap.get(p, 0),
+    heap.get(p, 1),
+    heap.get(n, node.offsets[0][2]),
+    heap.get(last, 0),
+    heap.inline(n, node.offsets[0][1]) == p,
+  );
+
+  expect!["collections true, point (3, 4), weight 5, last 7, interior true"].assert_eq(&out);
+}
+
+#[test]
+fn test_variant() {
+  let layouts = layout(&shapes()).unwrap();
+  let option = &layouts[3];
+  let mut heap = Heap::with_capacity(64);
+
+  let r = heap.alloc_uncollected(option.len, option.refs).unwrap();
+  option.init(&mut heap, r, 1);
+  heap.set(r, option.offsets[1][0], r);
+  let p = heap.inline(r, option.offsets[1][1]);
+  heap.set(p, 1, 9);
+
+  let mut roots = [p];
+  heap.collect(&mut roots[..]);
+  let p = roots[0];
+  let r = heap.base(p);
+  let some = format!("tag {}, self {}, y {}", heap.get(r, option.tag.unwrap()), heap.get(r, 0) == r, heap.get(p, 1));
+
+  option.init(&mut heap, r, 0);
+  let none = format!("tag {}, refs {:?}", heap.get(r, option.tag.unwrap()), (0 .. option.refs).map(|i| heap.get(r, i)).collect::<Vec<_>>());
+
+  expect![[r#"
+      tag 1, self true, y 9
+      tag 0, refs [0, 0, 0, 0]"#]].assert_eq(&format!("{}\n{}", some, none));
+}
+
+#[test]
+fn test_repr() {
+  let mut types = TypeStore::new();
+  let i64 = types.i64();
+  let bool = types.bool();
+  let array = types.array(bool);
+  let pair = types.tuple([i64, bool]);
+  let bytes = types.tuple([bool, bool, bool]);
+  let fun = types.fun(pair, bytes);
+
+  let out = [bool, i64, array, fun].map(|t| format!("{}: {:?}", types.display(t), repr(&types, t))).join("\n");
+
+  expect![[r#"
+      bool: Repr { size: 1, align: 1, class: Bool }
+      i64: Repr { size: 8, align: 8, class: Int }
+      Array[bool]: Repr { size: 8, align: 8, class: Ref }
+      Fun(i64, bool) -> (bool, bool, bool): Repr { size: 8, align: 8, class: Code }"#]].assert_eq(&out);
+}
@@ -0,0 +1,217 @@
+use expect_test::expect;
+use lilac::json::Json;
+use lilac::lsp;
+use std::io::Write;
+
+static SOURCE: &str = "\
+fun add(a, b) {
+  let c = a + b
+  var d = c
+  d = d + 1
+  return d
+}
+fun main(xs) {
+  return add(len(xs), 2), xs
+}
+";
+
+#[test]
+fn test_analyze() {
+  let analysis = lsp::analyze(SOURCE);
+  let mut out = String::new();
+
+  for x in analysis.occurrences.iter() {
+    let def = x.def.map_or(String::from("-"), |d| d.to_string());
+    let hover = x.hover.as_deref().unwrap_or("-");
+    out.push_str(&format!("{} {} {} {}\n", x.pos, x.len, def, hover));
+  }
+
+  expect![[r#"
+      4 3 4 fun add : Fun(i64, i64) -> (i64)
+      8 1 8 a : i64
+      11 1 11 b : i64
+      26 1 8 a : i64
+      30 1 11 b : i64
+      22 1 22 c : i64
+      42 1 22 c : i64
+      38 1 38 d : i64
+      50 1 38 d : i64
+      65 1 38 d : i64
+      73 4 73 fun main : forall '0 . Fun(Array['0]) -> (i64, Array['0])
+      78 2 78 xs : Array['0]
+      101 2 78 xs : Array['0]
+      97 3 - len : Fun(Array['0]) -> (i64)
+      93 3 4 add : Fun(i64, i64) -> (i64)
+      110 2 78 xs : Array['0]
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_diagnostics() {
+  let out =
+    [
+      "fun f(x) { return x ` 1 }",
+      "fun f(x) {\n  return x + \n}\nfun g( {",
+      "fun f(x) {\n  let a, b = x\n  return a\n}",
+      "fun f(x) {\n  return x + true\n}",
+      "fun f(x) {\n  return y\n}",
+      "fun f() { return é }",
+    ].map(|source| {
+      let analysis = lsp::analyze(source);
+      let mut out = String::new();
+      for d in analysis.diagnostics.iter() {
+        out.push_str(&format!("{} {} {}\n", d.pos, d.len, d.message));
+      }
+      return out;
+    }).join("--\n");
+
+  expect![[r#"
+      20 1 invalid token
+      20 1 expected RBrace
+      20 1 expected Fun
+      --
+      25 1 expected expression
+      34 1 expected Symbol
+      34 1 expected RParen
+      35 1 expected expression
+      35 1 expected RBrace
+      --
+      24 1 static error
+      --
+      20 1 type error
+      --
+      20 1 type error
+      --
+      17 2 invalid token
+      17 2 expected expression
+      17 2 expected RBrace
+      17 2 expected Fun
+  "#]].assert_eq(&out);
+}
+
+// Frames the messages of a client like the protocol does.
+
+fn frame(messages: &[&str]) -> String {
+  let mut out = String::new();
+  for m in messages.iter() {
+    let m = Json::parse(m).unwrap().to_string();
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", m.len(), m));
+  }
+  return out;
+}
+
+// Splits the output of the server into one message per line.
+
+fn unframe(output: &str) -> String {
+  let mut out = String::new();
+  for part in output.split("Content-Length: ").skip(1) {
+    let (len, body) = part.split_once("\r\n\r\n").unwrap();
+    assert_eq!(len.parse::<usize>().unwrap(), body.len());
+    out.push_str(body);
+    out.push('\n');
+  }
+  return out;
+}
+
+#[test]
+fn test_session() {
+  let open = Json::object([
+    ("jsonrpc", Json::from("2.0")),
+    ("method", Json::from("textDocument/didOpen")),
+    ("params", Json::object([
+      ("textDocument", Json::object([("uri", Json::from("file:///main.lil")), ("text", Json::from(SOURCE))])),
+    ])),
+  ]).to_string();
+
+  let input =
+    frame(&[
+      r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#,
+      r#"{"jsonrpc": "2.0", "method": "initialized", "params": {}}"#,
+      &open,
+      // hover over `d` in `return d`
+      r#"{"jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": {"textDocument": {"uri": "file:///main.lil"}, "position": {"line": 4, "character": 9}}}"#,
+      // hover over the name of `add`
+      r#"{"jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": {"textDocument": {"uri": "file:///main.lil"}, "position": {"line": 0, "character": 5}}}"#,
+      // go to the definition of `add` from its call
+      r#"{"jsonrpc": "2.0", "id": 4, "method": "textDocument/definition", "params": {"textDocument": {"uri": "file:///main.lil"}, "position": {"line": 7, "character": 10}}}"#,
+      // go to the definition of `d` from `d = d + 1`
+      r#"{"jsonrpc": "2.0", "id": 5, "method": "textDocument/definition", "params": {"textDocument": {"uri": "file:///main.lil"}, "position": {"line": 3, "character": 7}}}"#,
+      // nothing at the keyword `return`
+      r#"{"jsonrpc": "2.0", "id": 6, "method": "textDocument/hover", "params": {"textDocument": {"uri": "file:///main.lil"}, "position": {"line": 4, "character": 3}}}"#,
+      r#"{"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {"textDocument": {"uri": "file:///main.lil"}, "contentChanges": [{"text": "fun f(x) {\n  return x + true\n}\n"}]}}"#,
+      r#"{"jsonrpc": "2.0", "id": 7, "method": "textDocument/formatting", "params": {}}"#,
+      r#"{"jsonrpc": "2.0", "method": "textDocument/didClose", "params": {"textDocument": {"uri": "file:///main.lil"}}}"#,
+      r#"{"jsonrpc": "2.0", "id": 8, "method": "shutdown"}"#,
+      r#"{"jsonrpc": "2.0", "method": "exit"}"#,
+      r#"{"jsonrpc": "2.0", "id": 9, "method": "initialize", "params": {}}"#,
+    ]);
+
+  let mut output = Vec::new();
+  lsp::serve(&mut input.as_bytes(), &mut output).unwrap();
+  let out = unframe(&String::from_utf8(output).unwrap());
+
+  expect![[r#"
+      {"jsonrpc":"2.0","id":1,"result":{"capabilities":{"textDocumentSync":1,"hoverProvider":true,"definitionProvider":true},"serverInfo":{"name":"lilac"}}}
+      {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///main.lil","diagnostics":[]}}
+      {"jsonrpc":"2.0","id":2,"result":{"contents":{"kind":"plaintext","value":"d : i64"},"range":{"start":{"line":4,"character":9},"end":{"line":4,"character":10}}}}
+      {"jsonrpc":"2.0","id":3,"result":{"contents":{"kind":"plaintext","value":"fun add : Fun(i64, i64) -> (i64)"},"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":7}}}}
+      {"jsonrpc":"2.0","id":4,"result":{"uri":"file:///main.lil","range":{"start":{"line":0,"character":4},"end":{"line":0,"character":7}}}}
+      {"jsonrpc":"2.0","id":5,"result":{"uri":"file:///main.lil","range":{"start":{"line":2,"character":6},"end":{"line":2,"character":7}}}}
+      {"jsonrpc":"2.0","id":6,"result":null}
+      {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///main.lil","diagnostics":[{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"severity":1,"source":"lilac","message":"type error"}]}}
+      {"jsonrpc":"2.0","id":7,"error":{"code":-32601,"message":"method not found"}}
+      {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///main.lil","diagnostics":[]}}
+      {"jsonrpc":"2.0","id":8,"result":null}
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_non_ascii() {
+  // An invalid token covers its whole character, which is one UTF-16 code unit
+  // for `é` and two for `😀`.
+
+  let text = "fun f(x) {\n  return é😀, x\n}\n";
+  let open = Json::object([
+    ("jsonrpc", Json::from("2.0")),
+    ("method", Json::from("textDocument/didOpen")),
+    ("params", Json::object([
+      ("textDocument", Json::object([("uri", Json::from("file:///main.lil")), ("text", Json::from(text))])),
+    ])),
+  ]).to_string();
+
+  let mut output = Vec::new();
+  lsp::serve(&mut frame(&[&open]).as_bytes(), &mut output).unwrap();
+  let out = unframe(&String::from_utf8(output).unwrap());
+
+  expect![[r#"
+      {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///main.lil","diagnostics":[{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"severity":1,"source":"lilac","message":"invalid token"},{"range":{"start":{"line":1,"character":10},"end":{"line":1,"character":12}},"severity":1,"source":"lilac","message":"invalid token"},{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"severity":1,"source":"lilac","message":"expected expression"},{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"severity":1,"source":"lilac","message":"expected RBrace"},{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"severity":1,"source":"lilac","message":"expected Fun"}]}}
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_binary() {
+  let mut child =
+    std::process::Command::new(env!("CARGO_BIN_EXE_lilac"))
+      .arg("lsp")
+      .stdin(std::process::Stdio::piped())
+      .stdout(std::process::Stdio::piped())
+      .spawn()
+      .unwrap();
+
+  let input =
+    frame(&[
+      r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#,
+      r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#,
+      r#"{"jsonrpc": "2.0", "method": "exit"}"#,
+    ]);
+
+  child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+  let output = child.wait_with_output().unwrap();
+  let out = format!("status {}\n{}", output.status.code().unwrap(), unframe(&String::from_utf8(output.stdout).unwrap()));
+
+  expect![[r#"
+      status 0
+      {"jsonrpc":"2.0","id":1,"result":{"capabilities":{"textDocumentSync":1,"hoverProvider":true,"definitionProvider":true},"serverInfo":{"name":"lilac"}}}
+      {"jsonrpc":"2.0","id":2,"result":null}
+  "#]].assert_eq(&out);
+}
@@ -0,0 +1,169 @@
+use crate::util;
+use expect_test::expect;
+
+#[test]
+fn test_mono() {
+  let mut out = String::new();
+
+  util::dump_mono(&mut out, "
+    fun id(x) { x }
+    fun apply1(f, x) { f(x) }
+    fun unused(x) { x }
+    fun not(x) { ! x }
+    fun main(x, p) {
+      let y = apply1(id, x)
+      let q = apply1(not, id(p))
+      return y + 1, q
+    }
+  ", &["main"]);
+
+  expect![[r#"
+      === fun main : Fun(i64, bool) -> (i64, bool) ===
+      %0 LABEL 2
+      %1 = GET 0 : i64
+      %2 = GET 1 : bool
+      %3 = FUN #1 : Fun(i64) -> (i64)
+      %4 = FUN #2 : Fun(Fun(i64) -> (i64), i64) -> (i64)
+      %5 PUT 0 %3
+      %6 PUT 1 %1
+      %7 CALL %4
+      %8 ==> GOTO %9
+      %9 LABEL 1
+      %10 = GET 0 : i64
+      %11 = FUN #3 : Fun(bool) -> (bool)
+      %12 = FUN #4 : Fun(bool) -> (bool)
+      %13 PUT 0 %2
+      %14 CALL %12
+      %15 ==> GOTO %16
+      %16 LABEL 1
+      %17 = GET 0 : bool
+      %18 = FUN #5 : Fun(Fun(bool) -> (bool), bool) -> (bool)
+      %19 PUT 0 %11
+      %20 PUT 1 %17
+      %21 CALL %18
+      %22 ==> GOTO %23
+      %23 LABEL 1
+      %24 = GET 0 : bool
+      %25 = 1 : i64
+      %26 = add.i64 %10 %25 : i64
+      %27 PUT 0 %26
+      %28 PUT 1 %24
+      %29 RET
+      === fun id : Fun(i64) -> (i64) ===
+      %30 LABEL 1
+      %31 = GET 0 : i64
+      %32 PUT 0 %31
+      %33 RET
+      === fun apply1 : Fun(Fun(i64) -> (i64), i64) -> (i64) ===
+      %34 LABEL 2
+      %35 = GET 0 : Fun(i64) -> (i64)
+      %36 = GET 1 : i64
+      %37 PUT 0 %36
+      %38 TAIL-CALL %35
+      === fun not : Fun(bool) -> (bool) ===
+      %39 LABEL 1
+      %40 = GET 0 : bool
+      %41 = not.bool %40 : bool
+      %42 PUT 0 %41
+      %43 RET
+      === fun id : Fun(bool) -> (bool) ===
+      %44 LABEL 1
+      %45 = GET 0 : bool
+      %46 PUT 0 %45
+      %47 RET
+      === fun apply1 : Fun(Fun(bool) -> (bool), bool) -> (bool) ===
+      %48 LABEL 2
+      %49 = GET 0 : Fun(bool) -> (bool)
+      %50 = GET 1 : bool
+      %51 PUT 0 %50
+      %52 TAIL-CALL %49
+  "#]].assert_eq(out.drain(..).as_ref());
+}
+
+#[test]
+fn test_mono_error() {
+  let mut out = String::new();
+
+  util::dump_mono(&mut out, "fun id(x) { x }", &["id"]);
+  util::dump_mono(&mut out, "fun id(x) { x }", &["main"]);
+
+  expect![[r#"
+      error: entry point id is polymorphic
+      error: unknown entry point main
+  "#]].assert_eq(out.drain(..).as_ref());
+}
+
+#[test]
+fn test_deep_type() {
+  // A deep monomorphic type is not polymorphic recursion, however deep it is,
+  // and neither is a polymorphic function that wraps its argument.
+
+  let mut value = String::from("n + 1");
+  for _ in 0 .. 100 { value = format!("array(1, {})", value); }
+
+  let source =
+    format!("
+      fun id(x) {{ return x }}
+      fun wrap(x) {{ return array(1, x) }}
+      fun main(n) {{
+        let a = id({})
+        return len(a), len(wrap(wrap(a)))
+      }}
+    ", value);
+
+  expect!["1, 1"].assert_eq(&util::run_irp(&source, "main", [lilac::eval_iru::Value::Int(7)]));
+}
+
+#[test]
+fn test_polymorphic_recursion() {
+  use lilac::arr::Arr;
+  use lilac::irp::Fun;
+  use lilac::irp::Inst;
+  use lilac::irp::Module;
+  use lilac::symbol::Symbol;
+  use lilac::typecheck::TypeScheme;
+  use lilac::typeid::TypeId;
+  use lilac::typestore::TypeStore;
+
+  // fun foo(x) { foo([x]) }, at the type forall '0 . Fun('0) -> ()
+
+  let mut types = TypeStore::new();
+  let v = types.var(TypeId(0));
+  let b = types.array(v);
+  let a = types.tuple([v]);
+  let b = types.tuple([b]);
+  let c = types.tuple([]);
+  let f = types.fun(a, c);
+  let g = types.fun(b, c);
+  let i = types.i64();
+  let i = types.tuple([i]);
+  let h = types.fun(i, c);
+
+  let foo = Symbol::from_str("foo");
+  let main = Symbol::from_str("main");
+
+  let module =
+    Module {
+      code: Arr::from([
+        Inst::Label(1),
+        Inst::Get(0, v),
+        Inst::Const(foo, g),
+        Inst::Put(0, 1),
+        Inst::TailCall(2),
+        Inst::Label(1),
+        Inst::Get(0, TypeStore::I64),
+        Inst::Const(foo, h),
+        Inst::Put(0, 6),
+        Inst::TailCall(7),
+      ]),
+      decl: Arr::from([
+        Fun { name: foo, pos: 0, len: 5, scheme: TypeScheme(1, f) },
+        Fun { name: main, pos: 5, len: 5, scheme: TypeScheme(0, h) },
+      ]),
+      types,
+    };
+
+  let e = lilac::mono::monomorphize(module, &[main]).err().unwrap();
+
+  expect!["polymorphic recursion in foo"].assert_eq(&e.to_string());
+}
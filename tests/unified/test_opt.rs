@@ -0,0 +1,292 @@
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+use lilac::irp::Module;
+use lilac::opt;
+use lilac::opt::Pass;
+use lilac::symbol::Symbol;
+
+fn compile(source: &str) -> Module {
+  let module = util::lower(source);
+  let module = lilac::promote::promote(&module);
+  util::verify(&module);
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+  return lilac::make_irp::compile(&module, &environment, solver).unwrap();
+}
+
+fn optimize(source: &str, passes: &[Pass]) -> String {
+  return opt::optimize(compile(source), passes).to_string();
+}
+
+// Runs `name` without and with every pass, which must agree.
+
+fn run(source: &str, name: &str, args: &[Value]) -> String {
+  let name = Symbol::from_str(name);
+  let mut out = Vec::new();
+
+  for passes in [&[][..], &Pass::ALL[..]] {
+    let module = opt::optimize(compile(source), passes);
+    let module = lilac::mono::monomorphize(module, &[name]).unwrap();
+    let results = lilac::eval_irp::run(&module, name, args.iter().cloned()).unwrap();
+    out.push(results.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "));
+  }
+
+  assert_eq!(out[0], out[1]);
+  return out.pop().unwrap();
+}
+
+#[test]
+fn test_fold() {
+  // Operations that would trap at run time are left to trap then.
+
+  let out =
+    optimize("
+      fun f() {
+        let x = 6 * 7
+        let y = 9223372036854775807 + 1
+        return x / 0, -x, y == x, 1 << 65, if 1 < 2 { x } else { 0 }
+      }
+    ", &[Pass::Fold]);
+
+  expect![[r#"
+      === fun f : Fun() -> (i64, i64, bool, i64, i64) ===
+      %0 LABEL 0
+      %1 = 6 : i64
+      %2 = 7 : i64
+      %3 = 42 : i64
+      %4 = 9223372036854775807 : i64
+      %5 = 1 : i64
+      %6 = -9223372036854775808 : i64
+      %7 = 0 : i64
+      %8 = div.i64 %3 %7 : i64
+      %9 = -42 : i64
+      %10 = false : bool
+      %11 = 1 : i64
+      %12 = 65 : i64
+      %13 = shl.i64 %11 %12 : i64
+      %14 = 1 : i64
+      %15 = 2 : i64
+      %16 = true : bool
+      %17 ==> GOTO %22
+      %18 LABEL 0
+      %19 = 0 : i64
+      %20 PUT 0 %19
+      %21 ==> GOTO %25
+      %22 LABEL 0
+      %23 PUT 0 %3
+      %24 ==> GOTO %25
+      %25 LABEL 1
+      %26 = GET 0 : i64
+      %27 PUT 0 %8
+      %28 PUT 1 %9
+      %29 PUT 2 %10
+      %30 PUT 3 %13
+      %31 PUT 4 %26
+      %32 RET
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_copy() {
+  // A local that a loop never changes is passed around it unchanged.
+
+  let out =
+    optimize("
+      fun f(n) {
+        var k = n * 2
+        var i = 0
+        while i < n { i = i + k }
+        return i
+      }
+    ", &[Pass::Copy]);
+
+  expect![[r#"
+      === fun f : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 2 : i64
+      %3 = mul.i64 %1 %2 : i64
+      %4 = 0 : i64
+      %5 PUT 0 %4
+      %6 ==> GOTO %7
+      %7 LABEL 1
+      %8 = GET 0 : i64
+      %9 = cmplt.i64 %8 %1 : bool
+      %10 COND %9
+      %11 ==> GOTO %17
+      %12 ==> GOTO %13
+      %13 LABEL 0
+      %14 = add.i64 %8 %3 : i64
+      %15 PUT 0 %14
+      %16 ==> GOTO %7
+      %17 LABEL 0
+      %18 PUT 0 %8
+      %19 RET
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_thread() {
+  let out =
+    optimize("
+      fun f(x) {
+        loop {
+          if x { break }
+          return 1
+        }
+        return 2
+      }
+    ", &[Pass::Thread]);
+
+  expect![[r#"
+      === fun f : Fun(bool) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : bool
+      %2 ==> GOTO %3
+      %3 LABEL 0
+      %4 COND %1
+      %5 ==> GOTO %9
+      %6 ==> GOTO %13
+      %7 LABEL 0
+      %8 ==> GOTO %13
+      %9 LABEL 0
+      %10 = 1 : i64
+      %11 PUT 0 %10
+      %12 RET
+      %13 LABEL 0
+      %14 = 2 : i64
+      %15 PUT 0 %14
+      %16 RET
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_merge() {
+  let out =
+    optimize("
+      fun f(x) {
+        let y = loop { break x + 1 }
+        return y * 2
+      }
+    ", &[Pass::Merge]);
+
+  expect![[r#"
+      === fun f : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 1 : i64
+      %3 = add.i64 %1 %2 : i64
+      %4 = 2 : i64
+      %5 = mul.i64 %3 %4 : i64
+      %6 PUT 0 %5
+      %7 RET
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_dce() {
+  // The division may trap, so it stays.
+
+  let out =
+    optimize("
+      fun f(x) {
+        let a = x + 1
+        let b = x / 0
+        loop { return a }
+        return x
+      }
+    ", &[Pass::Dce]);
+
+  expect![[r#"
+      === fun f : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 1 : i64
+      %3 = add.i64 %1 %2 : i64
+      %4 = 0 : i64
+      %5 = div.i64 %1 %4 : i64
+      %6 ==> GOTO %7
+      %7 LABEL 0
+      %8 PUT 0 %3
+      %9 RET
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_all() {
+  let source = "
+    fun fib(n) {
+      var a = 1
+      var b = 0
+      var n = n
+      loop {
+        if n == 0 { return b }
+        let c = a + b
+        a = b
+        b = c
+        n = n - 1
+      }
+    }
+  ";
+
+  expect![[r#"
+      === fun fib : Fun(i64) -> (i64) ===
+      %0 LABEL 1
+      %1 = GET 0 : i64
+      %2 = 1 : i64
+      %3 = 0 : i64
+      %4 PUT 0 %1
+      %5 PUT 1 %3
+      %6 PUT 2 %2
+      %7 ==> GOTO %8
+      %8 LABEL 3
+      %9 = GET 0 : i64
+      %10 = GET 1 : i64
+      %11 = GET 2 : i64
+      %12 = 0 : i64
+      %13 = cmpeq.i64 %9 %12 : bool
+      %14 COND %13
+      %15 ==> GOTO %20
+      %16 ==> GOTO %17
+      %17 LABEL 0
+      %18 PUT 0 %10
+      %19 RET
+      %20 LABEL 0
+      %21 = add.i64 %11 %10 : i64
+      %22 = 1 : i64
+      %23 = sub.i64 %9 %22 : i64
+      %24 PUT 0 %23
+      %25 PUT 1 %21
+      %26 PUT 2 %10
+      %27 ==> GOTO %8
+  "#]].assert_eq(&optimize(source, &Pass::ALL));
+  expect!["55"].assert_eq(&run(source, "fib", &[Value::Int(10)]));
+}
+
+#[test]
+fn test_run() {
+  let source = "
+    fun tak(x, y, z) {
+      if y < x {
+        return tak(tak(x - 1, y, z), tak(y - 1, z, x), tak(z - 1, x, y))
+      } else {
+        return z
+      }
+    }
+    fun f(n) {
+      var s = 0
+      var i = 0
+      while i < n {
+        var j = i
+        while j < n {
+          if j % 3 == 0 { s = s + tak(j, 2, 1) } else { s = s - j }
+          j = j + 1
+        }
+        if true { i = i + 1 } else { i = i - 1 }
+      }
+      return s, 3 * 4 - 2
+    }
+  ";
+
+  expect!["-163, 10"].assert_eq(&run(source, "f", &[Value::Int(9)]));
+}
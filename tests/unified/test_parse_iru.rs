@@ -0,0 +1,158 @@
+use expect_test::expect;
+use lilac::eval_iru;
+use lilac::eval_iru::Value;
+use lilac::parse_iru;
+use lilac::symbol::Symbol;
+
+fn compile(source: &str) -> lilac::iru::Module {
+  let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(source.as_bytes(), store.arena()).0;
+  return lilac::make_iru::compile(&items);
+}
+
+#[test]
+fn test_round_trip() {
+  let module = compile("
+    fun a_long_function_name(p, r) {
+      var i = 0
+      while ! (i >= 10) {
+        i = i + 1
+        r[i] = r[i - 1] * 2
+      }
+      p.x = p.y
+      return -i, true, false, -7
+    }
+    fun f(x) {
+      let u, v = x
+      return a_long_function_name(x, u)
+    }
+  ");
+
+  let text = module.to_string();
+  let parsed = parse_iru::parse(&text).unwrap();
+
+  assert_eq!(parsed, module);
+  assert_eq!(parsed.to_string(), text);
+}
+
+#[test]
+fn test_hand_written() {
+  // The square of the sum of the arguments, with a call through a local.
+
+  let module = parse_iru::parse("
+    === fun add ===
+    %0 LABEL 2
+    %1 = GET 0
+    %2 = GET 1
+    %3 = %1 + %2
+    %4 PUT 0 %3
+    %5 RET
+
+    === fun main ===
+    %6 LABEL 2
+    %7 = GET 0
+    %8 = GET 1
+    %9 = CONST add
+    %10 = LOCAL %9
+    %11 = [ %10 ]
+    %12 PUT 0 %7
+    %13 PUT 1 %8
+    %14 CALL %11
+    %15 ==> GOTO %16
+    %16 LABEL 1
+    %17 = GET 0
+    %18 = %17 * %17
+    %19 PUT 0 %18
+    %20 RET
+  ").unwrap();
+
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+  let results = eval_iru::run(&module, Symbol::from_str("main"), [Value::Int(3), Value::Int(4)]).unwrap();
+
+  let out =
+    format!(
+      "{}\n{}\n",
+      environment[Symbol::from_str("main")].display(solver.types()),
+      results.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+    );
+
+  expect![[r#"
+      Fun(i64, i64) -> (i64)
+      49
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_long_names() {
+  // A name longer than eight bytes can be written out in full, as well as by
+  // the hash that it is displayed as.
+
+  let module = parse_iru::parse("
+    === fun apply_twice ===
+    %0 LABEL 1
+    %1 = GET 0
+    %2 = %1 + %1
+    %3 PUT 0 %2
+    %4 RET
+
+    === fun main ===
+    %5 LABEL 1
+    %6 = GET 0
+    %7 = CONST apply_twice
+    %8 PUT 0 %6
+    %9 TAIL-CALL %7
+  ").unwrap();
+
+  let name = Symbol::from_str("apply_twice");
+  let results = eval_iru::run(&module, Symbol::from_str("main"), [Value::Int(21)]).unwrap();
+
+  assert_eq!(module.decl[0].name, name);
+  assert_eq!(Symbol::parse(&name.to_string()), Some(name));
+  expect!["42"].assert_eq(&results.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "));
+}
+
+#[test]
+fn test_hash_bit() {
+  // Only the top bit of a symbol marks it as a hash, whatever the width of a
+  // pointer. A short name with a non-ASCII fourth byte is not a hash.
+
+  let name = Symbol::from_str("abcé");
+  let out =
+    [
+      name.to_string(),
+      format!("{:?}", Symbol::parse(&name.to_string()) == Some(name)),
+      format!("{:?}", Symbol::parse("Symbol(0x8000000000000001)").map(|s| s.to_string())),
+      format!("{:?}", Symbol::parse("Symbol(0x80000000)").map(|s| s.to_string())),
+    ].join("\n");
+
+  expect![[r#"
+      abcé
+      true
+      Some("Symbol(0x8000000000000001)")
+      None"#]].assert_eq(&out);
+}
+
+#[test]
+fn test_errors() {
+  let out =
+    [
+      "%0 RET",
+      "=== fun f ===\n%0 LABEL 0\n%2 RET",
+      "=== fun f ===\n%0 LABEL 0\n%1 = %0 ** %0",
+      "=== fun f ===\n%0 = [ .x ]",
+      "=== fun ===",
+    ].iter().map(|text| {
+      match parse_iru::parse(text) {
+        Ok(_) => "ok\n".to_string(),
+        Err(e) => format!("error: {}\n", e),
+      }
+    }).collect::<String>();
+
+  expect![[r#"
+      error: instruction outside a function at line 1
+      error: wrong program point at line 3
+      error: invalid line 3
+      error: invalid line 2
+      error: invalid line 1
+  "#]].assert_eq(&out);
+}
@@ -0,0 +1,208 @@
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru;
+use lilac::eval_iru::Value;
+use lilac::iru::Module;
+use lilac::parse_iru;
+use lilac::promote;
+use lilac::symbol::Symbol;
+
+fn compile(source: &str) -> Module {
+  return util::lower(source);
+}
+
+fn promote(module: &Module) -> Module {
+  let module = promote::promote(module);
+  util::verify(&module);
+  return module;
+}
+
+// Runs `name` before and after promotion, which must agree.
+
+fn run(module: &Module, name: &str, args: &[Value]) -> String {
+  let name = Symbol::from_str(name);
+  let before = eval_iru::run(module, name, args.iter().cloned()).unwrap();
+  let after = eval_iru::run(&promote(module), name, args.iter().cloned()).unwrap();
+  let before: Vec<String> = before.iter().map(|x| x.to_string()).collect();
+  let after: Vec<String> = after.iter().map(|x| x.to_string()).collect();
+  assert_eq!(before, after);
+  return after.join(", ");
+}
+
+#[test]
+fn test_loop() {
+  let module = compile("
+    fun f(n) {
+      var s = 0
+      var i = 0
+      while i < n {
+        i = i + 1
+        if i == 3 { continue }
+        s = s + i
+      }
+      return s
+    }
+  ");
+
+  expect![[r#"
+      === fun f ===
+      %0 LABEL 1
+      %1 = GET 0
+      %2 = 0
+      %3 = 0
+      %4 PUT 0 %3
+      %5 PUT 1 %2
+      %6 ==> GOTO %7
+      %7 LABEL 2
+      %8 = GET 0
+      %9 = GET 1
+      %10 = %8 < %1
+      %11 COND %10
+      %12 ==> GOTO %31
+      %13 ==> GOTO %14
+      %14 LABEL 0
+      %15 = 1
+      %16 = %8 + %15
+      %17 = 3
+      %18 = %16 == %17
+      %19 COND %18
+      %20 ==> GOTO %26
+      %21 ==> GOTO %22
+      %22 LABEL 0
+      %23 PUT 0 %16
+      %24 PUT 1 %9
+      %25 ==> GOTO %7
+      %26 LABEL 0
+      %27 = %9 + %16
+      %28 PUT 0 %16
+      %29 PUT 1 %27
+      %30 ==> GOTO %7
+      %31 LABEL 0
+      %32 PUT 0 %9
+      %33 RET
+  "#]].assert_eq(&promote(&module).to_string());
+
+  expect!["12"].assert_eq(&run(&module, "f", &[Value::Int(5)]));
+}
+
+#[test]
+fn test_nested() {
+  // Locals that are only set in some branches, in loops that are left early,
+  // and a call in between.
+
+  let module = compile("
+    fun g(x) { return x * 2 }
+    fun f(n) {
+      var s = 0
+      var i = 0
+      loop {
+        var j = 0
+        while j < i {
+          if j == 2 { break }
+          s = s + g(j)
+          j = j + 1
+        }
+        if s > 20 { return s, i }
+        i = i + 1
+        if i > n { break }
+      }
+      return s, i
+    }
+  ");
+
+  expect!["18, 11"].assert_eq(&run(&module, "f", &[Value::Int(10)]));
+  expect!["4, 4"].assert_eq(&run(&module, "f", &[Value::Int(3)]));
+
+  // NB: promoted code needs no cells, so it is typed without them.
+
+  let promoted = promote(&module);
+  let (environment, solver) = lilac::typecheck::typecheck(&promoted);
+  let typed = lilac::make_irp::compile(&promoted, &environment, solver).unwrap();
+
+  assert!(! promoted.code.iter().any(|inst| matches!(inst, lilac::iru::Inst::Local(_))));
+  assert!(! typed.code.iter().any(|inst| matches!(inst, lilac::irp::Inst::Local(_))));
+}
+
+#[test]
+fn test_shared_transfers() {
+  // A join that is reached from a `Cond` and from the continuation of a
+  // `Call`, with different values of the local, which are passed through
+  // blocks of their own.
+
+  let module = parse_iru::parse("
+    === fun f ===
+    %0 LABEL 1
+    %1 = GET 0
+    %2 = 0
+    %3 = LOCAL %2
+    %4 = 1
+    %5 = %1 < %4
+    %6 PUT 0 %1
+    %7 COND %5
+    %8 ==> GOTO %17
+    %9 ==> GOTO %10
+    %10 LABEL 1
+    %11 = GET 0
+    %12 [ %3 ] <- %11
+    %13 = CONST g
+    %14 PUT 0 %11
+    %15 CALL %13
+    %16 ==> GOTO %17
+    %17 LABEL 1
+    %18 = GET 0
+    %19 = [ %3 ]
+    %20 = %18 + %19
+    %21 PUT 0 %20
+    %22 RET
+    === fun g ===
+    %23 LABEL 1
+    %24 = GET 0
+    %25 = %24 + %24
+    %26 PUT 0 %25
+    %27 RET
+  ").unwrap();
+
+  expect![[r#"
+      === fun f ===
+      %0 LABEL 1
+      %1 = GET 0
+      %2 = 0
+      %3 = 1
+      %4 = %1 < %3
+      %5 PUT 0 %1
+      %6 COND %4
+      %7 ==> GOTO %9
+      %8 ==> GOTO %14
+      %9 LABEL 1
+      %10 = GET 0
+      %11 PUT 0 %10
+      %12 PUT 1 %2
+      %13 ==> GOTO %25
+      %14 LABEL 1
+      %15 = GET 0
+      %16 = CONST g
+      %17 PUT 0 %15
+      %18 CALL %16
+      %19 ==> GOTO %20
+      %20 LABEL 1
+      %21 = GET 0
+      %22 PUT 0 %21
+      %23 PUT 1 %15
+      %24 ==> GOTO %25
+      %25 LABEL 2
+      %26 = GET 1
+      %27 = GET 0
+      %28 = %27 + %26
+      %29 PUT 0 %28
+      %30 RET
+      === fun g ===
+      %31 LABEL 1
+      %32 = GET 0
+      %33 = %32 + %32
+      %34 PUT 0 %33
+      %35 RET
+  "#]].assert_eq(&promote(&module).to_string());
+
+  expect!["5"].assert_eq(&run(&module, "f", &[Value::Int(5)]));
+  expect!["-9"].assert_eq(&run(&module, "f", &[Value::Int(-3)]));
+}
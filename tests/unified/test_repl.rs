@@ -0,0 +1,208 @@
+use expect_test::expect;
+use lilac::repl;
+use lilac::repl::Repl;
+
+// Feeds the inputs to a fresh session, and returns what it prints, with each
+// error printed as the session goes on.
+
+fn session(inputs: &[&str]) -> String {
+  let mut repl = Repl::new();
+  let mut out = String::new();
+  for input in inputs.iter() {
+    if let Err(e) = repl.eval(input, &mut out) {
+      out.push_str(&format!("error: {}\n", e));
+    }
+  }
+  return out;
+}
+
+#[test]
+fn test_define_and_eval() {
+  let out =
+    session(&[
+      "fun add(a, b) {\n  return a + b\n}",
+      "fun twice(f, x) { return f(f(x)) }",
+      "fun inc(x) { return add(x, 1) }",
+      "twice(inc, 40)",
+      "inc(1) > 1, array(2, true)",
+      "",
+    ]);
+
+  expect![[r#"
+      fun add : Fun(i64, i64) -> (i64)
+      fun twice : forall '0 . Fun(Fun('0) -> ('0), '0) -> ('0)
+      fun inc : Fun(i64) -> (i64)
+      42
+      true, [true, true]
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_redefine() {
+  let out =
+    session(&[
+      "fun f(x) { return x }",
+      "fun g(x) { return f(x) }",
+      "fun f(x) { return x + 1 }",
+      "g(1)",
+      "fun h(x) { return g(x) * 2 }",
+      "fun f(x) { return x < 0 }",
+      "h(1)",
+    ]);
+
+  // NB: the last definition of `f` does not check against `h`, so the session
+  // keeps the previous one.
+
+  expect![[r#"
+      fun f : forall '0 . Fun('0) -> ('0)
+      fun g : forall '0 . Fun('0) -> ('0)
+      fun f : Fun(i64) -> (i64)
+      2
+      fun h : Fun(i64) -> (i64)
+      error: type error in h
+      4
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_commands() {
+  let out =
+    session(&[
+      "fun id(x) { return x }",
+      ":type id",
+      ":type id(true)",
+      ":type id",
+      ":iru id",
+      ":iru nope",
+      ":nope",
+    ]);
+
+  expect![[r#"
+      fun id : forall '0 . Fun('0) -> ('0)
+      forall '0 . Fun('0) -> ('0)
+      bool
+      forall '0 . Fun('0) -> ('0)
+      %0 LABEL 1
+      %1 = GET 0
+      %2 PUT 0 %1
+      %3 RET
+      error: unbound function nope
+      error: unknown command
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_errors() {
+  let out =
+    session(&[
+      "fun it(x) { return x * 2 }",
+      "it(21)",
+      "1 / 0",
+      "len(true)",
+      "nope(1)",
+      ":type nope",
+      "fun g(x) { return nope(x) }",
+      "fun h(x) { return 1 / x }",
+      "h(0)",
+      "1 + é",
+      "fun k() { return é }",
+    ]);
+
+  expect![[r#"
+      fun it : Fun(i64) -> (i64)
+      42
+      error: division by zero
+      error: type error
+      error: unbound function nope
+      error: unbound function nope
+      error: unbound function nope
+      fun h : Fun(i64) -> (i64)
+      error: division by zero in h
+      error: invalid token at byte 4
+      error: invalid token at byte 17
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_is_complete() {
+  let out =
+    format!("{} {} {}",
+      repl::is_complete("fun f(x) {\n  if x {\n"),
+      repl::is_complete("fun f(x) {\n  return x\n}\n"),
+      repl::is_complete("f(1, g(2)"),
+    );
+
+  expect!["false true false"].assert_eq(&out);
+}
+
+#[test]
+fn test_syntax_errors() {
+  let out =
+    session(&[
+      "fun f( {",
+      "}",
+      ")",
+      ":type f",
+      "1 2",
+      "1 }",
+      "fun g(x) { return x }\nfoo bar",
+      "g(1)",
+    ]);
+
+  expect![[r#"
+      error: expected Symbol at byte 7
+      error: expected Fun at byte 1
+      error: expected expression at byte 0
+      error: unbound function f
+      error: expected RBrace at byte 2
+      error: expected Fun at byte 3
+      error: expected Fun at byte 22
+      error: unbound function g
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_function_values() {
+  let out =
+    session(&[
+      "fun id(x) { return x }",
+      "fun inc(x) { return x + 1 }",
+      "id",
+      "inc, array(2, inc)",
+      "len",
+    ]);
+
+  expect![[r#"
+      fun id : forall '0 . Fun('0) -> ('0)
+      fun inc : Fun(i64) -> (i64)
+      <fun id : forall '0 . Fun('0) -> ('0)>
+      <fun inc : Fun(i64) -> (i64)>, [<fun inc : Fun(i64) -> (i64)>, <fun inc : Fun(i64) -> (i64)>]
+      <builtin len>
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_recheck() {
+  let out =
+    session(&[
+      "fun f(x) { return x }",
+      "fun g(x) { return f(x) }",
+      "fun h(x) { return g(x) + 1 }",
+      "fun k(x) { return x }",
+      "fun f(x) { return x < 0 }",
+      "fun f(x) { return x * 2 }",
+      "h(1), k(true)",
+    ]);
+
+  // NB: `h` only calls `f` through `g`, and is checked again too.
+
+  expect![[r#"
+      fun f : forall '0 . Fun('0) -> ('0)
+      fun g : forall '0 . Fun('0) -> ('0)
+      fun h : Fun(i64) -> (i64)
+      fun k : forall '0 . Fun('0) -> ('0)
+      error: type error in h
+      fun f : Fun(i64) -> (i64)
+      3, true
+  "#]].assert_eq(&out);
+}
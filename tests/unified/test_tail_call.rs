@@ -0,0 +1,199 @@
+use crate::util;
+use crate::util::agree;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+use lilac::symbol::Symbol;
+
+// NB: every test goes much deeper than the native stack, so the back ends only
+// pass if each tail call reuses the frame of its caller. The interpreters keep
+// their frames elsewhere, so `depth` checks that their stacks stay bounded.
+
+const DEPTH: i64 = 1_000_000;
+
+// Runs `name` with each interpreter for each of the first arguments `ns`, and
+// returns the most frames that its stack held.
+
+fn depth(source: &str, name: &str, ns: &[i64], rest: &[Value]) -> String {
+  let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(source.as_bytes(), store.arena()).0;
+  let module = lilac::make_iru::compile(&items);
+  let typed = util::mono(source, name);
+  let name = Symbol::from_str(name);
+  let mut out = Vec::new();
+
+  for &n in ns.iter() {
+    let args = || std::iter::once(Value::Int(n)).chain(rest.iter().cloned());
+    let (_, x) = lilac::eval_iru::run_with_depth(&module, name, args()).unwrap();
+    let (_, y) = lilac::eval_irp::run_with_depth(&typed, name, args()).unwrap();
+    out.push(format!("{}: iru {}, irp {}", n, x, y));
+  }
+
+  return out.join("\n");
+}
+
+// Returns the text of the function `name` in the WebAssembly module emitted for
+// the entry point `entry`.
+
+fn emit_wat(source: &str, entry: &str, name: &str) -> String {
+  let out = lilac::emit_wat::emit(&util::mono(source, entry)).unwrap();
+  let start = out.find(&format!("  ;; {}\n", name)).unwrap();
+  let end = out[start + 1 ..].find("  ;; ").map_or(out.len() - 2, |i| start + i);
+  return out[start .. end].lines().map(|s| format!("{}\n", &s[2 ..])).collect();
+}
+
+static SELF: &str = "
+  fun count(n, a, b) {
+    if n == 0 { return a, b }
+    return count(n - 1, b, a + 1)
+  }
+";
+
+// The tail calls alternate between a known function and a function value, and
+// between different numbers of arguments.
+
+static FUNCTION_VALUE: &str = "
+  fun apply(f, n, s) { return f(n, s) }
+  fun sum(n, s) {
+    if n == 0 { return s }
+    return apply(sum, n - 1, s + n)
+  }
+";
+
+// NB: a function can only refer to functions that are defined before it, so
+// the mutual recursion of typed code goes through a function value.
+
+static MUTUAL: &str = "
+  fun odd(even, n) {
+    if n == 0 { return false }
+    return even(n - 1)
+  }
+  fun even(n) {
+    if n == 0 { return true }
+    return odd(even, n - 1)
+  }
+";
+
+static MUTUAL_UNTYPED: &str = "
+  fun even(n) {
+    if n == 0 { return true }
+    return odd(n - 1)
+  }
+  fun odd(n) {
+    if n == 0 { return false }
+    return even(n - 1)
+  }
+";
+
+static NOT_TAIL: &str = "
+  fun depth(n) {
+    if n == 0 { return 0 }
+    return depth(n - 1) + 1
+  }
+";
+
+#[test]
+fn test_self() {
+  expect!["500000, 500000"].assert_eq(&agree(SELF, "count", &[Value::Int(DEPTH), Value::Int(0), Value::Int(0)]));
+  expect![[r#"
+      10: iru 1, irp 1
+      1000: iru 1, irp 1"#]].assert_eq(&depth(SELF, "count", &[10, 1000], &[Value::Int(0), Value::Int(0)]));
+}
+
+#[test]
+fn test_function_value() {
+  expect!["500000500000"].assert_eq(&agree(FUNCTION_VALUE, "sum", &[Value::Int(DEPTH), Value::Int(0)]));
+  expect![[r#"
+      10: iru 1, irp 1
+      1000: iru 1, irp 1"#]].assert_eq(&depth(FUNCTION_VALUE, "sum", &[10, 1000], &[Value::Int(0)]));
+}
+
+#[test]
+fn test_mutual() {
+  expect!["true"].assert_eq(&agree(MUTUAL, "even", &[Value::Int(DEPTH)]));
+  expect!["false"].assert_eq(&agree(MUTUAL, "even", &[Value::Int(7)]));
+}
+
+#[test]
+fn test_mutual_untyped() {
+  // NB: this does not typecheck, so only the reference interpreter runs it.
+
+  expect!["true"].assert_eq(&util::run(MUTUAL_UNTYPED, "even", [Value::Int(DEPTH)]));
+  expect!["false"].assert_eq(&util::run(MUTUAL_UNTYPED, "odd", [Value::Int(DEPTH)]));
+}
+
+#[test]
+fn test_not_tail() {
+  // Calls that are not tail calls do grow the stack of the interpreters.
+
+  expect!["1000"].assert_eq(&agree(NOT_TAIL, "depth", &[Value::Int(1000)]));
+  expect![[r#"
+      10: iru 11, irp 11
+      1000: iru 1001, irp 1001"#]].assert_eq(&depth(NOT_TAIL, "depth", &[10, 1000], &[]));
+}
+
+#[test]
+fn test_wat() {
+  // The host needs the tail call extension of WebAssembly, which can not be
+  // run here.
+
+  expect![[r#"
+      ;; apply
+      (func $f1 (export "apply") (type $t28) (param $p0 i32) (param $p1 i64) (param $p2 i64) (result i64)
+        (local $v22 i32)
+        (local $v23 i64)
+        (local $v24 i64)
+        local.get $p0
+        local.get $p1
+        local.get $p2
+        local.set $v24
+        local.set $v23
+        local.set $v22
+        local.get $v23
+        local.get $v24
+        local.get $v22
+        return_call_indirect (type $t24)
+        unreachable)
+  "#]].assert_eq(&emit_wat(FUNCTION_VALUE, "sum", "apply"));
+  expect![[r#"
+      ;; even
+      (func $f0 (export "even") (type $t15) (param $p0 i64) (result i32)
+        (local $v1 i64)
+        (local $v2 i64)
+        (local $v3 i32)
+        (local $v8 i32)
+        (local $v12 i32)
+        (local $v13 i64)
+        (local $v14 i64)
+        (local $v15 i32)
+        local.get $p0
+        local.set $v1
+        i64.const 0
+        local.set $v2
+        local.get $v1
+        local.get $v2
+        i64.eq
+        local.set $v3
+        local.get $v3
+        if
+          i32.const 1
+          local.set $v8
+          local.get $v8
+          return
+        else
+          i32.const 0
+          local.set $v12
+          i64.const 1
+          local.set $v13
+          local.get $v1
+          local.get $v13
+          i64.sub
+          local.set $v14
+          i32.const 1
+          local.set $v15
+          local.get $v12
+          local.get $v14
+          return_call $f1
+        end
+        unreachable)
+  "#]].assert_eq(&emit_wat(MUTUAL, "even", "even"));
+}
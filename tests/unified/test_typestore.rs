@@ -0,0 +1,46 @@
+use expect_test::expect;
+use lilac::typeid::TypeId;
+use lilac::typestore::Type;
+use lilac::typestore::TypeStore;
+
+#[test]
+fn test_intern() {
+  let mut t = TypeStore::new();
+
+  let a = t.i64();
+  let b = t.bool();
+  let x = t.tuple([a, b]);
+  let c = t.i64();
+  let d = t.bool();
+  let y = t.tuple([c, d]);
+  let z = t.tuple([b, a]);
+
+  assert!(a == t.i64());
+  assert!(x == y);
+  assert!(x != z);
+  assert!(t.array(x) == t.array(y));
+
+  let e = t.tuple([]);
+  let f = t.fun(e, x);
+
+  let u = t.tuple([]);
+  assert!(f == t.fun(u, y));
+  assert!(matches!(t[e], Type::Tuple(0, _)));
+  assert!(t.tuple_elts(y).collect::<Vec<_>>() == [a, b]);
+}
+
+#[test]
+fn test_display() {
+  let mut t = TypeStore::new();
+
+  let a = t.var(TypeId(0));
+  let b = t.var(TypeId(1));
+  let c = t.array(a);
+  let d = t.tuple([c, a]);
+  let f = t.fun(d, b);
+  let i = t.i64();
+  let g = t.tuple([f, i]);
+  let g = t.fun(g, d);
+
+  expect!["Fun(Fun(Array['0], '0) -> '1, i64) -> (Array['0], '0)"].assert_eq(&t.display(g).to_string());
+}
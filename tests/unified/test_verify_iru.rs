@@ -0,0 +1,219 @@
+use crate::util;
+use expect_test::expect;
+use lilac::eval_iru::Value;
+use lilac::parse_iru;
+use lilac::verify_iru;
+
+// Verifies each module, and lists its violations.
+
+fn verify(texts: &[&str]) -> String {
+  let mut out = String::new();
+  for text in texts.iter() {
+    match verify_iru::verify(&parse_iru::parse(text).unwrap()) {
+      Ok(()) => out.push_str("ok\n"),
+      Err(errors) => {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        out.push_str(&format!("{}\n", errors.join(", ")));
+      }
+    }
+  }
+  return out;
+}
+
+#[test]
+fn test_well_formed() {
+  let out = verify(&["
+    === fun f ===
+    %0 LABEL 2
+    %1 = GET 0
+    %2 = GET 1
+    %3 = %1 < %2
+    %4 COND %3
+    %5 ==> GOTO %7
+    %6 ==> GOTO %10
+    %7 LABEL 0
+    %8 PUT 0 %1
+    %9 RET
+    %10 LABEL 0
+    %11 = CONST f
+    %12 = LOCAL %2
+    %13 = [ %12 ]
+    %14 PUT 0 %13
+    %15 PUT 1 %1
+    %16 CALL %11
+    %17 ==> GOTO %18
+    %18 LABEL 1
+    %19 = GET 0
+    %20 [ %12 ] <- %19
+    %21 PUT 0 %19
+    %22 PUT 1 %2
+    %23 TAIL-CALL %11
+  "]);
+
+  expect![[r#"
+      ok
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_blocks() {
+  let out =
+    verify(&[
+      // A block after a block that does not end.
+      "=== fun f ===\n%0 LABEL 0\n%1 = 1\n%2 LABEL 0\n%3 RET",
+      // Code after a terminator, and a function that does not end.
+      "=== fun f ===\n%0 LABEL 0\n%1 RET\n%2 = 1\n%3 LABEL 0\n%4 = 1",
+      // A function that does not start with a label.
+      "=== fun f ===\n%0 RET",
+      // A `Cond` and a `Call` without their `Goto`s.
+      "=== fun f ===\n%0 LABEL 0\n%1 = true\n%2 COND %1\n%3 ==> GOTO %4\n%4 LABEL 0\n%5 = CONST f\n%6 CALL %5\n%7 RET",
+      // Gotos to a non-label and out of the function.
+      "=== fun f ===\n%0 LABEL 0\n%1 ==> GOTO %0\n%2 LABEL 0\n%3 ==> GOTO %1\n=== fun g ===\n%4 LABEL 0\n%5 ==> GOTO %0",
+    ]);
+
+  expect![[r#"
+      unterminated block at %1
+      instruction outside a block at %2, unterminated block at %4
+      instruction outside a block at %0
+      expected goto after %2, unterminated block at %3, expected goto after %6
+      goto to a non-label at %3, goto to a non-label at %5
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_transfers() {
+  let out =
+    verify(&[
+      // Too many arguments for the label, and a `Get` past its arguments.
+      "=== fun f ===\n%0 LABEL 1\n%1 = GET 1\n%2 = GET 0\n%3 PUT 0 %2\n%4 ==> GOTO %5\n%5 LABEL 0\n%6 RET",
+      // A `Put` that skips an index.
+      "=== fun f ===\n%0 LABEL 1\n%1 = GET 0\n%2 PUT 1 %1\n%3 PUT 1 %1\n%4 RET",
+      // A `Cond` with one `Goto`, and a `Cond` whose branches both get the
+      // same arguments.
+      "=== fun f ===\n%0 LABEL 0\n%1 = true\n%2 PUT 0 %1\n%3 COND %1\n%4 ==> GOTO %5\n%5 LABEL 1\n%6 RET\n%7 LABEL 0\n%8 RET",
+      "=== fun f ===\n%0 LABEL 0\n%1 = true\n%2 PUT 0 %1\n%3 COND %1\n%4 ==> GOTO %6\n%5 ==> GOTO %8\n%6 LABEL 1\n%7 RET\n%8 LABEL 0\n%9 RET",
+    ]);
+
+  expect![[r#"
+      get out of range at %1, arity mismatch at %4
+      put out of order at %2
+      expected goto after %3, unterminated block at %4
+      arity mismatch at %5
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_operands() {
+  let out =
+    verify(&[
+      // Uses of a later value, of a value in another function, and of a
+      // point that is not a value.
+      "=== fun f ===\n%0 LABEL 0\n%1 = %2 + %2\n%2 = 1\n%3 RET\n=== fun g ===\n%4 LABEL 0\n%5 = - %2\n%6 = - %4\n%7 RET",
+      // A local used as a value, and a value used as a local.
+      "=== fun f ===\n%0 LABEL 0\n%1 = 1\n%2 = LOCAL %1\n%3 = %2 + %1\n%4 = [ %1 ]\n%5 [ %3 ] <- %1\n%6 RET",
+    ]);
+
+  expect![[r#"
+      use before definition at %1, use before definition at %1, use before definition at %5, use before definition at %6
+      misused local at %3, misused local at %4, misused local at %5
+  "#]].assert_eq(&out);
+}
+
+#[test]
+fn test_lowered() {
+  // A loop that ends a function, and a name bound twice in a scope, which
+  // must not leak into the next function.
+
+  let out =
+    util::run("
+      fun f(n) {
+        var i = 0
+        var i = 1
+        while i < n { i = i + 1 }
+      }
+      fun g(i) {
+        f(i)
+        return i
+      }
+    ", "g", [Value::Int(3)]);
+
+  expect!["3"].assert_eq(&out);
+}
+
+#[test]
+fn test_scope_undo() {
+  // The inner scope binds `y` twice, and the outer `y` is visible again after
+  // it.
+
+  let source = "
+    fun f(x) {
+      let y = 0
+      if x {
+        let y = 1
+        let y = 2
+      }
+      return y
+    }
+  ";
+
+  let out = [true, false].map(|x| util::run(source, "f", [Value::Bool(x)])).join(", ");
+
+  expect!["0, 0"].assert_eq(&out);
+}
+
+#[test]
+fn test_tail_while() {
+  // The loop ends the function, so its exit is joined before the `RET`.
+
+  let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(b"fun f(n) { var i = 0\n while i < n { i = i + 1 } }", store.arena()).0;
+  let module = lilac::make_iru::compile(&items);
+
+  expect![[r#"
+      === fun f ===
+      %0 LABEL 1
+      %1 = GET 0
+      %2 = 0
+      %3 = LOCAL %2
+      %4 ==> GOTO %5
+      %5 LABEL 0
+      %6 = [ %3 ]
+      %7 = %6 < %1
+      %8 COND %7
+      %9 ==> GOTO %17
+      %10 ==> GOTO %11
+      %11 LABEL 0
+      %12 = [ %3 ]
+      %13 = 1
+      %14 = %12 + %13
+      %15 [ %3 ] <- %14
+      %16 ==> GOTO %5
+      %17 LABEL 0
+      %18 RET
+  "#]].assert_eq(&module.to_string());
+}
+
+#[test]
+fn test_missing_value() {
+  // An `if` without an `else`, or a missing expression, where a value is
+  // needed, ends its block with a static error.
+
+  let sources =
+    [
+      "fun h(x) { let y = if x { 1 } return y }",
+      "fun h(x) { 1 + if x { 2 } }",
+      "fun h(x) { 1 + if }",
+      "fun h(x) { let if }",
+    ];
+
+  let out =
+    sources.map(|source| {
+      [true, false].map(|x| util::run(source, "h", [Value::Bool(x)])).join(", ")
+    }).join("\n");
+
+  expect![[r#"
+      1, error: static error at %10
+      3, error: static error at %11
+      error: static error at %3, error: static error at %3
+      error: static error at %2, error: static error at %2"#]].assert_eq(&out);
+}
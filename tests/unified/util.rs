@@ -1,13 +1,28 @@
-pub(crate) fn dump(out: &mut impl std::fmt::Write, source: &str) {
+// Lowers a source file, and checks that the bytecode is well formed.
+
+pub(crate) fn lower(source: &str) -> lilac::iru::Module {
   let mut store = oxcart::Store::new();
+  let items = lilac::parse::parse(source.as_bytes(), store.arena()).0;
+  let module = lilac::make_iru::compile(&items);
+  verify(&module);
+  return module;
+}
 
-  let module = lilac::parse::parse(source.as_bytes(), store.arena()).0;
-  let module = lilac::make_iru::compile(&module);
+// Panics if the bytecode is not well formed.
 
-  let (environment, solver) = lilac::typecheck::typecheck(&module);
+pub(crate) fn verify(module: &lilac::iru::Module) {
+  if let Err(errors) = lilac::verify_iru::verify(module) {
+    panic!("invalid bytecode: {:?}", errors);
+  }
+}
+
+pub(crate) fn dump(out: &mut impl std::fmt::Write, source: &str) {
+  let module = lower(source);
+
+  let (environment, mut solver) = lilac::typecheck::typecheck(&module);
 
   for f in module.decl.iter() {
-    write!(out, "=== fun {} : {} ===\n", f.name, environment[f.name]).unwrap();
+    write!(out, "=== fun {} : {} ===\n", f.name, environment[f.name].display(solver.types())).unwrap();
 
     for i in f.pos .. f.pos + f.len {
       let inst = module.code[i];
@@ -32,18 +47,176 @@ pub(crate) fn dump(out: &mut impl std::fmt::Write, source: &str) {
         | lilac::iru::Inst::GetLocal(..)
         | lilac::iru::Inst::Op1(..)
         | lilac::iru::Inst::Op2(..) => {
-          let x = lilac::typeid::TypeId(i);
-          write!(out, "%{} {} : {}\n", i, inst, solver.resolve_value_type(x).unwrap()).unwrap();
+          let x = solver.resolve_value_type(lilac::typeid::TypeId(i)).unwrap();
+          write!(out, "%{} {} : {}\n", i, inst, solver.types().display(x)).unwrap();
         }
         | lilac::iru::Inst::Local(..) => {
-          let x = lilac::typeid::TypeId(i);
-          write!(out, "%{} {} : Local {}\n", i, inst, solver.resolve_value_type(x).unwrap()).unwrap();
+          let x = solver.resolve_value_type(lilac::typeid::TypeId(i)).unwrap();
+          write!(out, "%{} {} : Local {}\n", i, inst, solver.types().display(x)).unwrap();
         }
         | lilac::iru::Inst::Label(_) => {
           let x = solver.resolve_tuple_type(lilac::typeid::TypeId(i)).unwrap();
-          write!(out, "%{} {} : {}\n", i, inst, x).unwrap();
+          write!(out, "%{} {} : {}\n", i, inst, solver.types().display(x)).unwrap();
         }
       }
     }
   }
 }
+
+pub(crate) fn dump_irp(out: &mut impl std::fmt::Write, source: &str) {
+  let module = lower(source);
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+
+  match lilac::make_irp::compile(&module, &environment, solver) {
+    Ok(module) => write!(out, "{}", module).unwrap(),
+    Err(e) => write!(out, "error: {}\n", e).unwrap(),
+  }
+}
+
+pub(crate) fn dump_mono(out: &mut impl std::fmt::Write, source: &str, entry: &[&str]) {
+  let module = lower(source);
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+  let module = lilac::make_irp::compile(&module, &environment, solver).unwrap();
+  let entry = entry.iter().map(|s| lilac::symbol::Symbol::from_str(s)).collect::<Vec<_>>();
+
+  match lilac::mono::monomorphize(module, &entry) {
+    Ok(module) => write!(out, "{}", module).unwrap(),
+    Err(e) => write!(out, "error: {}\n", e).unwrap(),
+  }
+}
+
+pub(crate) fn run(source: &str, name: &str, args: impl IntoIterator<Item = lilac::eval_iru::Value>) -> String {
+  let module = lower(source);
+
+  match lilac::eval_iru::run(&module, lilac::symbol::Symbol::from_str(name), args) {
+    Ok(out) => out.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+    Err(e) => format!("error: {}", e),
+  }
+}
+
+pub(crate) fn run_irp(source: &str, name: &str, args: impl IntoIterator<Item = lilac::eval_iru::Value>) -> String {
+  let module = lower(source);
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+  let module = lilac::make_irp::compile(&module, &environment, solver).unwrap();
+  let name = lilac::symbol::Symbol::from_str(name);
+  let module = lilac::mono::monomorphize(module, &[name]).unwrap();
+
+  match lilac::eval_irp::run(&module, name, args) {
+    Ok(out) => out.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+    Err(e) => format!("error: {}", e),
+  }
+}
+
+pub(crate) fn mono(source: &str, name: &str) -> lilac::irp::Module {
+  let module = lower(source);
+  let (environment, solver) = lilac::typecheck::typecheck(&module);
+  let module = lilac::make_irp::compile(&module, &environment, solver).unwrap();
+  return lilac::mono::monomorphize(module, &[lilac::symbol::Symbol::from_str(name)]).unwrap();
+}
+
+// Returns a fresh path in the temporary directory.
+
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+  static COUNTER: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+  let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+  return std::env::temp_dir().join(format!("lilac-{}-{}-{}", std::process::id(), n, name));
+}
+
+// Runs a compiled program, and returns its output in the same format as
+// `run`.
+
+pub(crate) fn run_exe(exe: &std::path::Path) -> String {
+  return run_exe_env(exe, &[]);
+}
+
+pub(crate) fn run_exe_env(exe: &std::path::Path, env: &[(&str, &str)]) -> String {
+  let out = std::process::Command::new(exe).envs(env.iter().copied()).output().unwrap();
+  let _ = std::fs::remove_file(exe);
+  let s = if out.status.success() { out.stdout } else { out.stderr };
+  return String::from_utf8(s).unwrap().trim_end().to_string();
+}
+
+pub(crate) fn run_c(source: &str, name: &str, args: &[lilac::eval_iru::Value]) -> String {
+  let module = mono(source, name);
+  let name = lilac::symbol::Symbol::from_str(name);
+  let mut c = lilac::emit_c::emit(&module).unwrap();
+  c.push_str(&lilac::emit_c::emit_main(&module, name, args).unwrap());
+
+  let src = temp_path("main.c");
+  let exe = temp_path("main");
+  std::fs::write(&src, c).unwrap();
+  let status = std::process::Command::new("cc").arg("-O2").arg("-o").arg(&exe).arg(&src).status().unwrap();
+  let _ = std::fs::remove_file(&src);
+  assert!(status.success());
+  return run_exe(&exe);
+}
+
+pub(crate) fn run_x64(source: &str, name: &str, args: &[lilac::eval_iru::Value]) -> String {
+  return run_x64_env(source, name, args, &[]);
+}
+
+// Runs the compiled code with a collection at every allocation.
+
+pub(crate) fn run_x64_stress(source: &str, name: &str, args: &[lilac::eval_iru::Value]) -> String {
+  return run_x64_env(source, name, args, &[("LILAC_GC_STRESS", "1")]);
+}
+
+fn run_x64_env(source: &str, name: &str, args: &[lilac::eval_iru::Value], env: &[(&str, &str)]) -> String {
+  let module = mono(source, name);
+  let name = lilac::symbol::Symbol::from_str(name);
+  let asm = lilac::emit_x64::emit(&module).unwrap();
+  let host = lilac::emit_x64::emit_main(&module, name, args).unwrap();
+
+  let asm_path = temp_path("main.s");
+  let host_path = temp_path("host.c");
+  let exe = temp_path("main");
+  std::fs::write(&asm_path, asm).unwrap();
+  std::fs::write(&host_path, host).unwrap();
+  let status = std::process::Command::new("cc").arg("-o").arg(&exe).arg(&host_path).arg(&asm_path).status().unwrap();
+  let _ = std::fs::remove_file(&asm_path);
+  let _ = std::fs::remove_file(&host_path);
+  assert!(status.success());
+  return run_exe_env(&exe, env);
+}
+
+// Returns `None` if the system `llc` is not available.
+
+pub(crate) fn run_llvm(source: &str, name: &str, args: &[lilac::eval_iru::Value]) -> Option<String> {
+  let module = mono(source, name);
+  let name = lilac::symbol::Symbol::from_str(name);
+  let ll = lilac::emit_llvm::emit(&module).unwrap();
+  let host = lilac::emit_llvm::emit_main(&module, name, args).unwrap();
+
+  let ll_path = temp_path("main.ll");
+  let asm_path = temp_path("main.s");
+  let host_path = temp_path("host.c");
+  let exe = temp_path("main");
+  std::fs::write(&ll_path, ll).unwrap();
+  std::fs::write(&host_path, host).unwrap();
+  let llc = std::process::Command::new("llc").arg("-O2").arg("-relocation-model=pic").arg("-o").arg(&asm_path).arg(&ll_path).status();
+  let _ = std::fs::remove_file(&ll_path);
+  let Ok(status) = llc else {
+    let _ = std::fs::remove_file(&host_path);
+    return None;
+  };
+  assert!(status.success());
+  let status = std::process::Command::new("cc").arg("-o").arg(&exe).arg(&host_path).arg(&asm_path).status().unwrap();
+  let _ = std::fs::remove_file(&asm_path);
+  let _ = std::fs::remove_file(&host_path);
+  assert!(status.success());
+  return Some(run_exe(&exe));
+}
+
+// Runs `name` with every interpreter and back end, checks that they agree, and
+// returns the result. Without `llc`, the LLVM back end is left out.
+
+pub(crate) fn agree(source: &str, name: &str, args: &[lilac::eval_iru::Value]) -> String {
+  let x = run(source, name, args.iter().cloned());
+  assert_eq!(x, run_irp(source, name, args.iter().cloned()));
+  assert_eq!(x, run_c(source, name, args));
+  assert_eq!(x, run_x64(source, name, args));
+  if let Some(y) = run_llvm(source, name, args) {
+    assert_eq!(x, y);
+  }
+  return x;
+}